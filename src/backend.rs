@@ -0,0 +1,75 @@
+use serde_json::Value;
+use std::fmt;
+
+/// Why a backend call failed.
+#[derive(Debug)]
+pub enum BackendError {
+    NotFound,
+    /// an entity with the same unique field(name, username, key...) exists
+    Conflict(String),
+    /// the entity was refused, e.g. a service still referenced by routes
+    Rejected { status: u16, message: String },
+    /// kong could not be reached or answered something unexpected
+    Unavailable(String),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendError::NotFound => write!(f, "not found"),
+            BackendError::Conflict(message) => write!(f, "conflict: {}", message),
+            BackendError::Rejected { status, message } => write!(f, "status={} {}", status, message),
+            BackendError::Unavailable(why) => write!(f, "{}", why),
+        }
+    }
+}
+
+/// the id of an entity, empty when it has none
+pub fn id_of(entity: &Value) -> String {
+    entity["id"].as_str().unwrap_or_default().to_string()
+}
+
+impl From<BackendError> for String {
+    fn from(error: BackendError) -> String {
+        error.to_string()
+    }
+}
+
+/// Where the entities end up, selected by `--backend`: a live admin api(`http`), a DB-less
+/// declarative file(`file`) or memory only(`memory`, for dry runs and tests).
+///
+/// Collections are admin api paths without the leading slash: `services`, `consumers/<consumer>/jwt`,
+/// `apis/<api>/plugins`, `upstreams/<upstream>/targets`... Entities are addressed by id or name.
+pub trait KongBackend: Send + Sync {
+    /// where the entities go, for the logs
+    fn describe(&self) -> String;
+
+    /// version of the kong the entities are meant for
+    fn version(&self) -> Result<String, BackendError>;
+
+    /// every entity of a collection
+    fn list(&self, collection: &str) -> Result<Vec<Value>, BackendError>;
+
+    /// entities of a collection whose `field` is `value`
+    fn find(&self, collection: &str, field: &str, value: &str) -> Result<Vec<Value>, BackendError>;
+
+    fn get(&self, collection: &str, key: &str) -> Result<Value, BackendError>;
+
+    /// returns the entity created, with its id
+    fn create(&self, collection: &str, entity: &Value) -> Result<Value, BackendError>;
+
+    /// create the entity, or replace the one with the same name(`PUT /apis`)
+    fn upsert(&self, collection: &str, entity: &Value) -> Result<Value, BackendError>;
+
+    fn update(&self, collection: &str, key: &str, fields: &Value) -> Result<Value, BackendError>;
+
+    fn delete(&self, collection: &str, key: &str) -> Result<(), BackendError>;
+
+    /// replace the whole configuration with a declarative one(DB-less `/config`)
+    fn load_declarative(&self, config: &str) -> Result<(), BackendError>;
+
+    /// once the run is applied: writes the file of the file backend, checks a replay...
+    fn finish(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+use credential;
+use csv::{self, StringRecord};
+use entity::{ConsumerInfo, CredentialsInfo, KongConf};
+use serde_json::{self, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::mpsc::sync_channel;
+use std::sync::Mutex;
+use std::thread;
+
+/// separates the values of list cells(`tags`, `groups`, `acls.group`) in csv files
+const LIST_SEPARATOR: char = ';';
+
+/// records read ahead of the workers, per worker
+const READ_AHEAD: usize = 2;
+
+/// One consumer of a `consumers_from` file, with its credentials.
+#[derive(Debug, Deserialize)]
+pub struct ConsumerRecord {
+    #[serde(flatten)]
+    pub consumer: ConsumerInfo,
+    #[serde(default)]
+    pub credentials: Vec<RecordCredential>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecordCredential {
+    pub name: String,
+    #[serde(default)]
+    pub config: BTreeMap<String, Value>,
+}
+
+pub type Records = Box<dyn Iterator<Item = Result<ConsumerRecord, String>>>;
+
+/// Read a csv(`.csv`) or json lines(`.jsonl`, `.ndjson`) file of consumers, one record at a time.
+pub fn read(path: &str) -> Result<Records, String> {
+    let file = File::open(path).map_err(|why| format!("{}: {}", path, why))?;
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match extension.as_str() {
+        "csv" => read_csv(path, file),
+        "jsonl" | "ndjson" => Ok(read_jsonl(path, file)),
+        other => Err(format!(
+            "{}: unsupported consumers_from format `{}`, expected csv or jsonl",
+            path, other
+        )),
+    }
+}
+
+fn read_jsonl(path: &str, file: File) -> Records {
+    let path = path.to_string();
+    Box::new(
+        BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+            .map(move |(idx, line)| {
+                line.map_err(|why| why.to_string())
+                    .and_then(|line| serde_json::from_str(&line).map_err(|why| why.to_string()))
+                    .map_err(|why| format!("{}:{}: {}", path, idx + 1, why))
+            }),
+    )
+}
+
+fn read_csv(path: &str, file: File) -> Result<Records, String> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(file);
+    let headers = reader
+        .headers()
+        .map_err(|why| format!("{}: {}", path, why))?
+        .clone();
+    let path = path.to_string();
+    Ok(Box::new(reader.into_records().map(move |row| {
+        let row = row.map_err(|why| format!("{}: {}", path, why))?;
+        let line = row.position().map_or(0, |p| p.line());
+        csv_record(&headers, &row).map_err(|why| format!("{}:{}: {}", path, line, why))
+    })))
+}
+
+/// Columns are consumer fields(`username`, `custom_id`, `tags`, `groups`...) or credential fields
+/// named `<credential>.<field>`, e.g. `key-auth.key`. Empty cells are left out, `tags`, `groups`
+/// and `acls.group` cells hold `;` separated lists, every acls group being an acl of its own.
+fn csv_record(headers: &StringRecord, row: &StringRecord) -> Result<ConsumerRecord, String> {
+    let mut consumer = ConsumerInfo::default();
+    let mut credentials: Vec<RecordCredential> = Vec::new();
+    for (column, cell) in headers.iter().zip(row.iter()) {
+        if cell.is_empty() {
+            continue;
+        }
+        let (name, field) = match column.find('.') {
+            Some(dot) => (&column[..dot], &column[dot + 1..]),
+            None => {
+                match column {
+                    "username" => consumer.username = Some(cell.to_string()),
+                    "custom_id" => consumer.custom_id = Some(cell.to_string()),
+                    "tags" => consumer.tags = Some(list(cell)),
+                    "groups" => consumer.groups = Some(list(cell)),
+                    other => {
+                        consumer
+                            .extra
+                            .insert(other.to_string(), Value::String(cell.to_string()));
+                    }
+                }
+                continue;
+            }
+        };
+        if (name == "acls" || name == "acl") && field == "group" {
+            for group in list(cell) {
+                let mut config = BTreeMap::new();
+                config.insert(field.to_string(), Value::String(group));
+                credentials.push(RecordCredential {
+                    name: name.to_string(),
+                    config,
+                });
+            }
+            continue;
+        }
+        let idx = match credentials.iter().position(|c| c.name == name) {
+            Some(idx) => idx,
+            None => {
+                credentials.push(RecordCredential {
+                    name: name.to_string(),
+                    config: BTreeMap::new(),
+                });
+                credentials.len() - 1
+            }
+        };
+        credentials[idx]
+            .config
+            .insert(field.to_string(), Value::String(cell.to_string()));
+    }
+    if consumer.name().is_none() {
+        return Err("consumer without username/custom_id".to_string());
+    }
+    Ok(ConsumerRecord {
+        consumer,
+        credentials,
+    })
+}
+
+fn list(cell: &str) -> Vec<String> {
+    cell.split(LIST_SEPARATOR)
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Hand the records over to `concurrency` workers running `apply`.
+///
+/// Records are read on the calling thread only a few ahead of the workers, large files are
+/// never held in memory. Returns the number of records that could not be read.
+pub fn for_each<F>(records: Records, concurrency: usize, apply: F) -> usize
+where
+    F: Fn(ConsumerRecord) + Sync,
+{
+    let concurrency = concurrency.max(1);
+    let (sender, receiver) = sync_channel(concurrency * READ_AHEAD);
+    let receiver = Mutex::new(receiver);
+    let mut unreadable = 0;
+    thread::scope(|scope| {
+        for _ in 0..concurrency {
+            scope.spawn(|| loop {
+                let record = match receiver.lock().unwrap().recv() {
+                    Ok(record) => record,
+                    Err(_) => break,
+                };
+                apply(record);
+            });
+        }
+        for record in records {
+            match record {
+                Ok(record) => {
+                    if sender.send(record).is_err() {
+                        break;
+                    }
+                }
+                Err(why) => {
+                    error!("consumers_from: {}", why);
+                    unreadable += 1;
+                }
+            }
+        }
+        drop(sender);
+    });
+    unreadable
+}
+
+/// acl groups the consumers of a `consumers_from` file are in, unreadable records are skipped
+pub fn assigned_groups(path: &str) -> Result<BTreeSet<String>, String> {
+    let mut groups = BTreeSet::new();
+    for record in read(path)?.filter_map(Result::ok) {
+        groups.extend(record.consumer.groups.unwrap_or_default());
+        groups.extend(
+            record
+                .credentials
+                .iter()
+                .filter(|c| credential::endpoint(&c.name) == Some("acls"))
+                .filter_map(|c| c.config.get("group").and_then(|g| g.as_str()))
+                .map(String::from),
+        );
+    }
+    Ok(groups)
+}
+
+/// Move the consumers of `consumers_from` into the definition, for renderings needing them all
+/// at once(DB-less config, manifests...).
+pub fn expand(conf: &mut KongConf) -> Result<(), String> {
+    let path = match conf.consumers_from.take() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let mut consumers = conf.consumers.take().unwrap_or_default();
+    let mut credentials = conf.credentials.take().unwrap_or_default();
+    for record in read(&path)? {
+        let record = record?;
+        let target = record.consumer.name().unwrap_or_default().to_string();
+        credentials.extend(record.credentials.into_iter().map(|c| CredentialsInfo {
+            name: c.name,
+            target: target.clone(),
+            config: c.config,
+        }));
+        consumers.push(record.consumer);
+    }
+    conf.consumers = Some(consumers);
+    if !credentials.is_empty() {
+        conf.credentials = Some(credentials);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// a file of one test holding `contents`
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("kong-init-bulk-{}-{}", ::std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn records(path: &Path) -> Vec<Result<ConsumerRecord, String>> {
+        read(path.to_str().unwrap()).unwrap().collect()
+    }
+
+    #[test]
+    fn csv_columns_fill_consumers_and_credentials() {
+        let path = temp_file(
+            "partners.csv",
+            "username,custom_id,tags,key-auth.key,acls.group\n\
+             acme, p-1 ,gold;eu,acme-key,partners;reports\n\
+             ,,,orphan-key,\n",
+        );
+        let listed = records(&path);
+        assert_eq!(listed.len(), 2);
+        let acme = listed[0].as_ref().unwrap();
+        assert_eq!(acme.consumer.username, Some("acme".to_string()));
+        assert_eq!(acme.consumer.custom_id, Some("p-1".to_string()));
+        assert_eq!(acme.consumer.tags, Some(vec!["gold".to_string(), "eu".to_string()]));
+        let credentials: Vec<(&str, String)> = acme
+            .credentials
+            .iter()
+            .map(|c| (c.name.as_str(), serde_json::to_string(&c.config).unwrap()))
+            .collect();
+        assert_eq!(
+            credentials,
+            [
+                ("key-auth", r#"{"key":"acme-key"}"#.to_string()),
+                ("acls", r#"{"group":"partners"}"#.to_string()),
+                ("acls", r#"{"group":"reports"}"#.to_string()),
+            ]
+        );
+        assert!(listed[1].as_ref().unwrap_err().contains("partners.csv:3: consumer without username/custom_id"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn jsonl_lines_are_records() {
+        let path = temp_file(
+            "partners.jsonl",
+            "{\"username\": \"acme\", \"credentials\": [{\"name\": \"key-auth\", \"config\": {\"key\": \"acme-key\"}}]}\n\
+             \n\
+             not json\n",
+        );
+        let listed = records(&path);
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].as_ref().unwrap().credentials[0].config["key"], json!("acme-key"));
+        assert!(listed[1].as_ref().unwrap_err().contains("partners.jsonl:3:"));
+        fs::remove_file(&path).unwrap();
+        let unsupported = temp_file("partners.txt", "acme");
+        assert!(read(unsupported.to_str().unwrap()).is_err());
+        fs::remove_file(&unsupported).unwrap();
+    }
+
+    #[test]
+    fn every_record_is_applied_once() {
+        let lines: Vec<String> = (0..500).map(|n| format!("{{\"username\": \"partner-{}\"}}", n)).collect();
+        let path = temp_file("many.jsonl", &format!("{}\nbroken\n", lines.join("\n")));
+        let applied = AtomicUsize::new(0);
+        let unreadable = for_each(read(path.to_str().unwrap()).unwrap(), 8, |record| {
+            assert!(record.consumer.username.is_some());
+            applied.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!((applied.into_inner(), unreadable), (500, 1));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn groups_are_assigned_by_column_and_acls() {
+        let path = temp_file("groups.csv", "username,groups,acls.group\nacme,partners;eu,reports\n");
+        assert_eq!(records(&path)[0].as_ref().unwrap().consumer.groups, Some(vec!["partners".to_string(), "eu".to_string()]));
+        let groups: Vec<String> = assigned_groups(path.to_str().unwrap()).unwrap().into_iter().collect();
+        assert_eq!(groups, ["eu", "partners", "reports"]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use serde_json::{self, Value};
+use state;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// One admin api call and what kong answered, a file of its own in the cassette directory.
+///
+/// The request body is kept as sent, credential secrets included.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub method: String,
+    /// relative to the admin url, query included
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    pub status: u16,
+    /// the json kong answered, or its text when it was not json
+    #[serde(default)]
+    pub response: Value,
+}
+
+impl Exchange {
+    pub fn response_text(&self) -> String {
+        match &self.response {
+            Value::Null => String::new(),
+            Value::String(text) => text.clone(),
+            json => json.to_string(),
+        }
+    }
+
+    fn is_request(&self, method: &str, path: &str, body: Option<&Value>) -> bool {
+        self.method == method && self.path == path && self.body.as_ref() == body
+    }
+}
+
+pub enum Mode<'a> {
+    Record(&'a str),
+    Replay(&'a str),
+}
+
+/// digits numbering the exchange files, `000001-get.json`, in the order the calls were made
+const NUMBER_WIDTH: usize = 6;
+
+fn is_exchange_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    name.ends_with(".json")
+        && name.bytes().take(NUMBER_WIDTH).filter(u8::is_ascii_digit).count() == NUMBER_WIDTH
+        && name.get(NUMBER_WIDTH..).is_some_and(|rest| rest.starts_with('-'))
+}
+
+fn exchange_files(dir: &str) -> Result<Vec<PathBuf>, String> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|why| format!("{}: {}", dir, why))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| is_exchange_file(path))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Writes every exchange to the `--record` directory as it happens.
+pub struct Recorder {
+    dir: String,
+    count: AtomicUsize,
+}
+
+impl Recorder {
+    /// exchanges of an earlier recording in `dir` are removed
+    pub fn create(dir: &str) -> Result<Recorder, String> {
+        fs::create_dir_all(dir).map_err(|why| format!("{}: {}", dir, why))?;
+        for old in exchange_files(dir)? {
+            fs::remove_file(&old).map_err(|why| format!("{}: {}", old.display(), why))?;
+        }
+        Ok(Recorder {
+            dir: dir.to_string(),
+            count: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn record(&self, exchange: &Exchange) {
+        let idx = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        let name = format!("{:0width$}-{}.json", idx, exchange.method.to_lowercase(), width = NUMBER_WIDTH);
+        let path = Path::new(&self.dir).join(name);
+        let result = serde_json::to_string_pretty(exchange)
+            .map_err(|why| why.to_string())
+            .and_then(|text| state::write_private(&path.to_string_lossy(), &text));
+        if let Err(why) = result {
+            error!("record: {}", why);
+        }
+    }
+}
+
+/// Answers the calls from a `--replay` directory instead of kong.
+///
+/// A call is answered by the first exchange not replayed yet with the same method, path and
+/// body, so calls made concurrently may come in another order than recorded. Calls nothing was
+/// recorded for diverge from the recording: they are reported and fail like an unreachable kong.
+pub struct Replayer {
+    dir: String,
+    exchanges: Vec<Exchange>,
+    replayed: Mutex<Vec<bool>>,
+    diverged: AtomicUsize,
+}
+
+impl Replayer {
+    pub fn load(dir: &str) -> Result<Replayer, String> {
+        let mut exchanges = Vec::new();
+        for file in exchange_files(dir)? {
+            let text = fs::read_to_string(&file).map_err(|why| format!("{}: {}", file.display(), why))?;
+            let exchange = serde_json::from_str(&text).map_err(|why| format!("{}: {}", file.display(), why))?;
+            exchanges.push(exchange);
+        }
+        if exchanges.is_empty() {
+            return Err(format!("{}: no recorded exchanges", dir));
+        }
+        Ok(Replayer {
+            dir: dir.to_string(),
+            replayed: Mutex::new(vec![false; exchanges.len()]),
+            exchanges,
+            diverged: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn respond(&self, method: &str, path: &str, body: Option<&Value>) -> Result<&Exchange, String> {
+        let mut replayed = self.replayed.lock().unwrap();
+        let found = (0..self.exchanges.len())
+            .find(|idx| !replayed[*idx] && self.exchanges[*idx].is_request(method, path, body));
+        if let Some(idx) = found {
+            replayed[idx] = true;
+            return Ok(&self.exchanges[idx]);
+        }
+
+        self.diverged.fetch_add(1, Ordering::SeqCst);
+        let similar = (0..self.exchanges.len()).find(|idx| {
+            let exchange = &self.exchanges[*idx];
+            !replayed[*idx] && exchange.method == method && exchange.path == path
+        });
+        let why = match similar {
+            Some(idx) => format!(
+                "{} {} diverges from the recording, body {} was recorded but {} sent",
+                method,
+                path,
+                self.exchanges[idx].body.as_ref().unwrap_or(&Value::Null),
+                body.unwrap_or(&Value::Null)
+            ),
+            None => format!("{} {} was not recorded in {}", method, path, self.dir),
+        };
+        error!("replay: {}", why);
+        Err(why)
+    }
+
+    /// whether every call was replayed and every recorded exchange was called for
+    pub fn finish(&self) -> bool {
+        let replayed = self.replayed.lock().unwrap();
+        let missing: Vec<&Exchange> = self
+            .exchanges
+            .iter()
+            .zip(replayed.iter())
+            .filter(|(_, replayed)| !**replayed)
+            .map(|(exchange, _)| exchange)
+            .collect();
+        for exchange in &missing {
+            error!("replay: {} {} was recorded but never called", exchange.method, exchange.path);
+        }
+        let diverged = self.diverged.load(Ordering::SeqCst);
+        if diverged > 0 || !missing.is_empty() {
+            error!(
+                "replay: {} call(s) diverged, {} recorded exchange(s) left over",
+                diverged,
+                missing.len()
+            );
+            return false;
+        }
+        info!("replay: all {} exchanges of {} matched", self.exchanges.len(), self.dir);
+        true
+    }
+}
@@ -1,494 +1,530 @@
 use entity::{
-    AddRouteResp, AddServiceResp, ApiInfo, ConsumerDO, KongInfo, LegacyPluginAppliedType,
-    ListApiResp, PluginInfo, PluginList, PluginTarget, RouteInfo, RouteList, ServiceInfo,
-    ServiceList,
+    ApiInfo, CertificateInfo, ConsumerInfo, LegacyPluginAppliedType, PluginInfo, PluginTarget,
+    RouteInfo, ServiceInfo, TargetInfo, UpstreamInfo,
 };
 
-use http::StatusCode;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use reqwest::{Client, Error};
-use serde_json::{Map as SerdeMap, Value};
+use backend::{id_of, BackendError, KongBackend};
+use consumer;
+use serde::Serialize;
+use serde_json::{self, Map as SerdeMap, Value};
 use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::str::FromStr;
 
-pub struct KongApiClient<'t> {
-    pub base_url: &'t str,
-    client: Client,
+/// the json form of a payload
+fn to_value<T: Serialize>(payload: &T) -> Result<Value, BackendError> {
+    serde_json::to_value(payload).map_err(|why| BackendError::Unavailable(why.to_string()))
 }
 
-impl<'t> KongApiClient<'t> {
-    pub fn build_with_url_header(
-        kong_admin_url: &'t str,
-        custom_headers_opt: Option<Vec<&'t str>>,
-    ) -> KongApiClient<'t> {
-        let client = match custom_headers_opt {
-            None => Client::new(),
-            Some(header_strs) => {
-                let mut headers = HeaderMap::new();
-                header_strs.iter().for_each(|raw_header| {
-                    if !raw_header.contains(':') {
-                        warn!("invalid header value: {} has ignored!", raw_header)
-                    } else {
-                        let sp: Vec<&str> = raw_header.split(':').collect();
-                        debug!("[args] header value: raw_header={} ", raw_header);
-                        debug!("[args] header value: key={} ", sp[0]);
-                        debug!("[args] header value: value={} ", sp[1]);
-                        headers.insert(
-                            HeaderName::from_str(sp[0]).unwrap(),
-                            HeaderValue::from_str(sp[1].trim()).unwrap(),
-                        );
-                    }
-                });
-                Client::builder().default_headers(headers).build().unwrap()
-            }
-        };
+/// The operations the apply pipeline makes, on whichever backend `--backend` selected.
+pub struct KongApiClient {
+    backend: Box<dyn KongBackend>,
+}
 
-        KongApiClient {
-            base_url: kong_admin_url,
-            client,
-        }
+impl KongApiClient {
+    pub fn new(backend: Box<dyn KongBackend>) -> KongApiClient {
+        KongApiClient { backend }
     }
 
-    pub fn get_node_info(&self) -> Result<KongInfo, Error> {
-        self.client
-            .get(&format!("{}/", self.base_url))
-            .send()
-            .and_then(|mut res| res.json::<KongInfo>())
+    pub fn describe(&self) -> String {
+        self.backend.describe()
     }
 
-    /*********** services ****************/
-    pub fn list_services(&self, offset: Option<String>) -> Result<ServiceList, Error> {
-        let list_srv_url = match offset {
-            None => format!("{}/services", self.base_url),
-            Some(offset) => format!("{}/services?offset={}", self.base_url, offset),
-        };
+    pub fn finish(&self) -> Result<(), String> {
+        self.backend.finish()
+    }
 
-        self.client
-            .get(&list_srv_url)
-            .send()
-            .and_then(|mut res| res.json::<ServiceList>())
+    pub fn get_version(&self) -> Result<String, String> {
+        self.backend.version().map_err(String::from)
     }
 
-    pub fn delete_all_services(&self) {
-        self._delete_service_batch(None);
+    /// replace the whole configuration of a DB-less node
+    pub fn post_declarative_config(&self, declarative: &str) -> bool {
+        match self.backend.load_declarative(declarative) {
+            Err(why) => {
+                error!("post_declarative_config: {}", why);
+                false
+            }
+            Ok(()) => {
+                info!("declarative config has been loaded!");
+                true
+            }
+        }
     }
 
-    pub fn _delete_service_batch(&self, next_offset: Option<String>) {
-        let service_list = self.list_services(next_offset).unwrap();
+    /// ids of every entity of a collection
+    pub fn list_ids(&self, collection: &str) -> Result<Vec<String>, String> {
+        self.backend
+            .list(collection)
+            .map(|entities| entities.iter().map(id_of).collect())
+            .map_err(|why| format!("list {}: {}", collection, why))
+    }
 
-        service_list.data.iter().for_each(|service_item| {
-            self.delete_service(&service_item.id);
-        });
+    /// every entity of a collection, none when this kong has no such collection
+    pub fn list_entities(&self, collection: &str) -> Result<Vec<Value>, String> {
+        match self.backend.list(collection) {
+            Ok(entities) => Ok(entities),
+            Err(BackendError::NotFound) => Ok(Vec::new()),
+            Err(why) => Err(format!("list {}: {}", collection, why)),
+        }
+    }
 
-        if let Some(next) = service_list.offset {
-            self._delete_service_batch(Some(next))
+    /// the config kong gives a plugin by default(`/plugins/schema/<name>`), none when this
+    /// kong does not tell
+    pub fn plugin_defaults(&self, plugin: &str) -> Result<SerdeMap<String, Value>, String> {
+        let schema = match self.backend.get("plugins/schema", plugin) {
+            Ok(schema) => schema,
+            Err(BackendError::NotFound) => return Ok(SerdeMap::new()),
+            Err(why) => return Err(format!("get schema of plugin {}: {}", plugin, why)),
+        };
+        let fields = schema["fields"].as_object().cloned().unwrap_or_default();
+        Ok(fields
+            .into_iter()
+            .filter_map(|(field, definition)| definition.get("default").map(|d| (field, d.clone())))
+            .collect())
+    }
+
+    /// delete an entity, a missing one is skipped
+    fn delete_entity(&self, collection: &str, key: &str, kind: &str) -> Result<(), String> {
+        match self.backend.delete(collection, key) {
+            Ok(()) => info!("{} {} has removed!", kind, key),
+            Err(BackendError::NotFound) => debug!("{} {} not found, skip!", kind, key),
+            Err(why) => return Err(format!("delete {}: {} using {}", kind, why, key)),
         }
+        Ok(())
     }
 
-    pub fn delete_service(&self, service_id_or_name: &str) {
-        match self
-            .client
-            .delete(&format!(
-                "{}/services/{}",
-                self.base_url, service_id_or_name
-            )).send()
-        {
-            Err(why) => error!("delete_service: {} using id={}", why, service_id_or_name),
-            Ok(resp) => {
-                if resp.status() == StatusCode::NO_CONTENT {
-                    info!("service {} has removed!", service_id_or_name)
-                } else if resp.status() == StatusCode::NOT_FOUND {
-                    debug!("service {} not found, skip!", service_id_or_name)
-                } else {
-                    // TODO add body msg
-                    error!(
-                        "delete_service: {} using id={}",
-                        resp.status(),
-                        service_id_or_name
-                    )
-                }
-            }
+    /// create an entity of any collection, returns it with its id
+    pub fn create(&self, collection: &str, entity: &Value) -> Result<Value, String> {
+        self.backend
+            .create(collection, entity)
+            .map_err(|why| format!("create {}: {}", collection, why))
+    }
+
+    /// update the given fields of an entity of any collection
+    pub fn update(&self, collection: &str, key: &str, fields: &Value) -> Result<Value, String> {
+        self.backend
+            .update(collection, key, fields)
+            .map_err(|why| format!("update {}/{}: {}", collection, key, why))
+    }
+
+    /// delete an entity of any collection, a missing one is fine
+    pub fn delete(&self, collection: &str, key: &str) -> Result<(), String> {
+        match self.backend.delete(collection, key) {
+            Ok(()) | Err(BackendError::NotFound) => Ok(()),
+            Err(why) => Err(format!("delete {}/{}: {}", collection, key, why)),
         }
     }
 
+    /*********** services ****************/
+    pub fn delete_service(&self, service_id_or_name: &str) -> Result<(), String> {
+        self.delete_entity("services", service_id_or_name, "service")
+    }
+
     pub fn add_service(&self, payload: &ServiceInfo) -> Option<String> {
         let s_name = payload.get("name").unwrap();
 
-        match self
-            .client
-            .post(&format!("{}/services", self.base_url))
-            .json(payload)
-            .send()
-        {
+        match to_value(payload).and_then(|payload| self.backend.create("services", &payload)) {
             Err(why) => {
-                error!("add_service: {}", why);
+                warn!("add_service: {}", why);
                 None
             }
-            Ok(mut resp) => {
-                if resp.status() == StatusCode::CREATED {
-                    info!("Service {} has CREATED/updated!", s_name);
-                    resp.json::<AddServiceResp>().map(|obj| obj.id).ok()
-                } else {
-                    warn!("add_service: {}", resp.status());
-                    None
-                }
+            Ok(service) => {
+                info!("Service {} has CREATED/updated!", s_name);
+                Some(id_of(&service))
             }
         }
     }
 
-    /*********** routes ****************/
-    pub fn list_routes(&self, offset: Option<String>) -> Result<RouteList, Error> {
-        let list_route_url = match offset {
-            None => format!("{}/routes", self.base_url),
-            Some(offset) => format!("{}/routes?offset={}", self.base_url, offset),
-        };
-
-        self.client
-            .get(&list_route_url)
-            .send()
-            .and_then(|mut res| res.json::<RouteList>())
-    }
-
-    pub fn delete_all_routes(&self) {
-        self._delete_route_batch(None);
+    /*********** upstreams ****************/
+    pub fn delete_upstream(&self, upstream_name: &str) -> Result<(), String> {
+        self.delete_entity("upstreams", upstream_name, "upstream")
     }
 
-    pub fn _delete_route_batch(&self, next_offset: Option<String>) {
-        let route_list = self.list_routes(next_offset).unwrap();
+    pub fn add_upstream(&self, upstream_info: &UpstreamInfo) -> Option<String> {
+        let mut payload = upstream_info.config.clone();
+        payload.insert(
+            "name".to_string(),
+            Value::String(upstream_info.name.clone()),
+        );
 
-        route_list.data.iter().for_each(|route_item| {
-            self.delete_route(&route_item.id);
-        });
-
-        match route_list.offset {
-            None => {}
-            Some(next) => self._delete_route_batch(Some(next)),
+        match to_value(&payload).and_then(|payload| self.backend.create("upstreams", &payload)) {
+            Err(why) => {
+                warn!("add_upstream: {}", why);
+                None
+            }
+            Ok(upstream) => {
+                info!("Upstream {} has CREATED/updated!", upstream_info.name);
+                Some(id_of(&upstream))
+            }
         }
     }
 
-    pub fn delete_route(&self, route_id: &str) {
-        match self
-            .client
-            .delete(&format!("{}/routes/{}", self.base_url, route_id))
-            .send()
-        {
-            Err(why) => error!("delete_route: {} using id={}", why, route_id),
-            Ok(resp) => {
-                if resp.status() == StatusCode::NO_CONTENT {
-                    info!("route {} has removed!", route_id)
-                } else if resp.status() == StatusCode::NOT_FOUND {
-                    debug!("route {} not found, skip!", route_id)
-                } else {
-                    // TODO add body msg
-                    error!("delete_route: {} using id={}", resp.status(), route_id)
-                }
+    pub fn add_target(&self, upstream_id: &str, target_info: &TargetInfo) -> Result<(), String> {
+        let collection = format!("upstreams/{}/targets", upstream_id);
+        match to_value(target_info).and_then(|payload| self.backend.create(&collection, &payload)) {
+            Err(why) => Err(format!("add_target: {}", why)),
+            Ok(_) => {
+                info!(
+                    "Target {} of upstream {} has CREATED!",
+                    target_info.target, upstream_id
+                );
+                Ok(())
             }
         }
     }
-    /*********** routes end ****************/
+    /*********** upstreams end ****************/
 
-    /*********** plugins ****************/
-    pub fn list_plugins(&self, offset: Option<String>) -> Result<PluginList, Error> {
-        let list_plugins_url = match offset {
-            None => format!("{}/plugins", self.base_url),
-            Some(offset) => format!("{}/plugins?offset={}", self.base_url, offset),
-        };
-
-        self.client
-            .get(&list_plugins_url)
-            .send()
-            .and_then(|mut res| res.json::<PluginList>())
+    /*********** certificates ****************/
+    pub fn delete_certificate(&self, sni_or_id: &str) -> Result<(), String> {
+        self.delete_entity("certificates", sni_or_id, "certificate")
     }
 
-    pub fn delete_all_plugins(&self) {
-        self._delete_plugins_batch(None);
+    pub fn add_certificate(&self, certificate_info: &CertificateInfo) -> Result<(), String> {
+        let snis = certificate_info.snis.join(",");
+        match to_value(certificate_info).and_then(|payload| self.backend.create("certificates", &payload)) {
+            Err(why) => Err(format!("add_certificate: {}", why)),
+            Ok(_) => {
+                info!("Certificate for snis [{}] has CREATED!", snis);
+                Ok(())
+            }
+        }
     }
+    /*********** certificates end ****************/
 
-    pub fn _delete_plugins_batch(&self, next_offset: Option<String>) {
-        let plugin_list = self.list_plugins(next_offset).unwrap();
-
-        plugin_list.data.iter().for_each(|plugin_item| {
-            self.delete_plugin_by_id(&plugin_item.id);
-        });
-
-        if let Some(next) = plugin_list.offset {
-            self._delete_plugins_batch(Some(next))
-        }
+    /*********** routes ****************/
+    pub fn delete_route(&self, route_id: &str) -> Result<(), String> {
+        self.delete_entity("routes", route_id, "route")
     }
+    /*********** routes end ****************/
 
-    pub fn delete_plugin_by_id(&self, plugin_id: &str) {
-        match self
-            .client
-            .delete(&format!("{}/plugins/{}", self.base_url, plugin_id))
-            .send()
-        {
-            Err(why) => error!("delete_plugin_by_id: {} using id={}", why, plugin_id),
-            Ok(resp) => {
-                if resp.status() == StatusCode::NO_CONTENT {
-                    info!("plugin {} has removed!", plugin_id)
-                } else if resp.status() == StatusCode::NOT_FOUND {
-                    debug!("plugin {} not found, skip!", plugin_id)
-                } else {
-                    error!(
-                        "delete_plugin_by_id: {} using id={}",
-                        resp.status(),
-                        plugin_id
-                    )
-                }
-            }
-        }
+    /*********** plugins ****************/
+    pub fn delete_plugin_by_id(&self, plugin_id: &str) -> Result<(), String> {
+        self.delete_entity("plugins", plugin_id, "plugin")
     }
     /*********** plugins end ****************/
 
     pub fn add_route_to_service(
         &self,
         service_id: String,
-        mut route_info: RouteInfo,
+        route_info: &RouteInfo,
     ) -> Option<String> {
-        let route_cfg = &mut route_info.config;
+        let mut route_cfg = route_info.config.clone();
 
         let mut silly_obj_map = SerdeMap::new();
         silly_obj_map.insert("id".to_string(), Value::String(service_id));
         route_cfg.insert("service".to_string(), Value::Object(silly_obj_map));
 
-        match self
-            .client
-            .post(&format!("{}/routes", self.base_url))
-            .json(&route_cfg)
-            .send()
-        {
+        match to_value(&route_cfg).and_then(|payload| self.backend.create("routes", &payload)) {
             Err(why) => {
-                error!("add_route: {}", why);
+                warn!("add_route: {}", why);
                 None
             }
-            Ok(mut resp) => {
-                if resp.status() == StatusCode::CREATED {
-                    info!("Route {} has CREATED/updated!", route_info.name);
-                    resp.json::<AddRouteResp>().map(|obj| obj.id).ok()
-                } else {
-                    warn!(
-                        "add_route: status={} {}",
-                        resp.status(),
-                        resp.text().unwrap()
-                    );
-                    None
-                }
+            Ok(route) => {
+                info!("Route {} has CREATED/updated!", route_info.name);
+                Some(id_of(&route))
             }
         }
     }
 
-    pub fn get_api_counts(&self) -> Result<i32, Error> {
-        self.client
-            .get(&format!("{}/apis", self.base_url))
-            .send()
-            .and_then(|mut res| res.json::<ListApiResp>())
-            .map(|list_api_info| list_api_info.total)
+    pub fn get_api_counts(&self) -> Result<usize, String> {
+        self.backend.list("apis").map(|apis| apis.len()).map_err(String::from)
     }
 
-    pub fn delete_api(&self, api_name: &str) {
-        match self
-            .client
-            .delete(&format!("{}/apis/{}", self.base_url, api_name))
-            .send()
-        {
-            Err(why) => error!("delete_api: {}", why),
-            Ok(resp) => {
-                if resp.status() == StatusCode::NO_CONTENT {
-                    info!("API {} has removed!", api_name)
-                } else if resp.status() == StatusCode::NOT_FOUND {
-                    debug!("API {} not found, skip!", api_name)
-                } else {
-                    warn!("delete_api: {}", resp.status())
-                }
-            }
-        }
+    pub fn delete_api(&self, api_name: &str) -> Result<(), String> {
+        self.delete_entity("apis", api_name, "API")
     }
 
-    pub fn upsert_api(&self, api_name: &str, payload: &ApiInfo) {
-        match self
-            .client
-            .put(&format!("{}/apis", self.base_url))
-            .json(payload)
-            .send()
-        {
-            Err(why) => error!("upsert_api: {}", why),
-            Ok(mut resp) => {
-                if resp.status() == StatusCode::CREATED {
-                    info!("API {} has CREATED/updated!", api_name)
-                } else {
-                    warn!("upsert_api: {} {}", resp.status(), resp.text().unwrap())
-                }
+    pub fn upsert_api(&self, api_name: &str, payload: &ApiInfo) -> Result<(), String> {
+        match to_value(payload).and_then(|payload| self.backend.upsert("apis", &payload)) {
+            Err(why) => Err(format!("upsert_api: {}", why)),
+            Ok(_) => {
+                info!("API {} has CREATED/updated!", api_name);
+                Ok(())
             }
         }
     }
 
     /*********** consumers ****************/
 
-    pub fn init_guest_consumer(&self, custom_id: &str) -> String {
+    pub fn init_guest_consumer(&self, custom_id: &str) -> Result<String, String> {
         let payload = json!({
             "custom_id": custom_id,
             "username": custom_id
         });
 
-        match self
-            .client
-            .post(&format!("{}/consumers", self.base_url))
-            .json(&payload)
-            .send()
-        {
-            Err(why) => {
-                error!("upsert_consumer: {}", why);
-                String::from("error_id")
+        let consumer = match self.backend.create("consumers", &payload) {
+            Ok(consumer) => {
+                info!("upsert_consumer: custom_id={} has CREATED!", custom_id);
+                Ok(consumer)
             }
-            Ok(mut resp) => {
-                if resp.status() == StatusCode::CREATED {
-                    info!("upsert_consumer: custom_id={} has CREATED!", custom_id);
-                    resp.json::<ConsumerDO>().unwrap().id
-                } else if resp.status() == StatusCode::CONFLICT {
-                    self.client
-                        .get(&format!("{}/consumers/{}", self.base_url, custom_id))
-                        .send()
-                        .and_then(|mut res| res.json::<ConsumerDO>())
-                        .map(|c_info| c_info.id)
-                        .unwrap()
-                } else {
-                    info!(
-                        "upsert_consumer: unexpected status returned {}",
-                        resp.status()
-                    );
-                    String::from("error_id")
+            Err(BackendError::Conflict(_)) => self.backend.get("consumers", custom_id),
+            Err(why) => Err(why),
+        };
+        match consumer.map(|consumer| id_of(&consumer)) {
+            Ok(ref id) if !id.is_empty() => Ok(id.clone()),
+            Ok(_) => Err(format!("upsert consumer {}: no id returned", custom_id)),
+            Err(why) => Err(format!("upsert consumer {}: {}", custom_id, why)),
+        }
+    }
+
+    /// id of the consumer with this username or id, none when there is none
+    pub fn get_consumer_id(&self, username_or_id: &str) -> Result<Option<String>, String> {
+        match self.backend.get("consumers", username_or_id) {
+            Ok(consumer) => Ok(Some(id_of(&consumer))),
+            Err(BackendError::NotFound) => Ok(None),
+            Err(why) => Err(format!("get consumer {}: {}", username_or_id, why)),
+        }
+    }
+
+    /// every consumer but the ones kong-init keeps for itself
+    pub fn list_consumers(&self) -> Result<Vec<Value>, String> {
+        self.backend
+            .list("consumers")
+            .map(|consumers| consumers.into_iter().filter(|c| !consumer::is_reserved(c)).collect())
+            .map_err(String::from)
+    }
+
+    /// existing consumers with the username or the custom_id of a declared one
+    pub fn find_consumers(&self, consumer: &ConsumerInfo) -> Result<Vec<Value>, String> {
+        let mut found: Vec<Value> = Vec::new();
+        if let Some(username) = &consumer.username {
+            match self.backend.get("consumers", username) {
+                Ok(c) => found.push(c),
+                Err(BackendError::NotFound) => {}
+                Err(why) => return Err(format!("get consumer {}: {}", username, why)),
+            }
+        }
+        if let Some(custom_id) = &consumer.custom_id {
+            let matching = self
+                .backend
+                .find("consumers", "custom_id", custom_id)
+                .map_err(|why| format!("find consumer {}: {}", custom_id, why))?;
+            for c in matching {
+                if !found.iter().any(|f| f.get("id") == c.get("id")) {
+                    found.push(c);
                 }
             }
         }
+        Ok(found)
     }
 
-    pub fn add_consumer(&self, payload: &BTreeMap<String, String>) {
-        let username = payload.get("username").unwrap();
-        match self
-            .client
-            .post(&format!("{}/consumers", self.base_url))
-            .json(&payload)
-            .send()
-        {
+    /// create a consumer, returning its id
+    pub fn add_consumer(&self, consumer: &ConsumerInfo) -> Option<String> {
+        let name = consumer.name().unwrap_or_default();
+        match to_value(consumer).and_then(|payload| self.backend.create("consumers", &payload)) {
             Err(why) => {
-                error!("upsert_consumer: {}", why);
+                error!("add_consumer: {} using {}", why, name);
+                None
             }
-            Ok(resp) => {
-                if resp.status() == StatusCode::CREATED {
-                    info!("upsert_consumer: username={} has CREATED!", username);
-                } else if resp.status() == StatusCode::CONFLICT {
-                    info!("upsert_consumer: username={} has existed! skip..", username);
-                } else {
-                    error!(
-                        "upsert_consumer: unexpected status returned {}",
-                        resp.status()
-                    );
-                }
+            Ok(created) => {
+                info!("add_consumer: {} has CREATED!", name);
+                Some(id_of(&created))
             }
         }
     }
 
-    /*********** plugins end ****************/
+    pub fn update_consumer(&self, consumer_id: &str, payload: &BTreeMap<String, Value>) -> bool {
+        let result = to_value(payload).and_then(|fields| self.backend.update("consumers", consumer_id, &fields));
+        match result {
+            Err(why) => {
+                error!("update_consumer: {} using {}", why, consumer_id);
+                false
+            }
+            Ok(_) => {
+                info!(
+                    "succeed updating consumer {}: {}",
+                    consumer_id,
+                    payload.keys().cloned().collect::<Vec<String>>().join(",")
+                );
+                true
+            }
+        }
+    }
+
+    pub fn delete_consumer(&self, consumer_id: &str) -> bool {
+        match self.backend.delete("consumers", consumer_id) {
+            Ok(()) | Err(BackendError::NotFound) => {
+                info!("succeed deleting consumer {}", consumer_id);
+                true
+            }
+            Err(why) => {
+                error!("delete_consumer: {} using {}", why, consumer_id);
+                false
+            }
+        }
+    }
+
+    /// a consumer kong-init keeps for itself(`kong-init-...`), by username
+    pub fn get_reserved_consumer(&self, username: &str) -> Result<Option<Value>, String> {
+        match self.backend.get("consumers", username) {
+            Ok(consumer) => Ok(Some(consumer)),
+            Err(BackendError::NotFound) => Ok(None),
+            Err(why) => Err(format!("get consumer {}: {}", username, why)),
+        }
+    }
+
+    /// create a reserved consumer holding `custom_id` and return its id, none when it exists
+    pub fn create_reserved_consumer(&self, username: &str, custom_id: &str) -> Result<Option<String>, String> {
+        match self
+            .backend
+            .create("consumers", &json!({ "username": username, "custom_id": custom_id }))
+        {
+            Ok(created) => Ok(Some(id_of(&created))),
+            Err(BackendError::Conflict(_)) => Ok(None),
+            Err(why) => Err(format!("create consumer {}: {}", username, why)),
+        }
+    }
+
+    /// create or update a reserved consumer, holding `custom_id`
+    pub fn put_reserved_consumer(&self, username: &str, custom_id: &str) -> Result<(), String> {
+        let result = match self.get_reserved_consumer(username)? {
+            Some(_) => self
+                .backend
+                .update("consumers", username, &json!({ "custom_id": custom_id })),
+            None => self
+                .backend
+                .create("consumers", &json!({ "username": username, "custom_id": custom_id })),
+        };
+        result
+            .map(|_| ())
+            .map_err(|why| format!("put consumer {}: {}", username, why))
+    }
+
+    /// replace the `custom_id` of a reserved consumer by id, failing once it was deleted
+    pub fn update_reserved_consumer(&self, id: &str, custom_id: &str) -> Result<(), String> {
+        self.backend
+            .update("consumers", id, &json!({ "custom_id": custom_id }))
+            .map(|_| ())
+            .map_err(|why| format!("update consumer {}: {}", id, why))
+    }
+
+    /// delete a reserved consumer by username or id, a missing one is fine
+    pub fn delete_reserved_consumer(&self, username_or_id: &str) -> Result<(), String> {
+        match self.backend.delete("consumers", username_or_id) {
+            Ok(()) | Err(BackendError::NotFound) => Ok(()),
+            Err(why) => Err(format!("delete consumer {}: {}", username_or_id, why)),
+        }
+    }
+
+    /*********** consumers end ****************/
 
     /*********** credentials ****************/
 
+    pub fn list_credentials(&self, consumer: &str, endpoint: &str) -> Result<Vec<Value>, String> {
+        self.backend
+            .list(&format!("consumers/{}/{}", consumer, endpoint))
+            .map_err(String::from)
+    }
+
     pub fn add_credential(
         &self,
-        consumer_id: &str,
-        plugin_name: &str,
-        payload: &BTreeMap<String, String>,
-    ) {
-        let mut json_payload = HashMap::new();
-        let consumer = consumer_id.to_string();
-        let plugin = plugin_name.to_string();
+        consumer: &str,
+        endpoint: &str,
+        payload: &BTreeMap<String, Value>,
+    ) -> Result<(), String> {
+        let collection = format!("consumers/{}/{}", consumer, endpoint);
+        match to_value(payload).and_then(|payload| self.backend.create(&collection, &payload)) {
+            Err(why) => Err(format!("credentials: {} using {}/{}", why, consumer, endpoint)),
+            Ok(_) => {
+                info!(
+                    "succeed creating credential {} to consumer {}",
+                    endpoint, consumer
+                );
+                Ok(())
+            }
+        }
+    }
 
-        for (k, v) in payload.iter() {
-            json_payload.insert(k.to_string(), v.to_string());
+    pub fn update_credential(
+        &self,
+        consumer: &str,
+        endpoint: &str,
+        credential_id: &str,
+        payload: &BTreeMap<String, Value>,
+    ) -> Result<(), String> {
+        let collection = format!("consumers/{}/{}", consumer, endpoint);
+        let result = to_value(payload).and_then(|fields| self.backend.update(&collection, credential_id, &fields));
+        match result {
+            Err(why) => Err(format!(
+                "update_credential: {} using {}/{}/{}",
+                why, consumer, endpoint, credential_id
+            )),
+            Ok(_) => {
+                info!(
+                    "succeed updating credential {} {} of consumer {}: {}",
+                    endpoint,
+                    credential_id,
+                    consumer,
+                    payload.keys().cloned().collect::<Vec<String>>().join(",")
+                );
+                Ok(())
+            }
         }
+    }
 
-        match self
-            .client
-            .post(&format!(
-                "{}/consumers/{}/{}",
-                self.base_url, consumer, plugin
-            )).json(&json_payload)
-            .send()
-        {
-            Err(why) => error!("credentials: {}", why),
-            Ok(resp) => {
-                if resp.status() == StatusCode::CREATED || resp.status() == StatusCode::CONFLICT {
-                    info!(
-                        "succeed creating credential {} to consumer {}",
-                        plugin, consumer
-                    );
-                } else {
-                    // TODO add body msg
-                    error!(
-                        "_credentials: {} using {}/{}",
-                        resp.status(),
-                        consumer,
-                        plugin
-                    );
-                }
+    pub fn delete_credential(&self, consumer: &str, endpoint: &str, credential_id: &str) -> Result<(), String> {
+        let collection = format!("consumers/{}/{}", consumer, endpoint);
+        match self.backend.delete(&collection, credential_id) {
+            Ok(()) | Err(BackendError::NotFound) => {
+                info!(
+                    "succeed deleting credential {} {} of consumer {}",
+                    endpoint, credential_id, consumer
+                );
+                Ok(())
             }
+            Err(why) => Err(format!(
+                "delete_credential: {} using {}/{}/{}",
+                why, consumer, endpoint, credential_id
+            )),
         }
     }
 
     /*********** credentials end ****************/
 
+    /// create a plugin, an identical one already there counts as applied
+    fn create_plugin<T: Serialize>(&self, collection: &str, payload: &T) -> Result<(), BackendError> {
+        match to_value(payload).and_then(|payload| self.backend.create(collection, &payload)) {
+            Ok(_) | Err(BackendError::Conflict(_)) => Ok(()),
+            Err(why) => Err(why),
+        }
+    }
+
     fn _apply_plugin_to_one(
         &self,
         plugin_type: &str,
         plugin_conf: &BTreeMap<String, String>,
         api_name: &str,
-    ) {
+    ) -> Result<(), String> {
         let mut json_payload = HashMap::new();
         json_payload.insert("name".to_string(), plugin_type.to_string());
         for (k, v) in plugin_conf.iter() {
             json_payload.insert(format!("config.{}", k), v.to_string());
         }
 
-        match self
-            .client
-            .post(&format!("{}/apis/{}/plugins", self.base_url, api_name))
-            .json(&json_payload)
-            .send()
-        {
-            Err(why) => error!("apply_plugin_to_one: {}", why),
-            Ok(resp) => {
-                if resp.status() == StatusCode::CREATED || resp.status() == StatusCode::CONFLICT {
-                    info!(
-                        "succeed applying plugin {} to API {}",
-                        plugin_type, api_name
-                    )
-                } else {
-                    error!("_apply_plugin_to_one: {}", resp.status())
-                }
+        match self.create_plugin(&format!("apis/{}/plugins", api_name), &json_payload) {
+            Err(why) => Err(format!("_apply_plugin_to_one: {}", why)),
+            Ok(()) => {
+                info!(
+                    "succeed applying plugin {} to API {}",
+                    plugin_type, api_name
+                );
+                Ok(())
             }
         }
     }
 
-    fn _apply_plugin_to_all(&self, plugin_type: &str, plugin_conf: &BTreeMap<String, String>) {
+    fn _apply_plugin_to_all(&self, plugin_type: &str, plugin_conf: &BTreeMap<String, String>) -> Result<(), String> {
         let mut json_payload = HashMap::new();
         json_payload.insert("name".to_string(), plugin_type.to_string());
         for (k, v) in plugin_conf.iter() {
             json_payload.insert(format!("config.{}", k), v.to_string());
         }
 
-        match self
-            .client
-            .post(&format!("{}/plugins", self.base_url))
-            .json(&json_payload)
-            .send()
-        {
-            Err(why) => error!("apply_plugin_to_all: {}", why),
-            Ok(resp) => {
-                if resp.status() == StatusCode::CREATED || resp.status() == StatusCode::CONFLICT {
-                    info!("succeed applying plugin {} to all API", plugin_type)
-                } else {
-                    error!("_apply_plugin_to_all: {}", resp.status())
-                }
+        match self.create_plugin("plugins", &json_payload) {
+            Err(why) => Err(format!("_apply_plugin_to_all: {}", why)),
+            Ok(()) => {
+                info!("succeed applying plugin {} to all API", plugin_type);
+                Ok(())
             }
         }
     }
@@ -498,64 +534,53 @@ impl<'t> KongApiClient<'t> {
         plugin_type: &str,
         target_apis: (LegacyPluginAppliedType, Option<Vec<String>>),
         plugin_conf: &BTreeMap<String, String>,
-    ) {
+    ) -> Result<(), String> {
         match target_apis {
             (LegacyPluginAppliedType::ALL, _) => {
                 self._apply_plugin_to_all(plugin_type, plugin_conf)
             }
             (LegacyPluginAppliedType::SOME, Some(apis)) => {
                 for api_name in apis {
-                    self._apply_plugin_to_one(plugin_type, plugin_conf, &api_name)
+                    self._apply_plugin_to_one(plugin_type, plugin_conf, &api_name)?
                 }
+                Ok(())
             }
-            (_, _) => {}
+            (_, _) => Ok(()),
         }
     }
 
-    pub fn apply_plugin(&self, target: PluginTarget, plugin_conf: &PluginInfo) {
+    pub fn apply_plugin(&self, target: PluginTarget, plugin_conf: &PluginInfo) -> Result<(), String> {
         let mut json_payload = HashMap::new();
         json_payload.insert("name".to_string(), Value::String(plugin_conf.name.clone()));
         json_payload.insert("enabled".to_string(), Value::Bool(plugin_conf.enabled));
         for (k, v) in plugin_conf.config.iter() {
-            json_payload.insert(format!("config.{}", k), Value::String(v.to_string()));
+            json_payload.insert(format!("config.{}", k), v.clone());
         }
 
         match target {
             PluginTarget::GLOBAL => {
                 let msg = &format!("applying plugin {} to Global", plugin_conf.name);
-                self._apply_plugin(msg, &json_payload);
-            }
-            PluginTarget::SERVICES(services) => {
-                services.iter().for_each(|s_id| {
-                    let msg = &format!("applying plugin {} to service {}", plugin_conf.name, s_id);
-                    json_payload.insert("service_id".to_string(), Value::String(s_id.clone()));
-                    self._apply_plugin(msg, &json_payload);
-                });
-            }
-            PluginTarget::Routes(routes) => {
-                routes.iter().for_each(|r_id| {
-                    let msg = &format!("applying plugin {} to route {}", plugin_conf.name, r_id);
-                    json_payload.insert("route_id".to_string(), Value::String(r_id.clone()));
-                    self._apply_plugin(msg, &json_payload);
-                });
+                self._apply_plugin(msg, &json_payload)
             }
+            PluginTarget::SERVICES(services) => services.iter().try_for_each(|s_id| {
+                let msg = &format!("applying plugin {} to service {}", plugin_conf.name, s_id);
+                json_payload.insert("service_id".to_string(), Value::String(s_id.clone()));
+                self._apply_plugin(msg, &json_payload)
+            }),
+            PluginTarget::Routes(routes) => routes.iter().try_for_each(|r_id| {
+                let msg = &format!("applying plugin {} to route {}", plugin_conf.name, r_id);
+                json_payload.insert("route_id".to_string(), Value::String(r_id.clone()));
+                self._apply_plugin(msg, &json_payload)
+            }),
         }
     }
 
-    pub fn _apply_plugin(&self, target_desc: &str, payload: &HashMap<String, Value>) {
-        match self
-            .client
-            .post(&format!("{}/plugins", self.base_url))
-            .json(payload)
-            .send()
-        {
-            Err(why) => error!("apply_plugin_to_one: {}", why),
-            Ok(resp) => {
-                if resp.status() == StatusCode::CREATED || resp.status() == StatusCode::CONFLICT {
-                    info!("{}", target_desc)
-                } else {
-                    error!("_apply_plugin: error {}", resp.status())
-                }
+    pub fn _apply_plugin(&self, target_desc: &str, payload: &HashMap<String, Value>) -> Result<(), String> {
+        match self.create_plugin("plugins", payload) {
+            Err(why) => Err(format!("_apply_plugin: error {}", why)),
+            Ok(()) => {
+                info!("{}", target_desc);
+                Ok(())
             }
         }
     }
@@ -0,0 +1,176 @@
+use backend::id_of;
+use entity::ConsumerInfo;
+use serde_json::{self, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// fields kong manages itself, never compared nor patched
+const GENERATED_FIELDS: &[&str] = &["id", "created_at", "updated_at"];
+
+/// consumers kong-init keeps for itself(`lock::LOCK`, `fingerprint::MARKER`), never listed,
+/// exported nor pruned
+pub const RESERVED_PREFIX: &str = "kong-init-";
+
+pub fn is_reserved(consumer: &Value) -> bool {
+    consumer
+        .get("username")
+        .and_then(|u| u.as_str())
+        .is_some_and(|u| u.starts_with(RESERVED_PREFIX))
+}
+
+#[derive(Debug)]
+pub enum Change<'a> {
+    Create(&'a ConsumerInfo),
+    Update { id: String, fields: BTreeMap<String, Value> },
+    Unchanged { id: String },
+    Delete { id: String, name: String },
+}
+
+/// Match the declared consumers against the existing ones.
+///
+/// A declared consumer is found by username first, then by custom_id, so changing either one
+/// of them patches the consumer instead of creating another. Existing consumers no declared
+/// one matches are only deleted when `prune` is set and kong-init owns them(`owned` ids, see
+/// `State::consumers`), `keep` are ids declared elsewhere, e.g. by `k-upsert-consumer`.
+pub fn plan<'a>(
+    declared: &'a [ConsumerInfo],
+    existing: &[Value],
+    owned: &BTreeSet<String>,
+    keep: &[String],
+    prune: bool,
+) -> Result<Vec<Change<'a>>, String> {
+    let mut matched = vec![false; existing.len()];
+    let mut changes = Vec::new();
+
+    for consumer in declared {
+        if consumer.name().is_none() {
+            return Err(format!("consumer without username/custom_id: {:?}", consumer));
+        }
+        if let Some(username) = consumer.username.as_ref().filter(|u| u.starts_with(RESERVED_PREFIX)) {
+            return Err(format!("consumer {}: usernames starting with {} are kong-init's own", username, RESERVED_PREFIX));
+        }
+        let found = find(existing, &matched, "username", &consumer.username)
+            .or_else(|| find(existing, &matched, "custom_id", &consumer.custom_id));
+        match found {
+            None => changes.push(Change::Create(consumer)),
+            Some(idx) => {
+                matched[idx] = true;
+                let current = &existing[idx];
+                let id = id_of(current);
+                let fields = match serde_json::to_value(consumer).map_err(|why| why.to_string())? {
+                    Value::Object(fields) => fields,
+                    other => return Err(format!("consumer is not a mapping: {}", other)),
+                };
+                let changed: BTreeMap<String, Value> = fields
+                    .into_iter()
+                    .filter(|(k, _)| !GENERATED_FIELDS.contains(&k.as_str()))
+                    .filter(|(k, v)| !is_same(k, v, current.get(k.as_str()).unwrap_or(&Value::Null)))
+                    .collect();
+                if changed.is_empty() {
+                    changes.push(Change::Unchanged { id });
+                } else {
+                    changes.push(Change::Update { id, fields: changed });
+                }
+            }
+        }
+    }
+
+    if prune {
+        for (idx, current) in existing.iter().enumerate() {
+            let id = id_of(current);
+            if !matched[idx] && owned.contains(&id) && !keep.contains(&id) && !is_reserved(current) {
+                let name = current
+                    .get("username")
+                    .or_else(|| current.get("custom_id"))
+                    .and_then(|n| n.as_str())
+                    .unwrap_or(&id)
+                    .to_string();
+                changes.push(Change::Delete { id, name });
+            }
+        }
+    }
+    Ok(changes)
+}
+
+fn find(existing: &[Value], matched: &[bool], field: &str, value: &Option<String>) -> Option<usize> {
+    let value = value.as_ref()?;
+    (0..existing.len()).find(|idx| {
+        !matched[*idx] && existing[*idx].get(field).and_then(|v| v.as_str()) == Some(value.as_str())
+    })
+}
+
+fn is_same(field: &str, declared: &Value, existing: &Value) -> bool {
+    match (field, declared, existing) {
+        // kong keeps tags in its own order
+        ("tags", Value::Array(declared), Value::Array(existing)) => {
+            let mut declared: Vec<String> = declared.iter().map(|t| t.to_string()).collect();
+            let mut existing: Vec<String> = existing.iter().map(|t| t.to_string()).collect();
+            declared.sort();
+            existing.sort();
+            declared == existing
+        }
+        _ => declared == existing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declared(value: Value) -> Vec<ConsumerInfo> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// the changes as `(kind, id or name)`
+    fn summary(changes: &[Change]) -> Vec<(&'static str, String)> {
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Create(consumer) => ("create", consumer.name().unwrap_or_default().to_string()),
+                Change::Update { id, fields } => ("update", format!("{} {}", id, serde_json::to_string(fields).unwrap())),
+                Change::Unchanged { id } => ("unchanged", id.clone()),
+                Change::Delete { id, .. } => ("delete", id.clone()),
+            }).collect()
+    }
+
+    #[test]
+    fn consumers_are_matched_by_username_then_custom_id() {
+        let existing = vec![
+            json!({ "id": "1", "username": "billing", "custom_id": "b-1", "tags": ["b", "a"] }),
+            json!({ "id": "2", "username": "reports", "custom_id": "r-1" }),
+        ];
+        let declared = declared(json!([
+            { "username": "billing", "custom_id": "b-2", "tags": ["a", "b"] },
+            { "username": "reporting", "custom_id": "r-1" },
+            { "username": "audit" },
+        ]));
+        let changes = plan(&declared, &existing, &BTreeSet::new(), &[], false).unwrap();
+        assert_eq!(
+            summary(&changes),
+            [
+                ("update", r#"1 {"custom_id":"b-2"}"#.to_string()),
+                ("update", r#"2 {"username":"reporting"}"#.to_string()),
+                ("create", "audit".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_owned_consumers_are_pruned() {
+        let existing = vec![
+            json!({ "id": "owned", "username": "gone" }),
+            json!({ "id": "foreign", "username": "someone-elses" }),
+            json!({ "id": "guest", "username": "guest_user" }),
+        ];
+        let owned: BTreeSet<String> = ["owned", "guest"].iter().map(|id| id.to_string()).collect();
+        let keep = ["guest".to_string()];
+        let changes = plan(&[], &existing, &owned, &keep, true).unwrap();
+        assert_eq!(summary(&changes), [("delete", "owned".to_string())]);
+        assert!(plan(&[], &existing, &owned, &keep, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unnamed_consumers_are_refused() {
+        let declared = declared(json!([{ "tags": ["nameless"] }]));
+        assert!(plan(&declared, &[], &BTreeSet::new(), &[], false).is_err());
+    }
+}
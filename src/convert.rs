@@ -0,0 +1,286 @@
+use entity::{ApiInfo, LegacyKongConf, LegacyPluginInfo};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map as SerdeMap, Value};
+use serde_yaml;
+use std::cell::RefCell;
+
+/// Keeps `{{directive}}`s intact while converting: they are swapped for plain placeholders
+/// before parsing(`{{..}}` is a yaml flow mapping) and put back into the emitted text.
+#[derive(Default)]
+pub struct DirectivePlaceholders {
+    directives: RefCell<Vec<String>>,
+}
+
+impl DirectivePlaceholders {
+    pub fn protect(&self, text: &str) -> String {
+        let dd_re = Regex::new(r"\{\{(.+?)}}").unwrap();
+        dd_re
+            .replace_all(text, |caps: &::regex::Captures| {
+                let mut directives = self.directives.borrow_mut();
+                directives.push(caps[0].to_string());
+                placeholder(directives.len() - 1)
+            }).to_string()
+    }
+
+    pub fn restore(&self, text: &str) -> String {
+        let mut output = text.to_string();
+        for (idx, directive) in self.directives.borrow().iter().enumerate() {
+            output = output
+                .replace(&format!("\"{}\"", placeholder(idx)), directive)
+                .replace(&placeholder(idx), directive);
+        }
+        output
+    }
+}
+
+fn placeholder(idx: usize) -> String {
+    format!("__kong_init_directive_{}__", idx)
+}
+
+/// an entity to emit, with the comments explaining how it differs from its legacy source
+struct Annotated {
+    comments: Vec<String>,
+    value: Value,
+    /// emitted as a comment only, for entities the legacy definitions never applied
+    commented_out: bool,
+}
+
+/// Convert API style definitions into Service/Route style yaml.
+///
+/// Every API becomes a service plus a route, plugins are re-targeted onto those services.
+/// Wherever the semantics differ the emitted entity is preceded by a comment.
+pub fn convert_legacy(conf: &LegacyKongConf) -> Result<String, String> {
+    let mut services = Vec::new();
+    let mut routes = Vec::new();
+    for api_info in &conf.apis {
+        let (service, route) = convert_api(api_info)?;
+        services.push(service);
+        routes.push(route);
+    }
+
+    let plugins = conf
+        .plugins
+        .as_ref()
+        .map_or(Vec::new(), |plugins| plugins.iter().map(convert_plugin).collect());
+
+    let mut out = String::new();
+    out.push_str("# converted from API style definitions by `kong-init convert`\n");
+    out.push_str("# review the comments below where services/routes behave differently\n\n");
+    emit_section(&mut out, "services", &services)?;
+    emit_section(&mut out, "routes", &routes)?;
+    emit_section(&mut out, "plugins", &plugins)?;
+    if let Some(consumers) = &conf.consumers {
+        emit_section(&mut out, "consumers", &plain(consumers)?)?;
+    }
+    if let Some(path) = &conf.consumers_from {
+        let path = serde_json::to_string(path).map_err(|why| why.to_string())?;
+        out.push_str(&format!("consumers_from: {}\n\n", path));
+    }
+    if let Some(credentials) = &conf.credentials {
+        emit_section(&mut out, "credentials", &plain(credentials)?)?;
+    }
+    Ok(out)
+}
+
+fn convert_api(api_info: &ApiInfo) -> Result<(Annotated, Annotated), String> {
+    let name = api_info
+        .get("name")
+        .ok_or_else(|| format!("api without name: {:?}", api_info))?;
+    let route_name = format!("r-{}", name);
+
+    let mut service = SerdeMap::new();
+    let mut service_comments = Vec::new();
+    service.insert("name".to_string(), Value::String(name.clone()));
+
+    let mut route = SerdeMap::new();
+    let mut route_config = SerdeMap::new();
+    let mut route_comments = Vec::new();
+    route.insert("name".to_string(), Value::String(route_name.clone()));
+    route.insert("apply_to".to_string(), Value::String(name.clone()));
+
+    for (field, raw) in api_info {
+        match field.as_str() {
+            "name" | "created_at" | "id" => {}
+            "upstream_url" => {
+                service.insert("url".to_string(), Value::String(raw.clone()));
+            }
+            "upstream_connect_timeout" => {
+                service.insert("connect_timeout".to_string(), typed(raw));
+            }
+            "upstream_send_timeout" => {
+                service.insert("write_timeout".to_string(), typed(raw));
+            }
+            "upstream_read_timeout" => {
+                service.insert("read_timeout".to_string(), typed(raw));
+            }
+            "retries" => {
+                service.insert("retries".to_string(), typed(raw));
+            }
+            "uris" => {
+                route_config.insert("paths".to_string(), list(raw));
+            }
+            "hosts" => {
+                route_config.insert("hosts".to_string(), list(raw));
+            }
+            "methods" => {
+                route_config.insert("methods".to_string(), list(raw));
+            }
+            "strip_uri" => {
+                route_config.insert("strip_path".to_string(), typed(raw));
+            }
+            "preserve_host" => {
+                route_config.insert("preserve_host".to_string(), typed(raw));
+            }
+            "https_only" => {
+                if typed(raw) == Value::Bool(true) {
+                    route_config.insert("protocols".to_string(), json!(["https"]));
+                    route_comments.push(format!(
+                        "api {}: https_only answered plain http with 426, a https-only route does not match it at all(404)",
+                        name
+                    ));
+                }
+            }
+            "http_if_terminated" => route_comments.push(format!(
+                "api {}: http_if_terminated={} has no route equivalent and was dropped",
+                name, raw
+            )),
+            other => service_comments.push(format!(
+                "api {}: unknown field {}={} was dropped",
+                name, other, raw
+            )),
+        }
+    }
+
+    if !api_info.contains_key("upstream_url") {
+        service_comments.push(format!("api {}: no upstream_url, set `url` by hand", name));
+    }
+    route.insert("config".to_string(), Value::Object(route_config));
+
+    Ok((
+        Annotated {
+            comments: service_comments,
+            value: Value::Object(service),
+            commented_out: false,
+        },
+        Annotated {
+            comments: route_comments,
+            value: Value::Object(route),
+            commented_out: false,
+        },
+    ))
+}
+
+fn convert_plugin(plugin_info: &LegacyPluginInfo) -> Annotated {
+    let mut comments = vec![format!(
+        "legacy plugin `{}`, plugins are now named by their type",
+        plugin_info.name
+    )];
+
+    let target = match plugin_info.target_api.as_str() {
+        "all" => {
+            comments.push(
+                "target_api: all -> global, also applies to services/routes not declared here"
+                    .to_string(),
+            );
+            Some("global".to_string())
+        }
+        "none" => {
+            comments.push("target_api: none was never applied, left out of the definitions".to_string());
+            None
+        }
+        apis => {
+            comments.push("plugins on an API are applied to the service converted from it".to_string());
+            Some(format!("s[{}]", apis))
+        }
+    };
+
+    // the plugin's schema alone tells a number from a string of digits(a key, "007"), the values
+    // are kept as the strings they were
+    let config: SerdeMap<String, Value> = plugin_info
+        .config
+        .iter()
+        .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+        .collect();
+    let untyped: Vec<&str> = plugin_info
+        .config
+        .iter()
+        .filter(|(_, v)| typed(v) != Value::String(v.to_string()))
+        .map(|(k, _)| k.as_str())
+        .collect();
+    if !untyped.is_empty() {
+        comments.push(format!(
+            "config {} kept as strings, set those the plugin takes as numbers/booleans by hand",
+            untyped.join(", ")
+        ));
+    }
+
+    let mut plugin = SerdeMap::new();
+    plugin.insert("name".to_string(), Value::String(plugin_info.plugin_type.clone()));
+    plugin.insert("enabled".to_string(), Value::Bool(true));
+    if let Some(target) = &target {
+        plugin.insert("target".to_string(), Value::String(target.clone()));
+    }
+    if !config.is_empty() {
+        plugin.insert("config".to_string(), Value::Object(config));
+    }
+
+    Annotated {
+        comments,
+        value: Value::Object(plugin),
+        commented_out: target.is_none(),
+    }
+}
+
+/// legacy values are all strings, restore the numbers and booleans of the api fields
+fn typed(raw: &str) -> Value {
+    if let Ok(i) = raw.parse::<i64>() {
+        json!(i)
+    } else if raw == "true" || raw == "false" {
+        Value::Bool(raw == "true")
+    } else {
+        Value::String(raw.to_string())
+    }
+}
+
+fn list(raw: &str) -> Value {
+    Value::Array(
+        raw.split(',')
+            .map(|s| Value::String(s.trim().to_string()))
+            .filter(|s| s != "")
+            .collect(),
+    )
+}
+
+fn plain<T: Serialize>(entities: &[T]) -> Result<Vec<Annotated>, String> {
+    entities
+        .iter()
+        .map(|entity| {
+            serde_json::to_value(entity)
+                .map(|value| Annotated {
+                    comments: Vec::new(),
+                    value,
+                    commented_out: false,
+                }).map_err(|why| why.to_string())
+        }).collect()
+}
+
+fn emit_section(out: &mut String, section: &str, entities: &[Annotated]) -> Result<(), String> {
+    out.push_str(section);
+    out.push_str(":\n");
+    for entity in entities {
+        for comment in &entity.comments {
+            out.push_str(&format!("  # {}\n", comment));
+        }
+        let text = serde_yaml::to_string(&entity.value).map_err(|why| why.to_string())?;
+        let body = text.trim_start_matches("---").trim_start_matches('\n');
+        for (idx, line) in body.lines().enumerate() {
+            out.push_str(if entity.commented_out { "  # " } else { "  " });
+            out.push_str(if idx == 0 { "- " } else { "  " });
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out.push('\n');
+    Ok(())
+}
@@ -0,0 +1,503 @@
+use entity::{ConsumerInfo, CredentialsInfo};
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde_json::{self, Value};
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, BTreeSet};
+use state::State;
+use std::fs;
+
+/// credential types by admin api endpoint(`/consumers/{consumer}/<endpoint>`), with the
+/// field identifying a credential of that type
+const CREDENTIAL_TYPES: &[(&str, &str)] = &[
+    ("key-auth", "key"),
+    ("basic-auth", "username"),
+    ("hmac-auth", "username"),
+    ("jwt", "key"),
+    ("oauth2", "client_id"),
+    ("acls", "group"),
+];
+
+/// fields kong manages itself, never compared nor patched
+const GENERATED_FIELDS: &[&str] = &["id", "created_at", "consumer", "consumer_id"];
+
+/// admin api endpoint of a credential type, `None` for types that cannot be reconciled
+pub fn endpoint(plugin: &str) -> Option<&'static str> {
+    let plugin = if plugin == "acl" { "acls" } else { plugin };
+    CREDENTIAL_TYPES
+        .iter()
+        .find(|(endpoint, _)| *endpoint == plugin)
+        .map(|(endpoint, _)| *endpoint)
+}
+
+pub fn endpoints() -> Vec<&'static str> {
+    CREDENTIAL_TYPES.iter().map(|(endpoint, _)| *endpoint).collect()
+}
+
+fn natural_key(endpoint: &str) -> &'static str {
+    CREDENTIAL_TYPES
+        .iter()
+        .find(|(e, _)| *e == endpoint)
+        .map_or("id", |(_, key)| *key)
+}
+
+/// Inline file references of a credential, e.g. jwt's `rsa_public_key_file`.
+pub fn resolve_files(credential: &mut CredentialsInfo) -> Result<(), String> {
+    if let Some(path) = credential.config.remove("rsa_public_key_file") {
+        if credential.name != "jwt" {
+            return Err(format!("rsa_public_key_file is only supported by jwt, not {}", credential.name));
+        }
+        let path = path
+            .as_str()
+            .ok_or_else(|| "rsa_public_key_file must be a path".to_string())?
+            .to_string();
+        let key = fs::read_to_string(&path).map_err(|why| format!("{}: {}", path, why))?;
+        credential
+            .config
+            .insert("rsa_public_key".to_string(), Value::String(key));
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum Change {
+    Create(BTreeMap<String, Value>),
+    Update { id: String, fields: BTreeMap<String, Value> },
+    Unchanged { id: String },
+    Delete { id: String },
+}
+
+/// Match the declared credentials of one consumer and type against the existing ones.
+///
+/// Credentials are matched by natural key(key, username, client_id, group), declared ones
+/// without it(e.g. a key-auth letting kong generate the key) take any unmatched existing one.
+/// Existing credentials no declared one matches are only deleted when `prune` is set.
+pub fn plan(
+    endpoint: &str,
+    declared: &[&BTreeMap<String, Value>],
+    existing: &[Value],
+    prune: bool,
+) -> Vec<Change> {
+    let key = natural_key(endpoint);
+    let mut matched = vec![false; existing.len()];
+    let mut changes = Vec::new();
+
+    // keyed ones first, so unkeyed ones cannot take their counterparts
+    let (keyed, unkeyed): (Vec<&&BTreeMap<String, Value>>, Vec<_>) =
+        declared.iter().partition(|d| d.contains_key(key));
+    for config in keyed.into_iter().chain(unkeyed) {
+        let found = (0..existing.len()).find(|idx| {
+            !matched[*idx]
+                && config
+                    .get(key)
+                    .is_none_or(|k| existing[*idx].get(key).is_some_and(|e| text(e) == text(k)))
+        });
+        match found {
+            None => changes.push(Change::Create((*config).clone())),
+            Some(idx) => {
+                matched[idx] = true;
+                let current = &existing[idx];
+                let id = text(current.get("id").unwrap_or(&Value::Null));
+                let fields: BTreeMap<String, Value> = config
+                    .iter()
+                    .filter(|(k, _)| !GENERATED_FIELDS.contains(&k.as_str()))
+                    .filter(|(k, v)| !is_same(endpoint, k, v, current))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                if fields.is_empty() {
+                    changes.push(Change::Unchanged { id });
+                } else {
+                    changes.push(Change::Update { id, fields });
+                }
+            }
+        }
+    }
+
+    if prune {
+        for (idx, current) in existing.iter().enumerate() {
+            if !matched[idx] {
+                let id = text(current.get("id").unwrap_or(&Value::Null));
+                changes.push(Change::Delete { id });
+            }
+        }
+    }
+    changes
+}
+
+fn is_same(endpoint: &str, field: &str, declared: &Value, current: &Value) -> bool {
+    let existing = current.get(field).unwrap_or(&Value::Null);
+    match (endpoint, field) {
+        // stored as sha1(password .. consumer_id)
+        ("basic-auth", "password") => {
+            let consumer_id = current
+                .pointer("/consumer/id")
+                .or_else(|| current.get("consumer_id"))
+                .map_or(String::new(), text);
+            let hashed: String = Sha1::digest(format!("{}{}", text(declared), consumer_id).as_bytes())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            hashed == text(existing)
+        }
+        // hashed secrets cannot be compared, changing them means recreating the application
+        ("oauth2", "client_secret") if current.get("hash_secret") == Some(&Value::Bool(true)) => true,
+        _ => text(declared) == text(existing),
+    }
+}
+
+/// form style text of a value, lists are joined by comma
+pub fn text(v: &Value) -> String {
+    match v {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(text).collect::<Vec<String>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+/// fields generated when omitted, by credential type
+const GENERATED_SECRETS: &[(&str, &[&str])] = &[
+    ("jwt", &["key", "secret"]),
+    ("key-auth", &["key"]),
+    ("oauth2", &["client_id", "client_secret"]),
+];
+const SECRET_LEN: usize = 32;
+
+/// secrets generated for one credential, as handed out through `--secrets-output`
+#[derive(Debug, Serialize)]
+pub struct GeneratedSecret {
+    pub consumer: String,
+    pub credential: String,
+    pub index: usize,
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Fill in omitted jwt/key-auth/oauth2 keys and secrets with random values.
+///
+/// Values are kept in the state under the slot of the credential(see `secret_slot`), later
+/// runs reuse them instead of generating, whatever the order of the declared credentials.
+pub fn fill_secrets(credentials: &mut [CredentialsInfo], state: &mut State) -> Vec<GeneratedSecret> {
+    let mut generated = Vec::new();
+    let mut seen: BTreeMap<(String, String), usize> = BTreeMap::new();
+    let mut slots: BTreeMap<String, usize> = BTreeMap::new();
+    for credential in credentials.iter_mut() {
+        let fields = match GENERATED_SECRETS.iter().find(|(name, _)| *name == credential.name) {
+            Some((_, fields)) => fields,
+            None => continue,
+        };
+        let index = seen
+            .entry((credential.target.clone(), credential.name.clone()))
+            .or_insert(0);
+        *index += 1;
+
+        // asymmetric jwt algorithms verify with rsa_public_key, there is no secret
+        let asymmetric = credential
+            .config
+            .get("algorithm")
+            .and_then(|a| a.as_str())
+            .is_some_and(|a| !a.starts_with("HS"));
+        let missing: Vec<&str> = fields
+            .iter()
+            .filter(|f| !credential.config.contains_key(**f))
+            .filter(|f| !(asymmetric && **f == "secret"))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+
+        let mut slot = secret_slot(credential, fields);
+        // credentials declared the same way are told apart by their rank
+        let same = slots.entry(slot.clone()).or_insert(0);
+        *same += 1;
+        if *same > 1 {
+            slot = format!("{}#{}", slot, same);
+        }
+        // slots of earlier versions were `<consumer>:<type>:<index>`
+        let legacy = format!("{}:{}:{}", credential.target, credential.name, index);
+        if !state.secrets.contains_key(&slot) {
+            if let Some(kept) = state.secrets.remove(&legacy) {
+                state.secrets.insert(slot.clone(), kept);
+            }
+        }
+        let kept = state.secrets.entry(slot).or_default();
+        let mut values = BTreeMap::new();
+        for field in missing {
+            let value = kept
+                .entry(field.to_string())
+                .or_insert_with(|| random_secret(SECRET_LEN))
+                .clone();
+            credential
+                .config
+                .insert(field.to_string(), Value::String(value.clone()));
+            values.insert(field.to_string(), value);
+        }
+        generated.push(GeneratedSecret {
+            consumer: credential.target.clone(),
+            credential: credential.name.clone(),
+            index: *index,
+            fields: values,
+        });
+    }
+    generated
+}
+
+/// `<consumer>:<credential type>:<identity>`, the identity being the natural key of the
+/// credential(`{"key":"billing"}`) when declared, its declared fields but the generated ones
+/// otherwise(`{"name":"billing-app"}` for an oauth2 application, `{}` for a bare key-auth).
+fn secret_slot(credential: &CredentialsInfo, generated: &[&str]) -> String {
+    let key = natural_key(endpoint(&credential.name).unwrap_or_default());
+    let identity: BTreeMap<&String, &Value> = match credential.config.get_key_value(key) {
+        Some((field, value)) => Some((field, value)).into_iter().collect(),
+        None => credential
+            .config
+            .iter()
+            .filter(|(field, _)| !generated.contains(&field.as_str()))
+            .collect(),
+    };
+    format!(
+        "{}:{}:{}",
+        credential.target,
+        credential.name,
+        serde_json::to_string(&identity).unwrap_or_default()
+    )
+}
+
+fn random_secret(len: usize) -> String {
+    OsRng.sample_iter(&Alphanumeric).take(len).collect()
+}
+
+/// Turn the `groups` of consumers into acls credentials, returning the consumers whose acls
+/// are reconciled to exactly their groups.
+pub fn expand_groups(
+    consumers: &mut [ConsumerInfo],
+    credentials: &mut Option<Vec<CredentialsInfo>>,
+) -> BTreeSet<String> {
+    let mut exact = BTreeSet::new();
+    for consumer in consumers.iter_mut() {
+        let groups = match consumer.groups.take() {
+            Some(groups) => groups,
+            None => continue,
+        };
+        let target = match consumer.name() {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let declared = credentials.get_or_insert_with(Vec::new);
+        for config in acl_configs(&groups) {
+            let duplicate = declared.iter().any(|c| {
+                endpoint(&c.name) == Some("acls") && c.target == target && c.config == config
+            });
+            if !duplicate {
+                declared.push(CredentialsInfo {
+                    name: "acls".to_string(),
+                    target: target.clone(),
+                    config,
+                });
+            }
+        }
+        exact.insert(target);
+    }
+    exact
+}
+
+/// acls credential configs of a list of groups
+pub fn acl_configs(groups: &[String]) -> Vec<BTreeMap<String, Value>> {
+    groups
+        .iter()
+        .map(|group| {
+            let mut config = BTreeMap::new();
+            config.insert("group".to_string(), Value::String(group.clone()));
+            config
+        }).collect()
+}
+
+/// groups assigned through acls credentials
+pub fn assigned_groups(credentials: &[CredentialsInfo]) -> BTreeSet<String> {
+    credentials
+        .iter()
+        .filter(|c| endpoint(&c.name) == Some("acls"))
+        .filter_map(|c| c.config.get("group"))
+        .map(text)
+        .collect()
+}
+
+/// Every group an acl plugin allows(`whitelist`/`allow`, by plugin target) must be assigned to
+/// some consumer, nobody could pass it otherwise.
+pub fn check_acl_groups(allowed: &[(String, Value)], assigned: &BTreeSet<String>) -> Result<(), String> {
+    for (target, groups) in allowed {
+        let groups: Vec<String> = match groups {
+            Value::Array(items) => items.iter().map(text).collect(),
+            other => text(other).split(',').map(|g| g.trim().to_string()).collect(),
+        };
+        let missing: Vec<&String> = groups
+            .iter()
+            .filter(|g| !g.is_empty() && !assigned.contains(*g))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "acl plugin on {} allows groups no consumer is in: {}",
+                target,
+                missing.iter().map(|g| g.as_str()).collect::<Vec<&str>>().join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(value: Value) -> BTreeMap<String, Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn credential(name: &str, target: &str, value: Value) -> CredentialsInfo {
+        CredentialsInfo {
+            name: name.to_string(),
+            target: target.to_string(),
+            config: config(value),
+        }
+    }
+
+    /// the changes as `(kind, id or created config)`
+    fn summary(changes: &[Change]) -> Vec<(&'static str, String)> {
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Create(config) => ("create", serde_json::to_string(config).unwrap()),
+                Change::Update { id, fields } => ("update", format!("{} {}", id, serde_json::to_string(fields).unwrap())),
+                Change::Unchanged { id } => ("unchanged", id.clone()),
+                Change::Delete { id } => ("delete", id.clone()),
+            }).collect()
+    }
+
+    #[test]
+    fn credentials_are_matched_by_natural_key() {
+        let existing = vec![
+            json!({ "id": "a", "key": "stale", "consumer": { "id": "c" } }),
+            json!({ "id": "b", "key": "kept", "consumer": { "id": "c" } }),
+        ];
+        let (kept, added) = (config(json!({ "key": "kept" })), config(json!({ "key": "added" })));
+        let declared = [&kept, &added];
+
+        let expected = vec![("unchanged", "b".to_string()), ("create", r#"{"key":"added"}"#.to_string())];
+        assert_eq!(summary(&plan("key-auth", &declared, &existing, false)), expected);
+        let mut pruned = expected;
+        pruned.push(("delete", "a".to_string()));
+        assert_eq!(summary(&plan("key-auth", &declared, &existing, true)), pruned);
+    }
+
+    #[test]
+    fn changed_fields_are_updated() {
+        let existing = vec![json!({ "id": "j", "key": "billing", "secret": "old", "algorithm": "HS256" })];
+        let declared = config(json!({ "key": "billing", "secret": "new", "algorithm": "HS256" }));
+        assert_eq!(
+            summary(&plan("jwt", &[&declared], &existing, false)),
+            [("update", r#"j {"secret":"new"}"#.to_string())]
+        );
+    }
+
+    #[test]
+    fn unkeyed_credentials_take_what_the_keyed_ones_left() {
+        let existing = vec![json!({ "id": "generated", "key": "k1" }), json!({ "id": "declared", "key": "k2" })];
+        let (unkeyed, keyed) = (config(json!({})), config(json!({ "key": "k2" })));
+        assert_eq!(
+            summary(&plan("key-auth", &[&unkeyed, &keyed], &existing, true)),
+            [("unchanged", "declared".to_string()), ("unchanged", "generated".to_string())]
+        );
+    }
+
+    #[test]
+    fn basic_auth_passwords_are_compared_hashed() {
+        let hashed: String = Sha1::digest(b"s3cr3tc0nsumer")
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let existing = vec![json!({ "id": "b", "username": "billing", "password": hashed, "consumer": { "id": "c0nsumer" } })];
+        let same = config(json!({ "username": "billing", "password": "s3cr3t" }));
+        let changed = config(json!({ "username": "billing", "password": "other" }));
+        assert_eq!(summary(&plan("basic-auth", &[&same], &existing, false)), [("unchanged", "b".to_string())]);
+        assert_eq!(
+            summary(&plan("basic-auth", &[&changed], &existing, false)),
+            [("update", r#"b {"password":"other"}"#.to_string())]
+        );
+    }
+
+    #[test]
+    fn rsa_public_keys_are_read_from_their_file() {
+        let path = ::std::env::temp_dir().join(format!("kong-init-credential-{}.pem", ::std::process::id()));
+        fs::write(&path, "-----BEGIN PUBLIC KEY-----").unwrap();
+        let file = json!({ "key": "billing", "algorithm": "RS256", "rsa_public_key_file": path.to_str().unwrap() });
+        let mut jwt = credential("jwt", "billing", file.clone());
+        resolve_files(&mut jwt).unwrap();
+        assert_eq!(jwt.config["rsa_public_key"], json!("-----BEGIN PUBLIC KEY-----"));
+        assert!(!jwt.config.contains_key("rsa_public_key_file"));
+        assert!(resolve_files(&mut credential("key-auth", "billing", file)).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn omitted_secrets_are_generated_once() {
+        let declared = || {
+            vec![
+                credential("jwt", "billing", json!({ "algorithm": "HS256" })),
+                credential("jwt", "billing", json!({ "algorithm": "RS256", "rsa_public_key": "-----BEGIN" })),
+                credential("key-auth", "billing", json!({ "key": "given" })),
+            ]
+        };
+        let mut state = State::default();
+        let mut credentials = declared();
+        let generated = fill_secrets(&mut credentials, &mut state);
+        assert_eq!(generated.len(), 2);
+        let secret = credentials[0].config["secret"].as_str().unwrap().to_string();
+        assert_eq!(secret.len(), SECRET_LEN);
+        assert!(secret.chars().all(|c| c.is_ascii_alphanumeric()));
+        // asymmetric algorithms get a key, no secret, declared values are left alone
+        assert!(credentials[1].config.contains_key("key"));
+        assert!(!credentials[1].config.contains_key("secret"));
+        assert_eq!(credentials[2].config["key"], json!("given"));
+
+        // the next run reuses them, whatever the order
+        let mut again = declared();
+        again.reverse();
+        fill_secrets(&mut again, &mut state);
+        assert_eq!(again[2].config["secret"], json!(secret));
+        assert_eq!(again[1].config["key"], credentials[1].config["key"]);
+        assert_eq!(again[2].config["key"], credentials[0].config["key"]);
+    }
+
+    #[test]
+    fn consumer_groups_become_acls() {
+        let mut consumers: Vec<ConsumerInfo> =
+            serde_json::from_value(json!([{ "username": "billing", "groups": ["finance", "reports"] }, { "username": "guest" }]))
+                .unwrap();
+        let mut credentials = Some(vec![credential("acls", "billing", json!({ "group": "finance" }))]);
+        let exact = expand_groups(&mut consumers, &mut credentials);
+
+        assert_eq!(exact.into_iter().collect::<Vec<String>>(), ["billing"]);
+        let groups: Vec<(String, String)> = credentials
+            .unwrap()
+            .iter()
+            .map(|c| (c.target.clone(), text(&c.config["group"])))
+            .collect();
+        assert_eq!(
+            groups,
+            [("billing".to_string(), "finance".to_string()), ("billing".to_string(), "reports".to_string())]
+        );
+    }
+
+    #[test]
+    fn acl_plugins_allow_assigned_groups_only() {
+        let assigned = assigned_groups(&[credential("acl", "billing", json!({ "group": "finance" }))]);
+        let allowed = |groups: Value| vec![("s[billing]".to_string(), groups)];
+        assert!(check_acl_groups(&allowed(json!(["finance"])), &assigned).is_ok());
+        assert!(check_acl_groups(&allowed(json!("finance, ")), &assigned).is_ok());
+        assert_eq!(
+            check_acl_groups(&allowed(json!("finance,audit")), &assigned),
+            Err("acl plugin on s[billing] allows groups no consumer is in: audit".to_string())
+        );
+    }
+}
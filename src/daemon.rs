@@ -0,0 +1,129 @@
+//! What `--daemon` found on its last check, served over http on `--status-addr`.
+//!
+//! `GET /status` answers json for people and scripts, `GET /metrics` the prometheus text format
+//! so drift can be alerted on.
+use serde_json;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// time between two looks at the stop flag while waiting for the next check
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+/// how long a status client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// the outcome of the checks so far
+#[derive(Debug, Default, Serialize)]
+pub struct Status {
+    /// checks made, failed ones included
+    pub checks: u64,
+    pub failed_checks: u64,
+    /// unix time of the last check
+    pub checked_at: Option<u64>,
+    /// none until a check succeeded
+    pub in_sync: Option<bool>,
+    /// the changes bringing kong back to the definitions, as logged
+    pub drift: Vec<String>,
+    pub summary: Option<String>,
+    /// why the last check failed
+    pub error: Option<String>,
+    /// checks whose drift was healed(`--heal`)
+    pub heals: u64,
+}
+
+impl Status {
+    fn metrics(&self) -> String {
+        let gauge = |name: &str, help: &str, value: u64| {
+            format!("# HELP {0} {1}\n# TYPE {0} gauge\n{0} {2}\n", name, help, value)
+        };
+        let counter = |name: &str, help: &str, value: u64| {
+            format!("# HELP {0} {1}\n# TYPE {0} counter\n{0} {2}\n", name, help, value)
+        };
+        [
+            gauge(
+                "kong_init_drift_changes",
+                "changes bringing kong back to the definitions, found by the last check",
+                self.drift.len() as u64,
+            ),
+            gauge(
+                "kong_init_in_sync",
+                "whether kong matched the definitions on the last successful check",
+                self.in_sync.map_or(0, u64::from),
+            ),
+            gauge(
+                "kong_init_last_check_timestamp_seconds",
+                "unix time of the last check",
+                self.checked_at.unwrap_or_default(),
+            ),
+            counter("kong_init_checks_total", "drift checks made", self.checks),
+            counter("kong_init_failed_checks_total", "drift checks that failed", self.failed_checks),
+            counter("kong_init_heals_total", "drift checks whose changes were applied", self.heals),
+        ]
+            .concat()
+    }
+}
+
+/// Serve the status on `addr` from a thread of its own, each connection answered from its
+/// own thread so a slow client holds up no other.
+pub fn serve(addr: &str, status: Arc<Mutex<Status>>) -> Result<(), String> {
+    let listener = TcpListener::bind(addr).map_err(|why| format!("{}: {}", addr, why))?;
+    info!("serving the drift status on http://{}/status and /metrics", addr);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let status = status.clone();
+            thread::spawn(move || {
+                if let Err(why) = answer(stream, &status) {
+                    debug!("status request: {}", why);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn answer(stream: TcpStream, status: &Mutex<Status>) -> Result<(), String> {
+    // an idle client is dropped instead of keeping its thread forever
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT)).map_err(|why| why.to_string())?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).map_err(|why| why.to_string())?;
+    // the headers are read too, closing with unread ones would reset the connection
+    let mut header = String::new();
+    while reader.read_line(&mut header).map_err(|why| why.to_string())? > 2 {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (status_line, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/status")) => {
+            let body = serde_json::to_string_pretty(&*status.lock().unwrap()).map_err(|why| why.to_string())?;
+            ("200 OK", "application/json", body)
+        }
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", status.lock().unwrap().metrics()),
+        _ => ("404 Not Found", "text/plain", "not found, try /status or /metrics\n".to_string()),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status_line,
+        content_type,
+        body.len(),
+        body
+    ).map_err(|why| why.to_string())
+}
+
+/// Wait `interval` unless `stop` is raised meanwhile, returns whether it was.
+pub fn wait_unless_stopped(stop: &AtomicBool, interval: Duration) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < interval {
+        if stop.load(Ordering::SeqCst) {
+            return true;
+        }
+        thread::sleep(STOP_POLL.min(interval.saturating_sub(started.elapsed())));
+    }
+    stop.load(Ordering::SeqCst)
+}
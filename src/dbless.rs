@@ -0,0 +1,269 @@
+use entity::{parse_target, CertificateInfo, KongConf, PluginInfo, Target, UpstreamInfo};
+use serde_json::{self, Map as SerdeMap, Value};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+pub const FORMAT_VERSION: &str = "1.1";
+
+/// namespace for the deterministic ids of consumers created by `k-upsert-consumer`
+const GUEST_CONSUMER_NS: &str = "6b9a1e5e-4d0c-5b7e-9a3f-6b6f6e672d69";
+
+/// Resolves `k-upsert-consumer` directives without a database: every guest consumer gets a
+/// stable id derived from its name, and is added to the rendered consumers.
+#[derive(Default)]
+pub struct GuestConsumers {
+    names: RefCell<BTreeSet<String>>,
+}
+
+impl GuestConsumers {
+    pub fn upsert(&self, custom_id: &str) -> String {
+        self.names.borrow_mut().insert(custom_id.to_string());
+        guest_consumer_id(custom_id)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.names.borrow().iter().cloned().collect()
+    }
+}
+
+pub fn guest_consumer_id(custom_id: &str) -> String {
+    let ns = Uuid::parse_str(GUEST_CONSUMER_NS).unwrap();
+    Uuid::new_v5(&ns, custom_id.as_bytes()).to_string()
+}
+
+/// Convert a parsed config into Kong's declarative format, with routes and plugins nested
+/// under their service and credentials nested under their consumer.
+pub fn render(conf: &KongConf, guest_consumers: &[String]) -> Result<Value, String> {
+    let mut global_plugins = Vec::new();
+    let mut service_plugins: Vec<(String, Value)> = Vec::new();
+    let mut route_plugins: Vec<(String, Value)> = Vec::new();
+
+    for plugin_info in conf.plugins.as_ref().map_or(&[][..], |p| &p[..]) {
+        let plugin = render_plugin(plugin_info);
+        match parse_target(&plugin_info.target)? {
+            Target::Global => global_plugins.push(plugin),
+            Target::Services(names) => {
+                for name in names {
+                    service_plugins.push((name, plugin.clone()));
+                }
+            }
+            Target::Routes(names) => {
+                for name in names {
+                    route_plugins.push((name, plugin.clone()));
+                }
+            }
+        }
+    }
+
+    let mut services = Vec::new();
+    for service_info in &conf.services {
+        let service_name = match service_info.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            other => {
+                return Err(format!(
+                    "service name={:?}, the `name` field must be String type",
+                    other
+                ))
+            }
+        };
+
+        let mut routes = Vec::new();
+        for route_info in conf.routes.iter().filter(|r| r.apply_to == service_name) {
+            let mut route: SerdeMap<String, Value> = route_info
+                .config
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            route.insert("name".to_string(), Value::String(route_info.name.clone()));
+            let plugins = plugins_of(&route_plugins, &route_info.name);
+            if !plugins.is_empty() {
+                route.insert("plugins".to_string(), Value::Array(plugins));
+            }
+            routes.push(Value::Object(route));
+        }
+
+        let mut service: SerdeMap<String, Value> = service_info
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        if !routes.is_empty() {
+            service.insert("routes".to_string(), Value::Array(routes));
+        }
+        let plugins = plugins_of(&service_plugins, &service_name);
+        if !plugins.is_empty() {
+            service.insert("plugins".to_string(), Value::Array(plugins));
+        }
+        services.push(Value::Object(service));
+    }
+
+    for route_info in &conf.routes {
+        if !services.iter().any(|s| s["name"] == route_info.apply_to.as_str()) {
+            return Err(format!(
+                "route {} applies to unknown service {}",
+                route_info.name, route_info.apply_to
+            ));
+        }
+    }
+    for (name, _) in &service_plugins {
+        if !conf.services.iter().any(|s| s.get("name") == Some(&Value::String(name.clone()))) {
+            return Err(format!("plugin targets unknown service {}", name));
+        }
+    }
+    for (name, _) in &route_plugins {
+        if !conf.routes.iter().any(|r| &r.name == name) {
+            return Err(format!("plugin targets unknown route {}", name));
+        }
+    }
+
+    let mut doc = SerdeMap::new();
+    doc.insert(
+        "_format_version".to_string(),
+        Value::String(FORMAT_VERSION.to_string()),
+    );
+    if let Some(upstreams) = &conf.upstreams {
+        doc.insert(
+            "upstreams".to_string(),
+            Value::Array(upstreams.iter().map(render_upstream).collect()),
+        );
+    }
+    doc.insert("services".to_string(), Value::Array(services));
+    if !global_plugins.is_empty() {
+        doc.insert("plugins".to_string(), Value::Array(global_plugins));
+    }
+    let consumers = render_consumers(conf, guest_consumers)?;
+    if !consumers.is_empty() {
+        doc.insert("consumers".to_string(), Value::Array(consumers));
+    }
+    if let Some(certificates) = &conf.certificates {
+        doc.insert(
+            "certificates".to_string(),
+            Value::Array(certificates.iter().map(render_certificate).collect()),
+        );
+    }
+    Ok(Value::Object(doc))
+}
+
+fn render_upstream(upstream_info: &UpstreamInfo) -> Value {
+    let mut upstream: SerdeMap<String, Value> = upstream_info
+        .config
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    upstream.insert("name".to_string(), Value::String(upstream_info.name.clone()));
+    if !upstream_info.targets.is_empty() {
+        upstream.insert("targets".to_string(), json!(upstream_info.targets));
+    }
+    Value::Object(upstream)
+}
+
+fn render_certificate(certificate_info: &CertificateInfo) -> Value {
+    let snis: Vec<Value> = certificate_info
+        .snis
+        .iter()
+        .map(|sni| json!({ "name": sni }))
+        .collect();
+    json!({
+        "cert": certificate_info.cert,
+        "key": certificate_info.key,
+        "snis": snis,
+    })
+}
+
+fn render_plugin(plugin_info: &PluginInfo) -> Value {
+    let config: SerdeMap<String, Value> = plugin_info
+        .config
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    json!({
+        "name": plugin_info.name,
+        "enabled": plugin_info.enabled,
+        "config": config,
+    })
+}
+
+fn plugins_of(plugins: &[(String, Value)], owner: &str) -> Vec<Value> {
+    plugins
+        .iter()
+        .filter(|(name, _)| name == owner)
+        .map(|(_, plugin)| plugin.clone())
+        .collect()
+}
+
+/// field under a consumer holding credentials of the given plugin
+pub fn credential_field(plugin: &str) -> Option<&'static str> {
+    match plugin {
+        "jwt" => Some("jwt_secrets"),
+        "key-auth" => Some("keyauth_credentials"),
+        "basic-auth" => Some("basicauth_credentials"),
+        "hmac-auth" => Some("hmacauth_credentials"),
+        "oauth2" => Some("oauth2_credentials"),
+        "acls" | "acl" => Some("acls"),
+        _ => None,
+    }
+}
+
+fn render_consumers(conf: &KongConf, guest_consumers: &[String]) -> Result<Vec<Value>, String> {
+    let mut consumers: Vec<SerdeMap<String, Value>> = conf
+        .consumers
+        .as_ref()
+        .map_or(&[][..], |c| &c[..])
+        .iter()
+        .map(|consumer| match serde_json::to_value(consumer) {
+            Ok(Value::Object(fields)) => Ok(fields),
+            Ok(other) => Err(format!("consumer is not a mapping: {}", other)),
+            Err(why) => Err(why.to_string()),
+        }).collect::<Result<_, String>>()?;
+
+    for custom_id in guest_consumers {
+        let id = Value::String(guest_consumer_id(custom_id));
+        let existing = consumers
+            .iter_mut()
+            .find(|c| c.get("username") == Some(&Value::String(custom_id.clone())));
+        match existing {
+            Some(consumer) => {
+                consumer.insert("id".to_string(), id);
+            }
+            None => {
+                let mut guest = SerdeMap::new();
+                guest.insert("id".to_string(), id);
+                guest.insert("username".to_string(), Value::String(custom_id.clone()));
+                guest.insert("custom_id".to_string(), Value::String(custom_id.clone()));
+                consumers.push(guest);
+            }
+        }
+    }
+
+    for credential in conf.credentials.as_ref().map_or(&[][..], |c| &c[..]) {
+        let field = credential_field(&credential.name).ok_or_else(|| {
+            format!("credential type {} is not supported", credential.name)
+        })?;
+        let target = Value::String(credential.target.clone());
+        let consumer = consumers
+            .iter_mut()
+            .find(|c| {
+                c.get("username") == Some(&target)
+                    || c.get("custom_id") == Some(&target)
+                    || c.get("id") == Some(&target)
+            }).ok_or_else(|| {
+                format!(
+                    "credential {} targets unknown consumer {}",
+                    credential.name, credential.target
+                )
+            })?;
+        let config: SerdeMap<String, Value> = credential
+            .config
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let entry = consumer
+            .entry(field.to_string())
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(credentials) = entry {
+            credentials.push(Value::Object(config));
+        }
+    }
+
+    Ok(consumers.into_iter().map(Value::Object).collect())
+}
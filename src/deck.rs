@@ -0,0 +1,265 @@
+use consumer;
+use dbless;
+use entity::{
+    CertificateInfo, ConsumerInfo, CredentialsInfo, KongConf, PluginInfo, RouteInfo, ServiceInfo,
+    TargetInfo, UpstreamInfo,
+};
+use serde_json::{self, Map as SerdeMap, Value};
+use std::collections::BTreeMap;
+
+/// fields kong manages itself, not carried over from a dump(but see `with_id`)
+const GENERATED_FIELDS: &[&str] = &["id", "created_at", "updated_at"];
+
+const CREDENTIAL_FIELDS: &[(&str, &str)] = &[
+    ("jwt_secrets", "jwt"),
+    ("keyauth_credentials", "key-auth"),
+    ("basicauth_credentials", "basic-auth"),
+    ("hmacauth_credentials", "hmac-auth"),
+    ("oauth2_credentials", "oauth2"),
+    ("acls", "acls"),
+];
+
+/// decK state files share kong's declarative format
+pub fn to_deck(conf: &KongConf, guest_consumers: &[String]) -> Result<Value, String> {
+    dbless::render(conf, guest_consumers)
+}
+
+/// Convert a decK state file into a Service/Route style config.
+///
+/// Nested routes and plugins are flattened and linked back through `apply_to`/`target`.
+/// Consumer scoped plugins have no equivalent and are skipped with a warning, the consumers
+/// kong-init keeps for itself(a dump of a kong it applied to) are left out.
+pub fn from_deck(state: &Value) -> Result<KongConf, String> {
+    let mut conf = KongConf {
+        upstreams: None,
+        services: Vec::new(),
+        routes: Vec::new(),
+        plugins: None,
+        consumers: None,
+        consumers_from: None,
+        credentials: None,
+        certificates: None,
+    };
+    let mut plugins = Vec::new();
+    // unnamed routes are numbered per service, nested or top-level alike
+    let mut unnamed: BTreeMap<String, usize> = BTreeMap::new();
+    let mut route_name = |route: &Value, service: &str| {
+        str_field(route, "name").unwrap_or_else(|| {
+            let idx = unnamed.entry(service.to_string()).or_insert(0);
+            *idx += 1;
+            format!("{}-route-{}", service, idx)
+        })
+    };
+
+    for service in entries(state, "services")? {
+        let service_name = str_field(service, "name")
+            .ok_or_else(|| format!("service without name: {}", service))?;
+
+        let mut service_info = ServiceInfo::new();
+        for (k, v) in strip(service, &["routes", "plugins"]) {
+            service_info.insert(k, v);
+        }
+        conf.services.push(service_info);
+
+        for route in entries(service, "routes")? {
+            let route_name = route_name(route, &service_name);
+            for plugin in entries(route, "plugins")? {
+                plugins.push(plugin_info(plugin, format!("r[{}]", route_name))?);
+            }
+            conf.routes.push(RouteInfo {
+                name: route_name,
+                apply_to: service_name.clone(),
+                config: strip(route, &["name", "plugins", "service"]),
+            });
+        }
+        for plugin in entries(service, "plugins")? {
+            plugins.push(plugin_info(plugin, format!("s[{}]", service_name))?);
+        }
+    }
+
+    // routes may also be listed at the top level, referring to their service by name
+    for route in entries(state, "routes")? {
+        let apply_to = route
+            .get("service")
+            .and_then(|s| s.as_str().map(String::from).or_else(|| str_field(s, "name")))
+            .ok_or_else(|| format!("route without service: {}", route))?;
+        let route_name = route_name(route, &apply_to);
+        for plugin in entries(route, "plugins")? {
+            plugins.push(plugin_info(plugin, format!("r[{}]", route_name))?);
+        }
+        conf.routes.push(RouteInfo {
+            name: route_name,
+            apply_to,
+            config: strip(route, &["name", "plugins", "service"]),
+        });
+    }
+
+    let mut names: Vec<&str> = conf.routes.iter().map(|r| r.name.as_str()).collect();
+    names.sort();
+    if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(format!("route {} is listed twice, name one of them otherwise", pair[0]));
+    }
+
+    for plugin in entries(state, "plugins")? {
+        let reference = |field: &str| {
+            plugin
+                .get(field)
+                .and_then(|s| s.as_str().map(String::from).or_else(|| str_field(s, "name")))
+        };
+        if reference("consumer").is_some() {
+            warn!(
+                "skipping plugin {:?}: consumer scoped plugins are not supported",
+                str_field(plugin, "name")
+            );
+            continue;
+        }
+        let target = match (reference("route"), reference("service")) {
+            (Some(route), _) => format!("r[{}]", route),
+            (None, Some(service)) => format!("s[{}]", service),
+            (None, None) => "global".to_string(),
+        };
+        plugins.push(plugin_info(plugin, target)?);
+    }
+    if !plugins.is_empty() {
+        conf.plugins = Some(plugins);
+    }
+
+    let mut consumers = Vec::new();
+    let mut credentials = Vec::new();
+    for consumer in entries(state, "consumers")? {
+        if consumer::is_reserved(consumer) {
+            debug!("skipping consumer {}, kept by kong-init for itself", consumer["username"]);
+            continue;
+        }
+        let target = str_field(consumer, "username")
+            .or_else(|| str_field(consumer, "custom_id"))
+            .ok_or_else(|| format!("consumer without username/custom_id: {}", consumer))?;
+
+        let nested: Vec<&str> = CREDENTIAL_FIELDS
+            .iter()
+            .map(|(field, _)| *field)
+            .chain(Some("plugins"))
+            .collect();
+        let fields = with_id(strip(consumer, &nested), consumer).into_iter().collect();
+        let consumer_info: ConsumerInfo = serde_json::from_value(Value::Object(fields))
+            .map_err(|why| format!("consumer {}: {}", target, why))?;
+        consumers.push(consumer_info);
+
+        for (field, plugin) in CREDENTIAL_FIELDS {
+            for credential in entries(consumer, field)? {
+                credentials.push(CredentialsInfo {
+                    name: plugin.to_string(),
+                    target: target.clone(),
+                    config: with_id(strip(credential, &["consumer"]), credential)
+                        .into_iter()
+                        .filter(|(_, v)| !v.is_null())
+                        .collect(),
+                });
+            }
+        }
+        for plugin in entries(consumer, "plugins")? {
+            warn!(
+                "skipping plugin {:?} of consumer {}: consumer scoped plugins are not supported",
+                str_field(plugin, "name"),
+                target
+            );
+        }
+    }
+    if !consumers.is_empty() {
+        conf.consumers = Some(consumers);
+    }
+    if !credentials.is_empty() {
+        conf.credentials = Some(credentials);
+    }
+
+    let mut upstreams = Vec::new();
+    for upstream in entries(state, "upstreams")? {
+        let name = str_field(upstream, "name")
+            .ok_or_else(|| format!("upstream without name: {}", upstream))?;
+        let mut targets = Vec::new();
+        for target in entries(upstream, "targets")? {
+            targets.push(TargetInfo {
+                target: str_field(target, "target")
+                    .ok_or_else(|| format!("target without address in upstream {}", name))?,
+                weight: target.get("weight").and_then(|w| w.as_i64()),
+            });
+        }
+        upstreams.push(UpstreamInfo {
+            name,
+            targets,
+            config: strip(upstream, &["name", "targets"]),
+        });
+    }
+    if !upstreams.is_empty() {
+        conf.upstreams = Some(upstreams);
+    }
+
+    let mut certificates = Vec::new();
+    for certificate in entries(state, "certificates")? {
+        let snis = entries(certificate, "snis")?
+            .into_iter()
+            .filter_map(|sni| sni.as_str().map(String::from).or_else(|| str_field(sni, "name")))
+            .collect();
+        certificates.push(CertificateInfo {
+            cert: str_field(certificate, "cert")
+                .ok_or_else(|| "certificate without cert".to_string())?,
+            key: str_field(certificate, "key")
+                .ok_or_else(|| "certificate without key".to_string())?,
+            snis,
+        });
+    }
+    if !certificates.is_empty() {
+        conf.certificates = Some(certificates);
+    }
+
+    Ok(conf)
+}
+
+fn entries<'a>(parent: &'a Value, field: &str) -> Result<Vec<&'a Value>, String> {
+    match parent.get(field) {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::Array(items)) => Ok(items.iter().collect()),
+        Some(other) => Err(format!("`{}` must be a list, got {}", field, other)),
+    }
+}
+
+fn str_field(entity: &Value, field: &str) -> Option<String> {
+    entity.get(field).and_then(|v| v.as_str()).map(String::from)
+}
+
+/// entity fields without the kong generated ones and the given nested ones
+fn strip(entity: &Value, nested: &[&str]) -> BTreeMap<String, Value> {
+    let empty = SerdeMap::new();
+    entity
+        .as_object()
+        .unwrap_or(&empty)
+        .iter()
+        .filter(|(k, _)| !GENERATED_FIELDS.contains(&k.as_str()) && !nested.contains(&k.as_str()))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// Keep the id of consumers and credentials: plugins refer to consumers by id(`config.anonymous`)
+/// and declared credential ids are kept by kong-init.
+fn with_id(mut fields: BTreeMap<String, Value>, entity: &Value) -> BTreeMap<String, Value> {
+    if let Some(id) = entity.get("id").filter(|id| id.is_string()) {
+        fields.insert("id".to_string(), id.clone());
+    }
+    fields
+}
+
+fn plugin_info(plugin: &Value, target: String) -> Result<PluginInfo, String> {
+    Ok(PluginInfo {
+        name: str_field(plugin, "name").ok_or_else(|| format!("plugin without name: {}", plugin))?,
+        target,
+        config: strip(plugin, &[])
+            .remove("config")
+            .and_then(|c| c.as_object().cloned())
+            .map(|c| c.into_iter().collect())
+            .unwrap_or_default(),
+        enabled: plugin
+            .get("enabled")
+            .and_then(|e| e.as_bool())
+            .unwrap_or(true),
+    })
+}
@@ -0,0 +1,959 @@
+//! What applying the definitions changes in kong, entity by entity.
+//!
+//! Declared entities are matched against the live ones by natural key: services, upstreams
+//! and consumers by name, routes by service and then by content(kong 0.14 routes have no
+//! name), plugins by name and target, certificates by snis, credentials as `credential::plan`
+//! does. Every field kong-init manages is compared: one left out of the definitions is back to
+//! kong's default, a plugin config key left out has the plugin created again.
+//!
+//! Plugins, routes and services that are not declared are deleted, as the full apply clears
+//! them. Other entities are only deleted the way the full apply does: consumers kong-init owns
+//! and credentials with `--prune`, targets missing from a declared upstream.
+use backend::id_of;
+use client::KongApiClient;
+use consumer;
+use credential;
+use entity::{parse_target, KongConf, PluginInfo, Target};
+use graph::Graph;
+use reqwest::Url;
+use serde_json::{self, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Upstream,
+    Target,
+    Certificate,
+    Service,
+    Route,
+    Plugin,
+    Consumer,
+    Credential,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Kind::Upstream => "upstream",
+            Kind::Target => "target",
+            Kind::Certificate => "certificate",
+            Kind::Service => "service",
+            Kind::Route => "route",
+            Kind::Plugin => "plugin",
+            Kind::Consumer => "consumer",
+            Kind::Credential => "credential",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+/// A declared field whose live value differs.
+#[derive(Debug)]
+pub struct Difference {
+    /// dotted path of the field, e.g. `config.minute`
+    pub field: String,
+    pub declared: Value,
+    pub live: Value,
+    /// credential and certificate fields, their values are not shown
+    pub secret: bool,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.secret {
+            write!(f, "{} differs", self.field)
+        } else {
+            write!(f, "{}: {} -> {}", self.field, self.live, self.declared)
+        }
+    }
+}
+
+/// One entity to create, update or delete.
+#[derive(Debug)]
+pub struct Change {
+    pub action: Action,
+    pub kind: Kind,
+    /// what the entity is for the logs: `dummy`, `jwt on route r-dummy`...
+    pub key: String,
+    /// the live entity, none for creations
+    pub id: Option<String>,
+    /// the entity it belongs to: the service of a route, the consumer of a credential...
+    pub parent: Option<(Kind, String)>,
+    /// credential type of a credential
+    pub endpoint: Option<&'static str>,
+    /// what creations and updates send
+    pub fields: Map<String, Value>,
+    /// why an update is one
+    pub differences: Vec<Difference>,
+}
+
+impl Change {
+    /// `~ service dummy`
+    pub fn headline(&self) -> String {
+        let sign = match self.action {
+            Action::Create => '+',
+            Action::Update => '~',
+            Action::Delete => '-',
+        };
+        format!("{} {} {}", sign, self.kind, self.key)
+    }
+
+    /// `create service dummy`, names the change when it fails
+    pub fn describe(&self) -> String {
+        let verb = match self.action {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+        };
+        format!("{} {} {}", verb, self.kind, self.key)
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.headline())?;
+        if !self.differences.is_empty() {
+            let differences: Vec<String> = self.differences.iter().map(|d| d.to_string()).collect();
+            write!(f, ": {}", differences.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The changes applying the definitions makes.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    /// declared entities kong already holds as declared
+    pub unchanged: usize,
+    /// ids of the live entities declared ones matched, by kind and key
+    pub ids: BTreeMap<(Kind, String), String>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    fn count(&self, action: Action) -> usize {
+        self.changes.iter().filter(|c| c.action == action).count()
+    }
+
+    /// `1 to create, 2 to update, 0 to delete, 12 unchanged`
+    pub fn summary(&self) -> String {
+        format!(
+            "{} to create, {} to update, {} to delete, {} unchanged",
+            self.count(Action::Create),
+            self.count(Action::Update),
+            self.count(Action::Delete),
+            self.unchanged
+        )
+    }
+
+    fn matched(&mut self, kind: Kind, key: &str, live: &Value) {
+        self.ids.insert((kind, key.to_string()), id_of(live));
+    }
+
+    fn create(&mut self, kind: Kind, key: String, parent: Option<(Kind, String)>, fields: Map<String, Value>) {
+        self.changes.push(Change {
+            action: Action::Create,
+            kind,
+            key,
+            id: None,
+            parent,
+            endpoint: None,
+            fields,
+            differences: Vec::new(),
+        });
+    }
+
+    /// an update when the declared fields differ from the live ones, the fields left out are
+    /// reset to their default
+    fn compare(&mut self, kind: Kind, key: String, parent: Option<(Kind, String)>, mut fields: Map<String, Value>, compared: &Value, live: &Value) {
+        self.matched(kind, &key, live);
+        let mut found = compare_fields(kind, compared, live);
+        for difference in &found {
+            let left_out = compared.get(difference.field.as_str()).is_none_or(Value::is_null);
+            if left_out && !difference.field.contains('.') {
+                fields.insert(difference.field.clone(), difference.declared.clone());
+            }
+        }
+        // private keys are not logged
+        if kind == Kind::Certificate {
+            found.iter_mut().for_each(|d| d.secret = true);
+        }
+        if found.is_empty() {
+            self.unchanged += 1;
+            return;
+        }
+        self.changes.push(Change {
+            action: Action::Update,
+            kind,
+            key,
+            id: Some(id_of(live)),
+            parent,
+            endpoint: None,
+            fields,
+            differences: found,
+        });
+    }
+
+    fn delete(&mut self, kind: Kind, key: String, parent: Option<(Kind, String)>, live: &Value) {
+        self.changes.push(Change {
+            action: Action::Delete,
+            kind,
+            key,
+            id: Some(id_of(live)),
+            parent,
+            endpoint: None,
+            fields: Map::new(),
+            differences: Vec::new(),
+        });
+    }
+}
+
+/// What decides the changes besides the definitions, as the full apply takes them.
+pub struct Options<'a> {
+    /// delete owned consumers and credentials no longer declared
+    pub prune: bool,
+    /// consumers whose acls are exactly their `groups`
+    pub exact_acls: &'a BTreeSet<String>,
+    /// ids of the consumers kong-init owns(`State::consumers`)
+    pub owned: &'a BTreeSet<String>,
+    /// ids of consumers declared elsewhere, e.g. by `k-upsert-consumer`
+    pub keep: &'a [String],
+}
+
+/// the id an entity refers to through `<field>_id`(kong < 1.0) or `<field>.id`
+fn reference(entity: &Value, field: &str) -> Option<String> {
+    entity
+        .get(format!("{}_id", field).as_str())
+        .or_else(|| entity.get(field).and_then(|r| r.get("id")))
+        .and_then(|id| id.as_str())
+        .map(String::from)
+}
+
+fn same(declared: &Value, live: &Value) -> bool {
+    match (declared, live) {
+        (Value::Object(declared), live) => declared
+            .iter()
+            .all(|(k, v)| same(v, live.get(k.as_str()).unwrap_or(&Value::Null))),
+        (Value::Array(declared), Value::Array(live)) => {
+            declared.len() == live.len() && declared.iter().zip(live).all(|(d, l)| same(d, l))
+        }
+        (Value::Null, live) => live.is_null(),
+        // `20` and `"20"`, `"GET,POST"` and `["GET", "POST"]` are the same to kong
+        _ => declared == live || credential::text(declared) == credential::text(live),
+    }
+}
+
+/// the declared fields differing from the live ones, by dotted path
+fn differences(path: &str, declared: &Value, live: &Value, found: &mut Vec<Difference>) {
+    // a field missing from kong is compared field by field as well, `config: {}` is no change
+    if let Value::Object(declared) = declared {
+        for (field, value) in declared {
+            let path = if path.is_empty() {
+                field.clone()
+            } else {
+                format!("{}.{}", path, field)
+            };
+            differences(&path, value, live.get(field).unwrap_or(&Value::Null), found);
+        }
+    } else if !same(declared, live) {
+        found.push(Difference {
+            field: path.to_string(),
+            declared: declared.clone(),
+            live: live.clone(),
+            secret: false,
+        });
+    }
+}
+
+/// kong 0.14's defaults of the fields kong-init manages, what a field left out of the
+/// definitions is compared as
+fn defaults(kind: Kind, compared: &Map<String, Value>) -> Vec<(&'static str, Value)> {
+    match kind {
+        Kind::Service => {
+            let mut defaults = vec![
+                ("retries", json!(5)),
+                ("connect_timeout", json!(60000)),
+                ("write_timeout", json!(60000)),
+                ("read_timeout", json!(60000)),
+            ];
+            if !compared.contains_key("url") {
+                defaults.push(("protocol", json!("http")));
+                defaults.push(("port", json!(80)));
+                defaults.push(("path", Value::Null));
+            }
+            defaults
+        }
+        Kind::Route => vec![
+            ("protocols", json!(["http", "https"])),
+            ("methods", Value::Null),
+            ("hosts", Value::Null),
+            ("paths", Value::Null),
+            ("regex_priority", json!(0)),
+            ("strip_path", json!(true)),
+            ("preserve_host", json!(false)),
+        ],
+        Kind::Upstream => vec![
+            ("slots", json!(1000)),
+            ("hash_on", json!("none")),
+            ("hash_fallback", json!("none")),
+            ("hash_on_header", Value::Null),
+            ("hash_fallback_header", Value::Null),
+            ("hash_on_cookie", Value::Null),
+            ("hash_on_cookie_path", json!("/")),
+        ],
+        Kind::Target => vec![("weight", json!(100))],
+        _ => Vec::new(),
+    }
+}
+
+/// the managed fields differing from the live ones, a field missing on either side is taken
+/// as its default
+fn compare_fields(kind: Kind, compared: &Value, live: &Value) -> Vec<Difference> {
+    let mut compared = compared.clone();
+    let mut live = live.clone();
+    if let Value::Object(fields) = &mut compared {
+        let mut none = Map::new();
+        let live_fields = live.as_object_mut().unwrap_or(&mut none);
+        for (field, default) in defaults(kind, fields) {
+            or_default(fields, field, &default);
+            or_default(live_fields, field, &default);
+        }
+    }
+    let mut found = Vec::new();
+    differences("", &compared, &live, &mut found);
+    found
+}
+
+/// a missing or null field set to its default
+fn or_default(entity: &mut Map<String, Value>, field: &str, default: &Value) {
+    if entity.get(field).is_none_or(Value::is_null) {
+        entity.insert(field.to_string(), default.clone());
+    }
+}
+
+/// A service as kong keeps it: an `url` is split into protocol, host, port and path unless
+/// the live one kept it whole.
+fn service_fields(declared: &Map<String, Value>, live: &Value) -> Value {
+    let mut fields = declared.clone();
+    if live.get("url").is_none() {
+        if let Some(url) = fields.remove("url") {
+            let parsed = url.as_str().and_then(|url| Url::parse(url).ok());
+            if let Some(url) = parsed {
+                fields.insert("protocol".to_string(), json!(url.scheme()));
+                fields.insert("host".to_string(), json!(url.host_str()));
+                fields.insert("port".to_string(), json!(url.port_or_known_default()));
+                let path = if url.path() == "/" { None } else { Some(url.path()) };
+                fields.insert("path".to_string(), json!(path));
+            }
+        }
+    }
+    Value::Object(fields)
+}
+
+/// the target(`global`, `service <name>`, `route <name>`) of a declared plugin, one per
+/// service or route of `s[...]`/`r[...]`
+fn plugin_targets(plugin: &PluginInfo) -> Result<Vec<Option<(Kind, String)>>, String> {
+    let targets = match parse_target(&plugin.target).map_err(|why| format!("plugin {}: {}", plugin.name, why))? {
+        Target::Global => vec![None],
+        Target::Services(names) => names.into_iter().map(|s| Some((Kind::Service, s))).collect(),
+        Target::Routes(names) => names.into_iter().map(|r| Some((Kind::Route, r))).collect(),
+    };
+    Ok(targets)
+}
+
+fn plugin_key(name: &str, target: &Option<(Kind, String)>) -> String {
+    match target {
+        None => format!("{} on global", name),
+        Some((kind, key)) => format!("{} on {} {}", name, kind, key),
+    }
+}
+
+/// Compare the definitions with what kong holds.
+pub fn plan(cli: &KongApiClient, conf: &KongConf, options: &Options) -> Result<Plan, String> {
+    let mut plan = Plan::default();
+    plan_upstreams(cli, conf, &mut plan)?;
+    plan_certificates(cli, conf, &mut plan)?;
+    let (services, routes) = plan_services_routes(cli, conf, &mut plan)?;
+    plan_plugins(cli, conf, &services, &routes, &mut plan)?;
+    plan_consumers(cli, conf, options, &mut plan)?;
+    Ok(plan)
+}
+
+fn plan_upstreams(cli: &KongApiClient, conf: &KongConf, plan: &mut Plan) -> Result<(), String> {
+    let declared = match &conf.upstreams {
+        Some(upstreams) if !upstreams.is_empty() => upstreams,
+        _ => return Ok(()),
+    };
+    let live = cli.list_entities("upstreams")?;
+    for upstream in declared {
+        let mut fields: Map<String, Value> = upstream.config.clone().into_iter().collect();
+        fields.insert("name".to_string(), json!(upstream.name));
+        let parent = Some((Kind::Upstream, upstream.name.clone()));
+        let existing = match live.iter().find(|u| u["name"] == upstream.name.as_str()) {
+            None => {
+                plan.create(Kind::Upstream, upstream.name.clone(), None, fields);
+                for target in &upstream.targets {
+                    let fields = to_map(target)?;
+                    plan.create(Kind::Target, target.target.clone(), parent.clone(), fields);
+                }
+                continue;
+            }
+            Some(existing) => existing,
+        };
+        let compared = Value::Object(fields.clone());
+        plan.compare(Kind::Upstream, upstream.name.clone(), None, fields, &compared, existing);
+
+        // a target is updated by adding it again, kong keeps the last one
+        let targets = cli.list_entities(&format!("upstreams/{}/targets", id_of(existing)))?;
+        let mut matched = vec![false; targets.len()];
+        for target in &upstream.targets {
+            let fields = to_map(target)?;
+            match targets.iter().position(|t| t["target"] == target.target.as_str()) {
+                None => plan.create(Kind::Target, target.target.clone(), parent.clone(), fields),
+                Some(idx) => {
+                    matched[idx] = true;
+                    let compared = Value::Object(fields.clone());
+                    plan.compare(Kind::Target, target.target.clone(), parent.clone(), fields, &compared, &targets[idx]);
+                }
+            }
+        }
+        for (idx, target) in targets.iter().enumerate() {
+            let weight = target["weight"].as_i64().unwrap_or(1);
+            if !matched[idx] && weight > 0 {
+                let key = target["target"].as_str().unwrap_or_default().to_string();
+                plan.delete(Kind::Target, key, parent.clone(), target);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn plan_certificates(cli: &KongApiClient, conf: &KongConf, plan: &mut Plan) -> Result<(), String> {
+    let declared = match &conf.certificates {
+        Some(certificates) if !certificates.is_empty() => certificates,
+        _ => return Ok(()),
+    };
+    let live = cli.list_entities("certificates")?;
+    let snis_of = |snis: &[String]| {
+        let mut snis = snis.to_vec();
+        snis.sort();
+        snis
+    };
+    for certificate in declared {
+        let snis = snis_of(&certificate.snis);
+        let key = snis.join(",");
+        let fields = to_map(certificate)?;
+        let existing = live.iter().find(|c| {
+            let live_snis: Vec<String> = c["snis"]
+                .as_array()
+                .map(|s| s.iter().filter_map(|s| s.as_str()).map(String::from).collect())
+                .unwrap_or_default();
+            snis_of(&live_snis) == snis
+        });
+        match existing {
+            None => plan.create(Kind::Certificate, key, None, fields),
+            Some(existing) => {
+                let compared = json!({ "cert": certificate.cert.trim(), "key": certificate.key.trim() });
+                let trimmed = json!({
+                    "cert": existing["cert"].as_str().unwrap_or_default().trim(),
+                    "key": existing["key"].as_str().unwrap_or_default().trim(),
+                    "id": existing["id"],
+                });
+                plan.compare(Kind::Certificate, key, None, fields, &compared, &trimmed);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// names of live entities by id
+type Names = BTreeMap<String, String>;
+
+/// Returns the names of the live services and the names of the live routes by id.
+fn plan_services_routes(
+    cli: &KongApiClient,
+    conf: &KongConf,
+    plan: &mut Plan,
+) -> Result<(Names, Names), String> {
+    let live_services = cli.list_entities("services")?;
+    let live_routes = cli.list_entities("routes")?;
+    let service_names: BTreeMap<String, String> = live_services
+        .iter()
+        .map(|s| (id_of(s), s["name"].as_str().unwrap_or_default().to_string()))
+        .collect();
+
+    let mut declared_services = BTreeSet::new();
+    for service in &conf.services {
+        let name = match service.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            other => return Err(format!("the `name` of a service must be a string, not {:?}", other)),
+        };
+        declared_services.insert(name.clone());
+        let fields: Map<String, Value> = service.clone().into_iter().collect();
+        match live_services.iter().find(|s| s["name"] == name.as_str()) {
+            None => plan.create(Kind::Service, name, None, fields),
+            Some(existing) => {
+                let compared = service_fields(&fields, existing);
+                plan.compare(Kind::Service, name, None, fields, &compared, existing);
+            }
+        }
+    }
+
+    // the routes of a service matching a declared one exactly are kept, the others are
+    // updated into the remaining declared ones of the service in order
+    let mut route_names = BTreeMap::new();
+    let mut matched = vec![false; live_routes.len()];
+    let mut pending = Vec::new();
+    for route in &conf.routes {
+        if !declared_services.contains(&route.apply_to) {
+            return Err(format!("route {} applies to unknown service {}", route.name, route.apply_to));
+        }
+        let service_id = plan.ids.get(&(Kind::Service, route.apply_to.clone())).cloned();
+        let fields: Map<String, Value> = route.config.clone().into_iter().collect();
+        let compared = Value::Object(fields.clone());
+        let exact = live_routes.iter().enumerate().position(|(idx, r)| {
+            !matched[idx] && service_id.is_some() && reference(r, "service") == service_id
+                && compare_fields(Kind::Route, &compared, r).is_empty()
+        });
+        match exact {
+            Some(idx) => {
+                matched[idx] = true;
+                route_names.insert(id_of(&live_routes[idx]), route.name.clone());
+                plan.matched(Kind::Route, &route.name, &live_routes[idx]);
+                plan.unchanged += 1;
+            }
+            None => pending.push((route, service_id, fields)),
+        }
+    }
+    for (route, service_id, fields) in pending {
+        let parent = Some((Kind::Service, route.apply_to.clone()));
+        let other = live_routes.iter().enumerate().position(|(idx, r)| {
+            !matched[idx] && service_id.is_some() && reference(r, "service") == service_id
+        });
+        match other {
+            Some(idx) => {
+                matched[idx] = true;
+                route_names.insert(id_of(&live_routes[idx]), route.name.clone());
+                let compared = Value::Object(fields.clone());
+                plan.compare(Kind::Route, route.name.clone(), parent, fields, &compared, &live_routes[idx]);
+            }
+            None => plan.create(Kind::Route, route.name.clone(), parent, fields),
+        }
+    }
+    for (idx, route) in live_routes.iter().enumerate() {
+        if !matched[idx] {
+            let service = reference(route, "service")
+                .and_then(|id| service_names.get(&id).cloned())
+                .unwrap_or_default();
+            let key = format!("{}({})", id_of(route), credential::text(&route["paths"]));
+            plan.delete(Kind::Route, key, Some((Kind::Service, service)), route);
+        }
+    }
+
+    // routes before their services
+    for service in &live_services {
+        let name = service["name"].as_str().unwrap_or_default();
+        if !declared_services.contains(name) {
+            plan.delete(Kind::Service, name.to_string(), None, service);
+        }
+    }
+    Ok((service_names, route_names))
+}
+
+fn plan_plugins(
+    cli: &KongApiClient,
+    conf: &KongConf,
+    services: &BTreeMap<String, String>,
+    routes: &BTreeMap<String, String>,
+    plan: &mut Plan,
+) -> Result<(), String> {
+    let live = cli.list_entities("plugins")?;
+    // what a live plugin applies to, in the terms of the definitions
+    let target_of = |plugin: &Value| -> Option<(Kind, String)> {
+        if let Some(route) = reference(plugin, "route") {
+            return Some((Kind::Route, routes.get(&route).cloned().unwrap_or(route)));
+        }
+        if let Some(service) = reference(plugin, "service") {
+            return Some((Kind::Service, services.get(&service).cloned().unwrap_or(service)));
+        }
+        reference(plugin, "consumer")
+            .map(|consumer| (Kind::Consumer, consumer))
+            .or_else(|| reference(plugin, "api").map(|api| (Kind::Plugin, format!("of api {}", api))))
+    };
+
+    let mut schemas: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+    let mut matched = vec![false; live.len()];
+    let mut pending = Vec::new();
+    for plugin in conf.plugins.iter().flatten() {
+        if !schemas.contains_key(&plugin.name) {
+            schemas.insert(plugin.name.clone(), cli.plugin_defaults(&plugin.name)?);
+        }
+        let defaults = &schemas[&plugin.name];
+        for target in plugin_targets(plugin)? {
+            if let Some((kind, name)) = &target {
+                let declared = match kind {
+                    Kind::Service => conf.services.iter().any(|s| s.get("name") == Some(&json!(name))),
+                    _ => conf.routes.iter().any(|r| &r.name == name),
+                };
+                if !declared {
+                    return Err(format!("plugin {} targets unknown {} {}", plugin.name, kind, name));
+                }
+            }
+            let found = live.iter().enumerate().position(|(idx, p)| {
+                !matched[idx] && p["name"] == plugin.name.as_str() && target_of(p) == target
+            });
+            let key = plugin_key(&plugin.name, &target);
+            let mut fields = Map::new();
+            fields.insert("name".to_string(), json!(plugin.name));
+            fields.insert("enabled".to_string(), json!(plugin.enabled));
+            for (k, v) in &plugin.config {
+                fields.insert(format!("config.{}", k), v.clone());
+            }
+            let idx = match found {
+                Some(idx) => idx,
+                None => {
+                    pending.push((key, target, fields, Vec::new()));
+                    continue;
+                }
+            };
+            matched[idx] = true;
+            // config keys left out are compared as their default, or as unset
+            let mut effective = live[idx].clone();
+            let mut config: Map<String, Value> = plugin.config.clone().into_iter().collect();
+            let live_keys: Vec<String> = effective["config"]
+                .as_object()
+                .map(|c| c.keys().cloned().collect())
+                .unwrap_or_default();
+            for field in live_keys.iter().chain(defaults.keys()) {
+                let default = defaults.get(field).cloned().unwrap_or(Value::Null);
+                if !config.contains_key(field) {
+                    config.insert(field.clone(), default.clone());
+                }
+                if effective["config"].get(field.as_str()).is_none_or(Value::is_null) {
+                    effective["config"][field.as_str()] = default;
+                }
+            }
+            let compared = json!({ "enabled": plugin.enabled, "config": config });
+            let found = compare_fields(Kind::Plugin, &compared, &effective);
+            let left_out = found.iter().any(|d| {
+                let mut path = d.field.split('.');
+                path.next() == Some("config") && path.next().is_some_and(|k| !plugin.config.contains_key(k))
+            });
+            if left_out {
+                // kong keeps the config keys it is not sent, the plugin is created again without them
+                plan.delete(Kind::Plugin, key.clone(), target.clone(), &live[idx]);
+                pending.push((key, target, fields, found));
+            } else {
+                plan.compare(Kind::Plugin, key, target, fields, &compared, &effective);
+            }
+        }
+    }
+    // deleted before the plugins replacing them are created
+    for (idx, plugin) in live.iter().enumerate() {
+        if !matched[idx] {
+            let target = target_of(plugin);
+            let key = plugin_key(plugin["name"].as_str().unwrap_or_default(), &target);
+            plan.delete(Kind::Plugin, key, target, plugin);
+        }
+    }
+    for (key, target, fields, differences) in pending {
+        plan.create(Kind::Plugin, key, target, fields);
+        if let Some(created) = plan.changes.last_mut() {
+            created.differences = differences;
+        }
+    }
+    Ok(())
+}
+
+fn plan_consumers(cli: &KongApiClient, conf: &KongConf, options: &Options, plan: &mut Plan) -> Result<(), String> {
+    let declared = conf.consumers.as_ref().map_or(&[][..], |c| &c[..]);
+    let live: Vec<Value> = cli
+        .list_entities("consumers")?
+        .into_iter()
+        .filter(|c| !consumer::is_reserved(c))
+        .collect();
+    let changes = consumer::plan(declared, &live, options.owned, options.keep, options.prune)?;
+    // the changes of the declared consumers come first, in their order
+    for (idx, change) in changes.into_iter().enumerate() {
+        match change {
+            consumer::Change::Create(consumer_info) => {
+                let name = consumer_info.name().unwrap_or_default().to_string();
+                plan.create(Kind::Consumer, name, None, to_map(consumer_info)?);
+            }
+            consumer::Change::Update { id, fields } => {
+                let consumer_info = &declared[idx];
+                let existing = live.iter().find(|c| id_of(c) == id).cloned().unwrap_or_default();
+                let fields: Map<String, Value> = fields.into_iter().collect();
+                let compared = Value::Object(fields.clone());
+                let name = consumer_info.name().unwrap_or_default().to_string();
+                plan.compare(Kind::Consumer, name, None, fields, &compared, &existing);
+            }
+            consumer::Change::Unchanged { id } => {
+                plan.unchanged += 1;
+                let consumer_info = &declared[idx];
+                for name in consumer_info.username.iter().chain(consumer_info.custom_id.iter()) {
+                    plan.ids.insert((Kind::Consumer, name.clone()), id.clone());
+                }
+            }
+            consumer::Change::Delete { id, name } => {
+                let existing = json!({ "id": id });
+                plan.delete(Kind::Consumer, name, None, &existing);
+            }
+        }
+    }
+    for consumer_info in declared {
+        let id = consumer_info
+            .name()
+            .and_then(|name| plan.ids.get(&(Kind::Consumer, name.to_string())).cloned());
+        if let Some(id) = id {
+            for name in consumer_info.username.iter().chain(consumer_info.custom_id.iter()) {
+                plan.ids.insert((Kind::Consumer, name.clone()), id.clone());
+            }
+        }
+    }
+    plan_credentials(cli, conf, options, plan)
+}
+
+fn plan_credentials(cli: &KongApiClient, conf: &KongConf, options: &Options, plan: &mut Plan) -> Result<(), String> {
+    let mut grouped: BTreeMap<(String, &'static str), Vec<&BTreeMap<String, Value>>> = BTreeMap::new();
+    for credential_info in conf.credentials.iter().flatten() {
+        match credential::endpoint(&credential_info.name) {
+            Some(endpoint) => grouped
+                .entry((credential_info.target.clone(), endpoint))
+                .or_default()
+                .push(&credential_info.config),
+            None => warn!(
+                "credential type {} cannot be matched against existing ones, left out",
+                credential_info.name
+            ),
+        }
+    }
+    if options.prune {
+        for consumer in conf.consumers.iter().flatten() {
+            if let Some(username) = &consumer.username {
+                for endpoint in credential::endpoints() {
+                    grouped.entry((username.clone(), endpoint)).or_default();
+                }
+            }
+        }
+    }
+    for consumer in options.exact_acls {
+        grouped.entry((consumer.clone(), "acls")).or_default();
+    }
+
+    for ((consumer, endpoint), declared) in grouped {
+        let parent = Some((Kind::Consumer, consumer.clone()));
+        let is_created = plan
+            .changes
+            .iter()
+            .any(|c| c.kind == Kind::Consumer && c.action == Action::Create && c.key == consumer);
+        // consumers declared elsewhere(`k-upsert-consumer`, by hand) are addressed by name
+        let consumer_id = plan
+            .ids
+            .entry((Kind::Consumer, consumer.clone()))
+            .or_insert_with(|| consumer.clone())
+            .clone();
+        let existing = if is_created {
+            Vec::new()
+        } else {
+            cli.list_entities(&format!("consumers/{}/{}", consumer_id, endpoint))?
+        };
+        let exact = endpoint == "acls" && options.exact_acls.contains(&consumer);
+        let key = format!("{} of consumer {}", endpoint, consumer);
+        for change in credential::plan(endpoint, &declared, &existing, options.prune || exact) {
+            let mut change = match change {
+                credential::Change::Create(config) => Change {
+                    action: Action::Create,
+                    kind: Kind::Credential,
+                    key: key.clone(),
+                    id: None,
+                    parent: parent.clone(),
+                    endpoint: None,
+                    fields: config.into_iter().collect(),
+                    differences: Vec::new(),
+                },
+                credential::Change::Update { id, fields } => {
+                    let current = existing.iter().find(|c| id_of(c) == id).cloned().unwrap_or_default();
+                    let differences = fields
+                        .iter()
+                        .map(|(field, declared)| Difference {
+                            field: field.clone(),
+                            declared: declared.clone(),
+                            live: current.get(field.as_str()).cloned().unwrap_or(Value::Null),
+                            secret: true,
+                        }).collect();
+                    Change {
+                        action: Action::Update,
+                        kind: Kind::Credential,
+                        key: key.clone(),
+                        id: Some(id),
+                        parent: parent.clone(),
+                        endpoint: None,
+                        fields: fields.into_iter().collect(),
+                        differences,
+                    }
+                }
+                credential::Change::Unchanged { .. } => {
+                    plan.unchanged += 1;
+                    continue;
+                }
+                credential::Change::Delete { id } => Change {
+                    action: Action::Delete,
+                    kind: Kind::Credential,
+                    key: key.clone(),
+                    id: Some(id),
+                    parent: parent.clone(),
+                    endpoint: None,
+                    fields: Map::new(),
+                    differences: Vec::new(),
+                },
+            };
+            change.endpoint = Some(endpoint);
+            plan.changes.push(change);
+        }
+    }
+    Ok(())
+}
+
+fn to_map<T: ::serde::Serialize>(entity: &T) -> Result<Map<String, Value>, String> {
+    match serde_json::to_value(entity).map_err(|why| why.to_string())? {
+        Value::Object(fields) => Ok(fields),
+        other => Err(format!("{} is not a mapping", other)),
+    }
+}
+
+/// The ids known while applying a plan, those of the entities created added as they are.
+pub struct Ids(Mutex<BTreeMap<(Kind, String), String>>);
+
+impl Ids {
+    pub fn new(plan: &Plan) -> Ids {
+        Ids(Mutex::new(plan.ids.clone()))
+    }
+
+    /// the id of an entity, matched or created
+    pub fn get(&self, kind: Kind, key: &str) -> Option<String> {
+        self.of(&(kind, key.to_string())).ok()
+    }
+
+    fn of(&self, entity: &(Kind, String)) -> Result<String, String> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(entity)
+            .cloned()
+            .ok_or_else(|| format!("{} {} has no id", entity.0, entity.1))
+    }
+
+    fn insert(&self, kind: Kind, key: &str, id: String) {
+        self.0.lock().unwrap().insert((kind, key.to_string()), id);
+    }
+}
+
+/// Make one change in kong, the entities it belongs to are already there.
+pub fn apply(cli: &KongApiClient, change: &Change, ids: &Ids) -> Result<(), String> {
+    let parent_id = || match &change.parent {
+        Some(parent) => ids.of(parent),
+        None => Err(format!("{} {} belongs to nothing", change.kind, change.key)),
+    };
+    let collection = match change.kind {
+        Kind::Upstream => "upstreams".to_string(),
+        Kind::Target => format!("upstreams/{}/targets", parent_id()?),
+        Kind::Certificate => "certificates".to_string(),
+        Kind::Service => "services".to_string(),
+        Kind::Route => "routes".to_string(),
+        Kind::Plugin => "plugins".to_string(),
+        Kind::Consumer => "consumers".to_string(),
+        Kind::Credential => format!(
+            "consumers/{}/{}",
+            parent_id()?,
+            change.endpoint.unwrap_or_default()
+        ),
+    };
+    let mut fields = change.fields.clone();
+    match (change.action, change.kind, &change.parent) {
+        (Action::Create, Kind::Route, Some(_)) => {
+            fields.insert("service".to_string(), json!({ "id": parent_id()? }));
+        }
+        (Action::Create, Kind::Plugin, Some((Kind::Service, _))) => {
+            fields.insert("service_id".to_string(), json!(parent_id()?));
+        }
+        (Action::Create, Kind::Plugin, Some((Kind::Route, _))) => {
+            fields.insert("route_id".to_string(), json!(parent_id()?));
+        }
+        _ => {}
+    }
+
+    let id = change.id.clone().unwrap_or_default();
+    match change.action {
+        // a target is updated by adding it again
+        Action::Create | Action::Update if change.kind == Kind::Target => {
+            cli.create(&collection, &Value::Object(fields))?;
+        }
+        Action::Create => {
+            let created = cli.create(&collection, &Value::Object(fields))?;
+            let created_id = id_of(&created);
+            if change.kind == Kind::Consumer {
+                for alias in ["username", "custom_id"].iter().filter_map(|f| change.fields.get(*f)) {
+                    ids.insert(Kind::Consumer, alias.as_str().unwrap_or_default(), created_id.clone());
+                }
+            }
+            ids.insert(change.kind, &change.key, created_id);
+        }
+        Action::Update => {
+            cli.update(&collection, &id, &Value::Object(fields))?;
+        }
+        Action::Delete => cli.delete(&collection, &id)?,
+    }
+    debug!("applied {}", change);
+    Ok(())
+}
+
+/// when a change is made within its family(the entities proxying, the consumers): deletions
+/// of dependents first, then the entities depended on before their dependents
+fn stage(change: &Change) -> (bool, usize) {
+    let is_consumer = change.kind == Kind::Consumer || change.kind == Kind::Credential;
+    let stage = match (change.action, change.kind) {
+        (Action::Delete, Kind::Plugin) | (Action::Delete, Kind::Credential) => 0,
+        (Action::Delete, Kind::Route) | (Action::Delete, Kind::Target) | (Action::Delete, Kind::Consumer) => 1,
+        (Action::Delete, _) => 2,
+        (_, Kind::Upstream) | (_, Kind::Certificate) | (_, Kind::Consumer) => 3,
+        (_, Kind::Target) | (_, Kind::Service) | (_, Kind::Credential) => 4,
+        (_, Kind::Route) => 5,
+        (_, Kind::Plugin) => 6,
+    };
+    (is_consumer, stage)
+}
+
+/// Make every change of a plan, `concurrency` at a time. Returns the changes that failed or
+/// were skipped, the ids of the entities created are added to `ids`.
+pub fn apply_all(cli: &KongApiClient, plan: &Plan, ids: &Ids, concurrency: usize) -> Vec<String> {
+    let mut staged: Vec<&Change> = plan.changes.iter().collect();
+    staged.sort_by_key(|change| stage(change).1);
+
+    let mut graph = Graph::new();
+    let mut added: Vec<((bool, usize), usize)> = Vec::new();
+    for change in staged {
+        let at = stage(change);
+        let deps: Vec<usize> = added
+            .iter()
+            .filter(|(other, _)| other.0 == at.0 && other.1 < at.1)
+            .map(|(_, op)| *op)
+            .collect();
+        let op = graph.add(&change.describe(), &deps, move || apply(cli, change, ids));
+        added.push((at, op));
+    }
+    graph.run(concurrency)
+}
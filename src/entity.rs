@@ -3,23 +3,61 @@ use std::collections::BTreeMap;
 use serde_json::Value;
 pub type ApiInfo = BTreeMap<String, String>;
 pub type ServiceInfo = BTreeMap<String, Value>;
-pub type ConsumerInfo = BTreeMap<String, String>;
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct LegacyKongConf {
     pub apis: Vec<ApiInfo>,
     pub plugins: Option<Vec<LegacyPluginInfo>>,
-    pub consumers: Option<Vec<BTreeMap<String, String>>>,
+    pub consumers: Option<Vec<ConsumerInfo>>,
+    #[serde(default)]
+    pub consumers_from: Option<String>,
     pub credentials: Option<Vec<CredentialsInfo>>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct KongConf {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstreams: Option<Vec<UpstreamInfo>>,
     pub services: Vec<ServiceInfo>,
     pub routes: Vec<RouteInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub plugins: Option<Vec<PluginInfo>>,
-    pub consumers: Option<Vec<BTreeMap<String, String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consumers: Option<Vec<ConsumerInfo>>,
+    /// csv/jsonl file of more consumers with their credentials, streamed when applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consumers_from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub credentials: Option<Vec<CredentialsInfo>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificates: Option<Vec<CertificateInfo>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct UpstreamInfo {
+    pub name: String,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<TargetInfo>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct TargetInfo {
+    pub target: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct CertificateInfo {
+    pub cert: String,
+    pub key: String,
+
+    #[serde(default)]
+    pub snis: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -29,7 +67,7 @@ pub struct RouteInfo {
     pub config: BTreeMap<String, Value>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct LegacyPluginInfo {
     pub name: String,
     pub plugin_type: String,
@@ -37,92 +75,100 @@ pub struct LegacyPluginInfo {
     pub config: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct PluginInfo {
     pub name: String,
     pub target: String,
 
-    #[serde(default)]
-    pub config: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config: BTreeMap<String, Value>,
     pub enabled: bool,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
-pub struct CredentialsInfo {
-    pub name: String,
-    pub target: String,
-
-    #[serde(default)]
-    pub config: BTreeMap<String, String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ConsumerDO {
-    pub custom_id: Option<String>,
-    pub id: String,
-    pub created_at: i64,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ListApiResp {
-    pub total: i32,
-    pub next: Option<String>,
-    pub offset: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct AddServiceResp {
-    pub id: String,
-    pub created_at: i64,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct AddRouteResp {
-    pub id: String,
-    pub created_at: i64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct KongInfo {
-    pub version: String,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ServiceList {
-    pub data: Vec<ServiceItem>,
-    pub next: Option<String>,
-    pub offset: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct RouteList {
-    pub data: Vec<RouteItem>,
-    pub next: Option<String>,
-    pub offset: Option<String>,
+/// What the `target` of a plugin names.
+pub enum Target {
+    Global,
+    Services(Vec<String>),
+    Routes(Vec<String>),
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PluginList {
-    pub total: i32,
-    pub data: Vec<PluginItem>,
-    pub offset: Option<String>,
+/// The `target` of a plugin, the one parser of every mode and render target: names are taken
+/// as written between the commas(`r[r_dummy,api.v1]`), only empty ones are refused.
+pub fn parse_target(target: &str) -> Result<Target, String> {
+    let invalid = || format!("invalid plugin target {}, must one of global/s[service-a,service-b]/r[route-a,route-b]", target);
+    let names = |prefix: &str| -> Option<Result<Vec<String>, String>> {
+        if target.starts_with(prefix) && target.ends_with(']') {
+            let names: Vec<String> = target[prefix.len()..target.len() - 1]
+                .split(',')
+                .map(String::from)
+                .collect();
+            if names.iter().any(|name| name.is_empty()) {
+                Some(Err(invalid()))
+            } else {
+                Some(Ok(names))
+            }
+        } else {
+            None
+        }
+    };
+
+    if target == "global" {
+        Ok(Target::Global)
+    } else if let Some(services) = names("s[") {
+        services.map(Target::Services)
+    } else if let Some(routes) = names("r[") {
+        routes.map(Target::Routes)
+    } else {
+        Err(invalid())
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ServiceItem {
-    pub id: String,
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct CredentialsInfo {
     pub name: String,
-}
+    pub target: String,
 
-#[derive(Debug, Deserialize)]
-pub struct RouteItem {
-    pub id: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config: BTreeMap<String, Value>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PluginItem {
-    pub id: String,
-    pub name: String,
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ConsumerInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// acl groups, the consumer's acls are reconciled to exactly these
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+
+    /// any other consumer field, passed to kong as is
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+impl ConsumerInfo {
+    /// consumer created by `{{k-upsert-consumer:<custom_id>}}`
+    pub fn guest(custom_id: &str) -> ConsumerInfo {
+        ConsumerInfo {
+            username: Some(custom_id.to_string()),
+            custom_id: Some(custom_id.to_string()),
+            ..ConsumerInfo::default()
+        }
+    }
+
+    /// username, or custom_id for consumers without one
+    pub fn name(&self) -> Option<&str> {
+        self.username.as_ref().or(self.custom_id.as_ref()).map(|n| n.as_str())
+    }
+
+    /// whether a credential target(username or custom_id) refers to this consumer
+    pub fn is_target(&self, target: &str) -> bool {
+        self.username.as_ref().is_some_and(|u| u == target)
+            || self.custom_id.as_ref().is_some_and(|c| c == target)
+    }
 }
 
 pub enum LegacyPluginAppliedType {
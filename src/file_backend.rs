@@ -0,0 +1,170 @@
+use backend::{BackendError, KongBackend};
+use dbless::{self, FORMAT_VERSION};
+use memory_backend::MemoryBackend;
+use serde_json::{Map as SerdeMap, Value};
+use serde_yaml;
+use state;
+use std::collections::BTreeMap;
+
+/// Entities applied in memory, written as a DB-less declarative config once the run is done.
+///
+/// The entities nest under what they belong to, the way `--dbless` renders them: routes under
+/// their service, targets under their upstream, credentials under their consumer.
+pub struct FileBackend {
+    memory: MemoryBackend,
+    output: String,
+}
+
+impl FileBackend {
+    pub fn new(memory: MemoryBackend, output: &str) -> FileBackend {
+        FileBackend {
+            memory,
+            output: output.to_string(),
+        }
+    }
+}
+
+impl KongBackend for FileBackend {
+    fn describe(&self) -> String {
+        format!("file {}", self.output)
+    }
+
+    fn version(&self) -> Result<String, BackendError> {
+        self.memory.version()
+    }
+
+    fn list(&self, collection: &str) -> Result<Vec<Value>, BackendError> {
+        self.memory.list(collection)
+    }
+
+    fn find(&self, collection: &str, field: &str, value: &str) -> Result<Vec<Value>, BackendError> {
+        self.memory.find(collection, field, value)
+    }
+
+    fn get(&self, collection: &str, key: &str) -> Result<Value, BackendError> {
+        self.memory.get(collection, key)
+    }
+
+    fn create(&self, collection: &str, entity: &Value) -> Result<Value, BackendError> {
+        self.memory.create(collection, entity)
+    }
+
+    fn upsert(&self, collection: &str, entity: &Value) -> Result<Value, BackendError> {
+        self.memory.upsert(collection, entity)
+    }
+
+    fn update(&self, collection: &str, key: &str, fields: &Value) -> Result<Value, BackendError> {
+        self.memory.update(collection, key, fields)
+    }
+
+    fn delete(&self, collection: &str, key: &str) -> Result<(), BackendError> {
+        self.memory.delete(collection, key)
+    }
+
+    fn load_declarative(&self, config: &str) -> Result<(), BackendError> {
+        self.memory.load_declarative(config)
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        let declarative = match self.memory.declarative() {
+            // --dbless rendered it already
+            Some(declarative) => declarative,
+            None => serde_yaml::to_string(&render(self.memory.entities())?)
+                .map_err(|why| why.to_string())?,
+        };
+        state::write_private(&self.output, &declarative)?;
+        info!("declarative config written to {}", self.output);
+        Ok(())
+    }
+}
+
+/// the declarative config of the entities, by collection
+pub fn render(mut entities: BTreeMap<String, Vec<Value>>) -> Result<Value, String> {
+    let mut take = |collection: &str| entities.remove(collection).unwrap_or_default();
+    if !take("apis").is_empty() {
+        return Err("apis have no declarative form, use services and routes".to_string());
+    }
+    let mut services = take("services");
+    let mut routes = take("routes");
+    let mut upstreams = take("upstreams");
+    let targets = take("targets");
+    let mut consumers = take("consumers");
+    let certificates = take("certificates");
+    let plugins = take("plugins");
+    let credentials: Vec<(String, Vec<Value>)> = ["jwt", "key-auth", "basic-auth", "hmac-auth", "oauth2", "acls"]
+        .iter()
+        .map(|plugin| (plugin.to_string(), take(plugin)))
+        .collect();
+
+    // plugins go to the route, service or consumer they apply to, the others are global
+    let mut global_plugins = Vec::new();
+    for mut plugin in plugins {
+        let owner = ["route_id", "service_id", "consumer_id"]
+            .iter()
+            .find(|field| plugin[**field].is_string())
+            .map(|field| (*field, plugin[*field].clone()));
+        for field in &["route_id", "service_id", "consumer_id", "api_id"] {
+            if let Some(id) = remove(&mut plugin, field) {
+                if owner.as_ref().map(|(owner, _)| owner) != Some(field) {
+                    plugin[&field[..field.len() - 3]] = id;
+                }
+            }
+        }
+        match owner {
+            None => global_plugins.push(plugin),
+            Some(("route_id", id)) => push_to(&mut routes, &id, "plugins", plugin),
+            Some(("service_id", id)) => push_to(&mut services, &id, "plugins", plugin),
+            Some((_, id)) => push_to(&mut consumers, &id, "plugins", plugin),
+        }
+    }
+    for mut route in routes {
+        let service = remove(&mut route, "service").map(|s| s["id"].clone()).unwrap_or_default();
+        push_to(&mut services, &service, "routes", route);
+    }
+    for mut target in targets {
+        let upstream = remove(&mut target, "upstream_id").unwrap_or_default();
+        push_to(&mut upstreams, &upstream, "targets", target);
+    }
+    for (plugin, credentials) in credentials {
+        let field = dbless::credential_field(&plugin).unwrap();
+        for mut credential in credentials {
+            let consumer = remove(&mut credential, "consumer_id").unwrap_or_default();
+            push_to(&mut consumers, &consumer, field, credential);
+        }
+    }
+
+    let mut doc = SerdeMap::new();
+    doc.insert(
+        "_format_version".to_string(),
+        Value::String(FORMAT_VERSION.to_string()),
+    );
+    for (collection, entities) in [
+        ("upstreams", upstreams),
+        ("services", services),
+        ("plugins", global_plugins),
+        ("consumers", consumers),
+        ("certificates", certificates),
+    ] {
+        if !entities.is_empty() {
+            doc.insert(collection.to_string(), Value::Array(entities));
+        }
+    }
+    if let Some((collection, _)) = entities.iter().find(|(_, left)| !left.is_empty()) {
+        return Err(format!("{} have no declarative form", collection));
+    }
+    Ok(Value::Object(doc))
+}
+
+fn remove(entity: &mut Value, field: &str) -> Option<Value> {
+    entity.as_object_mut().and_then(|fields| fields.remove(field))
+}
+
+/// add `child` to the `field` list of the entity with the id
+fn push_to(entities: &mut [Value], id: &Value, field: &str, child: Value) {
+    if let Some(parent) = entities.iter_mut().find(|e| &e["id"] == id) {
+        match parent[field].as_array_mut() {
+            Some(children) => children.push(child),
+            None => parent[field] = Value::Array(vec![child]),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Fingerprints telling a run there is nothing to apply.
+//!
+//! After a complete run the sha256 of its resolved config and the sha256 of the entities it
+//! left in kong are kept in the `custom_id` of the `kong-init-fingerprint` consumer. The next
+//! run with the same config skips the apply as long as the live entities still match.
+use client::KongApiClient;
+use consumer;
+use entity::ConfFileStyle;
+use loader::sha256_hex;
+use serde_json::{self, Value};
+use std::fs;
+
+/// consumer holding the fingerprints of the last complete run
+pub const MARKER: &str = "kong-init-fingerprint";
+
+/// collections the live fingerprint covers, those missing from a version are empty
+const COLLECTIONS: &[&str] = &[
+    "apis",
+    "services",
+    "routes",
+    "plugins",
+    "upstreams",
+    "certificates",
+    "consumers",
+];
+
+/// listings of every credential of a type, whatever the consumer: one paged listing each
+const CREDENTIAL_LISTINGS: &[&str] = &["key-auths", "basic-auths", "hmac-auths", "jwts", "oauth2", "acls"];
+
+/// Hash of what the run applies: the resolved definitions(generated secrets included), the
+/// `consumers_from` file and the flags changing the outcome.
+pub fn config(conf: &ConfFileStyle, prune: bool) -> Result<String, String> {
+    let (definitions, consumers_from) = match conf {
+        ConfFileStyle::Suggested(conf) => (serde_json::to_value(conf), &conf.consumers_from),
+        ConfFileStyle::Legacy(conf) => (serde_json::to_value(conf), &conf.consumers_from),
+        ConfFileStyle::IllegalFormat { msg } => return Err(msg.clone()),
+    };
+    let consumers_from = match consumers_from {
+        Some(path) => sha256_hex(&fs::read(path).map_err(|why| format!("{}: {}", path, why))?),
+        None => String::new(),
+    };
+    // json objects are maps ordered by key, the text is canonical
+    let canonical = json!({
+        "kong-init": env!("CARGO_PKG_VERSION"),
+        "definitions": definitions.map_err(|why| why.to_string())?,
+        "consumers_from": consumers_from,
+        "prune": prune,
+    });
+    Ok(sha256_hex(canonical.to_string().as_bytes()))
+}
+
+/// Hash of the live entities, ordered by id and without the reserved consumers. The credentials
+/// are listed by type, the targets through their upstream.
+pub fn live(cli: &KongApiClient) -> Result<String, String> {
+    let mut entities = serde_json::Map::new();
+    for collection in COLLECTIONS {
+        let listed: Vec<Value> = cli
+            .list_entities(collection)?
+            .into_iter()
+            .filter(|e| !consumer::is_reserved(e))
+            .collect();
+        entities.insert(collection.to_string(), sorted(listed));
+    }
+    for listing in CREDENTIAL_LISTINGS {
+        entities.insert(listing.to_string(), sorted(cli.list_entities(listing)?));
+    }
+    let upstreams: Vec<String> = entities["upstreams"]
+        .as_array()
+        .map_or(vec![], |listed| listed.iter().filter_map(|e| e["id"].as_str().map(String::from)).collect());
+    let mut targets = Vec::new();
+    for upstream in &upstreams {
+        targets.extend(cli.list_entities(&format!("upstreams/{}/targets", upstream))?);
+    }
+    entities.insert("targets".to_string(), sorted(targets));
+    Ok(sha256_hex(Value::Object(entities).to_string().as_bytes()))
+}
+
+fn sorted(mut listed: Vec<Value>) -> Value {
+    listed.sort_by(|a, b| a["id"].as_str().cmp(&b["id"].as_str()));
+    Value::Array(listed)
+}
+
+/// whether the last complete run applied `config` and kong still holds what it left
+pub fn unchanged(cli: &KongApiClient, config: &str) -> Result<bool, String> {
+    let recorded = match cli.get_reserved_consumer(MARKER)? {
+        Some(marker) => marker["custom_id"].as_str().unwrap_or_default().to_string(),
+        None => return Ok(false),
+    };
+    match recorded.split_once('.') {
+        Some((recorded_config, recorded_live)) if recorded_config == config => {
+            Ok(recorded_live == live(cli)?)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// keep the fingerprints of a complete run
+pub fn record(cli: &KongApiClient, config: &str) -> Result<(), String> {
+    let fingerprint = format!("{}.{}", config, live(cli)?);
+    match cli.get_reserved_consumer(MARKER)? {
+        Some(ref marker) if marker["custom_id"] == fingerprint.as_str() => Ok(()),
+        _ => cli.put_reserved_consumer(MARKER, &fingerprint),
+    }
+}
+
+/// drop the fingerprints after a failed run, the next one applies whatever its config
+pub fn forget(cli: &KongApiClient) -> Result<(), String> {
+    cli.delete_reserved_consumer(MARKER)
+}
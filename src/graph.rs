@@ -0,0 +1,112 @@
+use logging;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+type Task<'a> = Box<dyn Fn() -> Result<(), String> + Sync + 'a>;
+
+struct Node<'a> {
+    name: String,
+    deps: Vec<usize>,
+    task: Task<'a>,
+}
+
+/// The operations of an apply and the ones each waits for: services before their routes,
+/// consumers before their credentials...
+///
+/// Operations run a layer at a time, a layer holding those whose dependencies are all in the
+/// layers before. Within a layer `concurrency` run at the same time, what they log is held back
+/// and written in the order they were added: the logs read the same whatever the timing.
+#[derive(Default)]
+pub struct Graph<'a> {
+    nodes: Vec<Node<'a>>,
+}
+
+impl<'a> Graph<'a> {
+    pub fn new() -> Graph<'a> {
+        Graph::default()
+    }
+
+    /// Add an operation run once all of `deps` succeeded, returns its id to depend on it.
+    pub fn add<F>(&mut self, name: &str, deps: &[usize], task: F) -> usize
+    where
+        F: Fn() -> Result<(), String> + Sync + 'a,
+    {
+        let mut deps = deps.to_vec();
+        deps.sort_unstable();
+        deps.dedup();
+        self.nodes.push(Node {
+            name: name.to_string(),
+            deps,
+            task: Box::new(task),
+        });
+        self.nodes.len() - 1
+    }
+
+    /// ids of the operations by layer, an operation is in the layer after its last dependency
+    fn layers(&self) -> Vec<Vec<usize>> {
+        let mut depth: Vec<usize> = Vec::with_capacity(self.nodes.len());
+        let mut layers: Vec<Vec<usize>> = Vec::new();
+        // dependencies are added first, the ids are in topological order
+        for (id, node) in self.nodes.iter().enumerate() {
+            let layer = node.deps.iter().map(|dep| depth[*dep] + 1).max().unwrap_or(0);
+            depth.push(layer);
+            if layers.len() <= layer {
+                layers.resize(layer + 1, Vec::new());
+            }
+            layers[layer].push(id);
+        }
+        layers
+    }
+
+    /// Run every operation, skipping those whose dependencies failed. Returns the names of
+    /// the operations that failed or were skipped.
+    pub fn run(self, concurrency: usize) -> Vec<String> {
+        let concurrency = concurrency.max(1);
+        let layers = self.layers();
+        debug!("applying {} operations in {} layers", self.nodes.len(), layers.len());
+        let mut failed = vec![false; self.nodes.len()];
+        let mut failures = Vec::new();
+
+        for layer in layers {
+            let mut runnable = Vec::with_capacity(layer.len());
+            for id in layer {
+                let node = &self.nodes[id];
+                match node.deps.iter().find(|dep| failed[**dep]) {
+                    Some(dep) => {
+                        warn!("skipped {}, {} failed", node.name, self.nodes[*dep].name);
+                        failed[id] = true;
+                        failures.push(node.name.clone());
+                    }
+                    None => runnable.push(id),
+                }
+            }
+
+            let outcomes: Vec<Mutex<Option<_>>> = runnable.iter().map(|_| Mutex::new(None)).collect();
+            let next = AtomicUsize::new(0);
+            thread::scope(|scope| {
+                for _ in 0..concurrency.min(runnable.len()) {
+                    scope.spawn(|| loop {
+                        let idx = next.fetch_add(1, Ordering::SeqCst);
+                        if idx >= runnable.len() {
+                            break;
+                        }
+                        let node = &self.nodes[runnable[idx]];
+                        *outcomes[idx].lock().unwrap() = Some(logging::capture(|| (node.task)()));
+                    });
+                }
+            });
+
+            for (id, outcome) in runnable.into_iter().zip(outcomes) {
+                let (result, records) = outcome.into_inner().unwrap().unwrap();
+                logging::replay(records);
+                if let Err(why) = result {
+                    error!("{}: {}", self.nodes[id].name, why);
+                    failed[id] = true;
+                    failures.push(self.nodes[id].name.clone());
+                }
+            }
+        }
+        failures
+    }
+}
@@ -0,0 +1,333 @@
+use backend::{BackendError, KongBackend};
+use cassette::{Exchange, Mode, Recorder, Replayer};
+use futures::sync::oneshot;
+use futures::{future, stream, Future, Stream};
+use http::{Method, StatusCode};
+use reqwest::async::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::{self, Value};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::timer::Delay;
+
+/// entities asked for per page of a listing, the most kong returns at once
+const PAGE_SIZE: usize = 1000;
+
+/// parse `key:value` header args, invalid ones are ignored with a warning
+pub fn parse_headers(header_strs: &[&str]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    header_strs.iter().for_each(|raw_header| {
+        if !raw_header.contains(':') {
+            warn!("invalid header value: {} has ignored!", raw_header)
+        } else {
+            let sp: Vec<&str> = raw_header.splitn(2, ':').collect();
+            debug!("[args] header value: raw_header={} ", raw_header);
+            debug!("[args] header value: key={} ", sp[0]);
+            debug!("[args] header value: value={} ", sp[1]);
+            headers.insert(
+                HeaderName::from_str(sp[0]).unwrap(),
+                HeaderValue::from_str(sp[1].trim()).unwrap(),
+            );
+        }
+    });
+    headers
+}
+
+/// percent-encode a query value
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        }).collect()
+}
+
+/// What an admin api call got back.
+struct ApiResponse {
+    status: StatusCode,
+    body: String,
+}
+
+impl ApiResponse {
+    /// the json body of a successful call
+    fn entity(self) -> Result<Value, BackendError> {
+        if !self.status.is_success() {
+            return Err(match self.status {
+                StatusCode::NOT_FOUND => BackendError::NotFound,
+                StatusCode::CONFLICT => BackendError::Conflict(self.body),
+                status => BackendError::Rejected {
+                    status: status.as_u16(),
+                    message: self.body,
+                },
+            });
+        }
+        if self.body.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_str(&self.body).map_err(|why| BackendError::Unavailable(why.to_string()))
+    }
+}
+
+type Call<T> = Box<dyn Future<Item = T, Error = BackendError> + Send>;
+
+/// How the calls reach kong.
+enum Transport {
+    Http(Client),
+    /// over http, writing every exchange to a cassette(`--record`)
+    Recording(Client, Recorder),
+    /// answered from a cassette(`--replay`), kong is never called
+    Replay(Replayer),
+}
+
+/// Spaces the calls out to at most `per_second`(`--rate-limit`), each call waiting for the
+/// next free slot.
+struct RateLimit {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimit {
+    fn new(per_second: f64) -> RateLimit {
+        RateLimit {
+            interval: Duration::from_nanos((1e9 / per_second) as u64),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// when the next call may start
+    fn reserve(&self) -> Instant {
+        let mut next = self.next.lock().unwrap();
+        let slot = (*next).max(Instant::now());
+        *next = slot + self.interval;
+        slot
+    }
+}
+
+/// What the calls in flight share: the connection pool of the client, the cassette and the
+/// rate limit.
+struct Api {
+    base_url: String,
+    transport: Transport,
+    limit: Option<RateLimit>,
+}
+
+impl Api {
+    /// Every admin api call goes through here, `path` is relative to the admin url.
+    fn execute(self: &Arc<Self>, method: Method, path: &str, body: Option<Value>) -> Call<ApiResponse> {
+        let client = match &self.transport {
+            Transport::Replay(replayer) => {
+                let response = replayer
+                    .respond(method.as_str(), path, body.as_ref())
+                    .map_err(BackendError::Unavailable)
+                    .and_then(|exchange| {
+                        let status = StatusCode::from_u16(exchange.status)
+                            .map_err(|why| BackendError::Unavailable(why.to_string()))?;
+                        Ok(ApiResponse {
+                            status,
+                            body: exchange.response_text(),
+                        })
+                    });
+                return Box::new(future::result(response));
+            }
+            Transport::Http(client) | Transport::Recording(client, _) => client,
+        };
+
+        let mut request = client.request(method.clone(), &format!("{}{}", self.base_url, path));
+        if let Some(body) = &body {
+            request = request.json(body);
+        }
+        let slot = match &self.limit {
+            Some(limit) => future::Either::A(Delay::new(limit.reserve()).then(|_| Ok(()))),
+            None => future::Either::B(future::ok(())),
+        };
+        let api = self.clone();
+        let path = path.to_string();
+        let response = slot
+            .and_then(move |()| request.send())
+            .and_then(|resp| {
+                let status = resp.status();
+                resp.into_body().concat2().map(move |body| ApiResponse {
+                    status,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                })
+            }).map_err(|why| BackendError::Unavailable(why.to_string()))
+            .map(move |response| {
+                if let Transport::Recording(_, recorder) = &api.transport {
+                    recorder.record(&Exchange {
+                        method: method.to_string(),
+                        path,
+                        body,
+                        status: response.status.as_u16(),
+                        response: serde_json::from_str(&response.body)
+                            .unwrap_or_else(|_| Value::String(response.body.clone())),
+                    });
+                }
+                response
+            });
+        Box::new(response)
+    }
+
+    fn call(self: &Arc<Self>, method: Method, path: &str, body: Option<&Value>) -> Call<Value> {
+        Box::new(self.execute(method, path, body.cloned()).and_then(ApiResponse::entity))
+    }
+
+    /// the pages of a listing one after the other, asking for `PAGE_SIZE` entities and
+    /// following their `offset`
+    fn pages(self: &Arc<Self>, path: &str) -> impl Stream<Item = Vec<Value>, Error = BackendError> {
+        let api = self.clone();
+        let path = path.to_string();
+        let separator = if path.contains('?') { '&' } else { '?' };
+        stream::unfold(Some(None), move |offset: Option<Option<String>>| {
+            let url = match offset? {
+                None => format!("{}{}size={}", path, separator, PAGE_SIZE),
+                Some(offset) => format!("{}{}size={}&offset={}", path, separator, PAGE_SIZE, encode(&offset)),
+            };
+            Some(api.call(Method::GET, &url, None).map(|page| {
+                let data = match page.get("data").and_then(|d| d.as_array()) {
+                    Some(data) => data.clone(),
+                    None => Vec::new(),
+                };
+                let next = page.get("offset").and_then(|o| o.as_str()).map(|o| Some(o.to_string()));
+                (data, next)
+            }))
+        })
+    }
+}
+
+/// The admin api of a live kong node.
+///
+/// Calls are made on a runtime of its own, the caller's thread blocking until they answer: a
+/// run's concurrency comes from its `--concurrency` threads, which share the pooled connections
+/// and the rate limit.
+pub struct HttpBackend {
+    api: Arc<Api>,
+    runtime: Runtime,
+}
+
+impl HttpBackend {
+    pub fn new(kong_admin_url: &str, custom_headers_opt: Option<Vec<&str>>) -> HttpBackend {
+        let client = match custom_headers_opt {
+            None => Client::new(),
+            Some(header_strs) => Client::builder()
+                .default_headers(parse_headers(&header_strs))
+                .build()
+                .unwrap(),
+        };
+
+        HttpBackend {
+            api: Arc::new(Api {
+                base_url: kong_admin_url.to_string(),
+                transport: Transport::Http(client),
+                limit: None,
+            }),
+            runtime: Runtime::new().expect("unable to start the runtime of the admin api client"),
+        }
+    }
+
+    /// settings can only change before the first call, while nothing else holds the api
+    fn api_mut(&mut self) -> &mut Api {
+        Arc::get_mut(&mut self.api).expect("http backend configured after its first call")
+    }
+
+    /// record the calls to a cassette directory, or replay them from one
+    pub fn use_cassette(&mut self, mode: &Mode) -> Result<(), String> {
+        let api = self.api_mut();
+        let transport = match mode {
+            Mode::Record(dir) => {
+                let client = match &api.transport {
+                    Transport::Http(client) | Transport::Recording(client, _) => client.clone(),
+                    Transport::Replay(_) => Client::new(),
+                };
+                Transport::Recording(client, Recorder::create(dir)?)
+            }
+            Mode::Replay(dir) => Transport::Replay(Replayer::load(dir)?),
+        };
+        api.transport = transport;
+        Ok(())
+    }
+
+    /// send at most `per_second` requests a second
+    pub fn limit_rate(&mut self, per_second: f64) {
+        self.api_mut().limit = Some(RateLimit::new(per_second));
+    }
+
+    /// run a call on the runtime and wait for it
+    fn wait<T: Send + 'static>(&self, call: Call<T>) -> Result<T, BackendError> {
+        let (sender, receiver) = oneshot::channel();
+        self.runtime
+            .executor()
+            .spawn(call.then(|result| sender.send(result).map_err(|_| ())));
+        receiver
+            .wait()
+            .unwrap_or_else(|_| Err(BackendError::Unavailable("the admin api call was dropped".to_string())))
+    }
+
+    fn call(&self, method: Method, path: &str, body: Option<&Value>) -> Result<Value, BackendError> {
+        self.wait(self.api.call(method, path, body))
+    }
+
+    /// every entity of a listing, its pages read one after the other and collected
+    fn list_pages(&self, path: &str) -> Result<Vec<Value>, BackendError> {
+        self.wait(Box::new(self.api.pages(path).concat2()))
+    }
+}
+
+impl KongBackend for HttpBackend {
+    fn describe(&self) -> String {
+        self.api.base_url.clone()
+    }
+
+    fn version(&self) -> Result<String, BackendError> {
+        let info = self.call(Method::GET, "/", None)?;
+        info.get("version")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .ok_or_else(|| BackendError::Unavailable(format!("no version in {}", info)))
+    }
+
+    fn list(&self, collection: &str) -> Result<Vec<Value>, BackendError> {
+        self.list_pages(&format!("/{}", collection))
+    }
+
+    fn find(&self, collection: &str, field: &str, value: &str) -> Result<Vec<Value>, BackendError> {
+        self.list_pages(&format!("/{}?{}={}", collection, field, encode(value)))
+    }
+
+    fn get(&self, collection: &str, key: &str) -> Result<Value, BackendError> {
+        self.call(Method::GET, &format!("/{}/{}", collection, key), None)
+    }
+
+    fn create(&self, collection: &str, entity: &Value) -> Result<Value, BackendError> {
+        self.call(Method::POST, &format!("/{}", collection), Some(entity))
+    }
+
+    fn upsert(&self, collection: &str, entity: &Value) -> Result<Value, BackendError> {
+        self.call(Method::PUT, &format!("/{}", collection), Some(entity))
+    }
+
+    fn update(&self, collection: &str, key: &str, fields: &Value) -> Result<Value, BackendError> {
+        self.call(Method::PATCH, &format!("/{}/{}", collection, key), Some(fields))
+    }
+
+    fn delete(&self, collection: &str, key: &str) -> Result<(), BackendError> {
+        self.call(Method::DELETE, &format!("/{}/{}", collection, key), None)
+            .map(|_| ())
+    }
+
+    fn load_declarative(&self, config: &str) -> Result<(), BackendError> {
+        self.call(Method::POST, "/config", Some(&json!({ "config": config })))
+            .map(|_| ())
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        match &self.api.transport {
+            Transport::Replay(replayer) if !replayer.finish() => {
+                Err("the run diverged from the recording".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
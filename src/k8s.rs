@@ -0,0 +1,460 @@
+use dbless;
+use entity::{
+    parse_target, CertificateInfo, ConsumerInfo, CredentialsInfo, KongConf, PluginInfo, RouteInfo,
+    ServiceInfo, Target,
+};
+use reqwest::Url;
+use serde_json::{Map as SerdeMap, Value};
+use std::collections::BTreeMap;
+
+const CONFIGURATION_API: &str = "configuration.konghq.com/v1";
+
+/// route fields expressed through `konghq.com/*` annotations of the Ingress
+const ROUTE_ANNOTATIONS: &[(&str, &str)] = &[
+    ("strip_path", "konghq.com/strip-path"),
+    ("preserve_host", "konghq.com/preserve-host"),
+    ("methods", "konghq.com/methods"),
+    ("protocols", "konghq.com/protocols"),
+    ("regex_priority", "konghq.com/regex-priority"),
+    ("https_redirect_status_code", "konghq.com/https-redirect-status-code"),
+];
+
+/// service fields overridden through a KongIngress
+const PROXY_FIELDS: &[&str] = &["connect_timeout", "read_timeout", "write_timeout", "retries"];
+
+pub struct Options<'a> {
+    pub namespace: Option<&'a str>,
+    pub ingress_class: &'a str,
+}
+
+/// Convert a parsed config into manifests for the Kong Ingress Controller.
+///
+/// Services become ExternalName Services pointing at their upstream, routes become Ingresses,
+/// and plugins are attached to both through the `konghq.com/plugins` annotation.
+pub fn render(
+    conf: &KongConf,
+    guest_consumers: &[String],
+    opts: &Options,
+) -> Result<Vec<Value>, String> {
+    let mut manifests = Vec::new();
+
+    // (plugin resource name, target) of every plugin attached to a service or route
+    let mut attached: Vec<(String, Target)> = Vec::new();
+    let mut plugin_names: Vec<String> = Vec::new();
+    let mut plugin_manifests = Vec::new();
+    for plugin_info in conf.plugins.as_ref().map_or(&[][..], |p| &p[..]) {
+        let name = unique_name(
+            &mut plugin_names,
+            resource_name(&format!("{}-{}", plugin_info.name, plugin_info.target)),
+        );
+        match parse_target(&plugin_info.target)? {
+            Target::Global => {
+                plugin_manifests.push(plugin_manifest(&name, plugin_info, true, opts))
+            }
+            target => {
+                plugin_manifests.push(plugin_manifest(&name, plugin_info, false, opts));
+                attached.push((name, target));
+            }
+        }
+    }
+    let plugins_of = |service: Option<&str>, route: Option<&str>| -> Vec<String> {
+        attached
+            .iter()
+            .filter(|(_, target)| match target {
+                Target::Services(names) => service.is_some_and(|s| names.iter().any(|n| n == s)),
+                Target::Routes(names) => route.is_some_and(|r| names.iter().any(|n| n == r)),
+                Target::Global => false,
+            }).map(|(name, _)| name.clone())
+            .collect()
+    };
+
+    let mut ports = BTreeMap::new();
+    for service_info in &conf.services {
+        let name = match service_info.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            other => {
+                return Err(format!(
+                    "service name={:?}, the `name` field must be String type",
+                    other
+                ))
+            }
+        };
+        let (service, kong_ingress, port) =
+            service_manifests(&name, service_info, &plugins_of(Some(&name), None), opts)?;
+        ports.insert(name, port);
+        manifests.push(service);
+        manifests.extend(kong_ingress);
+    }
+
+    let certificates = conf.certificates.as_ref().map_or(&[][..], |c| &c[..]);
+    for route_info in &conf.routes {
+        let port = ports.get(&route_info.apply_to).ok_or_else(|| {
+            format!(
+                "route {} applies to unknown service {}",
+                route_info.name, route_info.apply_to
+            )
+        })?;
+        manifests.push(ingress_manifest(
+            route_info,
+            *port,
+            &plugins_of(None, Some(&route_info.name)),
+            certificates,
+            opts,
+        ));
+    }
+    for (idx, certificate) in certificates.iter().enumerate() {
+        manifests.push(certificate_manifest(idx, certificate, opts));
+    }
+
+    manifests.extend(plugin_manifests);
+    manifests.extend(consumer_manifests(conf, guest_consumers, opts)?);
+
+    if conf.upstreams.is_some() {
+        warn!("upstreams are skipped, the ingress controller builds them from the endpoints of kubernetes Services");
+    }
+    Ok(manifests)
+}
+
+fn service_manifests(
+    name: &str,
+    service_info: &ServiceInfo,
+    plugins: &[String],
+    opts: &Options,
+) -> Result<(Value, Option<Value>, u16), String> {
+    let field = |k: &str| service_info.get(k).and_then(|v| v.as_str()).map(String::from);
+    let (protocol, host, port, path) = match field("url") {
+        Some(raw) => {
+            let url = Url::parse(&raw).map_err(|why| format!("service {}: {}: {}", name, raw, why))?;
+            (
+                url.scheme().to_string(),
+                url.host_str().map(String::from),
+                url.port_or_known_default(),
+                Some(url.path().to_string()).filter(|p| p != "/"),
+            )
+        }
+        None => (
+            field("protocol").unwrap_or_else(|| "http".to_string()),
+            field("host"),
+            service_info.get("port").and_then(|p| p.as_u64()).map(|p| p as u16),
+            field("path"),
+        ),
+    };
+    let host = host.ok_or_else(|| format!("service {} has neither url nor host", name))?;
+    let port = port.unwrap_or(if protocol == "https" { 443 } else { 80 });
+
+    let resource = resource_name(name);
+    let mut annotations = SerdeMap::new();
+    annotations.insert("konghq.com/protocol".to_string(), Value::String(protocol));
+    if let Some(path) = path {
+        annotations.insert("konghq.com/path".to_string(), Value::String(path));
+    }
+    if !plugins.is_empty() {
+        annotations.insert("konghq.com/plugins".to_string(), Value::String(plugins.join(",")));
+    }
+
+    let proxy: SerdeMap<String, Value> = PROXY_FIELDS
+        .iter()
+        .filter_map(|k| service_info.get(*k).map(|v| (k.to_string(), v.clone())))
+        .collect();
+    let kong_ingress = if proxy.is_empty() {
+        None
+    } else {
+        annotations.insert("konghq.com/override".to_string(), Value::String(resource.clone()));
+        Some(json!({
+            "apiVersion": CONFIGURATION_API,
+            "kind": "KongIngress",
+            "metadata": metadata(&resource, SerdeMap::new(), opts),
+            "proxy": proxy,
+        }))
+    };
+
+    let service = json!({
+        "apiVersion": "v1",
+        "kind": "Service",
+        "metadata": metadata(&resource, annotations, opts),
+        "spec": {
+            "type": "ExternalName",
+            "externalName": host,
+            "ports": [{ "protocol": "TCP", "port": port }],
+        },
+    });
+    Ok((service, kong_ingress, port))
+}
+
+fn ingress_manifest(
+    route_info: &RouteInfo,
+    port: u16,
+    plugins: &[String],
+    certificates: &[CertificateInfo],
+    opts: &Options,
+) -> Value {
+    let strings = |k: &str| -> Vec<String> {
+        route_info
+            .config
+            .get(k)
+            .and_then(|v| v.as_array())
+            .map_or(Vec::new(), |items| {
+                items.iter().filter_map(|i| i.as_str().map(String::from)).collect()
+            })
+    };
+
+    let mut annotations = SerdeMap::new();
+    for (field, value) in &route_info.config {
+        match ROUTE_ANNOTATIONS.iter().find(|(f, _)| f == field) {
+            Some((_, annotation)) => {
+                annotations.insert(annotation.to_string(), Value::String(string_value(value)));
+            }
+            None if field == "paths" || field == "hosts" => {}
+            None => warn!(
+                "route {}: field {} has no ingress annotation, skipped",
+                route_info.name, field
+            ),
+        }
+    }
+    if !plugins.is_empty() {
+        annotations.insert("konghq.com/plugins".to_string(), Value::String(plugins.join(",")));
+    }
+
+    let mut paths = strings("paths");
+    if paths.is_empty() {
+        paths.push("/".to_string());
+    }
+    let http_paths: Vec<Value> = paths
+        .iter()
+        .map(|path| {
+            json!({
+                "path": path,
+                "pathType": "ImplementationSpecific",
+                "backend": {
+                    "service": {
+                        "name": resource_name(&route_info.apply_to),
+                        "port": { "number": port },
+                    }
+                },
+            })
+        }).collect();
+
+    let hosts = strings("hosts");
+    let rules: Vec<Value> = if hosts.is_empty() {
+        vec![json!({ "http": { "paths": http_paths } })]
+    } else {
+        hosts
+            .iter()
+            .map(|host| json!({ "host": host, "http": { "paths": http_paths } }))
+            .collect()
+    };
+
+    let mut spec = SerdeMap::new();
+    spec.insert("ingressClassName".to_string(), Value::String(opts.ingress_class.to_string()));
+    let tls: Vec<Value> = certificates
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, certificate)| {
+            let covered: Vec<&String> = hosts.iter().filter(|h| certificate.snis.contains(h)).collect();
+            if covered.is_empty() {
+                None
+            } else {
+                Some(json!({ "hosts": covered, "secretName": certificate_name(idx, certificate) }))
+            }
+        }).collect();
+    if !tls.is_empty() {
+        spec.insert("tls".to_string(), Value::Array(tls));
+    }
+    spec.insert("rules".to_string(), Value::Array(rules));
+
+    json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "Ingress",
+        "metadata": metadata(&resource_name(&route_info.name), annotations, opts),
+        "spec": spec,
+    })
+}
+
+fn plugin_manifest(name: &str, plugin_info: &PluginInfo, global: bool, opts: &Options) -> Value {
+    let mut meta = metadata(name, SerdeMap::new(), opts);
+    let kind = if global {
+        // cluster scoped, picked up as a global plugin by its label
+        meta.remove("namespace");
+        meta.insert("labels".to_string(), json!({ "global": "true" }));
+        meta.insert(
+            "annotations".to_string(),
+            json!({ "kubernetes.io/ingress.class": opts.ingress_class }),
+        );
+        "KongClusterPlugin"
+    } else {
+        "KongPlugin"
+    };
+
+    let mut plugin = SerdeMap::new();
+    plugin.insert("apiVersion".to_string(), Value::String(CONFIGURATION_API.to_string()));
+    plugin.insert("kind".to_string(), Value::String(kind.to_string()));
+    plugin.insert("metadata".to_string(), Value::Object(meta));
+    plugin.insert("plugin".to_string(), Value::String(plugin_info.name.clone()));
+    if !plugin_info.enabled {
+        plugin.insert("disabled".to_string(), Value::Bool(true));
+    }
+    if !plugin_info.config.is_empty() {
+        plugin.insert("config".to_string(), json!(plugin_info.config));
+    }
+    Value::Object(plugin)
+}
+
+fn consumer_manifests(
+    conf: &KongConf,
+    guest_consumers: &[String],
+    opts: &Options,
+) -> Result<Vec<Value>, String> {
+    let mut consumers: Vec<ConsumerInfo> = conf.consumers.as_ref().map_or(Vec::new(), |c| c.clone());
+    for custom_id in guest_consumers {
+        if !consumers.iter().any(|c| c.username.as_ref() == Some(custom_id)) {
+            consumers.push(ConsumerInfo::guest(custom_id));
+        }
+    }
+
+    let mut credentials: Vec<Vec<String>> = vec![Vec::new(); consumers.len()];
+    let mut secrets = Vec::new();
+    for (idx, credential) in conf.credentials.as_ref().map_or(&[][..], |c| &c[..]).iter().enumerate() {
+        let owner = consumers
+            .iter()
+            .position(|c| c.is_target(&credential.target))
+            .ok_or_else(|| {
+                format!(
+                    "credential {} targets unknown consumer {}",
+                    credential.name, credential.target
+                )
+            })?;
+        let name = resource_name(&format!("{}-{}-{}", credential.target, credential.name, idx + 1));
+        secrets.push(credential_manifest(&name, credential, opts)?);
+        credentials[owner].push(name);
+    }
+
+    let mut manifests = Vec::new();
+    let mut consumer_names = Vec::new();
+    for (consumer, credentials) in consumers.iter().zip(credentials) {
+        let username = consumer
+            .name()
+            .ok_or_else(|| format!("consumer without username/custom_id: {:?}", consumer))?;
+        let mut annotations = SerdeMap::new();
+        annotations.insert(
+            "kubernetes.io/ingress.class".to_string(),
+            Value::String(opts.ingress_class.to_string()),
+        );
+        let mut manifest = SerdeMap::new();
+        manifest.insert("apiVersion".to_string(), Value::String(CONFIGURATION_API.to_string()));
+        manifest.insert("kind".to_string(), Value::String("KongConsumer".to_string()));
+        manifest.insert(
+            "metadata".to_string(),
+            Value::Object(metadata(
+                &unique_name(&mut consumer_names, resource_name(username)),
+                annotations,
+                opts,
+            )),
+        );
+        for (field, value) in &[("username", &consumer.username), ("custom_id", &consumer.custom_id)] {
+            if let Some(v) = value {
+                manifest.insert(field.to_string(), Value::String(v.clone()));
+            }
+        }
+        if !credentials.is_empty() {
+            manifest.insert("credentials".to_string(), json!(credentials));
+        }
+        manifests.push(Value::Object(manifest));
+    }
+    manifests.extend(secrets);
+    Ok(manifests)
+}
+
+fn credential_manifest(name: &str, credential: &CredentialsInfo, opts: &Options) -> Result<Value, String> {
+    let cred_type = match credential.name.as_str() {
+        "acls" | "acl" => "acl",
+        other if dbless::credential_field(other).is_some() => other,
+        other => return Err(format!("credential type {} is not supported", other)),
+    };
+    let mut data: SerdeMap<String, Value> = credential
+        .config
+        .iter()
+        .map(|(k, v)| (k.clone(), Value::String(string_value(v))))
+        .collect();
+    // `kongCredType` for controllers before 3.0, the label for later ones
+    data.insert("kongCredType".to_string(), Value::String(cred_type.to_string()));
+
+    let mut meta = metadata(name, SerdeMap::new(), opts);
+    meta.insert("labels".to_string(), json!({ "konghq.com/credential": cred_type }));
+    Ok(json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": meta,
+        "type": "Opaque",
+        "stringData": data,
+    }))
+}
+
+fn certificate_manifest(idx: usize, certificate: &CertificateInfo, opts: &Options) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": metadata(&certificate_name(idx, certificate), SerdeMap::new(), opts),
+        "type": "kubernetes.io/tls",
+        "stringData": {
+            "tls.crt": certificate.cert,
+            "tls.key": certificate.key,
+        },
+    })
+}
+
+fn certificate_name(idx: usize, certificate: &CertificateInfo) -> String {
+    match certificate.snis.first() {
+        Some(sni) => resource_name(&format!("tls-{}", sni)),
+        None => format!("tls-{}", idx + 1),
+    }
+}
+
+fn metadata(name: &str, annotations: SerdeMap<String, Value>, opts: &Options) -> SerdeMap<String, Value> {
+    let mut meta = SerdeMap::new();
+    meta.insert("name".to_string(), Value::String(name.to_string()));
+    if let Some(namespace) = opts.namespace {
+        meta.insert("namespace".to_string(), Value::String(namespace.to_string()));
+    }
+    if !annotations.is_empty() {
+        meta.insert("annotations".to_string(), Value::Object(annotations));
+    }
+    meta
+}
+
+/// annotations and secret data are strings, lists are joined by comma
+fn string_value(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(|i| i.as_str().map(String::from).unwrap_or_else(|| i.to_string()))
+            .collect::<Vec<String>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+/// kubernetes object names are lowercase RFC 1123 subdomains
+fn resource_name(name: &str) -> String {
+    let mut resource = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' {
+            resource.push(c.to_ascii_lowercase());
+        } else if !resource.ends_with('-') {
+            resource.push('-');
+        }
+    }
+    let resource = resource.trim_matches(|c| c == '-' || c == '.');
+    resource.chars().take(253).collect()
+}
+
+fn unique_name(taken: &mut Vec<String>, name: String) -> String {
+    let mut candidate = name.clone();
+    let mut n = 1;
+    while taken.contains(&candidate) {
+        n += 1;
+        candidate = format!("{}-{}", name, n);
+    }
+    taken.push(candidate.clone());
+    candidate
+}
@@ -0,0 +1,846 @@
+use http_backend;
+use glob::{glob, Pattern};
+use overlay;
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Url};
+use serde_json;
+use serde_yaml;
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use toml;
+
+const INCLUDE_KEY: &str = "include";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "yaml" | "yml" => Some(Format::Yaml),
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+
+    fn parse(self, contents: &str) -> Result<Value, String> {
+        match self {
+            Format::Yaml => serde_yaml::from_str::<Value>(contents).map_err(|e| e.to_string()),
+            Format::Json => serde_json::from_str::<Value>(contents).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_str::<Value>(contents).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Yaml => "yaml",
+            Format::Json => "json",
+            Format::Toml => "toml",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// where a config document is read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    File(PathBuf),
+    Stdin,
+    /// `https://host/kong.yaml`, optionally pinned with a `#sha256=<hex>` fragment
+    Http { url: Url, sha256: Option<String> },
+    /// `git+file:///repo#ref:path/kong.yaml`, read at the given commit of a local repository
+    Git { repo: PathBuf, rev: String, path: String },
+}
+
+impl Source {
+    pub fn parse(raw: &str) -> Result<Source, String> {
+        if raw == "-" {
+            Ok(Source::Stdin)
+        } else if raw.starts_with("http://") || raw.starts_with("https://") {
+            let mut url = Url::parse(raw).map_err(|why| format!("invalid url {}: {}", raw, why))?;
+            let sha256 = match url.fragment() {
+                None => None,
+                Some(fragment) => match fragment.strip_prefix("sha256=") {
+                    Some(hex) => Some(hex.to_lowercase()),
+                    None => {
+                        return Err(format!(
+                            "invalid url {}: unsupported fragment '{}', expected #sha256=<hex>",
+                            raw, fragment
+                        ))
+                    }
+                },
+            };
+            url.set_fragment(None);
+            Ok(Source::Http { url, sha256 })
+        } else if let Some(rest) = raw.strip_prefix("git+file://") {
+            let invalid = || {
+                format!(
+                    "invalid git source {}, expected git+file:///<repo>#<ref>:<path>",
+                    raw
+                )
+            };
+            let (repo, spec) = match rest.find('#') {
+                Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+                None => return Err(invalid()),
+            };
+            let (rev, path) = match spec.find(':') {
+                Some(idx) => (&spec[..idx], &spec[idx + 1..]),
+                None => return Err(invalid()),
+            };
+            if repo.is_empty() || rev.is_empty() || path.is_empty() {
+                return Err(invalid());
+            }
+            Ok(Source::Git {
+                repo: PathBuf::from(repo),
+                rev: rev.to_string(),
+                path: path.trim_start_matches('/').to_string(),
+            })
+        } else {
+            Ok(Source::File(PathBuf::from(raw)))
+        }
+    }
+
+    fn read(&self, http: &Client) -> Result<String, String> {
+        match self {
+            Source::File(path) => {
+                fs::read_to_string(path).map_err(|why| format!("{}: {}", self, why))
+            }
+            Source::Stdin => {
+                let mut contents = String::new();
+                io::stdin()
+                    .read_to_string(&mut contents)
+                    .map(|_| contents)
+                    .map_err(|why| format!("{}: {}", self, why))
+            }
+            Source::Http { url, sha256 } => {
+                let mut resp = http
+                    .get(url.clone())
+                    .send()
+                    .map_err(|why| format!("{}: {}", self, why))?;
+                if !resp.status().is_success() {
+                    return Err(format!("{}: unexpected status {}", self, resp.status()));
+                }
+                // the checksum is over the bytes as published, before any decoding
+                let mut body = Vec::new();
+                resp.copy_to(&mut body).map_err(|why| format!("{}: {}", self, why))?;
+                if let Some(expected) = sha256 {
+                    let actual = sha256_hex(&body);
+                    if &actual != expected {
+                        return Err(format!(
+                            "{}: checksum mismatch, expected sha256={} but got sha256={}",
+                            self, expected, actual
+                        ));
+                    }
+                }
+                String::from_utf8(body).map_err(|why| format!("{}: {}", self, why))
+            }
+            Source::Git { repo, rev, path } => {
+                let output = Command::new("git")
+                    .arg("-C")
+                    .arg(repo)
+                    .arg("show")
+                    .arg(format!("{}:{}", rev, path))
+                    .output()
+                    .map_err(|why| format!("{}: unable to run git: {}", self, why))?;
+                if !output.status.success() {
+                    return Err(format!(
+                        "{}: {}",
+                        self,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                String::from_utf8(output.stdout).map_err(|why| format!("{}: {}", self, why))
+            }
+        }
+    }
+
+    /// identity used for include cycle detection
+    fn canonical(&self) -> Result<Source, String> {
+        match self {
+            Source::File(path) => path
+                .canonicalize()
+                .map(Source::File)
+                .map_err(|why| format!("{}: {}", self, why)),
+            Source::Http { url, .. } => Ok(Source::Http {
+                url: url.clone(),
+                sha256: None,
+            }),
+            other => Ok(other.clone()),
+        }
+    }
+
+    fn format_hint(&self) -> Option<Format> {
+        let file_name = match self {
+            Source::File(path) => path.to_string_lossy().to_string(),
+            Source::Http { url, .. } => url.path().to_string(),
+            Source::Git { path, .. } => path.clone(),
+            Source::Stdin => return None,
+        };
+        Path::new(&file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Format::from_name)
+    }
+
+    /// resolve an include pattern relative to this source
+    fn resolve_include(&self, pattern: &str) -> Result<Vec<Source>, String> {
+        let base_dir = match self {
+            Source::File(path) => path.parent().unwrap_or_else(|| Path::new(".")).to_path_buf(),
+            Source::Stdin => PathBuf::from("."),
+            Source::Http { url, .. } => {
+                // no directory listing over http, patterns are taken literally
+                return url
+                    .join(pattern)
+                    .map(|url| vec![Source::Http { url, sha256: None }])
+                    .map_err(|why| format!("invalid include '{}': {}", pattern, why));
+            }
+            Source::Git { repo, rev, path } => {
+                return resolve_git_include(repo, rev, path, pattern);
+            }
+        };
+        let full_pattern = if Path::new(pattern).is_absolute() {
+            pattern.to_string()
+        } else {
+            format!(
+                "{}/{}",
+                Pattern::escape(&base_dir.to_string_lossy()),
+                pattern
+            )
+        };
+
+        let mut sources = Vec::new();
+        for entry in glob(&full_pattern)
+            .map_err(|why| format!("invalid include pattern '{}': {}", pattern, why))?
+        {
+            match entry {
+                Ok(p) => {
+                    if p.is_file() {
+                        sources.push(Source::File(p))
+                    }
+                }
+                Err(why) => return Err(format!("include '{}': {}", pattern, why)),
+            }
+        }
+        if sources.is_empty() {
+            return Err(format!("include '{}' matched no files", pattern));
+        }
+        Ok(sources)
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Stdin => write!(f, "<stdin>"),
+            Source::Http { url, .. } => write!(f, "{}", url),
+            Source::Git { repo, rev, path } => {
+                write!(f, "git+file://{}#{}:{}", repo.display(), rev, path)
+            }
+        }
+    }
+}
+
+/// match an include pattern against the files of `rev`, relative to the including file
+fn resolve_git_include(
+    repo: &Path,
+    rev: &str,
+    including: &str,
+    pattern: &str,
+) -> Result<Vec<Source>, String> {
+    let full_pattern = match Path::new(including).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() && !pattern.starts_with('/') => {
+            format!("{}/{}", Pattern::escape(&dir.to_string_lossy()), pattern)
+        }
+        _ => pattern.trim_start_matches('/').to_string(),
+    };
+    let matcher = Pattern::new(&normalize_path(&full_pattern))
+        .map_err(|why| format!("invalid include pattern '{}': {}", pattern, why))?;
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["ls-tree", "-r", "--name-only", rev])
+        .output()
+        .map_err(|why| format!("unable to run git: {}", why))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    let sources: Vec<Source> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|file| matcher.matches(file))
+        .map(|file| Source::Git {
+            repo: repo.to_path_buf(),
+            rev: rev.to_string(),
+            path: file.to_string(),
+        }).collect();
+    if sources.is_empty() {
+        return Err(format!("include '{}' matched no files", pattern));
+    }
+    Ok(sources)
+}
+
+/// resolve `.` and `..` components of a repository relative path
+fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            other => parts.push(other),
+        }
+    }
+    parts.join("/")
+}
+
+/// hex sha256 of a checksum pinned source, the fingerprints hash the same way
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// directories are expanded into their config files, sorted by file name
+pub fn expand_template_paths(tmpl_paths: &[&str]) -> Result<Vec<Source>, String> {
+    let mut sources = Vec::new();
+    for tmpl_path in tmpl_paths {
+        let path = Path::new(tmpl_path);
+        if !path.is_dir() {
+            sources.push(Source::parse(tmpl_path)?);
+            continue;
+        }
+        let entries = fs::read_dir(path)
+            .map_err(|why| format!("unable to read directory {}: {}", tmpl_path, why))?;
+        let mut conf_files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .and_then(|ext| ext.to_str())
+                    .and_then(Format::from_name)
+                    .is_some()
+            }).collect();
+        conf_files.sort();
+        sources.extend(conf_files.into_iter().map(Source::File));
+    }
+    if sources.iter().filter(|s| **s == Source::Stdin).count() > 1 {
+        return Err("stdin (`-`) can only be given once".to_string());
+    }
+    Ok(sources)
+}
+
+/// Reads config documents from their sources, resolving `include:` lists recursively.
+pub struct Loader<'a> {
+    /// overrides detection by file extension for top-level sources (yaml if unknown)
+    format: Option<Format>,
+    /// applied to the raw text of every document before parsing
+    replace: &'a dyn Fn(&str) -> String,
+    http: Client,
+    /// local files read so far, includes too
+    files: RefCell<Vec<PathBuf>>,
+}
+
+impl<'a> Loader<'a> {
+    pub fn new(
+        format: Option<Format>,
+        fetch_headers: &[&str],
+        replace: &'a dyn Fn(&str) -> String,
+    ) -> Loader<'a> {
+        let http = Client::builder()
+            .default_headers(if fetch_headers.is_empty() {
+                HeaderMap::new()
+            } else {
+                http_backend::parse_headers(fetch_headers)
+            }).build()
+            .unwrap();
+        Loader {
+            format,
+            replace,
+            http,
+            files: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Load one config document. Included files are merged in the listed order, the including
+    /// file is merged last so its own entries take precedence. Included files are always
+    /// detected by extension.
+    pub fn load(&self, source: &Source) -> Result<Value, String> {
+        let mut stack = Vec::new();
+        self.load_recursive(source, self.format, &mut stack)
+    }
+
+    /// the local files the documents loaded so far were read from
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files.borrow().clone()
+    }
+
+    fn load_recursive(
+        &self,
+        source: &Source,
+        format: Option<Format>,
+        stack: &mut Vec<Source>,
+    ) -> Result<Value, String> {
+        let canonical = source.canonical()?;
+        if let Source::File(path) = source {
+            self.files.borrow_mut().push(path.clone());
+        }
+        let original = source.read(&self.http)?;
+        let replaced = (self.replace)(&original);
+        let format = format
+            .or_else(|| source.format_hint())
+            .unwrap_or(Format::Yaml);
+
+        let mut doc = format
+            .parse(&replaced)
+            .map_err(|why| format!("{}: invalid {}: {}", source, format, why))?;
+
+        let includes = match doc.as_mapping_mut() {
+            Some(map) => map.remove(&Value::String(INCLUDE_KEY.to_string())),
+            None => None,
+        };
+        // positions are taken from the text as written, replacement may change or move lines
+        let lines = include_lines(&original);
+        let includes = match includes {
+            None | Some(Value::Null) => return Ok(doc),
+            Some(Value::Sequence(patterns)) => patterns,
+            Some(_) => {
+                return Err(format!(
+                    "{}:{}: `include` must be a list of file patterns",
+                    source, lines.key
+                ))
+            }
+        };
+
+        stack.push(canonical);
+        let mut merged = Value::Null;
+
+        for (idx, pattern) in includes.into_iter().enumerate() {
+            let origin = format!("{}:{}", source, lines.entry(idx));
+            let pattern = match pattern.as_str() {
+                Some(p) => p.to_string(),
+                None => {
+                    return Err(format!(
+                        "{}: include entries must be strings, got {:?}",
+                        origin, pattern
+                    ))
+                }
+            };
+
+            for included in source
+                .resolve_include(&pattern)
+                .map_err(|why| format!("{}: {}", origin, why))?
+            {
+                let included_canonical = included.canonical()?;
+                if stack.contains(&included_canonical) {
+                    let chain: Vec<String> = stack
+                        .iter()
+                        .chain(Some(&included_canonical))
+                        .map(|s| s.to_string())
+                        .collect();
+                    return Err(format!(
+                        "{}: include cycle detected: {}",
+                        origin,
+                        chain.join(" -> ")
+                    ));
+                }
+                debug!("{} includes {}", origin, included);
+                let included_doc = self
+                    .load_recursive(&included, None, stack)
+                    .map_err(|why| format!("{}\n  included from {}", why, origin))?;
+                overlay::merge_document(&mut merged, included_doc);
+            }
+        }
+        stack.pop();
+
+        overlay::merge_document(&mut merged, doc);
+        Ok(merged)
+    }
+}
+
+/// 1-based lines of the `include` key and of its entries, 0 when not found
+struct IncludeLines {
+    key: usize,
+    entries: Vec<usize>,
+}
+
+impl IncludeLines {
+    /// the line of an entry, the key's if the text could not be followed(e.g. a list from an env var)
+    fn entry(&self, idx: usize) -> usize {
+        self.entries.get(idx).cloned().unwrap_or(self.key)
+    }
+}
+
+/// Locate the top-level `include` list in a yaml, json or toml text: a block list(`- a`) or
+/// a flow one(`[a, b]`), possibly spanning several lines.
+fn include_lines(text: &str) -> IncludeLines {
+    let key_re = Regex::new(r#"(?m)^([ \t]*)"?include"?[ \t]*[:=]"#).unwrap();
+    let key = match key_re.captures_iter(text).min_by_key(|caps| caps[1].len()) {
+        Some(caps) => caps.get(0).unwrap(),
+        None => {
+            return IncludeLines {
+                key: 0,
+                entries: Vec::new(),
+            }
+        }
+    };
+    let bytes = text.as_bytes();
+    let line_at = |pos: usize| text[..pos].matches('\n').count() + 1;
+    let skip_blanks = |mut pos: usize| loop {
+        match bytes.get(pos) {
+            Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') => pos += 1,
+            Some(b'#') => {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            _ => return pos,
+        }
+    };
+
+    let mut entries = Vec::new();
+    let mut pos = skip_blanks(key.end());
+    match bytes.get(pos) {
+        Some(b'[') => {
+            // entries start after the `[` and after every `,` outside quotes and nested lists
+            pos = skip_blanks(pos + 1);
+            if bytes.get(pos) != Some(&b']') {
+                entries.push(line_at(pos));
+            }
+            let (mut quote, mut depth) = (None, 0);
+            while let Some(&b) = bytes.get(pos) {
+                match (quote, b) {
+                    (Some(q), _) if b == q => quote = None,
+                    (Some(b'"'), b'\\') => pos += 1,
+                    (Some(_), _) => {}
+                    (None, b'"') | (None, b'\'') => quote = Some(b),
+                    (None, b'[') | (None, b'{') => depth += 1,
+                    (None, b']') | (None, b'}') if depth == 0 => break,
+                    (None, b']') | (None, b'}') => depth -= 1,
+                    (None, b',') if depth == 0 => {
+                        pos = skip_blanks(pos + 1);
+                        if bytes.get(pos) != Some(&b']') {
+                            entries.push(line_at(pos));
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+                pos += 1;
+            }
+        }
+        Some(b'-') => {
+            // the dashes at the indentation of the first one, until a line indented less
+            let line_start = text[..pos].rfind('\n').map_or(0, |idx| idx + 1);
+            let indent = pos - line_start;
+            let first = line_at(pos);
+            for (offset, line) in text[line_start..].lines().enumerate() {
+                let trimmed = line.trim_start();
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    continue;
+                }
+                let line_indent = line.len() - trimmed.len();
+                if line_indent == indent && trimmed.starts_with('-') {
+                    entries.push(first + offset);
+                } else if line_indent <= indent {
+                    break;
+                }
+            }
+        }
+        _ => {}
+    }
+    IncludeLines {
+        key: line_at(key.start()),
+        entries,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// an empty directory for the files of one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("kong-init-loader-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn yaml(text: &str) -> Value {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    fn load_with(path: &Path, replace: &dyn Fn(&str) -> String) -> Result<Value, String> {
+        Loader::new(None, &[], replace).load(&Source::File(path.to_path_buf()))
+    }
+
+    fn load(path: &Path) -> Result<Value, String> {
+        load_with(path, &|text| text.to_string())
+    }
+
+    #[test]
+    fn nested_includes_are_merged_before_the_including_file() {
+        let dir = temp_dir("nested");
+        fs::create_dir_all(dir.join("services")).unwrap();
+        fs::write(
+            dir.join("kong.yaml"),
+            "include:\n  - services/*.yaml\nservices:\n  - name: orders\n    retries: 1\n",
+        ).unwrap();
+        fs::write(
+            dir.join("services/orders.yaml"),
+            "include: [../common.yaml]\nservices:\n  - name: orders\n    url: http://orders\n    retries: 5\n",
+        ).unwrap();
+        fs::write(dir.join("services/billing.yaml"), "services:\n  - name: billing\n    url: http://billing\n").unwrap();
+        fs::write(dir.join("common.yaml"), "plugins:\n  - name: prometheus\n    target: global\n").unwrap();
+
+        let loader = Loader::new(None, &[], &|text: &str| text.to_string());
+        let doc = loader.load(&Source::File(dir.join("kong.yaml"))).unwrap();
+        // glob matches come in file name order
+        assert_eq!(
+            doc,
+            yaml(
+                "plugins:
+  - name: prometheus
+    target: global
+services:
+  - name: billing
+    url: http://billing
+  - name: orders
+    url: http://orders
+    retries: 1
+"
+            )
+        );
+        assert_eq!(
+            loader.files(),
+            vec![
+                dir.join("kong.yaml"),
+                dir.join("services/billing.yaml"),
+                dir.join("services/orders.yaml"),
+                dir.join("services/../common.yaml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_matching_nothing_are_reported_at_their_entry() {
+        let dir = temp_dir("unmatched");
+        fs::write(
+            dir.join("kong.yaml"),
+            "# services/*.yaml is where services go\nservices: []\ninclude:\n  - common.yaml\n  - services/*.yaml\n",
+        ).unwrap();
+        fs::write(dir.join("common.yaml"), "plugins: []\n").unwrap();
+
+        let why = load(&dir.join("kong.yaml")).unwrap_err();
+        assert_eq!(
+            why,
+            format!("{}:5: include 'services/*.yaml' matched no files", dir.join("kong.yaml").display())
+        );
+    }
+
+    #[test]
+    fn origins_are_lines_as_written() {
+        let dir = temp_dir("origin");
+        // the replacement changes the entry and adds lines above it
+        fs::write(
+            dir.join("kong.yaml"),
+            "# ${BANNER}\ninclude: [\n  common.yaml,\n  ${MISSING}.yaml\n]\n",
+        ).unwrap();
+        fs::write(dir.join("common.yaml"), "plugins: []\n").unwrap();
+        let replace = |text: &str| text.replace("${BANNER}", "one\n# two\n# three").replace("${MISSING}", "missing");
+
+        let why = load_with(&dir.join("kong.yaml"), &replace).unwrap_err();
+        assert_eq!(
+            why,
+            format!("{}:4: include 'missing.yaml' matched no files", dir.join("kong.yaml").display())
+        );
+
+        fs::write(dir.join("kong.json"), "{\n  \"services\": [],\n  \"include\": [\n    \"common.yaml\",\n    42\n  ]\n}\n").unwrap();
+        let why = load(&dir.join("kong.json")).unwrap_err();
+        assert!(why.starts_with(&format!("{}:5: include entries must be strings", dir.join("kong.json").display())), "{}", why);
+    }
+
+    #[test]
+    fn formats_are_detected_by_extension_or_given() {
+        let dir = temp_dir("formats");
+        fs::write(dir.join("kong.yaml"), "services:\n  - name: orders\n    retries: 5\n").unwrap();
+        fs::write(dir.join("kong.json"), "{\"services\": [{\"name\": \"orders\", \"retries\": 5}]}").unwrap();
+        fs::write(dir.join("kong.toml"), "[[services]]\nname = \"orders\"\nretries = 5\n").unwrap();
+        fs::write(dir.join("kong.conf"), "[[services]]\nname = \"orders\"\nretries = 5\n").unwrap();
+
+        let expected = load(&dir.join("kong.yaml")).unwrap();
+        assert_eq!(load(&dir.join("kong.json")).unwrap(), expected);
+        assert_eq!(load(&dir.join("kong.toml")).unwrap(), expected);
+        let same = |text: &str| text.to_string();
+        let conf = Source::File(dir.join("kong.conf"));
+        assert_eq!(Loader::new(Some(Format::Toml), &[], &same).load(&conf).unwrap(), expected);
+        assert!(Loader::new(None, &[], &same).load(&conf).is_err());
+    }
+
+    #[test]
+    fn stdin_is_given_once() {
+        assert_eq!(expand_template_paths(&["-"]).unwrap(), vec![Source::Stdin]);
+        assert_eq!(
+            expand_template_paths(&["-", "kong.yaml", "-"]).unwrap_err(),
+            "stdin (`-`) can only be given once"
+        );
+    }
+
+    #[test]
+    fn include_cycles_are_reported_with_the_chain() {
+        let dir = temp_dir("cycle");
+        fs::write(dir.join("a.yaml"), "include:\n  - b.yaml\n").unwrap();
+        fs::write(dir.join("b.yaml"), "services: []\ninclude:\n  - a.yaml\n").unwrap();
+
+        let why = load(&dir.join("a.yaml")).unwrap_err();
+        let a = dir.join("a.yaml").canonicalize().unwrap();
+        let b = dir.join("b.yaml").canonicalize().unwrap();
+        assert_eq!(
+            why,
+            format!(
+                "{}:3: include cycle detected: {} -> {} -> {}\n  included from {}:2",
+                dir.join("b.yaml").display(),
+                a.display(),
+                b.display(),
+                a.display(),
+                dir.join("a.yaml").display()
+            )
+        );
+    }
+
+    /// answer every request with `body`, keeping the request heads
+    fn serve(body: &'static str, content_type: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(&stream);
+                let mut head = String::new();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                    head.push_str(&line);
+                    line.clear();
+                }
+                seen.lock().unwrap().push(head.to_lowercase());
+                let mut stream = &stream;
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    content_type,
+                    body.len(),
+                    body
+                );
+            }
+        });
+        (url, requests)
+    }
+
+    const REMOTE: &str = "# propriété de l'équipe api\nservices:\n  - name: orders\n    url: http://orders\n";
+
+    fn fetch(raw: &str, headers: &[&str]) -> Result<Value, String> {
+        Loader::new(None, headers, &|text: &str| text.to_string()).load(&Source::parse(raw)?)
+    }
+
+    #[test]
+    fn http_sources_are_fetched_with_the_path_headers() {
+        let (url, requests) = serve(REMOTE, "application/x-yaml");
+        let doc = fetch(&format!("{}/kong.yaml", url), &["Authorization:Bearer t0ken", "X-Team:api"]).unwrap();
+        assert_eq!(doc, yaml(REMOTE));
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("get /kong.yaml http/1.1"), "{}", requests[0]);
+        assert!(requests[0].contains("authorization: bearer t0ken\r\n"), "{}", requests[0]);
+        assert!(requests[0].contains("x-team: api\r\n"), "{}", requests[0]);
+    }
+
+    #[test]
+    fn checksums_are_over_the_published_bytes() {
+        // a charset the body is not in: decoding it would change the bytes hashed
+        let (url, _) = serve(REMOTE, "text/plain; charset=iso-8859-1");
+        let published = sha256_hex(REMOTE.as_bytes());
+
+        let doc = fetch(&format!("{}/kong.yaml#sha256={}", url, published.to_uppercase()), &[]).unwrap();
+        assert_eq!(doc, yaml(REMOTE));
+
+        let tampered = sha256_hex(b"services: []\n");
+        let why = fetch(&format!("{}/kong.yaml#sha256={}", url, tampered), &[]).unwrap_err();
+        assert_eq!(
+            why,
+            format!(
+                "{}/kong.yaml: checksum mismatch, expected sha256={} but got sha256={}",
+                url, tampered, published
+            )
+        );
+    }
+
+    fn git(repo: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["-c", "user.name=kong-init", "-c", "user.email=kong-init@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    fn commit(repo: &Path, url: &str) {
+        fs::write(repo.join("services/orders.yaml"), format!("services:\n  - name: orders\n    url: {}\n", url)).unwrap();
+        git(repo, &["add", "-A"]);
+        git(repo, &["commit", "-q", "-m", url]);
+    }
+
+    #[test]
+    fn git_sources_are_read_at_a_branch_tag_or_commit() {
+        let repo = temp_dir("git");
+        git(&repo, &["init", "-q"]);
+        git(&repo, &["checkout", "-q", "-b", "main"]);
+        fs::create_dir_all(repo.join("services")).unwrap();
+        fs::write(repo.join("kong.yaml"), "include:\n  - services/*.yaml\n").unwrap();
+        commit(&repo, "http://orders-v1");
+        git(&repo, &["tag", "v1"]);
+        let first = git(&repo, &["rev-parse", "HEAD"]);
+        commit(&repo, "http://orders-v2");
+        git(&repo, &["checkout", "-q", "-b", "canary"]);
+        commit(&repo, "http://orders-v3");
+        // the working tree is not what is read
+        fs::write(repo.join("services/orders.yaml"), "services: [").unwrap();
+
+        let url_at = |rev: &str| {
+            let doc = fetch(&format!("git+file://{}#{}:kong.yaml", repo.display(), rev), &[]).unwrap();
+            doc["services"][0]["url"].as_str().unwrap().to_string()
+        };
+        assert_eq!(url_at("main"), "http://orders-v2");
+        assert_eq!(url_at("canary"), "http://orders-v3");
+        assert_eq!(url_at("v1"), "http://orders-v1");
+        assert_eq!(url_at(&first), "http://orders-v1");
+        assert_eq!(url_at(&first[..8]), "http://orders-v1");
+
+        let why = fetch(&format!("git+file://{}#v9:kong.yaml", repo.display()), &[]).unwrap_err();
+        assert!(why.starts_with(&format!("git+file://{}#v9:kong.yaml: ", repo.display())), "{}", why);
+    }
+}
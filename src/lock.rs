@@ -0,0 +1,129 @@
+//! Advisory lock keeping kong-init runs against the same kong from applying at the same time.
+//!
+//! The lock is the `kong-init-lock` consumer, its `custom_id` tells who holds it and until when:
+//! `<host>:<pid>@<unix time>`. Creating it takes the lock, kong refuses a second one. A lock
+//! whose lease expired(its holder died) is deleted by id and taken over, two runs doing so at
+//! once delete the same one and only one of them creates the next. The holder renews its lease
+//! every third of `--lock-ttl` while it applies, so only a dead holder's lock expires.
+use client::KongApiClient;
+use std::env;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// consumer standing for the lock
+pub const LOCK: &str = "kong-init-lock";
+
+/// time between two attempts while the lock is held by another run
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// time between two looks at the stop flag of the renewal
+const RENEW_POLL: Duration = Duration::from_millis(100);
+
+/// the lock this run holds
+struct Held {
+    cli: Arc<KongApiClient>,
+    id: String,
+    /// raised to stop the renewal of the lease
+    stop: Arc<AtomicBool>,
+    renewal: JoinHandle<()>,
+}
+
+static HELD: Mutex<Option<Held>> = Mutex::new(None);
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+/// who runs, as told to the other runs waiting for the lock
+fn holder() -> String {
+    let host = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    format!("{}:{}", host, process::id())
+}
+
+/// the holder of a lock and the end of its lease
+fn parse(custom_id: &str) -> (&str, u64) {
+    match custom_id.rsplit_once('@') {
+        Some((holder, expires)) => (holder, expires.parse().unwrap_or_default()),
+        None => (custom_id, 0),
+    }
+}
+
+/// Take the lock for `ttl`, waiting up to `wait` for the run holding it to release it.
+pub fn acquire(cli: &Arc<KongApiClient>, ttl: Duration, wait: Duration) -> Result<(), String> {
+    let me = holder();
+    let deadline = Instant::now() + wait;
+    let mut waiting_for = String::new();
+    loop {
+        let lease = format!("{}@{}", me, unix_now() + ttl.as_secs());
+        if let Some(id) = cli.create_reserved_consumer(LOCK, &lease)? {
+            info!("acquired the lock as {} for {}s", me, ttl.as_secs());
+            let stop = Arc::new(AtomicBool::new(false));
+            let renewal = renew(cli.clone(), id.clone(), me, ttl, stop.clone());
+            *HELD.lock().unwrap() = Some(Held { cli: cli.clone(), id, stop, renewal });
+            return Ok(());
+        }
+
+        let lock = match cli.get_reserved_consumer(LOCK)? {
+            Some(lock) => lock,
+            // released in between
+            None => continue,
+        };
+        let (other, expires) = parse(lock["custom_id"].as_str().unwrap_or_default());
+        if expires <= unix_now() {
+            warn!("the lock of {} expired, taking it over", other);
+            cli.delete_reserved_consumer(lock["id"].as_str().unwrap_or_default())?;
+            continue;
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "held by {} for {}s more(see --lock-wait)",
+                other,
+                expires.saturating_sub(unix_now())
+            ));
+        }
+        if waiting_for != other {
+            info!("waiting for the lock held by {}...", other);
+            waiting_for = other.to_string();
+        }
+        sleep(RETRY_INTERVAL);
+    }
+}
+
+/// Push the end of the lease forward every third of `ttl` until `stop` is raised, a run longer
+/// than its lease keeps the lock.
+fn renew(cli: Arc<KongApiClient>, id: String, me: String, ttl: Duration, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+    let interval = (ttl / 3).max(RENEW_POLL);
+    thread::spawn(move || {
+        let mut renewed = Instant::now();
+        while !stop.load(Ordering::SeqCst) {
+            sleep(RENEW_POLL);
+            if renewed.elapsed() < interval || stop.load(Ordering::SeqCst) {
+                continue;
+            }
+            let lease = format!("{}@{}", me, unix_now() + ttl.as_secs());
+            match cli.update_reserved_consumer(&id, &lease) {
+                Ok(()) => debug!("renewed the lock until {}", lease),
+                Err(why) => warn!("unable to renew the lock: {}", why),
+            }
+            renewed = Instant::now();
+        }
+    })
+}
+
+/// Release the lock if this run holds it, it is left to expire when kong cannot be reached.
+pub fn release() {
+    if let Some(held) = HELD.lock().unwrap().take() {
+        held.stop.store(true, Ordering::SeqCst);
+        let _ = held.renewal.join();
+        match held.cli.delete_reserved_consumer(&held.id) {
+            Ok(()) => info!("released the lock"),
+            Err(why) => warn!("unable to release the lock, it expires with its lease: {}", why),
+        }
+    }
+}
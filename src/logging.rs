@@ -0,0 +1,86 @@
+use log::{self, Level, Log, Metadata, Record};
+use pretty_env_logger;
+use std::cell::RefCell;
+use std::env;
+
+thread_local! {
+    /// records held back on this thread, while a `capture` runs
+    static HELD: RefCell<Option<Vec<Held>>> = const { RefCell::new(None) };
+}
+
+/// A record logged while captured, written later by `replay`.
+pub struct Held {
+    level: Level,
+    target: String,
+    message: String,
+}
+
+/// pretty_env_logger, except for the threads capturing what they log
+struct HoldingLogger {
+    inner: Box<dyn Log>,
+}
+
+impl Log for HoldingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let held = HELD.with(|held| match held.borrow_mut().as_mut() {
+            Some(held) => {
+                held.push(Held {
+                    level: record.level(),
+                    target: record.target().to_string(),
+                    message: record.args().to_string(),
+                });
+                true
+            }
+            None => false,
+        });
+        if !held {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// the pretty_env_logger configured by `env_var`, able to hold records back
+pub fn init(env_var: &str) {
+    let mut builder = pretty_env_logger::formatted_builder().unwrap();
+    if let Ok(filters) = env::var(env_var) {
+        builder.parse(&filters);
+    }
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(HoldingLogger {
+        inner: Box::new(logger),
+    })).unwrap();
+}
+
+/// Run `task`, holding back what it logs on this thread instead of writing it. Concurrent
+/// tasks `replay` their records afterwards, in an order that does not depend on timing.
+pub fn capture<T, F: FnOnce() -> T>(task: F) -> (T, Vec<Held>) {
+    let outer = HELD.with(|held| held.borrow_mut().replace(Vec::new()));
+    let result = task();
+    let records = HELD.with(|held| std::mem::replace(&mut *held.borrow_mut(), outer));
+    (result, records.unwrap_or_default())
+}
+
+/// write records held back by `capture`
+pub fn replay(records: Vec<Held>) {
+    for held in records {
+        log::logger().log(
+            &Record::builder()
+                .level(held.level)
+                .target(&held.target)
+                .args(format_args!("{}", held.message))
+                .build(),
+        );
+    }
+}
@@ -1,10 +1,14 @@
 #![allow(dead_code)]
 
 extern crate clap;
+extern crate csv;
+extern crate futures;
+extern crate glob;
 extern crate http;
 #[macro_use]
 extern crate log;
 extern crate pretty_env_logger;
+extern crate rand;
 extern crate regex;
 extern crate reqwest;
 extern crate semver;
@@ -14,27 +18,71 @@ extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 extern crate serde_yaml;
+extern crate sha1;
+extern crate sha2;
+extern crate signal_hook;
+extern crate tokio;
+extern crate toml;
+extern crate uuid;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use backend::KongBackend;
 use client::KongApiClient;
+use dbless::GuestConsumers;
+use file_backend::FileBackend;
+use graph::Graph;
+use http_backend::HttpBackend;
+use memory_backend::MemoryBackend;
 use entity::{
-    ApiInfo, ConfFileStyle, ConsumerInfo, CredentialsInfo, KongConf, LegacyKongConf,
-    LegacyPluginAppliedType, LegacyPluginInfo, PluginInfo, PluginTarget, RouteInfo, ServiceInfo,
+    parse_target, ApiInfo, CertificateInfo, ConfFileStyle, ConsumerInfo, CredentialsInfo,
+    KongConf, LegacyKongConf, LegacyPluginAppliedType, LegacyPluginInfo, PluginInfo, PluginTarget,
+    RouteInfo, ServiceInfo, Target, UpstreamInfo,
 };
+use loader::{Format, Loader};
+use state::State;
 use regex::Regex;
+use reqwest::Url;
 use semver::Version;
 use serde_yaml::Error;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
 use std::iter::FromIterator;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
 use std::time::Duration;
 use serde_json::Value;
+use serde_yaml::Value as YamlValue;
 
+mod backend;
+mod bulk;
+mod cassette;
 mod client;
+mod consumer;
+mod convert;
+mod credential;
+mod daemon;
+mod dbless;
+mod diff;
+mod deck;
 mod entity;
+mod file_backend;
+mod fingerprint;
+mod graph;
+mod http_backend;
+mod k8s;
+mod loader;
+mod lock;
+mod logging;
+mod memory_backend;
+mod openapi;
+mod overlay;
+mod state;
+mod terraform;
+mod watch;
 
 fn main() {
     let logger_key = "RUST_LOG";
@@ -46,162 +94,1066 @@ fn main() {
         }
     }
 
-    pretty_env_logger::init();
+    logging::init(logger_key);
 
     let matches = App::new("kong-init")
         .version("0.8.0-rc-6")
         .about("")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .args(&template_args())
+        .args(&kong_args())
         .arg(
-            Arg::with_name("path")
-                .required(true)
-                .short("p")
-                .long("path")
+            Arg::with_name("concurrency")
+                .long("concurrency")
                 .takes_value(true)
-                .help("path to route defination file"),
+                .default_value("4")
+                .help("number of admin api operations(and consumers from `consumers_from` files) applied at the same time"),
         ).arg(
-            Arg::with_name("admin-url")
-                .required(true)
-                .long("url")
+            Arg::with_name("force")
+                .long("force")
+                .help("apply even when the config and kong are unchanged since the last run"),
+        ).arg(
+            Arg::with_name("lock-wait")
+                .long("lock-wait")
+                .takes_value(true)
+                .default_value("120")
+                .help("seconds to wait for another kong-init run on the same kong to release its lock"),
+        ).arg(
+            Arg::with_name("lock-ttl")
+                .long("lock-ttl")
                 .takes_value(true)
-                .help("admin url of kong-server(e.g. http://kong_ip:8001)"),
+                .default_value("300")
+                .help("seconds of the lease of the lock, renewed while the run applies, after which a run that died holding it is taken over"),
         ).arg(
-            Arg::with_name("header")
-                .long("header")
-                .multiple(true)
+            Arg::with_name("secrets-output")
+                .long("secrets-output")
                 .takes_value(true)
-                .help("add custom header for admin-api request"),
+                .requires("state")
+                .help("write the generated credential secrets as json to this file(mode 0600), `-` for stdout"),
+        ).arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .default_value("-")
+                .help("declarative config written by the file backend(mode 0600), `-` for stdout"),
+        ).arg(
+            Arg::with_name("dbless")
+                .long("dbless")
+                .help("push the whole configuration to `/config` of a DB-less kong node(>= 1.1) instead of issuing per-entity calls"),
         ).arg(
-            Arg::with_name("wait")
-                .long("wait")
-                .short("w")
-                .help("wait until kong-server is ready(suit for init under cloud environment)"),
+            Arg::with_name("daemon")
+                .long("daemon")
+                .conflicts_with_all(&["dbless", "watch"])
+                .help("keep running, comparing kong with the definitions every --interval seconds and reporting the drift, until SIGTERM"),
+        ).arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .takes_value(true)
+                .default_value("60")
+                .help("seconds between two drift checks of --daemon"),
+        ).arg(
+            Arg::with_name("heal")
+                .long("heal")
+                .requires("daemon")
+                .help("apply the changes bringing kong back to the definitions when --daemon finds drift"),
+        ).arg(
+            Arg::with_name("status-addr")
+                .long("status-addr")
+                .takes_value(true)
+                .requires("daemon")
+                .help("address(e.g. 127.0.0.1:9542) serving the drift found by --daemon on /status(json) and /metrics(prometheus)"),
+        ).arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .conflicts_with("dbless")
+                .help("keep running, applying only what changed in kong whenever the definition files(included and referenced ones too) change"),
+        ).subcommand(
+            SubCommand::with_name("check")
+                .about("compare kong with the definition files without changing it, exit code 0 when in sync, 2 on drift and 1 on errors")
+                .args(&template_args())
+                .args(&kong_args()),
+        ).subcommand(
+            SubCommand::with_name("render")
+                .about("render the definition files into another format without touching kong")
+                .args(&template_args())
+                .arg(
+                    Arg::with_name("target")
+                        .long("target")
+                        .short("t")
+                        .required(true)
+                        .takes_value(true)
+                        .possible_values(&["dbless", "deck", "k8s", "terraform"])
+                        .help("output format, `dbless` is kong's declarative config(kong.yml), `deck` a decK state file, `k8s` manifests for the Kong Ingress Controller, `terraform` HCL for the kevholditch/kong provider"),
+                ).arg(
+                    Arg::with_name("namespace")
+                        .long("namespace")
+                        .takes_value(true)
+                        .help("namespace of the rendered k8s manifests"),
+                ).arg(
+                    Arg::with_name("ingress-class")
+                        .long("ingress-class")
+                        .takes_value(true)
+                        .default_value("kong")
+                        .help("ingress class of the rendered k8s manifests"),
+                ).arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("file to write to, stdout if omitted"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("convert")
+                .about("convert API style definition files(`apis`) into services/routes style")
+                .args(&template_args())
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("file to write to, stdout if omitted"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("import-openapi")
+                .about("generate services/routes/plugins from an OpenAPI 3 spec(yaml/json)")
+                .arg(
+                    Arg::with_name("spec")
+                        .long("spec")
+                        .short("s")
+                        .required(true)
+                        .takes_value(true)
+                        .help("OpenAPI 3 spec to import"),
+                ).arg(
+                    Arg::with_name("service-name")
+                        .long("service-name")
+                        .takes_value(true)
+                        .help("name of the generated service, defaults to `x-kong-name` or the slugified info.title"),
+                ).arg(
+                    Arg::with_name("merge")
+                        .long("merge")
+                        .short("m")
+                        .takes_value(true)
+                        .help("existing definition file the generated entities are merged into"),
+                ).arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("file to write to, stdout if omitted"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("import-deck")
+                .about("convert a decK state file(yaml/json) into a kong-init definition file")
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .short("i")
+                        .required(true)
+                        .takes_value(true)
+                        .help("decK state file to convert"),
+                ).arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("file to write to, stdout if omitted"),
+                ),
         ).get_matches();
 
-    let tmpl_path = matches.value_of("path").unwrap();
-    let admin_url = matches.value_of("admin-url").unwrap();
+    match matches.subcommand() {
+        ("check", Some(sub_matches)) => {
+            let tmpl_opts = TemplateOpts::from_matches(sub_matches);
+            let backend = build_backend(sub_matches);
+            let run_opts = RunOpts {
+                is_wait: sub_matches.is_present("wait"),
+                is_dbless: false,
+                is_prune: sub_matches.is_present("prune"),
+                is_force: false,
+                fingerprint: false,
+                lock: None,
+                state_path: sub_matches.value_of("state"),
+                secrets_output: None,
+                concurrency: 1,
+            };
+            check(&tmpl_opts, backend, &run_opts);
+        }
+        ("render", Some(sub_matches)) => {
+            let tmpl_opts = TemplateOpts::from_matches(sub_matches);
+            let target = sub_matches.value_of("target").unwrap();
+            let k8s_opts = k8s::Options {
+                namespace: sub_matches.value_of("namespace"),
+                ingress_class: sub_matches.value_of("ingress-class").unwrap(),
+            };
+            if let Err(why) = render(&tmpl_opts, target, &k8s_opts, sub_matches.value_of("output")) {
+                error!("unable to render: {}", why);
+                exit(1)
+            }
+            return;
+        }
+        ("convert", Some(sub_matches)) => {
+            let tmpl_opts = TemplateOpts::from_matches(sub_matches);
+            if let Err(why) = convert_legacy(&tmpl_opts, sub_matches.value_of("output")) {
+                error!("unable to convert: {}", why);
+                exit(1)
+            }
+            return;
+        }
+        ("import-openapi", Some(sub_matches)) => {
+            let spec = sub_matches.value_of("spec").unwrap();
+            if let Err(why) = import_openapi(
+                spec,
+                sub_matches.value_of("service-name"),
+                sub_matches.value_of("merge"),
+                sub_matches.value_of("output"),
+            ) {
+                error!("unable to import OpenAPI spec: {}", why);
+                exit(1)
+            }
+            return;
+        }
+        ("import-deck", Some(sub_matches)) => {
+            let input = sub_matches.value_of("input").unwrap();
+            if let Err(why) = import_deck(input, sub_matches.value_of("output")) {
+                error!("unable to import decK state: {}", why);
+                exit(1)
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let tmpl_opts = TemplateOpts::from_matches(&matches);
+    let backend = build_backend(&matches);
 
-    let custom_headers_opt: Option<Vec<&str>> =
-        matches.values_of("header").map(|values| values.collect());
     info!("Start serving KongInit...");
-    info!("Connecting to Kong on {} using {}", admin_url, tmpl_path);
+    info!(
+        "Connecting to Kong on {} using {}",
+        backend.describe(),
+        tmpl_opts.paths.join(", ")
+    );
+
+    let concurrency = matches
+        .value_of("concurrency")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|why| {
+            error!("invalid --concurrency: {}", why);
+            exit(1)
+        });
+    let seconds = |name: &str| {
+        let raw = matches.value_of(name).unwrap();
+        raw.parse::<u64>().map(Duration::from_secs).unwrap_or_else(|why| {
+            error!("invalid --{}: {}", name, why);
+            exit(1)
+        })
+    };
+    // no other run to wait for in a recording or a dry run
+    let is_locked = matches.value_of("backend") == Some("http")
+        && !matches.is_present("record")
+        && !matches.is_present("replay");
+    let lock = if is_locked {
+        Some((seconds("lock-ttl"), seconds("lock-wait")))
+    } else {
+        None
+    };
+    let run_opts = RunOpts {
+        is_wait: matches.is_present("wait"),
+        is_dbless: matches.is_present("dbless"),
+        is_prune: matches.is_present("prune"),
+        is_force: matches.is_present("force"),
+        fingerprint: matches.value_of("backend") == Some("http"),
+        lock,
+        state_path: matches.value_of("state"),
+        secrets_output: matches.value_of("secrets-output"),
+        concurrency,
+    };
 
-    let is_wait = matches.is_present("wait");
+    if matches.is_present("watch") {
+        watch_and_apply(&tmpl_opts, backend, &run_opts);
+    }
+    if matches.is_present("daemon") {
+        let daemon_opts = DaemonOpts {
+            interval: seconds("interval"),
+            is_heal: matches.is_present("heal"),
+            status_addr: matches.value_of("status-addr"),
+        };
+        run_daemon(&tmpl_opts, backend, &run_opts, &daemon_opts);
+    }
 
-    if let Err(_e) = runc(tmpl_path, admin_url, custom_headers_opt, is_wait) {
+    if let Err(_e) = runc(&tmpl_opts, backend, &run_opts) {
         //        error!("unable to init kong: {}", _e);
-        std::process::exit(1)
+        exit(1)
+    }
+}
+
+/// the backend selected by `--backend`, where the run applies the definitions
+fn build_backend(matches: &ArgMatches) -> Box<dyn KongBackend> {
+    let cassette = matches
+        .value_of("record")
+        .map(cassette::Mode::Record)
+        .or_else(|| matches.value_of("replay").map(cassette::Mode::Replay));
+    let kong_version = matches.value_of("kong-version").unwrap();
+    let backend = matches.value_of("backend").unwrap();
+    if backend != "http" && cassette.is_some() {
+        error!("--record and --replay need the http backend, not {}", backend);
+        exit(1)
+    }
+    let rate_limit = matches.value_of("rate-limit").map(|raw| match raw.parse::<f64>() {
+        Ok(per_second) if per_second > 0.0 && per_second.is_finite() => per_second,
+        _ => {
+            error!("--rate-limit must be a positive number of requests per second: {}", raw);
+            exit(1)
+        }
+    });
+
+    match backend {
+        "memory" => Box::new(MemoryBackend::new(kong_version)),
+        "file" => Box::new(FileBackend::new(
+            MemoryBackend::new(kong_version),
+            matches.value_of("output").unwrap_or("-"),
+        )),
+        _ => {
+            let admin_url = matches.value_of("admin-url").unwrap_or_else(|| {
+                error!("--url is required by the http backend");
+                exit(1)
+            });
+            let custom_headers_opt: Option<Vec<&str>> =
+                matches.values_of("header").map(|values| values.collect());
+            let mut http = HttpBackend::new(admin_url, custom_headers_opt);
+            if let Some(mode) = &cassette {
+                http.use_cassette(mode).unwrap_or_else(|why| {
+                    error!("unable to use cassette: {}", why);
+                    exit(1)
+                });
+            }
+            if let Some(per_second) = rate_limit {
+                http.limit_rate(per_second);
+            }
+            Box::new(http)
+        }
     }
 }
 
-struct ExecutionContext<'t> {
-    kong_cli: Box<KongApiClient<'t>>,
+/// args locating kong and what kong-init keeps about it, shared by the runs and `check`
+fn kong_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("admin-url")
+            .long("url")
+            .takes_value(true)
+            .help("admin url of kong-server(e.g. http://kong_ip:8001)"),
+        Arg::with_name("header")
+            .long("header")
+            .multiple(true)
+            .takes_value(true)
+            .help("add custom header for admin-api request"),
+        Arg::with_name("wait")
+            .long("wait")
+            .short("w")
+            .help("wait until kong-server is ready(suit for init under cloud environment)"),
+        Arg::with_name("prune")
+            .long("prune")
+            .help("delete credentials of the declared consumers that are not declared, and consumers kong-init owns(see --state) that are no longer declared"),
+        Arg::with_name("state")
+            .long("state")
+            .takes_value(true)
+            .help("state file(json, mode 0600) remembering generated secrets and owned consumers between runs, enables secret generation"),
+        Arg::with_name("rate-limit")
+            .long("rate-limit")
+            .takes_value(true)
+            .help("requests per second sent to the admin api at most(e.g. 20, 0.5), unlimited if omitted"),
+        Arg::with_name("record")
+            .long("record")
+            .takes_value(true)
+            .help("write every admin api request and response to this directory(holding credential secrets too), to replay them later"),
+        Arg::with_name("replay")
+            .long("replay")
+            .takes_value(true)
+            .conflicts_with("record")
+            .help("answer the admin api calls from a --record directory instead of kong, failing on any call that diverges from the recording"),
+        Arg::with_name("backend")
+            .long("backend")
+            .takes_value(true)
+            .possible_values(&["http", "file", "memory"])
+            .default_value("http")
+            .help("where to apply the definitions: the admin api at --url(http), a DB-less declarative file(file, see --output) or memory only(memory, a dry run)"),
+        Arg::with_name("kong-version")
+            .long("kong-version")
+            .takes_value(true)
+            .default_value("0.14.1")
+            .help("version of kong the file and memory backends behave as"),
+    ]
+}
+
+/// args locating and reading the definition files, shared by all commands
+fn template_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("path")
+            .required(true)
+            .short("p")
+            .long("path")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("path to route defination file or directory, repeat to layer overlays on top of each other. `-` reads from stdin, `https://..[#sha256=<hex>]` fetches over http and `git+file:///<repo>#<ref>:<path>` reads from a local git repository"),
+        Arg::with_name("path-header")
+            .long("path-header")
+            .multiple(true)
+            .takes_value(true)
+            .number_of_values(1)
+            .help("add custom header(e.g. Authorization) when fetching definition files over http"),
+        Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["yaml", "json", "toml"])
+            .help("format of the definition files, detected by file extension if omitted(stdin defaults to yaml)"),
+    ]
+}
+
+struct TemplateOpts<'a> {
+    paths: Vec<&'a str>,
+    format: Option<Format>,
+    path_headers: Vec<&'a str>,
+}
+
+impl<'a> TemplateOpts<'a> {
+    fn from_matches(matches: &'a ArgMatches) -> TemplateOpts<'a> {
+        TemplateOpts {
+            paths: matches.values_of("path").unwrap().collect(),
+            format: matches.value_of("format").and_then(Format::from_name),
+            path_headers: matches
+                .values_of("path-header")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+struct ExecutionContext {
+    kong_cli: Arc<KongApiClient>,
     support_api: bool,
     support_service_route: bool,
+    support_dbless: bool,
     // legacy mode
-    api_names: Vec<String>,
-    // suggested mode
-    service_name_id_mapping: HashMap<String, String>,
-    route_name_id_mapping: HashMap<String, String>,
-}
-
-impl<'t> ExecutionContext<'t> {
-    pub fn new(
-        admin_url: &'t str,
-        custom_headers_opt: Option<Vec<&'t str>>,
-    ) -> ExecutionContext<'t> {
-        let kong_cli = KongApiClient::build_with_url_header(admin_url, custom_headers_opt);
+    api_names: Mutex<Vec<String>>,
+    // suggested mode, filled in by concurrent operations
+    service_name_id_mapping: Mutex<HashMap<String, String>>,
+    route_name_id_mapping: Mutex<HashMap<String, String>>,
+}
+
+impl ExecutionContext {
+    pub fn new(backend: Box<dyn KongBackend>) -> ExecutionContext {
+        let kong_cli = KongApiClient::new(backend);
         ExecutionContext {
-            api_names: Vec::new(),
-            kong_cli: Box::new(kong_cli),
+            api_names: Mutex::new(Vec::new()),
+            kong_cli: Arc::new(kong_cli),
             support_api: false,
             support_service_route: false,
-            service_name_id_mapping: HashMap::new(),
-            route_name_id_mapping: HashMap::new(),
+            support_dbless: false,
+            service_name_id_mapping: Mutex::new(HashMap::new()),
+            route_name_id_mapping: Mutex::new(HashMap::new()),
         }
     }
 }
 
-fn runc(
-    tmpl_path: &str,
-    admin_url: &str,
-    custom_headers_opt: Option<Vec<&str>>,
+/// flags of a run against the admin api
+struct RunOpts<'a> {
     is_wait: bool,
+    is_dbless: bool,
+    is_prune: bool,
+    is_force: bool,
+    /// skip unchanged runs, only the admin api keeps the fingerprints between runs
+    fingerprint: bool,
+    /// lease and wait of the lock held while applying
+    lock: Option<(Duration, Duration)>,
+    state_path: Option<&'a str>,
+    secrets_output: Option<&'a str>,
+    concurrency: usize,
+}
+
+fn runc(
+    tmpl_opts: &TemplateOpts,
+    backend: Box<dyn KongBackend>,
+    run_opts: &RunOpts,
 ) -> Result<(), Error> {
-    let mut context = ExecutionContext::new(admin_url, custom_headers_opt);
+    let mut context = ExecutionContext::new(backend);
+    let mut state = State::load(run_opts.state_path).unwrap_or_else(|why| {
+        error!("unable to load state: {}", why);
+        exit(1)
+    });
+    let is_prune = run_opts.is_prune;
 
-    if is_wait {
-        let mut is_connected = false;
-        let retry_interval_ms = 5000;
-        while !is_connected {
-            is_connected = verify_kong_version(&mut context);
-            info!("retry in {}ms", retry_interval_ms);
-            sleep(Duration::from_millis(retry_interval_ms));
-        }
-    } else {
-        let is_connected = verify_kong_version(&mut context);
-        if !is_connected {
-            std::process::exit(1);
+    connect(&mut context, run_opts.is_wait);
+
+    if run_opts.is_dbless {
+        if !context.support_dbless {
+            error!("DB-less mode requires kong version >= 1.1");
+            exit(1);
         }
+        apply_dbless(&context, tmpl_opts, &mut state, run_opts.secrets_output);
+        finish(&context);
+        return Ok(());
+    } else if !context.support_api && !context.support_service_route {
+        error!("kong version currently not supported, consider using --dbless");
+        exit(1);
     }
 
-    let deserialized_conf = parse_template(tmpl_path, &context);
+    if let Some((ttl, wait)) = run_opts.lock {
+        if let Err(why) = lock::acquire(&context.kong_cli, ttl, wait) {
+            error!("unable to acquire the lock: {}", why);
+            exit(1)
+        }
+    }
 
-    match deserialized_conf {
-        ConfFileStyle::Legacy(legacy_conf) => {
-            clear_before_init_legacy(&context);
+    let owned_ids = RefCell::new(Vec::new());
+    let mut deserialized_conf = parse_template(tmpl_opts, &|custom_id| {
+        let id = context.kong_cli.init_guest_consumer(custom_id).unwrap_or_else(|why| {
+            error!("{}", why);
+            String::from("error_id")
+        });
+        owned_ids.borrow_mut().push(id.clone());
+        id
+    });
+    let mut owned_ids = owned_ids.into_inner();
+    let exact_acls = prepare_groups(&mut deserialized_conf).unwrap_or_else(|why| {
+        error!("invalid config: {}", why);
+        exit(1)
+    });
+    let credentials = match &mut deserialized_conf {
+        ConfFileStyle::Suggested(conf) => conf.credentials.as_mut(),
+        ConfFileStyle::Legacy(conf) => conf.credentials.as_mut(),
+        ConfFileStyle::IllegalFormat { .. } => None,
+    };
+    if let Some(credentials) = credentials {
+        generate_secrets(credentials, &mut state, run_opts.secrets_output);
+    }
 
-            if let Some(consumers) = &legacy_conf.consumers {
-                init_consumers(&context, consumers);
+    let config_fingerprint = if run_opts.fingerprint {
+        fingerprint::config(&deserialized_conf, is_prune)
+            .map_err(|why| warn!("unable to fingerprint the config, applying it: {}", why))
+            .ok()
+    } else {
+        None
+    };
+    if let Some(config) = &config_fingerprint {
+        match fingerprint::unchanged(&context.kong_cli, config) {
+            Ok(true) if run_opts.is_force => info!("config and kong unchanged since the last run, applying anyway(--force)"),
+            Ok(true) => {
+                info!("config and kong unchanged since the last run, nothing to apply(--force to apply anyway)");
+                if let Err(why) = state.save() {
+                    error!("unable to save state: {}", why);
+                    exit(1)
+                }
+                lock::release();
+                finish(&context);
+                return Ok(());
             }
+            Ok(false) => debug!("config or kong changed since the last run"),
+            Err(why) => warn!("unable to compare with the last run, applying: {}", why),
+        }
+    }
+
+    let state = Mutex::new(state);
+    let mut graph = Graph::new();
+    match &deserialized_conf {
+        ConfFileStyle::Legacy(legacy_conf) => {
+            let cleared = clear_before_init_legacy(&context, &mut graph);
 
-            if let Some(credentials) = &legacy_conf.credentials {
-                init_credentials(&context, credentials);
+            let mut prune_consumers = is_prune;
+            if let Some(path) = &legacy_conf.consumers_from {
+                let (ids, complete) = import_consumers(&context, path, run_opts.concurrency, is_prune);
+                owned_ids.extend(ids);
+                prune_consumers &= complete;
             }
+            let consumer_ops = init_consumers(
+                &context,
+                &mut graph,
+                legacy_conf.consumers.as_ref().map_or(&[][..], |c| &c[..]),
+                &owned_ids,
+                &state,
+                prune_consumers,
+            );
 
-            init_apis(&mut context, &legacy_conf.apis);
+            init_credentials(
+                &context,
+                &mut graph,
+                legacy_conf.credentials.as_ref().map_or(&[][..], |c| &c[..]),
+                legacy_conf.consumers.as_ref(),
+                &exact_acls,
+                is_prune,
+                &consumer_ops,
+            );
 
+            let api_ops = init_apis(&context, &mut graph, &legacy_conf.apis);
 
             if let Some(plugins) = &legacy_conf.plugins {
-                apply_plugins_to_api(&context, plugins);
+                apply_plugins_to_api(&context, &mut graph, plugins, cleared, &api_ops);
             }
         }
         ConfFileStyle::Suggested(suggested_conf) => {
-            clear_before_init(&context);
+            let cleared = clear_before_init(&context, &mut graph);
 
-            if let Some(consumers) = &suggested_conf.consumers {
-                init_consumers(&context, consumers);
+            let mut prune_consumers = is_prune;
+            if let Some(path) = &suggested_conf.consumers_from {
+                let (ids, complete) = import_consumers(&context, path, run_opts.concurrency, is_prune);
+                owned_ids.extend(ids);
+                prune_consumers &= complete;
             }
+            let consumer_ops = init_consumers(
+                &context,
+                &mut graph,
+                suggested_conf.consumers.as_ref().map_or(&[][..], |c| &c[..]),
+                &owned_ids,
+                &state,
+                prune_consumers,
+            );
+
+            init_credentials(
+                &context,
+                &mut graph,
+                suggested_conf.credentials.as_ref().map_or(&[][..], |c| &c[..]),
+                suggested_conf.consumers.as_ref(),
+                &exact_acls,
+                is_prune,
+                &consumer_ops,
+            );
 
-            if let Some(credentials) = &suggested_conf.credentials {
-                init_credentials(&context, credentials);
+            let upstream_ops = match &suggested_conf.upstreams {
+                Some(upstreams) => init_upstreams(&context, &mut graph, upstreams),
+                None => HashMap::new(),
+            };
+
+            if let Some(certificates) = &suggested_conf.certificates {
+                init_certificates(&context, &mut graph, certificates);
             }
 
-            init_services(&mut context, &suggested_conf.services);
-            init_routes(&mut context, suggested_conf.routes);
+            let service_ops = init_services(&context, &mut graph, &suggested_conf.services, cleared, &upstream_ops);
+            let route_ops = init_routes(&context, &mut graph, &suggested_conf.routes, &service_ops);
 
             if let Some(plugins) = &suggested_conf.plugins {
-                apply_plugins_to_service_route(&context, plugins);
+                apply_plugins_to_service_route(&context, &mut graph, plugins, cleared, &service_ops, &route_ops);
             }
         }
         ConfFileStyle::IllegalFormat { msg } => {
             error!("invalid format: {}", msg);
-            std::process::exit(1);
+            exit(1);
+        }
+    }
+
+    let failed = graph.run(run_opts.concurrency);
+    if let Err(why) = state.into_inner().unwrap().save() {
+        error!("unable to save state: {}", why);
+        exit(1)
+    }
+    if let Some(config) = &config_fingerprint {
+        let kept = if failed.is_empty() {
+            fingerprint::record(&context.kong_cli, config)
+        } else {
+            fingerprint::forget(&context.kong_cli)
+        };
+        if let Err(why) = kept {
+            warn!("unable to keep the fingerprint of this run, the next one applies anyway: {}", why);
         }
     }
+    if !failed.is_empty() {
+        error!("{} operations failed or were skipped: {}", failed.len(), failed.join(", "));
+        exit(1)
+    }
 
+    lock::release();
+    finish(&context);
     Ok(())
 }
 
+/// check the version of kong, waiting for it to be reachable with `--wait`
+fn connect(context: &mut ExecutionContext, is_wait: bool) {
+    if is_wait {
+        let mut is_connected = false;
+        let retry_interval_ms = 5000;
+        while !is_connected {
+            is_connected = verify_kong_version(context);
+            info!("retry in {}ms", retry_interval_ms);
+            sleep(Duration::from_millis(retry_interval_ms));
+        }
+    } else {
+        let is_connected = verify_kong_version(context);
+        if !is_connected {
+            exit(1);
+        }
+    }
+}
+
+/// `--watch`: apply the difference between the definitions and kong, then again whenever the
+/// files read change. Invalid definitions are reported and the next change awaited.
+fn watch_and_apply(tmpl_opts: &TemplateOpts, backend: Box<dyn KongBackend>, run_opts: &RunOpts) -> ! {
+    let mut context = ExecutionContext::new(backend);
+    connect(&mut context, run_opts.is_wait);
+    if !context.support_service_route {
+        error!("--watch requires kong with services/routes(0.13, 0.14)");
+        exit(1);
+    }
+    let mut state = State::load(run_opts.state_path).unwrap_or_else(|why| {
+        error!("unable to load state: {}", why);
+        exit(1)
+    });
+
+    // the --path files and directories, those read added by each iteration
+    let paths: Vec<PathBuf> = tmpl_opts
+        .paths
+        .iter()
+        .filter(|path| !path.contains("://") && **path != "-")
+        .map(PathBuf::from)
+        .collect();
+    let mut watched = paths.clone();
+    loop {
+        let before = watch::stamp(&watched);
+        let mut read = Vec::new();
+        match apply_changes(&context, tmpl_opts, run_opts, &mut state, &mut read) {
+            Ok(()) => info!("kong is up to date with the definitions"),
+            Err(why) => error!("{}", why),
+        }
+        lock::release();
+        // definitions that could not be read are watched as they were before, until fixed
+        if !read.is_empty() {
+            watched = read;
+        }
+        watched.extend(paths.iter().cloned());
+        watched.sort();
+        watched.dedup();
+        let mut stamps = watch::stamp(&watched);
+        // a file changed while applying is seen as changed
+        for (path, seen) in before {
+            if let Some(stamp) = stamps.get_mut(&path) {
+                *stamp = seen;
+            }
+        }
+
+        info!("watching {} files for changes...", watched.len());
+        let changed: Vec<String> = watch::wait(&stamps)
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        info!("{} changed", changed.join(", "));
+    }
+}
+
+/// `check`: compare kong with the definitions and print the changes bringing it back to them,
+/// with the differing fields of each entity. Exits 0 when in sync, 2 on drift and 1 on errors.
+fn check(tmpl_opts: &TemplateOpts, backend: Box<dyn KongBackend>, run_opts: &RunOpts) -> ! {
+    let mut context = ExecutionContext::new(backend);
+    connect(&mut context, run_opts.is_wait);
+    if !context.support_service_route {
+        error!("check requires kong with services/routes(0.13, 0.14)");
+        exit(1);
+    }
+    let mut state = State::load(run_opts.state_path).unwrap_or_else(|why| {
+        error!("unable to load state: {}", why);
+        exit(1)
+    });
+    let plan = load_declared(&context, tmpl_opts, run_opts, &mut state, false, &mut Vec::new())
+        .and_then(|declared| plan_changes(&context, &declared, run_opts, &state))
+        .unwrap_or_else(|why| {
+            error!("unable to check: {}", why);
+            exit(1)
+        });
+
+    for change in &plan.changes {
+        println!("{}", change.headline());
+        for difference in &change.differences {
+            println!("    {}", difference);
+        }
+    }
+    if plan.is_empty() {
+        println!("in sync: {} entities as declared", plan.unchanged);
+        exit(0)
+    }
+    println!("drift: {}", plan.summary());
+    exit(2)
+}
+
+/// flags of `--daemon`
+struct DaemonOpts<'a> {
+    interval: Duration,
+    is_heal: bool,
+    status_addr: Option<&'a str>,
+}
+
+/// `--daemon`: compare kong with the definitions every `--interval` until SIGTERM(or SIGINT),
+/// reporting the drift and healing it with `--heal`. The definitions are read again for each
+/// check, a check that fails is reported and the next one made as planned.
+fn run_daemon(tmpl_opts: &TemplateOpts, backend: Box<dyn KongBackend>, run_opts: &RunOpts, daemon_opts: &DaemonOpts) -> ! {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in &[signal_hook::SIGTERM, signal_hook::SIGINT] {
+        if let Err(why) = signal_hook::flag::register(*signal, Arc::clone(&stop)) {
+            error!("unable to handle signal {}: {}", signal, why);
+            exit(1)
+        }
+    }
+    let mut context = ExecutionContext::new(backend);
+    connect(&mut context, run_opts.is_wait);
+    if !context.support_service_route {
+        error!("--daemon requires kong with services/routes(0.13, 0.14)");
+        exit(1);
+    }
+    let mut state = State::load(run_opts.state_path).unwrap_or_else(|why| {
+        error!("unable to load state: {}", why);
+        exit(1)
+    });
+    let status = Arc::new(Mutex::new(daemon::Status::default()));
+    if let Some(addr) = daemon_opts.status_addr {
+        if let Err(why) = daemon::serve(addr, Arc::clone(&status)) {
+            error!("unable to serve the drift status: {}", why);
+            exit(1)
+        }
+    }
+
+    info!(
+        "checking kong for drift every {}s{}",
+        daemon_opts.interval.as_secs(),
+        if daemon_opts.is_heal { ", healing it" } else { "" }
+    );
+    loop {
+        let checked = check_drift(&context, tmpl_opts, run_opts, &mut state, daemon_opts.is_heal);
+        lock::release();
+        {
+            let mut status = status.lock().unwrap();
+            status.checks += 1;
+            status.checked_at = Some(lock::unix_now());
+            match checked {
+                Ok((plan, healed)) => {
+                    status.in_sync = Some(plan.is_empty() || healed);
+                    status.drift = plan.changes.iter().map(|c| c.to_string()).collect();
+                    status.summary = Some(plan.summary());
+                    status.error = None;
+                    if healed {
+                        status.heals += 1;
+                    }
+                }
+                Err(why) => {
+                    error!("unable to check for drift: {}", why);
+                    status.failed_checks += 1;
+                    status.error = Some(why);
+                }
+            }
+        }
+        if daemon::wait_unless_stopped(&stop, daemon_opts.interval) {
+            info!("stopping on signal");
+            exit(0)
+        }
+    }
+}
+
+/// One `--daemon` check, returns the drift found and whether it was healed.
+fn check_drift(
+    context: &ExecutionContext,
+    tmpl_opts: &TemplateOpts,
+    run_opts: &RunOpts,
+    state: &mut State,
+    is_heal: bool,
+) -> Result<(diff::Plan, bool), String> {
+    let declared = load_declared(context, tmpl_opts, run_opts, state, is_heal, &mut Vec::new())?;
+    let mut plan = plan_changes(context, &declared, run_opts, state)?;
+    // drift is healed under the lock, compared again as a run may have applied meanwhile
+    if let (false, true, Some((ttl, wait))) = (plan.is_empty(), is_heal, run_opts.lock) {
+        lock::acquire(&context.kong_cli, ttl, wait).map_err(|why| format!("unable to acquire the lock: {}", why))?;
+        plan = plan_changes(context, &declared, run_opts, state)?;
+    }
+    if plan.is_empty() {
+        info!("no drift, {} entities as declared", plan.unchanged);
+        return Ok((plan, false));
+    }
+    warn!("drift detected: {}", plan.summary());
+    for change in &plan.changes {
+        warn!("{}", change);
+    }
+    if !is_heal {
+        return Ok((plan, false));
+    }
+    apply_plan(context, &plan, &declared, run_opts, state)?;
+    info!("drift healed");
+    Ok((plan, true))
+}
+
+/// One `--watch` iteration: load the definitions and make only the changes they need in kong.
+/// `read` gets the local files the definitions were read from.
+fn apply_changes(
+    context: &ExecutionContext,
+    tmpl_opts: &TemplateOpts,
+    run_opts: &RunOpts,
+    state: &mut State,
+    read: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let declared = load_declared(context, tmpl_opts, run_opts, state, true, read)?;
+    if let Some((ttl, wait)) = run_opts.lock {
+        lock::acquire(&context.kong_cli, ttl, wait).map_err(|why| format!("unable to acquire the lock: {}", why))?;
+    }
+    let plan = plan_changes(context, &declared, run_opts, state)?;
+    for change in &plan.changes {
+        info!("{}", change);
+    }
+    info!("{}", plan.summary());
+    apply_plan(context, &plan, &declared, run_opts, state)
+}
+
+/// The definitions as `--watch` and `--daemon` compare them with kong.
+struct Declared {
+    conf: KongConf,
+    exact_acls: BTreeSet<String>,
+    /// ids of the consumers of `k-upsert-consumer`, owned but never pruned
+    guests: Vec<String>,
+}
+
+/// Load the services/routes style definitions, `read` gets the local files they were read from.
+/// When `is_applying` the consumers of `k-upsert-consumer` are created and the generated
+/// secrets saved as a run does, otherwise kong and the state are left untouched: the missing
+/// consumers are declared and the secrets kept so far compared.
+fn load_declared(
+    context: &ExecutionContext,
+    tmpl_opts: &TemplateOpts,
+    run_opts: &RunOpts,
+    state: &mut State,
+    is_applying: bool,
+    read: &mut Vec<PathBuf>,
+) -> Result<Declared, String> {
+    let guests = RefCell::new(Vec::new());
+    let missing = RefCell::new(Vec::new());
+    // a consumer kong could not tell about fails the whole load, never stands in as drift
+    let failed = RefCell::new(None);
+    let (mut conf_style, files) = load_template(tmpl_opts, &|custom_id| {
+        let found = if is_applying {
+            context.kong_cli.init_guest_consumer(custom_id).map(Some)
+        } else {
+            context.kong_cli.get_consumer_id(custom_id)
+        };
+        match found {
+            Ok(Some(id)) => {
+                guests.borrow_mut().push(id.clone());
+                id
+            }
+            Ok(None) => {
+                missing.borrow_mut().push(custom_id.to_string());
+                String::new()
+            }
+            Err(why) => {
+                failed.borrow_mut().get_or_insert(why);
+                String::new()
+            }
+        }
+    })?;
+    if let Some(why) = failed.into_inner() {
+        return Err(why);
+    }
+    read.extend(files);
+    if let ConfFileStyle::Suggested(conf) = &mut conf_style {
+        if let Some(path) = &conf.consumers_from {
+            read.push(PathBuf::from(path));
+        }
+        bulk::expand(conf).map_err(|why| format!("invalid config: {}", why))?;
+    }
+    let exact_acls = prepare_groups(&mut conf_style).map_err(|why| format!("invalid config: {}", why))?;
+    let mut conf = match conf_style {
+        ConfFileStyle::Suggested(conf) => conf,
+        ConfFileStyle::Legacy(_) => {
+            return Err("API style definitions(`apis`) are not supported, convert them to services/routes first".to_string())
+        }
+        ConfFileStyle::IllegalFormat { msg } => return Err(format!("invalid format: {}", msg)),
+    };
+    for custom_id in missing.into_inner() {
+        let consumers = conf.consumers.get_or_insert_with(Vec::new);
+        if !consumers.iter().any(|c| c.is_target(&custom_id)) {
+            consumers.push(ConsumerInfo::guest(&custom_id));
+        }
+    }
+    match conf.credentials.as_mut() {
+        Some(credentials) if is_applying => generate_secrets(credentials, state, run_opts.secrets_output),
+        Some(credentials) if state.is_persistent() => {
+            // filled from a copy without a file, what is generated is thrown away
+            let mut kept = State::default();
+            kept.secrets = state.secrets.clone();
+            credential::fill_secrets(credentials, &mut kept);
+        }
+        _ => {}
+    }
+    Ok(Declared {
+        conf,
+        exact_acls,
+        guests: guests.into_inner(),
+    })
+}
+
+/// the changes bringing kong to the definitions
+fn plan_changes(
+    context: &ExecutionContext,
+    declared: &Declared,
+    run_opts: &RunOpts,
+    state: &State,
+) -> Result<diff::Plan, String> {
+    diff::plan(
+        &context.kong_cli,
+        &declared.conf,
+        &diff::Options {
+            prune: run_opts.is_prune,
+            exact_acls: &declared.exact_acls,
+            owned: &state.consumers,
+            keep: &declared.guests,
+        },
+    )
+}
+
+/// Make the changes of a plan, kong-init owning the consumers declared as a run does.
+fn apply_plan(
+    context: &ExecutionContext,
+    plan: &diff::Plan,
+    declared: &Declared,
+    run_opts: &RunOpts,
+    state: &mut State,
+) -> Result<(), String> {
+    let ids = diff::Ids::new(plan);
+    let failed = diff::apply_all(&context.kong_cli, plan, &ids, run_opts.concurrency);
+
+    for consumer_info in declared.conf.consumers.iter().flatten() {
+        if let Some(id) = consumer_info.name().and_then(|name| ids.get(diff::Kind::Consumer, name)) {
+            state.consumers.insert(id);
+        }
+    }
+    state.consumers.extend(declared.guests.iter().cloned());
+    for change in &plan.changes {
+        let is_deleted = change.action == diff::Action::Delete && !failed.contains(&change.describe());
+        if change.kind == diff::Kind::Consumer && is_deleted {
+            state.consumers.remove(change.id.as_ref().unwrap());
+        }
+    }
+    state.save().map_err(|why| format!("unable to save state: {}", why))?;
+    context.kong_cli.finish()?;
+    if !failed.is_empty() {
+        return Err(format!("{} changes failed or were skipped: {}", failed.len(), failed.join(", ")));
+    }
+    Ok(())
+}
+
+/// end the run with `code`, releasing the lock it may hold
+fn exit(code: i32) -> ! {
+    lock::release();
+    std::process::exit(code)
+}
+
+/// let the backend complete the run(write the file, check a replay...), failing fails the run
+fn finish(context: &ExecutionContext) {
+    if let Err(why) = context.kong_cli.finish() {
+        error!("unable to finish on {}: {}", context.kong_cli.describe(), why);
+        exit(1);
+    }
+}
+
 fn verify_kong_version(context: &mut ExecutionContext) -> bool {
     let cli = &context.kong_cli;
 
-    match cli.get_node_info() {
+    match cli.get_version() {
         Err(why) => {
-            error!("Could not reach Kong on {}; reason: {}", cli.base_url, why);
+            error!("Could not reach Kong on {}; reason: {}", cli.describe(), why);
             false
         }
-        Ok(kong_info) => {
-            let kong_ver = &kong_info.version;
+        Ok(kong_ver) => {
+            let kong_ver = &kong_ver;
             info!("Kong version is {}", &kong_ver);
 
             let mapped_semver_ce_ver = if kong_ver.ends_with("enterprise-edition") {
@@ -239,50 +1191,113 @@ fn verify_kong_version(context: &mut ExecutionContext) -> bool {
                 context.support_api = true;
                 context.support_service_route = true;
             } else {
-                // version >= 0.15.X, currently not supported.
-                error!("kong version {}, currently not supported.", &kong_ver);
-                std::process::exit(1);
+                // version >= 0.15.X, only supported through DB-less declarative config.
+                warn!("kong version {}, only DB-less mode is supported.", &kong_ver);
             }
+            // declarative config(`/config`) is available since 1.1
+            context.support_dbless =
+                Version::parse(mapped_semver_ce_ver) >= Version::parse("1.1.0");
             true
         }
     }
 }
 
-fn parse_template(tmpl_file_path: &str, context: &ExecutionContext) -> ConfFileStyle {
-    let mut contents = String::new();
+/// `upsert_consumer` resolves `{{k-upsert-consumer:<custom_id>}}` into the consumer's id
+fn parse_template(
+    tmpl_opts: &TemplateOpts,
+    upsert_consumer: &dyn Fn(&str) -> String,
+) -> ConfFileStyle {
+    load_template(tmpl_opts, upsert_consumer)
+        .map(|(conf_style, _)| conf_style)
+        .unwrap_or_else(|why| {
+            error!("{}", why);
+            exit(1)
+        })
+}
 
-    match File::open(tmpl_file_path)
-        .and_then(|mut file| file.read_to_string(&mut contents))
-        .map_err(Error::io)
-        .and_then(|_| {
-            if contents.contains("apis:\n") && contents.contains("services:\n")
-                || (contents.contains("apis:\n") && contents.contains("services:\n")) {
-                Ok(ConfFileStyle::IllegalFormat { msg: "yaml file cannot contains both 'apis' and 'services/routes' at the same time".to_string() })
-            } else if contents.contains("apis:\n") {
-                serde_yaml::from_str::<LegacyKongConf>(&replace_env_and_directive(&contents, context))
-                    .map(ConfFileStyle::Legacy)
-            } else {
-                serde_yaml::from_str::<KongConf>(&replace_env_and_directive(&contents, context))
-                    .map(ConfFileStyle::Suggested)
-            }
-        }) {
-        Err(why) => {
-            error!("invalid yaml file: {}", why);
-            std::process::exit(1)
+/// Like `parse_template`, failing instead of exiting. Also returns the local files read: the
+/// definition files, those they include and those their credentials refer to.
+fn load_template(
+    tmpl_opts: &TemplateOpts,
+    upsert_consumer: &dyn Fn(&str) -> String,
+) -> Result<(ConfFileStyle, Vec<PathBuf>), String> {
+    let (mut conf_style, mut files) = load_template_with(tmpl_opts, &|text| {
+        replace_env_and_directive(text, upsert_consumer)
+    })?;
+    let credentials = match &mut conf_style {
+        ConfFileStyle::Suggested(conf) => conf.credentials.as_mut(),
+        ConfFileStyle::Legacy(conf) => conf.credentials.as_mut(),
+        ConfFileStyle::IllegalFormat { .. } => None,
+    };
+    for credential in credentials.into_iter().flatten() {
+        if let Some(path) = credential.config.get("rsa_public_key_file").and_then(|p| p.as_str()) {
+            files.push(PathBuf::from(path));
         }
-        Ok(kong_conf) => kong_conf
+        credential::resolve_files(credential).map_err(|why| format!("invalid config: {}", why))?;
     }
+    Ok((conf_style, files))
+}
+
+/// `replace` is applied to the raw text of every definition file before parsing
+fn parse_template_with(
+    tmpl_opts: &TemplateOpts,
+    replace: &dyn Fn(&str) -> String,
+) -> ConfFileStyle {
+    load_template_with(tmpl_opts, replace)
+        .map(|(conf_style, _)| conf_style)
+        .unwrap_or_else(|why| {
+            error!("{}", why);
+            exit(1)
+        })
 }
 
-fn replace_env_and_directive(input: &str, context: &ExecutionContext) -> String {
+/// `parse_template_with` failing instead of exiting, with the local files read
+fn load_template_with(
+    tmpl_opts: &TemplateOpts,
+    replace: &dyn Fn(&str) -> String,
+) -> Result<(ConfFileStyle, Vec<PathBuf>), String> {
+    let mut merged = YamlValue::Null;
+
+    let loader = Loader::new(tmpl_opts.format, &tmpl_opts.path_headers, replace);
+    let files = loader::expand_template_paths(&tmpl_opts.paths)?;
+
+    for source in files {
+        let doc = loader
+            .load(&source)
+            .map_err(|why| format!("invalid config: {}", why))?;
+        debug!("merging {}", source);
+        overlay::merge_document(&mut merged, doc)
+    }
+
+    let has_key = |key: &str| merged.get(key).is_some_and(|v| !v.is_null());
+    let is_legacy = has_key("apis");
+    if is_legacy && (has_key("services") || has_key("routes")) {
+        let msg = "yaml file cannot contains both 'apis' and 'services/routes' at the same time".to_string();
+        return Ok((ConfFileStyle::IllegalFormat { msg }, loader.files()));
+    }
+
+    // round-trip through text: the yaml text deserializer is lenient about scalar types
+    // (e.g. `second: 20` into a String field) while `from_value` is not.
+    let conf_style = serde_yaml::to_string(&merged)
+        .and_then(|text| {
+            if is_legacy {
+                serde_yaml::from_str::<LegacyKongConf>(&text).map(ConfFileStyle::Legacy)
+            } else {
+                serde_yaml::from_str::<KongConf>(&text).map(ConfFileStyle::Suggested)
+            }
+        }).map_err(|why| format!("invalid config: {}", why))?;
+    Ok((conf_style, loader.files()))
+}
+
+fn replace_env_and_directive(input: &str, upsert_consumer: &dyn Fn(&str) -> String) -> String {
     let after_env = _replace_env(input);
     debug!("full text after env replacement: \n{}", after_env);
-    let after_d = _replace_directive(&after_env, context);
+    let after_d = _replace_directive(&after_env, upsert_consumer);
     debug!("full text after directive replacement: \n{}", after_d);
     after_d
 }
 
-fn _replace_directive(input: &str, context: &ExecutionContext) -> String {
+fn _replace_directive(input: &str, upsert_consumer: &dyn Fn(&str) -> String) -> String {
     let dd_re = Regex::new(r"\{\{(.+?)}}").unwrap();
 
     let mut shit = HashMap::new();
@@ -295,10 +1310,7 @@ fn _replace_directive(input: &str, context: &ExecutionContext) -> String {
         match vec[0] {
             "k-upsert-consumer" => {
                 debug!("create new consumer {}", vec[1]);
-                shit.insert(
-                    cap_str.to_string(),
-                    context.kong_cli.init_guest_consumer(vec[1]),
-                );
+                shit.insert(cap_str.to_string(), upsert_consumer(vec[1]));
             }
             _ => warn!("directive parsing error {}", vec[0]),
         }
@@ -333,151 +1345,822 @@ fn _replace_env(input: &str) -> String {
     output
 }
 
-fn init_consumers(context: &ExecutionContext, consumers: &[ConsumerInfo]) {
-    for consumer_info in consumers {
-        debug!("consumer_info {:?}", consumer_info);
-        context.kong_cli.add_consumer(&consumer_info);
+/// parse Service/Route style definition files without a database
+fn parse_offline(
+    tmpl_opts: &TemplateOpts,
+    upsert_consumer: &dyn Fn(&str) -> String,
+) -> Result<KongConf, String> {
+    let mut conf = parse_template(tmpl_opts, upsert_consumer);
+    if let ConfFileStyle::Suggested(conf) = &mut conf {
+        bulk::expand(conf)?;
+    }
+    prepare_groups(&mut conf)?;
+    match conf {
+        ConfFileStyle::Suggested(conf) => Ok(conf),
+        ConfFileStyle::Legacy(_) => Err(
+            "API style definitions(`apis`) are not supported, convert them to services/routes first"
+                .to_string(),
+        ),
+        ConfFileStyle::IllegalFormat { msg } => Err(format!("invalid format: {}", msg)),
+    }
+}
+
+/// Turn consumer `groups` into acls credentials and check the groups acl plugins allow, returns
+/// the consumers whose acls are reconciled to exactly their groups.
+fn prepare_groups(conf: &mut ConfFileStyle) -> Result<BTreeSet<String>, String> {
+    let (allowed, consumers_from): (Vec<(String, Value)>, Option<String>) = match conf {
+        ConfFileStyle::Suggested(conf) => (
+            conf.plugins
+                .iter()
+                .flatten()
+                .filter(|p| p.name == "acl" && p.enabled)
+                .flat_map(|p| {
+                    ["whitelist", "allow"]
+                        .iter()
+                        .filter_map(move |k| p.config.get(*k))
+                        .map(move |groups| (p.target.clone(), groups.clone()))
+                }).collect(),
+            conf.consumers_from.clone(),
+        ),
+        ConfFileStyle::Legacy(conf) => (
+            conf.plugins
+                .iter()
+                .flatten()
+                .filter(|p| p.plugin_type == "acl")
+                .filter_map(|p| {
+                    p.config
+                        .get("whitelist")
+                        .map(|groups| (p.target_api.clone(), Value::String(groups.clone())))
+                }).collect(),
+            conf.consumers_from.clone(),
+        ),
+        ConfFileStyle::IllegalFormat { .. } => return Ok(BTreeSet::new()),
+    };
+    let (consumers, credentials) = match conf {
+        ConfFileStyle::Suggested(conf) => (&mut conf.consumers, &mut conf.credentials),
+        ConfFileStyle::Legacy(conf) => (&mut conf.consumers, &mut conf.credentials),
+        ConfFileStyle::IllegalFormat { .. } => return Ok(BTreeSet::new()),
+    };
+    let exact = credential::expand_groups(
+        consumers.as_mut().map_or(&mut [][..], |c| &mut c[..]),
+        credentials,
+    );
+
+    if !allowed.is_empty() {
+        let mut assigned =
+            credential::assigned_groups(credentials.as_ref().map_or(&[][..], |c| &c[..]));
+        if let Some(path) = consumers_from {
+            assigned.extend(bulk::assigned_groups(&path)?);
+        }
+        credential::check_acl_groups(&allowed, &assigned)?;
+    }
+    Ok(exact)
+}
+
+fn render(
+    tmpl_opts: &TemplateOpts,
+    target: &str,
+    k8s_opts: &k8s::Options,
+    output: Option<&str>,
+) -> Result<(), String> {
+    let guests = GuestConsumers::default();
+    let text = match target {
+        "terraform" => {
+            let conf = parse_offline(tmpl_opts, &|custom_id| {
+                guests.upsert(custom_id);
+                terraform::consumer_reference(custom_id)
+            })?;
+            terraform::render(&conf, &guests.names())?
+        }
+        "k8s" => {
+            // consumers are referenced by username in kubernetes, ids are assigned by kong
+            let conf = parse_offline(tmpl_opts, &|custom_id| {
+                guests.upsert(custom_id);
+                custom_id.to_string()
+            })?;
+            yaml_documents(&k8s::render(&conf, &guests.names(), k8s_opts)?)?
+        }
+        "dbless" | "deck" => {
+            let conf = parse_offline(tmpl_opts, &|custom_id| guests.upsert(custom_id))?;
+            let rendered = if target == "dbless" {
+                dbless::render(&conf, &guests.names())?
+            } else {
+                deck::to_deck(&conf, &guests.names())?
+            };
+            yaml_documents(&[rendered])?
+        }
+        other => return Err(format!("unknown render target {}", other)),
+    };
+    write_output(output, &text)?;
+    info!("rendered {} config", target);
+    Ok(())
+}
+
+/// multi-document yaml, every document starts with its own `---`
+fn yaml_documents(documents: &[Value]) -> Result<String, String> {
+    let mut text = Vec::new();
+    for document in documents {
+        text.push(serde_yaml::to_string(document).map_err(|why| why.to_string())?);
     }
-    info!("finished loading Consumers...");
+    Ok(text.join("\n"))
+}
+
+fn convert_legacy(tmpl_opts: &TemplateOpts, output: Option<&str>) -> Result<(), String> {
+    // directives and env placeholders are carried over verbatim instead of being resolved
+    let directives = convert::DirectivePlaceholders::default();
+    let legacy = match parse_template_with(tmpl_opts, &|text| directives.protect(text)) {
+        ConfFileStyle::Legacy(conf) => conf,
+        ConfFileStyle::Suggested(_) => {
+            return Err("definitions are already in services/routes style".to_string())
+        }
+        ConfFileStyle::IllegalFormat { msg } => return Err(format!("invalid format: {}", msg)),
+    };
+    let text = convert::convert_legacy(&legacy)?;
+    write_output(output, directives.restore(&text).trim_end())?;
+    info!("converted {} apis into services/routes", legacy.apis.len());
+    Ok(())
+}
+
+fn import_deck(input: &str, output: Option<&str>) -> Result<(), String> {
+    let contents = fs::read_to_string(input).map_err(|why| format!("{}: {}", input, why))?;
+    // yaml is a superset of json, covers both kinds of decK dumps
+    let state: Value =
+        serde_yaml::from_str(&contents).map_err(|why| format!("{}: {}", input, why))?;
+    let conf = deck::from_deck(&state)?;
+    let text = serde_yaml::to_string(&conf).map_err(|why| why.to_string())?;
+    write_output(output, &text)?;
+    info!("imported decK state from {}", input);
+    Ok(())
+}
+
+fn import_openapi(
+    spec_path: &str,
+    service_name: Option<&str>,
+    merge: Option<&str>,
+    output: Option<&str>,
+) -> Result<(), String> {
+    let contents = fs::read_to_string(spec_path).map_err(|why| format!("{}: {}", spec_path, why))?;
+    let spec: Value =
+        serde_yaml::from_str(&contents).map_err(|why| format!("{}: {}", spec_path, why))?;
+    let conf = openapi::import(&spec, service_name)?;
+    // through text, `to_value` turns integers into floats
+    let generated: YamlValue = serde_yaml::to_string(&conf)
+        .and_then(|text| serde_yaml::from_str(&text))
+        .map_err(|why| why.to_string())?;
+
+    // the existing file is merged as is, env vars and directives stay unresolved
+    let directives = convert::DirectivePlaceholders::default();
+    let mut merged = match merge {
+        Some(path) => {
+            let existing = fs::read_to_string(path).map_err(|why| format!("{}: {}", path, why))?;
+            serde_yaml::from_str(&directives.protect(&existing))
+                .map_err(|why| format!("{}: {}", path, why))?
+        }
+        None => YamlValue::Null,
+    };
+    overlay::merge_document(&mut merged, generated);
+
+    let text = serde_yaml::to_string(&merged).map_err(|why| why.to_string())?;
+    write_output(output, &directives.restore(&text))?;
+    info!(
+        "imported {} routes of service {} from {}",
+        conf.routes.len(),
+        conf.services[0]["name"],
+        spec_path
+    );
+    Ok(())
+}
+
+fn write_output(output: Option<&str>, text: &str) -> Result<(), String> {
+    match output {
+        None => {
+            println!("{}", text);
+            Ok(())
+        }
+        Some(path) => {
+            fs::write(path, format!("{}\n", text)).map_err(|why| format!("{}: {}", path, why))
+        }
+    }
+}
+
+fn apply_dbless(
+    context: &ExecutionContext,
+    tmpl_opts: &TemplateOpts,
+    state: &mut State,
+    secrets_output: Option<&str>,
+) {
+    let guests = GuestConsumers::default();
+    let declarative = parse_offline(tmpl_opts, &|custom_id| guests.upsert(custom_id))
+        .and_then(|mut conf| {
+            if let Some(credentials) = conf.credentials.as_mut() {
+                generate_secrets(credentials, state, secrets_output);
+            }
+            dbless::render(&conf, &guests.names())
+        }).and_then(|rendered| serde_yaml::to_string(&rendered).map_err(|why| why.to_string()));
+    match declarative {
+        Err(why) => {
+            error!("unable to render DB-less config: {}", why);
+            exit(1);
+        }
+        Ok(declarative) => {
+            debug!("declarative config: \n{}", declarative);
+            if !context.kong_cli.post_declarative_config(&declarative) {
+                exit(1);
+            }
+        }
+    }
+    info!("finished loading DB-less config...");
     info!("=================================");
 }
 
-fn init_credentials(context: &ExecutionContext, credentials: &[CredentialsInfo]) {
+/// Fill in omitted credential secrets, only with a state file to keep them in.
+///
+/// The state is saved before anything is applied, a failed run must not lose secrets
+/// that may already have reached kong.
+fn generate_secrets(
+    credentials: &mut [CredentialsInfo],
+    state: &mut State,
+    secrets_output: Option<&str>,
+) {
+    if !state.is_persistent() {
+        return;
+    }
+    let generated = credential::fill_secrets(credentials, state);
+    let saved = state.save().and_then(|_| match secrets_output {
+        Some(path) => serde_json::to_string_pretty(&generated)
+            .map_err(|why| why.to_string())
+            .and_then(|text| state::write_private(path, &text)),
+        None => Ok(()),
+    });
+    if let Err(why) = saved {
+        error!("unable to save generated secrets: {}", why);
+        exit(1)
+    }
+    info!("{} credentials with generated secrets", generated.len());
+}
+
+/// Reconcile the declared consumers, the state remembers the ones kong-init owns. Returns the
+/// operation of each declared consumer by username and custom_id, their credentials wait for it.
+///
+/// `owned_ids` are the consumers created besides `consumers`(by `k-upsert-consumer`, from
+/// `consumers_from`), owned as well.
+fn init_consumers<'a>(
+    context: &'a ExecutionContext,
+    graph: &mut Graph<'a>,
+    consumers: &'a [ConsumerInfo],
+    owned_ids: &[String],
+    state: &'a Mutex<State>,
+    prune: bool,
+) -> HashMap<&'a str, usize> {
+    let changes = {
+        let mut state = state.lock().unwrap();
+        if prune && !state.is_persistent() {
+            warn!("consumers are only pruned with --state, it remembers the ones kong-init owns");
+        }
+        let existing = context.kong_cli.list_consumers().unwrap_or_else(|why| {
+            error!("consumers: {}", why);
+            exit(1)
+        });
+        let changes = consumer::plan(consumers, &existing, &state.consumers, owned_ids, prune)
+            .unwrap_or_else(|why| {
+                error!("consumers: {}", why);
+                exit(1)
+            });
+
+        // consumers deleted by hand are forgotten
+        let existing_ids: Vec<&str> = existing
+            .iter()
+            .filter_map(|c| c.get("id").and_then(|id| id.as_str()))
+            .collect();
+        state.consumers.retain(|id| existing_ids.contains(&id.as_str()));
+        state.consumers.extend(owned_ids.iter().filter(|id| existing_ids.contains(&id.as_str())).cloned());
+        changes
+    };
+
+    let client = &context.kong_cli;
+    let mut ops = HashMap::new();
+    let mut all = Vec::new();
+    // the changes of the declared consumers come first, in their order
+    for (idx, change) in changes.into_iter().enumerate() {
+        let op = match change {
+            consumer::Change::Create(consumer_info) => {
+                let name = format!("create consumer {}", consumer_info.name().unwrap_or_default());
+                graph.add(&name, &[], move || {
+                    debug!("consumer_info {:?}", consumer_info);
+                    let id = client
+                        .add_consumer(consumer_info)
+                        .ok_or_else(|| "unable to create consumer".to_string())?;
+                    state.lock().unwrap().consumers.insert(id);
+                    Ok(())
+                })
+            }
+            consumer::Change::Update { id, fields } => {
+                graph.add(&format!("update consumer {}", id), &[], move || {
+                    state.lock().unwrap().consumers.insert(id.clone());
+                    if client.update_consumer(&id, &fields) {
+                        Ok(())
+                    } else {
+                        Err("unable to update consumer".to_string())
+                    }
+                })
+            }
+            consumer::Change::Unchanged { id } => {
+                debug!("consumer {} is up to date", id);
+                state.lock().unwrap().consumers.insert(id);
+                continue;
+            }
+            consumer::Change::Delete { id, name } => {
+                graph.add(&format!("prune consumer {}", name), &[], move || {
+                    if !client.delete_consumer(&id) {
+                        return Err("unable to delete consumer".to_string());
+                    }
+                    info!("pruned consumer {}, no longer declared", name);
+                    state.lock().unwrap().consumers.remove(&id);
+                    Ok(())
+                })
+            }
+        };
+        if let Some(consumer_info) = consumers.get(idx) {
+            for name in consumer_info.username.iter().chain(consumer_info.custom_id.iter()) {
+                ops.insert(name.as_str(), op);
+            }
+        }
+        all.push(op);
+    }
+    stage_done(graph, "Consumers", &all);
+    ops
+}
+
+/// `exact_acls` are the consumers declaring `groups`, their acls are pruned regardless of `prune`.
+/// The credentials of a declared consumer wait for its operation in `consumer_ops`.
+#[allow(clippy::too_many_arguments)]
+fn init_credentials<'a>(
+    context: &'a ExecutionContext,
+    graph: &mut Graph<'a>,
+    credentials: &'a [CredentialsInfo],
+    consumers: Option<&Vec<ConsumerInfo>>,
+    exact_acls: &BTreeSet<String>,
+    prune: bool,
+    consumer_ops: &HashMap<&str, usize>,
+) {
+    let client = &context.kong_cli;
+    let after = |consumer: &str| consumer_ops.get(consumer).map_or(vec![], |op| vec![*op]);
+    let mut all = Vec::new();
+
+    // declared credentials by consumer and endpoint, reconciled together
+    let mut grouped: BTreeMap<(String, &str), Vec<&BTreeMap<String, Value>>> = BTreeMap::new();
     for credential_info in credentials {
         debug!("credential_info {:?}", credential_info);
+        match credential::endpoint(&credential_info.name) {
+            Some(endpoint) => grouped
+                .entry((credential_info.target.clone(), endpoint))
+                .or_default()
+                .push(&credential_info.config),
+            None => {
+                warn!(
+                    "credential type {} cannot be matched against existing ones, always created",
+                    credential_info.name
+                );
+                let name = format!("{} of consumer {}", credential_info.name, credential_info.target);
+                all.push(graph.add(&name, &after(&credential_info.target), move || {
+                    client.add_credential(
+                        &credential_info.target,
+                        &credential_info.name,
+                        &credential_info.config,
+                    )
+                }));
+            }
+        }
+    }
+    if prune {
+        // declared consumers without any credential of a type lose all of them
+        for consumer in consumers.map_or(&[][..], |c| &c[..]) {
+            if let Some(username) = &consumer.username {
+                for endpoint in credential::endpoints() {
+                    grouped.entry((username.clone(), endpoint)).or_default();
+                }
+            }
+        }
+    }
 
-        let consumer_id = &credential_info.target;
-        let plugin = &credential_info.name;
-        let plugin_conf = &credential_info.config;
+    for consumer in exact_acls {
+        grouped.entry((consumer.clone(), "acls")).or_default();
+    }
 
-        context
-            .kong_cli
-            .add_credential(consumer_id, plugin, plugin_conf);
+    for ((consumer, endpoint), declared) in grouped {
+        let exact = endpoint == "acls" && exact_acls.contains(&consumer);
+        let name = format!("{} of consumer {}", endpoint, consumer);
+        all.push(graph.add(&name, &after(&consumer), move || {
+            reconcile_credentials(client, &consumer, endpoint, &declared, prune || exact)
+        }));
     }
-    info!("finished loading Credentials...");
+    stage_done(graph, "Credentials", &all);
+}
+
+/// Reconcile the declared credentials of one consumer and type with the existing ones.
+fn reconcile_credentials(
+    client: &KongApiClient,
+    consumer: &str,
+    endpoint: &str,
+    declared: &[&BTreeMap<String, Value>],
+    prune: bool,
+) -> Result<(), String> {
+    let existing = client
+        .list_credentials(consumer, endpoint)
+        .map_err(|why| format!("credentials: {}", why))?;
+    for change in credential::plan(endpoint, declared, &existing, prune) {
+        match change {
+            credential::Change::Create(config) => client.add_credential(consumer, endpoint, &config)?,
+            credential::Change::Update { id, fields } => {
+                client.update_credential(consumer, endpoint, &id, &fields)?
+            }
+            credential::Change::Unchanged { id } => {
+                debug!("credential {} {} of consumer {} is up to date", endpoint, id, consumer);
+            }
+            credential::Change::Delete { id } => client.delete_credential(consumer, endpoint, &id)?,
+        }
+    }
+    Ok(())
+}
+
+/// Reconcile the consumers of a `consumers_from` file with their credentials, `concurrency`
+/// at a time. Returns the ids of the consumers, kong-init owns them like the declared ones, and
+/// whether every record was applied: consumers of failed ones must not be pruned.
+fn import_consumers(
+    context: &ExecutionContext,
+    path: &str,
+    concurrency: usize,
+    prune: bool,
+) -> (Vec<String>, bool) {
+    let records = bulk::read(path).unwrap_or_else(|why| {
+        error!("consumers_from: {}", why);
+        exit(1)
+    });
+    let client = &context.kong_cli;
+    let ids = Mutex::new(Vec::new());
+    let failed = AtomicUsize::new(0);
+    let unreadable = bulk::for_each(records, concurrency, |record| {
+        let name = record.consumer.name().unwrap_or_default().to_string();
+        match import_consumer(client, record, prune) {
+            Ok(id) => ids.lock().unwrap().push(id),
+            Err(why) => {
+                error!("consumers_from: consumer {}: {}", name, why);
+                failed.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    });
+    let ids = ids.into_inner().unwrap();
+    let failed = failed.into_inner() + unreadable;
+    info!("{} consumers from {}, {} failed", ids.len(), path, failed);
+    if failed > 0 && prune {
+        warn!("consumers are not pruned, {} records of {} failed", failed, path);
+    }
+    info!("finished loading Consumers from {}...", path);
     info!("=================================");
+    (ids, failed == 0)
 }
 
-fn init_apis(context: &mut ExecutionContext, apis: &[ApiInfo]) {
+fn import_consumer(
+    client: &KongApiClient,
+    mut record: bulk::ConsumerRecord,
+    prune: bool,
+) -> Result<String, String> {
+    let groups = record.consumer.groups.take();
+    let existing = client.find_consumers(&record.consumer)?;
+    let declared = std::slice::from_ref(&record.consumer);
+    let mut consumer_id = None;
+    for change in consumer::plan(declared, &existing, &BTreeSet::new(), &[], false)? {
+        match change {
+            consumer::Change::Create(consumer_info) => {
+                consumer_id = client.add_consumer(consumer_info);
+            }
+            consumer::Change::Update { id, fields } => {
+                if !client.update_consumer(&id, &fields) {
+                    return Err(format!("unable to update consumer {}", id));
+                }
+                consumer_id = Some(id);
+            }
+            consumer::Change::Unchanged { id } => consumer_id = Some(id),
+            consumer::Change::Delete { .. } => {}
+        }
+    }
+    let consumer_id = consumer_id.ok_or_else(|| "unable to create consumer".to_string())?;
+
+    let group_configs = credential::acl_configs(groups.as_ref().map_or(&[][..], |g| &g[..]));
+    let mut grouped: BTreeMap<&str, Vec<&BTreeMap<String, Value>>> = BTreeMap::new();
+    if prune {
+        for endpoint in credential::endpoints() {
+            grouped.entry(endpoint).or_default();
+        }
+    }
+    if groups.is_some() {
+        grouped.entry("acls").or_default().extend(group_configs.iter());
+    }
+    for credential_info in &record.credentials {
+        match credential::endpoint(&credential_info.name) {
+            Some(endpoint) => grouped.entry(endpoint).or_default().push(&credential_info.config),
+            None => {
+                warn!(
+                    "credential type {} cannot be matched against existing ones, always created",
+                    credential_info.name
+                );
+                client.add_credential(&consumer_id, &credential_info.name, &credential_info.config)?;
+            }
+        }
+    }
+    for (endpoint, declared) in &grouped {
+        let exact = *endpoint == "acls" && groups.is_some();
+        reconcile_credentials(client, &consumer_id, endpoint, declared, prune || exact)?;
+    }
+    Ok(consumer_id)
+}
+
+/// Returns the operation of each api by name, the plugins applied to it wait for it.
+fn init_apis<'a>(context: &'a ExecutionContext, graph: &mut Graph<'a>, apis: &'a [ApiInfo]) -> HashMap<&'a str, usize> {
+    let mut ops = HashMap::new();
     for api_info in apis {
-        debug!("{:?}", api_info);
         let api_name = api_info.get("name").unwrap();
-        context.api_names.push(api_name.clone());
-        context.kong_cli.delete_api(&api_name);
-        context.kong_cli.upsert_api(&api_name, api_info);
+        context.api_names.lock().unwrap().push(api_name.clone());
+        let op = graph.add(&format!("API {}", api_name), &[], move || {
+            debug!("{:?}", api_info);
+            context.kong_cli.delete_api(&api_name)?;
+            context.kong_cli.upsert_api(&api_name, api_info)
+        });
+        ops.insert(api_name.as_str(), op);
     }
-    info!("finished loading APIs...");
-    info!("=================================");
+    stage_done(graph, "APIs", &ops.values().cloned().collect::<Vec<usize>>());
+    ops
 }
 
-fn apply_plugins_to_api(context: &ExecutionContext, plugins: &[LegacyPluginInfo]) {
+fn apply_plugins_to_api<'a>(
+    context: &'a ExecutionContext,
+    graph: &mut Graph<'a>,
+    plugins: &'a [LegacyPluginInfo],
+    cleared: usize,
+    api_ops: &HashMap<&str, usize>,
+) {
+    let mut all = Vec::new();
     for plugin_info in plugins {
-        debug!("{:?}", plugin_info);
-        let plugin_type = &plugin_info.plugin_type;
-        let plugin_conf = &plugin_info.config;
-
-        let target_apis: (LegacyPluginAppliedType, Option<Vec<String>>) =
-            match &plugin_info.target_api as &str {
-                "all" => (LegacyPluginAppliedType::ALL, None),
-                "none" => (LegacyPluginAppliedType::NONE, None),
-                others => (
-                    LegacyPluginAppliedType::SOME,
-                    Some(Vec::from_iter(others.split(',').map(String::from))),
-                ),
-            };
+        let mut deps = vec![cleared];
+        match &plugin_info.target_api as &str {
+            "all" => deps.extend(api_ops.values()),
+            "none" => {}
+            others => deps.extend(others.split(',').filter_map(|api| api_ops.get(api))),
+        }
+
+        let name = format!("plugin {} to {}", plugin_info.plugin_type, plugin_info.target_api);
+        all.push(graph.add(&name, &deps, move || {
+            debug!("{:?}", plugin_info);
+            let plugin_type = &plugin_info.plugin_type;
+            let plugin_conf = &plugin_info.config;
 
-        context
-            .kong_cli
-            .apply_plugin_to_api_legacy(plugin_type, target_apis, plugin_conf);
+            let target_apis: (LegacyPluginAppliedType, Option<Vec<String>>) =
+                match &plugin_info.target_api as &str {
+                    "all" => (LegacyPluginAppliedType::ALL, None),
+                    "none" => (LegacyPluginAppliedType::NONE, None),
+                    others => (
+                        LegacyPluginAppliedType::SOME,
+                        Some(Vec::from_iter(others.split(',').map(String::from))),
+                    ),
+                };
+
+            context
+                .kong_cli
+                .apply_plugin_to_api_legacy(plugin_type, target_apis, plugin_conf)
+        }));
     }
-    info!("finished loading plugins...");
-    info!("=================================");
+    stage_done(graph, "plugins", &all);
+}
+
+/// log the end of a stage once its operations are done
+fn stage_done(graph: &mut Graph, stage: &'static str, ops: &[usize]) -> usize {
+    graph.add(stage, ops, move || {
+        info!("finished loading {}...", stage);
+        info!("=================================");
+        Ok(())
+    })
 }
 
-fn clear_before_init_legacy(context: &ExecutionContext) {
+/// Add the deletion of every entity of a collection, after `deps`. Returns an operation done
+/// once all of them are.
+fn delete_all<'a>(
+    context: &'a ExecutionContext,
+    graph: &mut Graph<'a>,
+    collection: &'static str,
+    delete: fn(&KongApiClient, &str) -> Result<(), String>,
+    deps: &[usize],
+) -> usize {
+    let ids = context.kong_cli.list_ids(collection).unwrap_or_else(|why| {
+        error!("clear_before_init: {}", why);
+        exit(1)
+    });
+    let ops: Vec<usize> = ids
+        .into_iter()
+        .map(|id| {
+            graph.add(&format!("delete {} {}", collection, id), deps, move || {
+                delete(&context.kong_cli, &id)
+            })
+        }).collect();
+    graph.add(&format!("clear {}", collection), &ops, || Ok(()))
+}
+
+/// Returns an operation done once the entities are cleared.
+fn clear_before_init_legacy<'a>(context: &'a ExecutionContext, graph: &mut Graph<'a>) -> usize {
     info!("clear_before_init");
-    context.kong_cli.delete_all_plugins();
+    delete_all(context, graph, "plugins", KongApiClient::delete_plugin_by_id, &[])
 }
 
-fn clear_before_init(context: &ExecutionContext) {
+/// Returns an operation done once the entities are cleared, plugins before the routes they
+/// apply to and routes before their services.
+fn clear_before_init<'a>(context: &'a ExecutionContext, graph: &mut Graph<'a>) -> usize {
     info!("clear_before_init");
-    context.kong_cli.delete_all_plugins();
-    context.kong_cli.delete_all_routes();
-    context.kong_cli.delete_all_services();
+    let plugins = delete_all(context, graph, "plugins", KongApiClient::delete_plugin_by_id, &[]);
+    let routes = delete_all(context, graph, "routes", KongApiClient::delete_route, &[plugins]);
+    delete_all(context, graph, "services", KongApiClient::delete_service, &[routes])
+}
+
+/// Returns the operation of each upstream by name, the services using it wait for it.
+fn init_upstreams<'a>(
+    context: &'a ExecutionContext,
+    graph: &mut Graph<'a>,
+    upstreams: &'a [UpstreamInfo],
+) -> HashMap<&'a str, usize> {
+    let mut ops = HashMap::new();
+    for upstream_info in upstreams {
+        let op = graph.add(&format!("upstream {}", upstream_info.name), &[], move || {
+            debug!("upstream_info {:?}", upstream_info);
+            context.kong_cli.delete_upstream(&upstream_info.name)?;
+            let upstream_id = context
+                .kong_cli
+                .add_upstream(upstream_info)
+                .ok_or_else(|| "unable to create upstream, its targets are skipped".to_string())?;
+            for target_info in &upstream_info.targets {
+                context.kong_cli.add_target(&upstream_id, target_info)?;
+            }
+            Ok(())
+        });
+        ops.insert(upstream_info.name.as_str(), op);
+    }
+    stage_done(graph, "upstreams", &ops.values().cloned().collect::<Vec<usize>>());
+    ops
+}
+
+fn init_certificates<'a>(context: &'a ExecutionContext, graph: &mut Graph<'a>, certificates: &'a [CertificateInfo]) {
+    let mut all = Vec::new();
+    for certificate_info in certificates {
+        let name = format!("certificate {}", certificate_info.snis.join(","));
+        all.push(graph.add(&name, &[], move || {
+            if let Some(sni) = certificate_info.snis.first() {
+                context.kong_cli.delete_certificate(sni)?;
+            }
+            context.kong_cli.add_certificate(certificate_info)
+        }));
+    }
+    stage_done(graph, "certificates", &all);
 }
 
-fn init_services(context: &mut ExecutionContext, services: &[ServiceInfo]) {
+/// the host a service proxies to, an upstream name when it balances over one
+fn service_host(service_info: &ServiceInfo) -> Option<String> {
+    match service_info.get("host") {
+        Some(Value::String(host)) => Some(host.clone()),
+        _ => service_info
+            .get("url")
+            .and_then(|url| url.as_str())
+            .and_then(|url| Url::parse(url).ok())
+            .and_then(|url| url.host_str().map(String::from)),
+    }
+}
+
+/// Returns the operation of each service by name, its routes and plugins wait for it.
+fn init_services<'a>(
+    context: &'a ExecutionContext,
+    graph: &mut Graph<'a>,
+    services: &'a [ServiceInfo],
+    cleared: usize,
+    upstream_ops: &HashMap<&str, usize>,
+) -> HashMap<&'a str, usize> {
+    let mut ops = HashMap::new();
     for service_info in services {
         let serde_value_field = service_info.get("name").unwrap();
         match serde_value_field {
             Value::String(service_name) => {
-                let sid = context.kong_cli.add_service(&service_info).unwrap();
-                context
-                    .service_name_id_mapping
-                    .insert(service_name.to_string(), sid);
+                let mut deps = vec![cleared];
+                let host = service_host(service_info);
+                deps.extend(host.and_then(|host| upstream_ops.get(host.as_str())));
+                let op = graph.add(&format!("service {}", service_name), &deps, move || {
+                    let sid = context
+                        .kong_cli
+                        .add_service(service_info)
+                        .ok_or_else(|| "unable to create service".to_string())?;
+                    context
+                        .service_name_id_mapping
+                        .lock()
+                        .unwrap()
+                        .insert(service_name.to_string(), sid);
+                    Ok(())
+                });
+                ops.insert(service_name.as_str(), op);
             }
             _ => {
                 error!("failed adding service name={:?}, the `name` field must be String type", serde_value_field);
-                std::process::exit(1);
+                exit(1);
             }
         }
     }
-    info!("finished loading services...");
-    info!("=================================");
+    stage_done(graph, "services", &ops.values().cloned().collect::<Vec<usize>>());
+    ops
 }
 
-fn init_routes(context: &mut ExecutionContext, routes: Vec<RouteInfo>) {
+/// Returns the operation of each route by name, its plugins wait for it.
+fn init_routes<'a>(
+    context: &'a ExecutionContext,
+    graph: &mut Graph<'a>,
+    routes: &'a [RouteInfo],
+    service_ops: &HashMap<&str, usize>,
+) -> HashMap<&'a str, usize> {
+    let mut ops = HashMap::new();
     for route_info in routes {
-        let route_name = route_info.name.clone();
-        let service_id = &context.service_name_id_mapping[&route_info.apply_to];
-        let rid = context
-            .kong_cli
-            .add_route_to_service(service_id.to_string().clone(), route_info)
-            .unwrap();
-        context.route_name_id_mapping.insert(route_name, rid);
-    }
-    info!("finished loading routes...");
-    info!("=================================");
+        let service_op = *service_ops.get(route_info.apply_to.as_str()).unwrap_or_else(|| {
+            error!("route {} applies to unknown service {}", route_info.name, route_info.apply_to);
+            exit(1)
+        });
+        let op = graph.add(&format!("route {}", route_info.name), &[service_op], move || {
+            let service_id = context.service_name_id_mapping.lock().unwrap()[&route_info.apply_to].clone();
+            let rid = context
+                .kong_cli
+                .add_route_to_service(service_id, route_info)
+                .ok_or_else(|| "unable to create route".to_string())?;
+            context
+                .route_name_id_mapping
+                .lock()
+                .unwrap()
+                .insert(route_info.name.clone(), rid);
+            Ok(())
+        });
+        ops.insert(route_info.name.as_str(), op);
+    }
+    stage_done(graph, "routes", &ops.values().cloned().collect::<Vec<usize>>());
+    ops
 }
 
-fn apply_plugins_to_service_route(context: &ExecutionContext, plugins: &[PluginInfo]) {
-    let service_re = Regex::new(r"^s\[[-0-9a-zA-Z,]+]$").unwrap();
-    let route_re = Regex::new(r"^r\[[-0-9a-zA-Z,]+]$").unwrap();
+fn apply_plugins_to_service_route<'a>(
+    context: &'a ExecutionContext,
+    graph: &mut Graph<'a>,
+    plugins: &'a [PluginInfo],
+    cleared: usize,
+    service_ops: &HashMap<&str, usize>,
+    route_ops: &HashMap<&str, usize>,
+) {
+    let mut all = Vec::new();
 
     for plugin_info in plugins {
         debug!("pluinInfo {:?}", plugin_info);
 
-        let mut target = &plugin_info.target.clone();
+        let target = &plugin_info.target;
+        let op_of = |ops: &HashMap<&str, usize>, name: &str| -> usize {
+            *ops.get(name).unwrap_or_else(|| {
+                error!("plugin {} targets unknown {}", plugin_info.name, name);
+                exit(1)
+            })
+        };
 
-        let plugin_target = if target == "global" {
-            PluginTarget::GLOBAL
-        } else if service_re.is_match(target) {
-            let mut t = target.trim_left_matches("s[").to_string();
-            let tm = t.len();
-            t.truncate(tm - 1);
-            let tmp = Vec::from_iter(t.split(',').map(String::from))
-                .iter()
-                .map(|s_name| context.service_name_id_mapping[s_name].clone())
-                .collect();
-            debug!("plugin {} with service target {:?}", plugin_info.name, tmp);
-            PluginTarget::SERVICES(tmp)
-        } else if route_re.is_match(target) {
-            let mut t = target.trim_left_matches("r[").to_string();
-            let tm = t.len();
-            t.truncate(tm - 1);
-            let tmp = Vec::from_iter(t.split(',').map(String::from))
-                .iter()
-                .map(|r_name| context.route_name_id_mapping[r_name].clone())
-                .collect();
-            debug!("plugin {} with route target {:?}", plugin_info.name, tmp);
-            PluginTarget::Routes(tmp)
-        } else {
-            error!("invalid plugin defination: invalid target field, must one of global/services(s:[service-a,service-b,service-c])/routes(r:[route-a,route-b])");
-            std::process::exit(1);
+        let mut deps = vec![cleared];
+        let parsed = parse_target(target).unwrap_or_else(|why| {
+            error!("invalid plugin defination: {}", why);
+            std::process::exit(1)
+        });
+        let (services, routes) = match parsed {
+            Target::Global => (None, None),
+            Target::Services(services) => {
+                deps.extend(services.iter().map(|s_name| op_of(service_ops, s_name)));
+                (Some(services), None)
+            }
+            Target::Routes(routes) => {
+                deps.extend(routes.iter().map(|r_name| op_of(route_ops, r_name)));
+                (None, Some(routes))
+            }
         };
 
-        context.kong_cli.apply_plugin(plugin_target, plugin_info);
+        let name = format!("plugin {} to {}", plugin_info.name, target);
+        all.push(graph.add(&name, &deps, move || {
+            // the ids are known once the services and routes are created
+            let plugin_target = match (&services, &routes) {
+                (Some(services), _) => {
+                    let mapping = context.service_name_id_mapping.lock().unwrap();
+                    let tmp = services.iter().map(|s_name| mapping[s_name].clone()).collect();
+                    debug!("plugin {} with service target {:?}", plugin_info.name, tmp);
+                    PluginTarget::SERVICES(tmp)
+                }
+                (_, Some(routes)) => {
+                    let mapping = context.route_name_id_mapping.lock().unwrap();
+                    let tmp = routes.iter().map(|r_name| mapping[r_name].clone()).collect();
+                    debug!("plugin {} with route target {:?}", plugin_info.name, tmp);
+                    PluginTarget::Routes(tmp)
+                }
+                _ => PluginTarget::GLOBAL,
+            };
+            context.kong_cli.apply_plugin(plugin_target, plugin_info)
+        }));
     }
-    info!("finished loading plugins...");
-    info!("=================================");
+    stage_done(graph, "plugins", &all);
 }
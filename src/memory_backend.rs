@@ -0,0 +1,415 @@
+use backend::{id_of, BackendError, KongBackend};
+use serde_json::{Map as SerdeMap, Value};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// fields identifying an entity besides its id, by collection
+const UNIQUE_FIELDS: &[(&str, &[&str])] = &[
+    ("apis", &["name"]),
+    ("services", &["name"]),
+    ("routes", &["name"]),
+    ("upstreams", &["name"]),
+    ("consumers", &["username", "custom_id"]),
+    ("key-auth", &["key"]),
+    ("basic-auth", &["username"]),
+    ("hmac-auth", &["username"]),
+    ("jwt", &["key"]),
+    ("oauth2", &["client_id"]),
+];
+
+/// fields kong generates when a credential is created without them
+const GENERATED_SECRETS: &[(&str, &[&str])] = &[
+    ("key-auth", &["key"]),
+    ("jwt", &["key", "secret"]),
+    ("hmac-auth", &["secret"]),
+    ("oauth2", &["client_id", "client_secret"]),
+];
+
+/// field referencing an entity of a collection, e.g. `consumer_id` of credentials
+const REFERENCES: &[(&str, &str)] = &[
+    ("apis", "api_id"),
+    ("services", "service_id"),
+    ("routes", "route_id"),
+    ("consumers", "consumer_id"),
+    ("upstreams", "upstream_id"),
+];
+
+/// A kong kept in memory, behaving like the admin api of `version`: for dry runs and tests.
+///
+/// Ids are derived from the entities, whatever the order they are created in the same run gives
+/// the same ids. Listings are ordered by id, as kong's.
+pub struct MemoryBackend {
+    version: String,
+    major_minor: (u64, u64),
+    store: Mutex<Store>,
+}
+
+#[derive(Default)]
+struct Store {
+    /// by collection, nested ones(credentials, targets) flat under their own name
+    entities: BTreeMap<String, Vec<Value>>,
+    /// the last declarative config loaded
+    declarative: Option<String>,
+}
+
+/// where the entities of a collection path are: `consumers/<id>/jwt` is `jwt` entities whose
+/// `consumer_id` is the consumer's id
+struct Location {
+    collection: String,
+    parent: Option<(&'static str, String)>,
+}
+
+impl Location {
+    fn contains(&self, entity: &Value) -> bool {
+        match &self.parent {
+            None => true,
+            Some((field, id)) => entity[*field].as_str() == Some(id.as_str()),
+        }
+    }
+}
+
+impl MemoryBackend {
+    pub fn new(version: &str) -> MemoryBackend {
+        let mut numbers = version
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|n| n.parse().unwrap_or(0));
+        let major_minor = (numbers.next().unwrap_or(0), numbers.next().unwrap_or(0));
+        MemoryBackend {
+            version: version.to_string(),
+            major_minor,
+            store: Mutex::new(Store::default()),
+        }
+    }
+
+    /// every entity by collection
+    pub fn entities(&self) -> BTreeMap<String, Vec<Value>> {
+        self.store.lock().unwrap().entities.clone()
+    }
+
+    pub fn declarative(&self) -> Option<String> {
+        self.store.lock().unwrap().declarative.clone()
+    }
+
+    /// collections the admin api of the version has
+    fn serves(&self, collection: &str) -> bool {
+        match collection {
+            "apis" => self.major_minor < (1, 0),
+            "services" | "routes" => self.major_minor >= (0, 13),
+            _ => true,
+        }
+    }
+
+    fn locate(&self, store: &Store, path: &str) -> Result<Location, BackendError> {
+        let segments: Vec<&str> = path.split('/').collect();
+        let location = match segments.as_slice() {
+            [collection] => Location {
+                collection: collection.to_string(),
+                parent: None,
+            },
+            [parent, key, collection] => {
+                let field = REFERENCES
+                    .iter()
+                    .find(|(c, _)| c == parent)
+                    .map(|(_, field)| *field)
+                    .ok_or(BackendError::NotFound)?;
+                let id = store
+                    .lookup(&Location::flat(parent), key)
+                    .map(|idx| id_of(&store.entities[*parent][idx]))
+                    .ok_or(BackendError::NotFound)?;
+                Location {
+                    collection: collection.to_string(),
+                    parent: Some((field, id)),
+                }
+            }
+            _ => return Err(BackendError::NotFound),
+        };
+        if !self.serves(segments[0]) {
+            return Err(BackendError::NotFound);
+        }
+        Ok(location)
+    }
+
+    fn insert(&self, store: &mut Store, location: &Location, entity: &Value) -> Result<Value, BackendError> {
+        let mut entity = match nested(entity) {
+            Value::Object(fields) => fields,
+            other => return Err(rejected(format!("entity must be an object, not {}", other))),
+        };
+        if let Some((field, id)) = &location.parent {
+            entity.insert(field.to_string(), Value::String(id.clone()));
+        }
+        store.check_references(&entity)?;
+        store.check_unique(location, &entity, None)?;
+
+        let entities = store.entities.entry(location.collection.clone()).or_default();
+        if !entity.contains_key("id") {
+            // identical entities are told apart by how many there are already
+            let seed = format!("kong-init:{}:{}", location.collection, Value::Object(entity.clone()));
+            let id = (0..)
+                .map(|n| Uuid::new_v5(&Uuid::NAMESPACE_OID, format!("{}:{}", seed, n).as_bytes()).to_string())
+                .find(|id| !entities.iter().any(|e| e["id"].as_str() == Some(id)))
+                .unwrap();
+            entity.insert("id".to_string(), Value::String(id));
+        }
+        let id = id_of(&Value::Object(entity.clone()));
+        let generate = |what: &str| {
+            Uuid::new_v5(&Uuid::NAMESPACE_OID, format!("kong-init:{}:{}", id, what).as_bytes())
+                .to_simple()
+                .to_string()
+        };
+        let secrets = GENERATED_SECRETS
+            .iter()
+            .find(|(c, _)| *c == location.collection)
+            .map_or(&[][..], |(_, fields)| *fields);
+        for field in secrets {
+            if !entity.contains_key(*field) {
+                entity.insert(field.to_string(), Value::String(generate(field)));
+            }
+        }
+        if location.collection == "plugins" && !entity.contains_key("enabled") {
+            entity.insert("enabled".to_string(), Value::Bool(true));
+        }
+
+        let entity = Value::Object(entity);
+        let position = entities.partition_point(|e| e["id"].as_str().unwrap_or_default() < id.as_str());
+        entities.insert(position, entity.clone());
+        Ok(entity)
+    }
+}
+
+impl Location {
+    fn flat(collection: &str) -> Location {
+        Location {
+            collection: collection.to_string(),
+            parent: None,
+        }
+    }
+}
+
+impl Store {
+    /// position of the entity with `key` as id or name
+    fn lookup(&self, location: &Location, key: &str) -> Option<usize> {
+        let by_name: &[&str] = match location.collection.as_str() {
+            "consumers" => &["username"],
+            "apis" | "services" | "routes" | "upstreams" => &["name"],
+            _ => &[],
+        };
+        self.entities.get(&location.collection)?.iter().position(|e| {
+            location.contains(e)
+                && (e["id"].as_str() == Some(key)
+                    || by_name.iter().any(|f| e[*f].as_str() == Some(key))
+                    || (location.collection == "certificates"
+                        && e["snis"].as_array().is_some_and(|snis| snis.iter().any(|s| s == key))))
+        })
+    }
+
+    fn check_references(&self, entity: &SerdeMap<String, Value>) -> Result<(), BackendError> {
+        let mut references: Vec<(&str, &Value)> = REFERENCES
+            .iter()
+            .filter_map(|(collection, field)| entity.get(*field).map(|id| (*collection, id)))
+            .collect();
+        if let Some(service) = entity.get("service") {
+            references.push(("services", &service["id"]));
+        }
+        for (collection, id) in references {
+            let exists = self
+                .entities
+                .get(collection)
+                .is_some_and(|entities| entities.iter().any(|e| &e["id"] == id));
+            if !exists {
+                return Err(rejected(format!("no {} with id {}", collection, id)));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_unique(
+        &self,
+        location: &Location,
+        entity: &SerdeMap<String, Value>,
+        except: Option<usize>,
+    ) -> Result<(), BackendError> {
+        let existing: Vec<&Value> = self
+            .entities
+            .get(&location.collection)
+            .map(|entities| {
+                entities
+                    .iter()
+                    .enumerate()
+                    .filter(|(idx, _)| Some(*idx) != except)
+                    .map(|(_, e)| e)
+                    .collect()
+            }).unwrap_or_default();
+        let fields = UNIQUE_FIELDS
+            .iter()
+            .find(|(c, _)| *c == location.collection)
+            .map_or(&[][..], |(_, fields)| *fields);
+        for field in fields {
+            if let Some(value) = entity.get(*field).filter(|v| !v.is_null()) {
+                if existing.iter().any(|e| &e[*field] == value) {
+                    return Err(BackendError::Conflict(format!("{} {} already exists", field, value)));
+                }
+            }
+        }
+        let same = |e: &Value, fields: &[&str]| {
+            fields
+                .iter()
+                .all(|f| &e[*f] == entity.get(*f).unwrap_or(&Value::Null))
+        };
+        let duplicate = match location.collection.as_str() {
+            "plugins" => existing
+                .iter()
+                .any(|e| same(e, &["name", "api_id", "service_id", "route_id", "consumer_id"])),
+            "acls" => existing.iter().any(|e| same(e, &["consumer_id", "group"])),
+            _ => false,
+        };
+        if duplicate {
+            return Err(BackendError::Conflict(format!("{} already exists", location.collection)));
+        }
+        Ok(())
+    }
+}
+
+impl KongBackend for MemoryBackend {
+    fn describe(&self) -> String {
+        format!("memory(kong {})", self.version)
+    }
+
+    fn version(&self) -> Result<String, BackendError> {
+        Ok(self.version.clone())
+    }
+
+    fn list(&self, collection: &str) -> Result<Vec<Value>, BackendError> {
+        let store = self.store.lock().unwrap();
+        let location = self.locate(&store, collection)?;
+        Ok(store
+            .entities
+            .get(&location.collection)
+            .map(|entities| entities.iter().filter(|e| location.contains(e)).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn find(&self, collection: &str, field: &str, value: &str) -> Result<Vec<Value>, BackendError> {
+        Ok(self
+            .list(collection)?
+            .into_iter()
+            .filter(|e| e[field].as_str() == Some(value))
+            .collect())
+    }
+
+    fn get(&self, collection: &str, key: &str) -> Result<Value, BackendError> {
+        let store = self.store.lock().unwrap();
+        let location = self.locate(&store, collection)?;
+        let idx = store.lookup(&location, key).ok_or(BackendError::NotFound)?;
+        Ok(store.entities[&location.collection][idx].clone())
+    }
+
+    fn create(&self, collection: &str, entity: &Value) -> Result<Value, BackendError> {
+        let mut store = self.store.lock().unwrap();
+        let location = self.locate(&store, collection)?;
+        self.insert(&mut store, &location, entity)
+    }
+
+    fn upsert(&self, collection: &str, entity: &Value) -> Result<Value, BackendError> {
+        let mut store = self.store.lock().unwrap();
+        let location = self.locate(&store, collection)?;
+        let mut entity = entity.clone();
+        if let Some(idx) = entity["name"].as_str().and_then(|name| store.lookup(&location, name)) {
+            let replaced = store.entities.get_mut(&location.collection).unwrap().remove(idx);
+            entity["id"] = replaced["id"].clone();
+        }
+        self.insert(&mut store, &location, &entity)
+    }
+
+    fn update(&self, collection: &str, key: &str, fields: &Value) -> Result<Value, BackendError> {
+        let mut store = self.store.lock().unwrap();
+        let location = self.locate(&store, collection)?;
+        let idx = store.lookup(&location, key).ok_or(BackendError::NotFound)?;
+        let fields = match nested(fields) {
+            Value::Object(fields) => fields,
+            other => return Err(rejected(format!("fields must be an object, not {}", other))),
+        };
+        let mut updated = match &store.entities[&location.collection][idx] {
+            Value::Object(entity) => entity.clone(),
+            _ => SerdeMap::new(),
+        };
+        updated.extend(fields);
+        store.check_references(&updated)?;
+        store.check_unique(&location, &updated, Some(idx))?;
+        let updated = Value::Object(updated);
+        store.entities.get_mut(&location.collection).unwrap()[idx] = updated.clone();
+        Ok(updated)
+    }
+
+    fn delete(&self, collection: &str, key: &str) -> Result<(), BackendError> {
+        let mut store = self.store.lock().unwrap();
+        let location = self.locate(&store, collection)?;
+        let idx = store.lookup(&location, key).ok_or(BackendError::NotFound)?;
+        let id = id_of(&store.entities[&location.collection][idx]);
+        if location.collection == "services"
+            && store
+                .entities
+                .get("routes")
+                .is_some_and(|routes| routes.iter().any(|r| r["service"]["id"].as_str() == Some(&id)))
+        {
+            return Err(rejected(format!("service {} is still used by routes", key)));
+        }
+        store.entities.get_mut(&location.collection).unwrap().remove(idx);
+
+        // kong deletes what references the entity along
+        if let Some((_, field)) = REFERENCES.iter().find(|(c, _)| *c == location.collection) {
+            for entities in store.entities.values_mut() {
+                entities.retain(|e| e[*field].as_str() != Some(id.as_str()));
+            }
+        }
+        Ok(())
+    }
+
+    fn load_declarative(&self, config: &str) -> Result<(), BackendError> {
+        if self.major_minor < (1, 1) {
+            return Err(BackendError::NotFound);
+        }
+        self.store.lock().unwrap().declarative = Some(config.to_string());
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        let store = self.store.lock().unwrap();
+        for (collection, entities) in &store.entities {
+            info!("{}: {} {}", self.describe(), entities.len(), collection);
+            for entity in entities {
+                debug!("{} {}", collection, entity);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn rejected(message: String) -> BackendError {
+    BackendError::Rejected { status: 400, message }
+}
+
+/// `config.x` fields as nested objects, the way kong stores them
+fn nested(entity: &Value) -> Value {
+    let fields = match entity {
+        Value::Object(fields) => fields,
+        other => return other.clone(),
+    };
+    let mut result = SerdeMap::new();
+    for (key, value) in fields {
+        let mut path: Vec<&str> = key.split('.').collect();
+        let last = path.pop().unwrap_or_default();
+        let mut current = &mut result;
+        for part in path {
+            let inner = current
+                .entry(part.to_string())
+                .or_insert_with(|| Value::Object(SerdeMap::new()));
+            if !inner.is_object() {
+                *inner = Value::Object(SerdeMap::new());
+            }
+            current = inner.as_object_mut().unwrap();
+        }
+        current.insert(last.to_string(), value.clone());
+    }
+    Value::Object(result)
+}
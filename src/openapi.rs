@@ -0,0 +1,368 @@
+use entity::{KongConf, PluginInfo, RouteInfo, ServiceInfo};
+use regex::{self, Regex};
+use serde_json::{Map as SerdeMap, Value};
+use std::collections::BTreeMap;
+
+const METHODS: &[&str] = &["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+const PLUGIN_EXT_PREFIX: &str = "x-kong-plugin-";
+
+/// operations of one path sharing the same auth and plugins end up in the same route
+struct RouteGroup {
+    methods: Vec<String>,
+    operation_ids: Vec<String>,
+    security: Vec<String>,
+    plugins: BTreeMap<String, Value>,
+}
+
+/// Generate services, routes and plugins from an OpenAPI 3 spec.
+///
+/// The first of `servers` becomes the service, every path becomes a route per group of methods
+/// sharing the same security requirements and `x-kong-plugin-<name>` extensions.
+pub fn import(spec: &Value, service_name: Option<&str>) -> Result<KongConf, String> {
+    match spec.get("openapi").and_then(|v| v.as_str()) {
+        Some(version) if version.starts_with("3.") => {}
+        other => return Err(format!("only OpenAPI 3 specs are supported, got openapi={:?}", other)),
+    }
+
+    let service_name = match service_name {
+        Some(name) => name.to_string(),
+        None => spec
+            .get("x-kong-name")
+            .or_else(|| spec.pointer("/info/title"))
+            .and_then(|v| v.as_str())
+            .map(slugify)
+            .ok_or_else(|| "spec has no info.title, use --service-name".to_string())?,
+    };
+    let service = build_service(spec, &service_name)?;
+
+    let schemes = spec
+        .pointer("/components/securitySchemes")
+        .and_then(|v| v.as_object())
+        .cloned()
+        .unwrap_or_default();
+    let root_security = security_of(spec.get("security"));
+    let root_route_defaults = object_of(spec.get("x-kong-route-defaults"));
+
+    let mut routes = Vec::new();
+    // (route name, schemes, route scoped plugins) of every generated route
+    let mut route_plugins: Vec<(String, Vec<String>, BTreeMap<String, Value>)> = Vec::new();
+    let empty = SerdeMap::new();
+    for (path, item) in spec.get("paths").and_then(|v| v.as_object()).unwrap_or(&empty) {
+        let mut route_defaults = root_route_defaults.clone();
+        route_defaults.extend(object_of(item.get("x-kong-route-defaults")));
+
+        let groups = group_operations(item, &root_security);
+        for group in &groups {
+            let name = route_name(&service_name, path, group, groups.len() > 1);
+            let mut config: BTreeMap<String, Value> = BTreeMap::new();
+            config.insert("paths".to_string(), json!([kong_path(path)]));
+            config.insert("methods".to_string(), json!(group.methods));
+            // spec paths are full paths below the server url
+            config.insert("strip_path".to_string(), Value::Bool(false));
+            config.extend(route_defaults.clone());
+
+            routes.push(RouteInfo {
+                name: name.clone(),
+                apply_to: service_name.clone(),
+                config,
+            });
+            route_plugins.push((name, group.security.clone(), group.plugins.clone()));
+        }
+    }
+
+    let mut plugins = Vec::new();
+    for (name, ext) in plugin_extensions(spec) {
+        plugins.push(plugin_info(&name, &ext, format!("s[{}]", service_name))?);
+    }
+
+    // auth goes onto the service when every route shares the root requirements,
+    // otherwise onto the routes: a route cannot opt out of a service plugin.
+    let uniform = route_plugins.iter().all(|(_, security, _)| *security == root_security);
+    let mut auth: Vec<(String, Vec<String>)> = Vec::new();
+    if uniform {
+        for scheme in &root_security {
+            auth.push((scheme.clone(), Vec::new()));
+        }
+    } else {
+        for (route, security, _) in &route_plugins {
+            for scheme in security {
+                match auth.iter_mut().find(|(s, _)| s == scheme) {
+                    Some((_, routes)) => routes.push(route.clone()),
+                    None => auth.push((scheme.clone(), vec![route.clone()])),
+                }
+            }
+        }
+    }
+    for (scheme, routes) in auth {
+        let definition = schemes
+            .get(&scheme)
+            .ok_or_else(|| format!("security scheme {} is not defined in components", scheme))?;
+        let (plugin, config) = match auth_plugin(&scheme, definition) {
+            Some(auth) => auth,
+            None => continue,
+        };
+        let target = if uniform {
+            format!("s[{}]", service_name)
+        } else {
+            format!("r[{}]", routes.join(","))
+        };
+        plugins.push(PluginInfo {
+            name: plugin,
+            target,
+            config,
+            enabled: true,
+        });
+    }
+
+    for (route, _, extensions) in &route_plugins {
+        for (name, ext) in extensions {
+            plugins.push(plugin_info(name, ext, format!("r[{}]", route))?);
+        }
+    }
+
+    Ok(KongConf {
+        upstreams: None,
+        services: vec![service],
+        routes,
+        plugins: if plugins.is_empty() { None } else { Some(plugins) },
+        consumers: None,
+        consumers_from: None,
+        credentials: None,
+        certificates: None,
+    })
+}
+
+fn build_service(spec: &Value, service_name: &str) -> Result<ServiceInfo, String> {
+    let defaults = object_of(spec.get("x-kong-service-defaults"));
+    let servers = spec.get("servers").and_then(|v| v.as_array());
+    if servers.is_some_and(|s| s.len() > 1) {
+        warn!("spec declares several servers, only the first one is used");
+    }
+
+    let mut service = ServiceInfo::new();
+    service.insert("name".to_string(), Value::String(service_name.to_string()));
+    match servers.and_then(|s| s.first()) {
+        Some(server) => {
+            let url = server_url(server)?;
+            if url.starts_with('/') && !defaults.contains_key("url") && !defaults.contains_key("host") {
+                return Err(format!(
+                    "server url {} is relative, set the upstream in `x-kong-service-defaults`",
+                    url
+                ));
+            }
+            if !url.starts_with('/') {
+                service.insert("url".to_string(), Value::String(url));
+            } else if !defaults.contains_key("url") {
+                service.insert("path".to_string(), Value::String(url));
+            }
+        }
+        None if defaults.contains_key("url") || defaults.contains_key("host") => {}
+        None => {
+            return Err(
+                "spec declares no servers, set the upstream in `x-kong-service-defaults`".to_string(),
+            )
+        }
+    }
+    service.extend(defaults);
+    Ok(service)
+}
+
+/// server url with its `{variables}` set to their defaults
+fn server_url(server: &Value) -> Result<String, String> {
+    let url = server
+        .get("url")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "server without url".to_string())?;
+    let var_re = Regex::new(r"\{(.+?)}").unwrap();
+    let mut missing = None;
+    let url = var_re.replace_all(url, |caps: &regex::Captures| {
+        match server
+            .pointer(&format!("/variables/{}/default", &caps[1]))
+            .and_then(|v| v.as_str())
+        {
+            Some(default) => default.to_string(),
+            None => {
+                missing = Some(caps[1].to_string());
+                String::new()
+            }
+        }
+    });
+    match missing {
+        Some(var) => Err(format!("server variable {} has no default", var)),
+        None => Ok(url.trim_end_matches('/').to_string()),
+    }
+}
+
+fn group_operations(item: &Value, root_security: &[String]) -> Vec<RouteGroup> {
+    let path_plugins = plugin_extensions(item);
+    let mut groups: Vec<RouteGroup> = Vec::new();
+
+    for method in METHODS {
+        let op = match item.get(*method) {
+            Some(op) => op,
+            None => continue,
+        };
+        let security = match op.get("security") {
+            Some(security) => security_of(Some(security)),
+            None => root_security.to_vec(),
+        };
+        let mut plugins = path_plugins.clone();
+        plugins.extend(plugin_extensions(op));
+
+        let operation_id = op.get("operationId").and_then(|v| v.as_str());
+        match groups
+            .iter_mut()
+            .find(|g| g.security == security && g.plugins == plugins)
+        {
+            Some(group) => {
+                group.methods.push(method.to_uppercase());
+                group.operation_ids.extend(operation_id.map(String::from));
+            }
+            None => groups.push(RouteGroup {
+                methods: vec![method.to_uppercase()],
+                operation_ids: operation_id.map(String::from).into_iter().collect(),
+                security,
+                plugins,
+            }),
+        }
+    }
+    groups
+}
+
+/// names of the schemes of all requirement alternatives, in order of appearance
+fn security_of(security: Option<&Value>) -> Vec<String> {
+    let requirements = security.and_then(|v| v.as_array()).map_or(&[][..], |a| &a[..]);
+    if requirements.len() > 1 {
+        warn!("alternative security requirements are all enabled, configure `anonymous` on the auth plugins to accept either");
+    }
+    let mut schemes = Vec::new();
+    for requirement in requirements {
+        for scheme in requirement.as_object().map(|r| r.keys()).into_iter().flatten() {
+            if !schemes.contains(scheme) {
+                schemes.push(scheme.clone());
+            }
+        }
+    }
+    schemes
+}
+
+/// the kong plugin implementing a security scheme
+fn auth_plugin(scheme: &str, definition: &Value) -> Option<(String, BTreeMap<String, Value>)> {
+    let field = |name: &str| definition.get(name).and_then(|v| v.as_str()).unwrap_or("");
+    let mut config = BTreeMap::new();
+    let plugin = match field("type") {
+        "http" if field("scheme").eq_ignore_ascii_case("bearer") => "jwt",
+        "apiKey" => {
+            config.insert("key_names".to_string(), json!([field("name")]));
+            if field("in") == "query" {
+                config.insert("key_in_body".to_string(), Value::Bool(false));
+            }
+            "key-auth"
+        }
+        "oauth2" => {
+            let flows = object_of(definition.get("flows"));
+            let mut scopes: Vec<String> = Vec::new();
+            for flow in flows.values() {
+                for scope in object_of(flow.get("scopes")).keys() {
+                    if !scopes.contains(scope) {
+                        scopes.push(scope.clone());
+                    }
+                }
+            }
+            for (flow, grant) in &[
+                ("authorizationCode", "enable_authorization_code"),
+                ("implicit", "enable_implicit_grant"),
+                ("clientCredentials", "enable_client_credentials"),
+                ("password", "enable_password_grant"),
+            ] {
+                if flows.contains_key(*flow) {
+                    config.insert(grant.to_string(), Value::Bool(true));
+                }
+            }
+            if !scopes.is_empty() {
+                config.insert("scopes".to_string(), json!(scopes));
+                config.insert("mandatory_scope".to_string(), Value::Bool(true));
+            }
+            "oauth2"
+        }
+        other => {
+            warn!(
+                "security scheme {}(type {}) has no kong plugin equivalent, skipped",
+                scheme, other
+            );
+            return None;
+        }
+    };
+    Some((plugin.to_string(), config))
+}
+
+/// `x-kong-plugin-<name>` extensions of a spec object
+fn plugin_extensions(object: &Value) -> BTreeMap<String, Value> {
+    object_of(Some(object))
+        .into_iter()
+        .filter_map(|(k, v)| k.strip_prefix(PLUGIN_EXT_PREFIX).map(|name| (name.to_string(), v)))
+        .collect()
+}
+
+/// `x-kong-plugin-<name>: { enabled: .., config: {..} }`
+fn plugin_info(name: &str, ext: &Value, target: String) -> Result<PluginInfo, String> {
+    if !ext.is_object() && !ext.is_null() {
+        return Err(format!("{}{} must be a mapping", PLUGIN_EXT_PREFIX, name));
+    }
+    Ok(PluginInfo {
+        name: name.to_string(),
+        target,
+        config: object_of(ext.get("config")).into_iter().collect(),
+        enabled: ext.get("enabled").and_then(|v| v.as_bool()).unwrap_or(true),
+    })
+}
+
+fn object_of(v: Option<&Value>) -> SerdeMap<String, Value> {
+    v.and_then(|v| v.as_object()).cloned().unwrap_or_default()
+}
+
+fn route_name(service: &str, path: &str, group: &RouteGroup, split: bool) -> String {
+    if group.operation_ids.len() == 1 && group.methods.len() == 1 {
+        return slugify(&group.operation_ids[0]);
+    }
+    let mut name = format!("{}-{}", service, slugify(path));
+    if split {
+        name = format!("{}-{}", name, group.methods.join("-").to_lowercase());
+    }
+    name.trim_end_matches('-').to_string()
+}
+
+/// `/pets/{petId}` -> `/pets/(?<petId>[^/]+)$`, plain paths stay prefix matches
+fn kong_path(path: &str) -> String {
+    let param_re = Regex::new(r"\{([^}/]+)}").unwrap();
+    if !param_re.is_match(path) {
+        return path.to_string();
+    }
+    let mut kong_path = String::new();
+    let mut last = 0;
+    for caps in param_re.captures_iter(path) {
+        let whole = caps.get(0).unwrap();
+        kong_path.push_str(&regex::escape(&path[last..whole.start()]));
+        let group: String = caps[1]
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        kong_path.push_str(&format!("(?<{}>[^/]+)", group));
+        last = whole.end();
+    }
+    kong_path.push_str(&regex::escape(&path[last..]));
+    kong_path.push('$');
+    kong_path
+}
+
+fn slugify(s: &str) -> String {
+    let mut slug = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
@@ -0,0 +1,334 @@
+use serde_yaml::{Mapping, Value};
+
+const PATCH_KEY: &str = "$patch";
+const PATCH_DELETE: &str = "delete";
+
+/// Deep-merge `overlay` on top of `base`, kustomize style.
+///
+/// Top-level entity sections (services, routes, plugins, ...) are merged entry by entry,
+/// matched by the entity's natural key. Any entry or mapping value marked with
+/// `$patch: delete` removes its counterpart from `base` instead of being merged.
+pub fn merge_document(base: &mut Value, overlay: Value) {
+    match (base.as_mapping_mut(), overlay) {
+        (Some(base_map), Value::Mapping(overlay_map)) => {
+            for (k, v) in overlay_map {
+                let section = k.as_str().map(String::from);
+                let is_entity_list = v.is_sequence()
+                    && section.as_ref().is_some_and(|s| is_entity_section(s));
+
+                if is_patch_delete(&v) {
+                    base_map.remove(&k);
+                } else if is_entity_list {
+                    let section = section.unwrap();
+                    let merged = match base_map.remove(&k) {
+                        Some(Value::Sequence(base_entries)) => {
+                            merge_entities(&section, base_entries, v)
+                        }
+                        _ => merge_entities(&section, Vec::new(), v),
+                    };
+                    base_map.insert(k, merged);
+                } else {
+                    merge_entry(base_map, k, v);
+                }
+            }
+        }
+        (_, overlay) => *base = strip_patch_markers(overlay),
+    }
+}
+
+fn is_entity_section(section: &str) -> bool {
+    matches!(
+        section,
+        "apis"
+            | "upstreams"
+            | "services"
+            | "routes"
+            | "plugins"
+            | "consumers"
+            | "credentials"
+            | "certificates"
+    )
+}
+
+fn is_patch_delete(v: &Value) -> bool {
+    v.get(PATCH_KEY).and_then(|p| p.as_str()) == Some(PATCH_DELETE)
+}
+
+/// natural key used to match an entity of `section` across documents
+fn entity_key(section: &str, entity: &Value) -> Option<String> {
+    let field = |name: &str| entity.get(name).and_then(scalar_to_string);
+    match section {
+        "consumers" => field("username").or_else(|| field("custom_id")),
+        "credentials" => {
+            let natural = ["key", "username", "client_id", "group"]
+                .iter()
+                .filter_map(|f| entity.get("config").and_then(|c| c.get(*f)))
+                .filter_map(scalar_to_string)
+                .next()
+                .unwrap_or_default();
+            Some(format!("{}/{}/{}", field("name")?, field("target")?, natural))
+        }
+        "certificates" => entity
+            .get("snis")
+            .and_then(|snis| snis.as_sequence())
+            .and_then(|snis| snis.iter().filter_map(scalar_to_string).next())
+            .or_else(|| field("cert")),
+        // legacy plugins carry a unique custom name, suggested ones are keyed by type + target
+        "plugins" if entity.get("plugin_type").is_none() => {
+            Some(format!("{}/{}", field("name")?, field("target")?))
+        }
+        _ => field("name"),
+    }
+}
+
+fn scalar_to_string(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn merge_entities(section: &str, mut base: Vec<Value>, overlay: Value) -> Value {
+    let overlay_entries = match overlay {
+        Value::Sequence(entries) => entries,
+        _ => Vec::new(),
+    };
+
+    for entry in overlay_entries {
+        let key = entity_key(section, &entry);
+        let pos = key.as_ref().and_then(|key| {
+            base.iter()
+                .position(|b| entity_key(section, b).as_ref() == Some(key))
+        });
+
+        match pos {
+            Some(idx) if is_patch_delete(&entry) => {
+                debug!("overlay: remove {} {:?}", section, key);
+                base.remove(idx);
+            }
+            Some(idx) => {
+                debug!("overlay: merge {} {:?}", section, key);
+                merge_value(&mut base[idx], entry);
+            }
+            None if is_patch_delete(&entry) => {
+                warn!("overlay: {} {:?} marked for deletion but not found", section, key);
+            }
+            None => base.push(strip_patch_markers(entry)),
+        }
+    }
+    Value::Sequence(base)
+}
+
+fn merge_value(base: &mut Value, overlay: Value) {
+    match (base.as_mapping_mut(), overlay) {
+        (Some(base_map), Value::Mapping(overlay_map)) => {
+            for (k, v) in overlay_map {
+                merge_entry(base_map, k, v);
+            }
+        }
+        (_, overlay) => *base = strip_patch_markers(overlay),
+    }
+}
+
+fn merge_entry(base_map: &mut Mapping, k: Value, v: Value) {
+    if is_patch_delete(&v) {
+        base_map.remove(&k);
+        return;
+    }
+    match base_map.get_mut(&k) {
+        Some(existing) => merge_value(existing, v),
+        None => {
+            base_map.insert(k, strip_patch_markers(v));
+        }
+    }
+}
+
+/// drop delete markers that have nothing left to delete
+fn strip_patch_markers(v: Value) -> Value {
+    match v {
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .filter(|(_, v)| !is_patch_delete(v))
+                .map(|(k, v)| (k, strip_patch_markers(v)))
+                .collect(),
+        ),
+        Value::Sequence(seq) => Value::Sequence(
+            seq.into_iter()
+                .filter(|v| !is_patch_delete(v))
+                .map(strip_patch_markers)
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::UpstreamInfo;
+    use loader::{self, Loader};
+    use serde_yaml;
+    use std::env;
+    use std::fs;
+
+    fn yaml(text: &str) -> Value {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    fn layered(docs: &[&str]) -> Value {
+        let mut merged = Value::Null;
+        for doc in docs {
+            merge_document(&mut merged, yaml(doc));
+        }
+        merged
+    }
+
+    #[test]
+    fn later_files_take_precedence() {
+        let merged = layered(&[
+            "services:\n  - name: orders\n    url: http://orders:8080\n    retries: 3\n",
+            "services:\n  - name: orders\n    url: http://orders-staging:8080\n",
+            "services:\n  - name: orders\n    url: http://orders-prod:8080\n",
+        ]);
+        assert_eq!(
+            merged,
+            yaml("services:\n  - name: orders\n    url: http://orders-prod:8080\n    retries: 3\n")
+        );
+    }
+
+    #[test]
+    fn directories_are_layered_by_file_name() {
+        let dir = env::temp_dir().join(format!("kong-init-overlay-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("10-base.yaml"), "services:\n  - name: orders\n    url: http://base\n").unwrap();
+        fs::write(dir.join("20-prod.yml"), "services:\n  - name: orders\n    url: http://prod\n").unwrap();
+        fs::write(dir.join("README.md"), "not a config file").unwrap();
+
+        let sources = loader::expand_template_paths(&[dir.to_str().unwrap()]).unwrap();
+        assert_eq!(
+            sources,
+            vec![
+                loader::Source::File(dir.join("10-base.yaml")),
+                loader::Source::File(dir.join("20-prod.yml")),
+            ]
+        );
+        let same = |text: &str| text.to_string();
+        let loader = Loader::new(None, &[], &same);
+        let mut merged = Value::Null;
+        for source in &sources {
+            merge_document(&mut merged, loader.load(source).unwrap());
+        }
+        assert_eq!(merged, yaml("services:\n  - name: orders\n    url: http://prod\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entities_are_merged_by_natural_key() {
+        let merged = layered(&[
+            "services:
+  - name: orders
+    url: http://orders
+  - name: billing
+    url: http://billing
+plugins:
+  - name: rate-limiting
+    target: s[orders]
+    config:
+      minute: 20
+",
+            "services:
+  - name: billing
+    url: http://billing-v2
+  - name: invoices
+    url: http://invoices
+plugins:
+  - name: rate-limiting
+    target: s[orders]
+    config:
+      hour: 500
+  - name: rate-limiting
+    target: s[billing]
+    config:
+      minute: 5
+",
+        ]);
+        assert_eq!(
+            merged,
+            yaml(
+                "services:
+  - name: orders
+    url: http://orders
+  - name: billing
+    url: http://billing-v2
+  - name: invoices
+    url: http://invoices
+plugins:
+  - name: rate-limiting
+    target: s[orders]
+    config:
+      minute: 20
+      hour: 500
+  - name: rate-limiting
+    target: s[billing]
+    config:
+      minute: 5
+"
+            )
+        );
+    }
+
+    #[test]
+    fn upstreams_are_merged_by_name() {
+        let merged = layered(&[
+            "upstreams:
+  - name: orders
+    targets:
+      - target: orders-1:8080
+    config:
+      hash_on: consumer
+      slots: 1000
+",
+            "upstreams:
+  - name: orders
+    config:
+      slots: 100
+",
+        ]);
+        let upstreams: Vec<UpstreamInfo> = serde_yaml::from_value(merged["upstreams"].clone()).unwrap();
+        assert_eq!(upstreams.len(), 1);
+        assert_eq!(upstreams[0].targets.len(), 1);
+        assert_eq!(upstreams[0].config["hash_on"], json!("consumer"));
+        assert_eq!(upstreams[0].config["slots"], json!(100));
+    }
+
+    #[test]
+    fn patch_delete_removes_entities_and_fields() {
+        let merged = layered(&[
+            "services:
+  - name: orders
+    url: http://orders
+    retries: 3
+  - name: legacy
+    url: http://legacy
+plugins:
+  - name: prometheus
+    target: global
+",
+            "services:
+  - name: legacy
+    $patch: delete
+  - name: orders
+    retries:
+      $patch: delete
+  - name: unknown
+    $patch: delete
+plugins:
+  $patch: delete
+",
+        ]);
+        assert_eq!(merged, yaml("services:\n  - name: orders\n    url: http://orders\n"));
+    }
+}
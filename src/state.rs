@@ -0,0 +1,112 @@
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// What kong-init has to remember between runs, kept in the `--state` file.
+///
+/// The file holds secrets, it is written with mode 0600.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// generated credential secrets by `<consumer>:<credential type>:<identity>`(see `credential::fill_secrets`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, BTreeMap<String, String>>,
+    /// ids of the consumers kong-init created or reconciled, the only ones `--prune` deletes
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub consumers: BTreeSet<String>,
+
+    #[serde(skip)]
+    path: Option<String>,
+}
+
+impl State {
+    /// a missing file is an empty state, without a path nothing is persisted
+    pub fn load(path: Option<&str>) -> Result<State, String> {
+        let mut state = match path {
+            Some(path) if Path::new(path).exists() => {
+                let contents = fs::read_to_string(path).map_err(|why| format!("{}: {}", path, why))?;
+                serde_json::from_str(&contents).map_err(|why| format!("{}: {}", path, why))?
+            }
+            _ => State::default(),
+        };
+        state.path = path.map(String::from);
+        Ok(state)
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        match &self.path {
+            None => Ok(()),
+            Some(path) => {
+                let text = serde_json::to_string_pretty(self).map_err(|why| why.to_string())?;
+                // write aside and rename, an interrupted run must not lose the secrets
+                let tmp = format!("{}.tmp", path);
+                write_private(&tmp, &text)?;
+                fs::rename(&tmp, path).map_err(|why| format!("{}: {}", path, why))
+            }
+        }
+    }
+}
+
+/// write a file only its owner can read, `-` prints to stdout
+pub fn write_private(path: &str, text: &str) -> Result<(), String> {
+    if path == "-" {
+        println!("{}", text);
+        return Ok(());
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).map_err(|why| format!("{}: {}", path, why))?;
+    // an existing file keeps its mode on open
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(|why| format!("{}: {}", path, why))?;
+    }
+    file.write_all(format!("{}\n", text).as_bytes())
+        .map_err(|why| format!("{}: {}", path, why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn saved_state_is_private_and_reloaded() {
+        let path = env::temp_dir().join(format!("kong-init-state-{}.json", ::std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut state = State::load(Some(path)).unwrap();
+        assert!(state.is_persistent() && state.secrets.is_empty());
+        let mut secret = BTreeMap::new();
+        secret.insert("secret".to_string(), "kept".to_string());
+        state.secrets.insert("billing:jwt:{}".to_string(), secret.clone());
+        state.save().unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert_eq!(State::load(Some(path)).unwrap().secrets["billing:jwt:{}"], secret);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn states_without_a_path_are_not_saved() {
+        let state = State::load(None).unwrap();
+        assert!(!state.is_persistent());
+        assert!(state.save().is_ok());
+    }
+}
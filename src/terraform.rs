@@ -0,0 +1,345 @@
+use entity::{
+    parse_target, CertificateInfo, ConsumerInfo, CredentialsInfo, KongConf, PluginInfo, Target,
+    UpstreamInfo,
+};
+use regex::{Captures, Regex};
+use reqwest::Url;
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+
+/// marks a consumer reference inside a string, see `consumer_reference`
+const REF_OPEN: &str = "__tf_ref_kong_consumer.";
+const REF_CLOSE: &str = ".id__";
+
+/// service/route fields taken over as is by `kong_service`/`kong_route`
+const SERVICE_FIELDS: &[&str] = &[
+    "protocol", "host", "port", "path", "retries", "connect_timeout", "write_timeout", "read_timeout",
+];
+const ROUTE_FIELDS: &[&str] = &[
+    "protocols", "methods", "hosts", "paths", "strip_path", "preserve_host", "regex_priority",
+    "https_redirect_status_code", "path_handling", "snis", "headers",
+];
+
+/// Placeholder resolving `{{k-upsert-consumer:<custom_id>}}` to the id of the `kong_consumer`
+/// resource once rendered, Terraform assigns the ids.
+pub fn consumer_reference(custom_id: &str) -> String {
+    format!("{}{}{}", REF_OPEN, ident(custom_id), REF_CLOSE)
+}
+
+/// Convert a parsed config into HCL for the Kong Terraform provider(kevholditch/kong).
+///
+/// Entities refer to each other through resource references instead of names,
+/// e.g. a route's `service_id = kong_service.<name>.id`.
+pub fn render(conf: &KongConf, guest_consumers: &[String]) -> Result<String, String> {
+    let mut hcl = Hcl::default();
+
+    for upstream_info in conf.upstreams.as_ref().map_or(&[][..], |u| &u[..]) {
+        render_upstream(&mut hcl, upstream_info)?;
+    }
+
+    for service_info in &conf.services {
+        let name = match service_info.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            other => {
+                return Err(format!(
+                    "service name={:?}, the `name` field must be String type",
+                    other
+                ))
+            }
+        };
+        let mut attrs = vec![("name".to_string(), Attr::value(&name))];
+        if let Some(raw) = service_info.get("url").and_then(|v| v.as_str()) {
+            let url = Url::parse(raw).map_err(|why| format!("service {}: {}: {}", name, raw, why))?;
+            attrs.push(("protocol".to_string(), Attr::value(url.scheme())));
+            attrs.push(("host".to_string(), Attr::value(url.host_str().unwrap_or(""))));
+            if let Some(port) = url.port_or_known_default() {
+                attrs.push(("port".to_string(), Attr::Value(json!(port))));
+            }
+            if url.path() != "/" {
+                attrs.push(("path".to_string(), Attr::value(url.path())));
+            }
+        }
+        attrs.extend(known_fields(&name, service_info, SERVICE_FIELDS, &["name", "url"]));
+        hcl.resource("kong_service", &name, attrs)?;
+    }
+
+    for route_info in &conf.routes {
+        if !conf.services.iter().any(|s| s.get("name") == Some(&Value::String(route_info.apply_to.clone()))) {
+            return Err(format!(
+                "route {} applies to unknown service {}",
+                route_info.name, route_info.apply_to
+            ));
+        }
+        let mut attrs = vec![
+            ("name".to_string(), Attr::value(&route_info.name)),
+            ("service_id".to_string(), Attr::reference("kong_service", &route_info.apply_to)),
+        ];
+        attrs.extend(known_fields(&route_info.name, &route_info.config, ROUTE_FIELDS, &[]));
+        hcl.resource("kong_route", &route_info.name, attrs)?;
+    }
+
+    for plugin_info in conf.plugins.as_ref().map_or(&[][..], |p| &p[..]) {
+        render_plugin(&mut hcl, plugin_info)?;
+    }
+
+    let mut consumers: Vec<ConsumerInfo> = conf.consumers.as_ref().map_or(Vec::new(), |c| c.clone());
+    for custom_id in guest_consumers {
+        if !consumers.iter().any(|c| c.username.as_ref() == Some(custom_id)) {
+            consumers.push(ConsumerInfo::guest(custom_id));
+        }
+    }
+    for consumer in &consumers {
+        let name = consumer
+            .name()
+            .ok_or_else(|| format!("consumer without username/custom_id: {:?}", consumer))?;
+        let attrs = [("username", &consumer.username), ("custom_id", &consumer.custom_id)]
+            .iter()
+            .filter_map(|(k, v)| v.as_ref().map(|v| (k.to_string(), Attr::value(v))))
+            .collect();
+        hcl.resource("kong_consumer", name, attrs)?;
+    }
+
+    for (idx, credential) in conf.credentials.as_ref().map_or(&[][..], |c| &c[..]).iter().enumerate() {
+        let consumer = consumers
+            .iter()
+            .find(|c| c.is_target(&credential.target))
+            .ok_or_else(|| {
+                format!(
+                    "credential {} targets unknown consumer {}",
+                    credential.name, credential.target
+                )
+            })?;
+        let owner = consumer.name().unwrap();
+        render_credential(&mut hcl, idx, owner, credential)?;
+    }
+
+    for certificate_info in conf.certificates.as_ref().map_or(&[][..], |c| &c[..]) {
+        render_certificate(&mut hcl, certificate_info)?;
+    }
+
+    Ok(hcl.finish())
+}
+
+fn render_upstream(hcl: &mut Hcl, upstream_info: &UpstreamInfo) -> Result<(), String> {
+    let mut attrs = vec![("name".to_string(), Attr::value(&upstream_info.name))];
+    for (k, v) in &upstream_info.config {
+        attrs.push((k.clone(), Attr::Value(v.clone())));
+    }
+    hcl.resource("kong_upstream", &upstream_info.name, attrs)?;
+
+    for target in &upstream_info.targets {
+        let mut attrs = vec![
+            ("target".to_string(), Attr::value(&target.target)),
+            ("upstream_id".to_string(), Attr::reference("kong_upstream", &upstream_info.name)),
+        ];
+        // the provider requires a weight, 100 is kong's default
+        attrs.push(("weight".to_string(), Attr::Value(json!(target.weight.unwrap_or(100)))));
+        hcl.resource("kong_target", &format!("{}_{}", upstream_info.name, target.target), attrs)?;
+    }
+    Ok(())
+}
+
+fn render_plugin(hcl: &mut Hcl, plugin_info: &PluginInfo) -> Result<(), String> {
+    let owners: Vec<(Option<(&str, String)>, String)> = match parse_target(&plugin_info.target)? {
+        Target::Global => vec![(None, format!("{}_global", plugin_info.name))],
+        Target::Services(names) => names
+            .into_iter()
+            .map(|n| (Some(("service_id", reference("kong_service", &n))), format!("{}_s_{}", plugin_info.name, n)))
+            .collect(),
+        Target::Routes(names) => names
+            .into_iter()
+            .map(|n| (Some(("route_id", reference("kong_route", &n))), format!("{}_r_{}", plugin_info.name, n)))
+            .collect(),
+    };
+
+    for (owner, resource) in owners {
+        let mut attrs = vec![("name".to_string(), Attr::value(&plugin_info.name))];
+        if let Some((field, reference)) = owner {
+            attrs.push((field.to_string(), Attr::Raw(reference)));
+        }
+        attrs.push(("enabled".to_string(), Attr::Value(Value::Bool(plugin_info.enabled))));
+        if !plugin_info.config.is_empty() {
+            let config = serde_json::to_string_pretty(&plugin_info.config).map_err(|why| why.to_string())?;
+            attrs.push(("config_json".to_string(), Attr::Heredoc(config)));
+        }
+        hcl.resource("kong_plugin", &resource, attrs)?;
+    }
+    Ok(())
+}
+
+fn render_credential(
+    hcl: &mut Hcl,
+    idx: usize,
+    owner: &str,
+    credential: &CredentialsInfo,
+) -> Result<(), String> {
+    let (resource_type, fields): (&str, &[&str]) = match credential.name.as_str() {
+        "jwt" => ("kong_consumer_jwt_auth", &["algorithm", "key", "rsa_public_key", "secret"]),
+        "key-auth" => ("kong_consumer_key_auth", &["key"]),
+        "basic-auth" => ("kong_consumer_basic_auth", &["username", "password"]),
+        "acls" | "acl" => ("kong_consumer_acl", &["group"]),
+        "oauth2" => (
+            "kong_consumer_oauth2",
+            &["name", "client_id", "client_secret", "hash_secret", "redirect_uris"],
+        ),
+        other => {
+            warn!("credential {} of consumer {} has no terraform resource, skipped", other, owner);
+            return Ok(());
+        }
+    };
+
+    let mut attrs = vec![("consumer_id".to_string(), Attr::reference("kong_consumer", owner))];
+    for (k, v) in &credential.config {
+        if !fields.contains(&k.as_str()) {
+            warn!("credential {} of consumer {}: field {} is not supported, skipped", credential.name, owner, k);
+        } else if let (Some(uris), "redirect_uris") = (v.as_str(), k.as_str()) {
+            // form style `a,b` lists
+            let uris: Vec<&str> = uris.split(',').map(|s| s.trim()).collect();
+            attrs.push((k.clone(), Attr::Value(json!(uris))));
+        } else if let (Some(flag), "hash_secret") = (v.as_str(), k.as_str()) {
+            attrs.push((k.clone(), Attr::Value(Value::Bool(flag == "true"))));
+        } else {
+            attrs.push((k.clone(), Attr::Value(v.clone())));
+        }
+    }
+    hcl.resource(resource_type, &format!("{}_{}_{}", owner, credential.name, idx + 1), attrs)
+}
+
+fn render_certificate(hcl: &mut Hcl, certificate_info: &CertificateInfo) -> Result<(), String> {
+    let name = certificate_info
+        .snis
+        .first()
+        .cloned()
+        .unwrap_or_else(|| "certificate".to_string());
+    let mut attrs = vec![
+        ("certificate".to_string(), Attr::Heredoc(certificate_info.cert.trim_end().to_string())),
+        ("private_key".to_string(), Attr::Heredoc(certificate_info.key.trim_end().to_string())),
+    ];
+    if !certificate_info.snis.is_empty() {
+        attrs.push(("snis".to_string(), Attr::Value(json!(certificate_info.snis))));
+    }
+    hcl.resource("kong_certificate", &name, attrs)
+}
+
+/// fields of `entity` the provider knows, in the order of `known`
+fn known_fields(
+    owner: &str,
+    entity: &BTreeMap<String, Value>,
+    known: &[&str],
+    handled: &[&str],
+) -> Vec<(String, Attr)> {
+    for field in entity.keys() {
+        if !known.contains(&field.as_str()) && !handled.contains(&field.as_str()) {
+            warn!("{}: field {} is not supported by the terraform provider, skipped", owner, field);
+        }
+    }
+    known
+        .iter()
+        .filter_map(|k| entity.get(*k).map(|v| (k.to_string(), Attr::Value(v.clone()))))
+        .collect()
+}
+
+enum Attr {
+    Value(Value),
+    /// an expression written as is, e.g. a resource reference
+    Raw(String),
+    /// a multi-line string
+    Heredoc(String),
+}
+
+impl Attr {
+    fn value(s: &str) -> Attr {
+        Attr::Value(Value::String(s.to_string()))
+    }
+
+    fn reference(resource_type: &str, name: &str) -> Attr {
+        Attr::Raw(reference(resource_type, name))
+    }
+}
+
+fn reference(resource_type: &str, name: &str) -> String {
+    format!("{}.{}.id", resource_type, ident(name))
+}
+
+#[derive(Default)]
+struct Hcl {
+    out: String,
+    /// resource addresses in use
+    taken: Vec<String>,
+}
+
+impl Hcl {
+    fn resource(&mut self, resource_type: &str, name: &str, attrs: Vec<(String, Attr)>) -> Result<(), String> {
+        let label = ident(name);
+        let address = format!("{}.{}", resource_type, label);
+        // references are derived from names, a renamed resource would break them
+        if self.taken.contains(&address) {
+            return Err(format!("{} is declared twice(names differing only in special characters?)", address));
+        }
+        self.taken.push(address);
+
+        self.out.push_str(&format!("resource \"{}\" \"{}\" {{\n", resource_type, label));
+        let width = attrs.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+        for (k, attr) in attrs {
+            let value = match attr {
+                Attr::Value(v) => expression(&v),
+                Attr::Raw(raw) => raw,
+                Attr::Heredoc(text) => format!("<<EOT\n{}\nEOT", escape_template(&text)),
+            };
+            self.out.push_str(&format!("  {:width$} = {}\n", k, value, width = width));
+        }
+        self.out.push_str("}\n\n");
+        Ok(())
+    }
+
+    fn finish(self) -> String {
+        // consumer placeholders become interpolations once every string is escaped
+        let ref_re = Regex::new(&format!(
+            "{}([A-Za-z0-9_-]+){}",
+            REF_OPEN.replace('.', r"\."),
+            REF_CLOSE.replace('.', r"\.")
+        )).unwrap();
+        ref_re
+            .replace_all(self.out.trim_end(), |caps: &Captures| {
+                format!("${{kong_consumer.{}.id}}", &caps[1])
+            }).to_string()
+    }
+}
+
+fn expression(v: &Value) -> String {
+    match v {
+        Value::String(s) => format!(
+            "\"{}\"",
+            escape_template(&s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+        ),
+        Value::Array(items) => format!(
+            "[{}]",
+            items.iter().map(expression).collect::<Vec<String>>().join(", ")
+        ),
+        Value::Object(map) => {
+            let fields: Vec<String> = map
+                .iter()
+                .map(|(k, v)| format!("{} = {}", expression(&Value::String(k.clone())), expression(v)))
+                .collect();
+            format!("{{ {} }}", fields.join(", "))
+        }
+        other => other.to_string(),
+    }
+}
+
+/// `${` and `%{` start template sequences in HCL strings
+fn escape_template(s: &str) -> String {
+    s.replace("${", "$${").replace("%{", "%%{")
+}
+
+/// terraform resource names: letters, digits, `_` and `-`, not starting with a digit
+fn ident(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect();
+    if !ident.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        ident.insert(0, '_');
+    }
+    ident
+}
@@ -0,0 +1,52 @@
+//! Polling of the files a run read, for `--watch`.
+//!
+//! Files are compared by modification time and size, a directory by its own modification time
+//! (files added to or removed from it). Editors often write a file in several steps, a change
+//! is only reported once the files stayed the same for `DEBOUNCE`.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+/// time between two looks at the files
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// time the files must stay unchanged before a change is reported
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// what a file looked like, none when it is missing
+pub type Stamps = BTreeMap<PathBuf, Option<(SystemTime, u64)>>;
+
+pub fn stamp(paths: &[PathBuf]) -> Stamps {
+    paths
+        .iter()
+        .map(|path| {
+            let seen = fs::metadata(path)
+                .and_then(|meta| meta.modified().map(|modified| (modified, meta.len())))
+                .ok();
+            (path.clone(), seen)
+        }).collect()
+}
+
+/// Wait until one of the files differs from `since` and then settles, returns those changed.
+pub fn wait(since: &Stamps) -> Vec<PathBuf> {
+    let paths: Vec<PathBuf> = since.keys().cloned().collect();
+    let mut current = stamp(&paths);
+    while current == *since {
+        sleep(POLL_INTERVAL);
+        current = stamp(&paths);
+    }
+    loop {
+        sleep(DEBOUNCE);
+        let settled = stamp(&paths);
+        if settled == current {
+            break;
+        }
+        current = settled;
+    }
+    paths
+        .into_iter()
+        .filter(|path| current.get(path) != since.get(path))
+        .collect()
+}
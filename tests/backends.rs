@@ -0,0 +1,88 @@
+//! The memory and file backends, applying the examples without any kong.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use serde_yaml::Value as YamlValue;
+use std::fs;
+use support::{kong_init, success, temp_dir};
+
+#[test]
+fn memory_backend_dry_runs() {
+    for (example, version) in [("example/kong11.yaml", "0.12.3"), ("example/kong14.v2.yaml", "0.14.1")] {
+        success(
+            kong_init()
+                .args(["--backend", "memory", "--kong-version", version, "--path", example])
+                .env("ADMIN_JWT_SECRET", "dry-run"),
+        );
+    }
+}
+
+#[test]
+fn file_backend_writes_declarative_config() {
+    let dir = temp_dir("file-backend");
+    let output = dir.join("kong.yml");
+    let output = output.to_str().unwrap();
+    for _ in 0..2 {
+        success(
+            kong_init()
+                .args(["--backend", "file", "--output", output, "--path", "example/kong14.v2.yaml"])
+                .env("ADMIN_JWT_SECRET", "declared"),
+        );
+    }
+
+    let config: YamlValue = serde_yaml::from_str(&fs::read_to_string(output).unwrap()).unwrap();
+    assert_eq!(config["_format_version"], YamlValue::from("1.1"));
+    let services = config["services"].as_sequence().unwrap();
+    let mut names: Vec<&str> = services.iter().map(|s| s["name"].as_str().unwrap()).collect();
+    names.sort();
+    assert_eq!(names, ["dummy", "netdisk"]);
+    let dummy = services.iter().find(|s| s["name"] == "dummy").unwrap();
+    assert_eq!(dummy["routes"].as_sequence().unwrap().len(), 2);
+    assert_eq!(dummy["plugins"][0]["name"], YamlValue::from("rate-limiting"));
+    let routes = dummy["routes"].as_sequence().unwrap();
+    assert!(routes.iter().any(|r| r["plugins"][0]["name"] == "jwt"));
+    assert_eq!(config["plugins"][0]["name"], YamlValue::from("prometheus"));
+
+    let consumers = config["consumers"].as_sequence().unwrap();
+    let admin = consumers.iter().find(|c| c["username"] == "admin").unwrap();
+    assert_eq!(admin["jwt_secrets"][0]["secret"], YamlValue::from("declared"));
+    assert!(admin["jwt_secrets"][0].get("consumer_id").is_none());
+
+    // the same run gives the same file
+    let first = fs::read_to_string(output).unwrap();
+    success(
+        kong_init()
+            .args(["--backend", "file", "--output", output, "--path", "example/kong14.v2.yaml"])
+            .env("ADMIN_JWT_SECRET", "declared"),
+    );
+    assert_eq!(first, fs::read_to_string(output).unwrap());
+}
+
+#[test]
+fn file_backend_refuses_apis() {
+    let output = kong_init()
+        .args(["--backend", "file", "--kong-version", "0.12.3", "--path", "example/kong11.yaml"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("apis have no declarative form"));
+}
+
+#[test]
+fn backend_flags_are_checked() {
+    // the http backend needs a url
+    let output = kong_init().args(["--path", "example/kong11.yaml"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--url is required"));
+
+    let output = kong_init()
+        .args(["--backend", "memory", "--path", "example/kong11.yaml", "--record", "cassette"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("need the http backend"));
+}
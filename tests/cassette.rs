@@ -0,0 +1,75 @@
+//! Recording the admin api calls of a run and replaying them without kong.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs;
+use support::{kong_init, success, temp_dir, MockKong};
+
+/// nothing listens there, replays must not call kong
+const NO_KONG: &str = "http://127.0.0.1:9";
+
+#[test]
+fn recorded_run_replays_without_kong() {
+    let dir = temp_dir("cassette");
+    let cassette = dir.join("kong14");
+    let cassette = cassette.to_str().unwrap();
+    let kong = MockKong::start("0.14.1");
+    success(
+        kong.kong_init()
+            .args(["--path", "example/kong14.v2.yaml", "--record", cassette])
+            .env("ADMIN_JWT_SECRET", "recorded"),
+    );
+    let recorded = fs::read_dir(cassette).unwrap().count();
+    let requests: usize = kong.store().requests.values().sum();
+    assert_eq!(recorded, requests);
+
+    success(
+        kong_init()
+            .args(["--url", NO_KONG, "--path", "example/kong14.v2.yaml", "--replay", cassette])
+            .env("ADMIN_JWT_SECRET", "recorded"),
+    );
+
+    // another secret is another credential body than recorded
+    let output = kong_init()
+        .args(["--url", NO_KONG, "--path", "example/kong14.v2.yaml", "--replay", cassette])
+        .env("ADMIN_JWT_SECRET", "changed")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("diverges from the recording"));
+
+    // recording again replaces the earlier exchanges
+    success(
+        kong.kong_init()
+            .args(["--path", "example/kong14.v2.yaml", "--record", cassette])
+            .env("ADMIN_JWT_SECRET", "changed"),
+    );
+    success(
+        kong_init()
+            .args(["--url", NO_KONG, "--path", "example/kong14.v2.yaml", "--replay", cassette])
+            .env("ADMIN_JWT_SECRET", "changed"),
+    );
+}
+
+#[test]
+fn missing_calls_fail_the_replay() {
+    let dir = temp_dir("cassette-missing");
+    let cassette = dir.join("kong11");
+    let cassette = cassette.to_str().unwrap();
+    let kong = MockKong::start("0.12.3");
+    success(kong.kong_init().args(["--path", "example/kong11.yaml", "--record", cassette]));
+
+    // a run making fewer calls leaves recorded exchanges over
+    let consumers_only = dir.join("consumers.yaml");
+    fs::write(&consumers_only, "apis: []\nconsumers:\n  - username: admin\n    custom_id: admin\n").unwrap();
+    let output = kong_init()
+        .args(["--url", NO_KONG, "--path", consumers_only.to_str().unwrap(), "--replay", cassette])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("recorded but never called"));
+}
@@ -0,0 +1,175 @@
+//! `check`: comparing kong with the definitions, the exit code telling the outcome.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs;
+use std::net::TcpListener;
+use std::process::Output;
+use support::{kong_init, success, temp_dir, MockKong};
+
+const ADMIN_JWT_SECRET: &str = "s3cr3t-from-env";
+
+fn check(kong: &MockKong, path: &str) -> Output {
+    kong_init()
+        .args(["check", "--url", &kong.url, "--path", path])
+        .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET)
+        .output()
+        .unwrap()
+}
+
+/// a kong the example was applied to
+fn applied() -> MockKong {
+    let kong = MockKong::start("0.14.1");
+    success(
+        kong.kong_init()
+            .args(["--path", "example/kong14.v2.yaml"])
+            .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET),
+    );
+    kong
+}
+
+#[test]
+fn kong_as_applied_is_in_sync() {
+    let kong = applied();
+    let requests = kong.store().requests.clone();
+
+    let output = check(&kong, "example/kong14.v2.yaml");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert_eq!(stdout, "in sync: 12 entities as declared\n");
+    // only read
+    let mut after = kong.store().requests.clone();
+    after.remove("GET");
+    let mut before = requests;
+    before.remove("GET");
+    assert_eq!(after, before);
+}
+
+#[test]
+fn drift_is_printed_by_entity() {
+    let kong = applied();
+    {
+        let mut store = kong.store();
+        store.patch("services", "dummy", "url", json!("http://hotfix:7090/dummy"));
+        let prometheus = store.find("plugins", "name", "prometheus").unwrap()["id"].clone();
+        store.remove("plugins", prometheus.as_str().unwrap());
+        store.insert("services", json!({ "name": "by-hand", "url": "http://by-hand:80" }));
+    }
+
+    let output = check(&kong, "example/kong14.v2.yaml");
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "~ service dummy
+    url: \"http://hotfix:7090/dummy\" -> \"http://host.docker.internal:7090/dummy\"
+- service by-hand
++ plugin prometheus on global
+drift: 1 to create, 1 to update, 1 to delete, 10 unchanged
+"
+    );
+    // nothing healed
+    assert!(kong.store().find("services", "name", "by-hand").is_some());
+    assert!(kong.store().find("plugins", "name", "prometheus").is_none());
+}
+
+#[test]
+fn errors_exit_with_1() {
+    let kong = applied();
+    let output = check(&kong, "example/kong11.yaml");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("API style definitions(`apis`) are not supported"));
+    assert!(output.stdout.is_empty());
+
+    // nothing listens there
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let output = kong_init()
+        .args(["check", "--path", "example/kong14.v2.yaml"])
+        .args(["--url", &format!("http://{}", addr)])
+        .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Could not reach Kong"));
+}
+
+#[test]
+fn failed_lookups_are_errors() {
+    let kong = applied();
+    kong.store().failing.push("GET /consumers/guest_user".to_string());
+    let output = check(&kong, "example/kong14.v2.yaml");
+    assert_eq!(output.status.code(), Some(1), "{}", String::from_utf8_lossy(&output.stdout));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unable to check: get consumer guest_user: status=500"), "{}", stderr);
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn removed_fields_are_drift() {
+    let kong = applied();
+    let dir = temp_dir("check-removed");
+    let path = dir.join("kong.yaml");
+    let example = fs::read_to_string("example/kong14.v2.yaml").unwrap();
+    let removed = example.replace("      strip_path: false\n", "").replace("      hour: 1800\n", "");
+    assert_ne!(removed, example);
+    fs::write(&path, removed).unwrap();
+
+    let output = check(&kong, path.to_str().unwrap());
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "~ route r-netdisk
+    strip_path: false -> true
+- plugin rate-limiting on service netdisk
+- plugin rate-limiting on service dummy
++ plugin rate-limiting on service netdisk
+    config.hour: 1800 -> null
++ plugin rate-limiting on service dummy
+    config.hour: 1800 -> null
+drift: 2 to create, 1 to update, 2 to delete, 9 unchanged
+"
+    );
+    // nothing healed
+    let store = kong.store();
+    assert!(store.all("plugins").iter().all(|p| p["name"] != "rate-limiting" || p["config"]["hour"] == json!(1800)));
+}
+
+const KEY_AUTH: &str = "services: []
+routes: []
+consumers:
+  - username: billing
+credentials:
+  - name: key-auth
+    target: billing
+";
+
+#[test]
+fn check_leaves_the_state_alone() {
+    let dir = temp_dir("check-state");
+    let path = dir.join("kong.yaml");
+    let state = dir.join("state.json");
+    fs::write(&path, KEY_AUTH).unwrap();
+    let kong = MockKong::start("0.14.1");
+    success(kong.kong_init().args(["--path", path.to_str().unwrap(), "--state", state.to_str().unwrap()]));
+    let saved = fs::read_to_string(&state).unwrap();
+    let check = |path: &str| {
+        kong_init()
+            .args(["check", "--url", &kong.url, "--path", path, "--state", state.to_str().unwrap()])
+            .output()
+            .unwrap()
+    };
+
+    // the generated key kept in the state is compared
+    let output = check(path.to_str().unwrap());
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // a credential to generate a key for is drift, the key is not kept
+    fs::write(&path, format!("{}  - name: key-auth\n    target: billing\n    config:\n      tags: [ci]\n", KEY_AUTH)).unwrap();
+    let output = check(path.to_str().unwrap());
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("+ credential key-auth of consumer billing"));
+    assert_eq!(fs::read_to_string(&state).unwrap(), saved);
+}
@@ -0,0 +1,123 @@
+//! Applying the operations concurrently, in the order of their dependencies.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use support::{kong_init, success, temp_dir, MockKong};
+
+/// services with routes and plugins each, consumers with credentials
+fn many_entities(path: &Path, count: usize) {
+    let mut yaml = String::from("services:\n");
+    for i in 0..count {
+        writeln!(yaml, "  - name: s{}\n    url: http://upstream-{}:8080", i, i).unwrap();
+    }
+    yaml.push_str("routes:\n");
+    for i in 0..count {
+        for j in 0..2 {
+            writeln!(yaml, "  - name: r{}-{}\n    apply_to: s{}\n    config:\n      paths: [/s{}/r{}]", i, j, i, i, j).unwrap();
+        }
+    }
+    yaml.push_str("plugins:\n  - name: cors\n    target: global\n    enabled: true\n");
+    for i in 0..count {
+        writeln!(yaml, "  - name: rate-limiting\n    target: s[s{}]\n    enabled: true\n    config:\n      minute: {}", i, i + 1).unwrap();
+        writeln!(yaml, "  - name: key-auth\n    target: r[r{}-0]\n    enabled: true", i).unwrap();
+    }
+    yaml.push_str("consumers:\n");
+    for i in 0..count {
+        writeln!(yaml, "  - username: c{}", i).unwrap();
+    }
+    yaml.push_str("credentials:\n");
+    for i in 0..count {
+        writeln!(yaml, "  - name: key-auth\n    target: c{}\n    config:\n      key: key-{}", i, i).unwrap();
+    }
+    fs::write(path, yaml).unwrap();
+}
+
+#[test]
+fn logs_and_results_do_not_depend_on_concurrency() {
+    let dir = temp_dir("concurrency");
+    let conf = dir.join("many.yaml");
+    many_entities(&conf, 12);
+
+    let output = dir.join("kong.yml");
+    let mut runs = Vec::new();
+    for concurrency in &["1", "16"] {
+        let run = success(kong_init().args([
+            "--backend",
+            "file",
+            "--output",
+            output.to_str().unwrap(),
+            "--concurrency",
+            concurrency,
+            "--path",
+            conf.to_str().unwrap(),
+        ]));
+        runs.push((String::from_utf8(run.stderr).unwrap(), fs::read_to_string(&output).unwrap()));
+    }
+    assert_eq!(runs[0].0, runs[1].0);
+    assert_eq!(runs[0].1, runs[1].1);
+
+    // routes are created after their service, plugins after what they apply to
+    let logs = &runs[1].0;
+    let position = |line: &str| logs.find(line).unwrap_or_else(|| panic!("no {} in {}", line, logs));
+    assert!(position("Service \"s3\" has CREATED") < position("Route r3-1 has CREATED"));
+    assert!(position("finished loading routes") < position("applying plugin key-auth to route"));
+    assert!(position("add_consumer: c5 has CREATED") < position("succeed creating credential key-auth to consumer c5"));
+
+    let kong = MockKong::start("0.14.1");
+    for _ in 0..2 {
+        success(kong.kong_init().args(["--concurrency", "8", "--path", conf.to_str().unwrap()]));
+    }
+    let store = kong.store();
+    assert_eq!(store.all("services").len(), 12);
+    assert_eq!(store.all("routes").len(), 24);
+    assert_eq!(store.all("plugins").len(), 25);
+    assert_eq!(store.credentials("c7", "key-auth")[0]["key"], json!("key-7"));
+}
+
+#[test]
+fn failed_operations_skip_what_depends_on_them() {
+    let dir = temp_dir("concurrency-failed");
+    let conf = dir.join("duplicate.yaml");
+    // the second service of the same name is refused, and so its route never created
+    fs::write(
+        &conf,
+        "services:\n  - name: s\n    url: http://a\n  - name: s\n    url: http://b\n\
+         routes:\n  - name: r\n    apply_to: s\n    config:\n      paths: [/r]\n",
+    ).unwrap();
+    let output = kong_init()
+        .args(["--backend", "memory", "--concurrency", "1", "--path", conf.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let logs = String::from_utf8_lossy(&output.stderr);
+    assert!(logs.contains("service s: unable to create service"), "{}", logs);
+    assert!(logs.contains("skipped route r, service s failed"), "{}", logs);
+}
+
+#[test]
+fn refused_calls_fail_the_run() {
+    let dir = temp_dir("concurrency-refused");
+    let conf = dir.join("taken-key.yaml");
+    // both consumers declare the same key, kong refuses the second one
+    fs::write(
+        &conf,
+        "services: []\nroutes: []\nconsumers:\n  - username: a\n  - username: b\n\
+         credentials:\n  - name: key-auth\n    target: a\n    config:\n      key: shared\n\
+         \x20 - name: key-auth\n    target: b\n    config:\n      key: shared\n",
+    ).unwrap();
+    let output = kong_init()
+        .args(["--backend", "memory", "--concurrency", "1", "--path", conf.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let logs = String::from_utf8_lossy(&output.stderr);
+    assert!(logs.contains("key-auth of consumer b: credentials:"), "{}", logs);
+    assert!(logs.contains("skipped Credentials, key-auth of consumer b failed"), "{}", logs);
+}
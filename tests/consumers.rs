@@ -0,0 +1,192 @@
+//! Reconciliation of consumers, their credentials and acl groups across runs.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use support::{success, temp_dir, MockKong};
+
+const VERSION: &str = "0.14.1";
+
+fn write(dir: &Path, name: &str, text: &str) -> String {
+    let path: PathBuf = dir.join(name);
+    fs::write(&path, text).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn groups(kong: &MockKong, username: &str) -> Vec<String> {
+    let mut groups: Vec<String> = kong
+        .store()
+        .credentials(username, "acls")
+        .iter()
+        .map(|acl| acl["group"].as_str().unwrap().to_string())
+        .collect();
+    groups.sort();
+    groups
+}
+
+#[test]
+fn reserved_usernames_cannot_be_declared() {
+    let dir = temp_dir("reserved-consumers");
+    let kong = MockKong::start(VERSION);
+    let path = write(&dir, "kong.yaml", "services: []\nroutes: []\nconsumers:\n  - username: kong-init-lock\n");
+    let output = kong.kong_init().args(["--path", &path]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("usernames starting with kong-init- are kong-init's own"));
+}
+
+#[test]
+fn owned_consumers_are_updated_and_pruned() {
+    let dir = temp_dir("owned-consumers");
+    let state = dir.join("state.json");
+    let state = state.to_str().unwrap();
+    let kong = MockKong::start(VERSION);
+    let first = write(
+        &dir,
+        "first.yaml",
+        "services: []\nroutes: []\nconsumers:\n  - username: alice\n    tags: [a, b]\n  - username: bob\n",
+    );
+    success(kong.kong_init().args(["--path", &first, "--state", state, "--prune"]));
+    let alice = kong.store().find("consumers", "username", "alice").unwrap();
+    assert_eq!(alice["tags"], json!(["a", "b"]));
+
+    // declared without --state, kong-init does not own it
+    let foreign = write(&dir, "foreign.yaml", "services: []\nroutes: []\nconsumers:\n  - username: carol\n");
+    success(kong.kong_init().args(["--path", &foreign]));
+
+    let second = write(
+        &dir,
+        "second.yaml",
+        "services: []\nroutes: []\nconsumers:\n  - username: alice\n    custom_id: a-1\n    tags: [b, a]\n",
+    );
+    success(kong.kong_init().args(["--path", &second, "--state", state, "--prune"]));
+
+    let store = kong.store();
+    let consumers = store.all("consumers");
+    let mut usernames: Vec<&str> = consumers.iter().filter_map(|c| c["username"].as_str()).collect();
+    usernames.sort();
+    assert_eq!(usernames, ["alice", "carol"]);
+    // kong-init's own consumers are left alone
+    assert!(store.find("consumers", "username", "kong-init-fingerprint").is_some());
+    let patched = store.find("consumers", "username", "alice").unwrap();
+    assert_eq!(patched["id"], alice["id"]);
+    assert_eq!(patched["custom_id"], "a-1");
+    // reordered tags are not a change
+    assert_eq!(patched["tags"], json!(["a", "b"]));
+}
+
+#[test]
+fn credentials_are_patched_and_pruned() {
+    let dir = temp_dir("credentials");
+    let kong = MockKong::start(VERSION);
+    let first = write(
+        &dir,
+        "first.yaml",
+        "services: []\nroutes: []\nconsumers:\n  - username: alice\ncredentials:\n  - name: key-auth\n    target: alice\n    config:\n      key: first-key\n  - name: basic-auth\n    target: alice\n    config:\n      username: alice\n      password: first\n",
+    );
+    success(kong.kong_init().args(["--path", &first]));
+    assert_eq!(kong.store().credentials("alice", "key-auth")[0]["key"], "first-key");
+    let basic = kong.store().credentials("alice", "basic-auth")[0].clone();
+
+    let second = write(
+        &dir,
+        "second.yaml",
+        "services: []\nroutes: []\nconsumers:\n  - username: alice\ncredentials:\n  - name: basic-auth\n    target: alice\n    config:\n      username: alice\n      password: second\n",
+    );
+    success(kong.kong_init().args(["--path", &second]));
+    // not pruned without --prune
+    assert_eq!(kong.store().credentials("alice", "key-auth").len(), 1);
+    let patched = kong.store().credentials("alice", "basic-auth")[0].clone();
+    assert_eq!(patched["id"], basic["id"]);
+    assert_ne!(patched["password"], basic["password"]);
+
+    success(kong.kong_init().args(["--path", &second, "--prune"]));
+    assert!(kong.store().credentials("alice", "key-auth").is_empty());
+    assert_eq!(kong.store().credentials("alice", "basic-auth").len(), 1);
+
+    // the hashed password matches, nothing to patch
+    let patches = kong.store().requests.get("PATCH").cloned().unwrap_or(0);
+    success(kong.kong_init().args(["--path", &second, "--prune"]));
+    assert_eq!(kong.store().requests.get("PATCH").cloned().unwrap_or(0), patches);
+}
+
+#[test]
+fn groups_are_exactly_the_acls() {
+    let dir = temp_dir("groups");
+    let kong = MockKong::start(VERSION);
+    let with_groups = |groups: &str| {
+        format!(
+            "services: []\nroutes: []\nconsumers:\n  - username: alice\n    groups: {}\n",
+            groups
+        )
+    };
+    let first = write(&dir, "first.yaml", &with_groups("[admins, ops]"));
+    success(kong.kong_init().args(["--path", &first]));
+    assert_eq!(groups(&kong, "alice"), ["admins", "ops"]);
+
+    let second = write(&dir, "second.yaml", &with_groups("[ops]"));
+    success(kong.kong_init().args(["--path", &second]));
+    assert_eq!(groups(&kong, "alice"), ["ops"]);
+
+    let none = write(&dir, "none.yaml", &with_groups("[]"));
+    success(kong.kong_init().args(["--path", &none]));
+    assert!(groups(&kong, "alice").is_empty());
+}
+
+#[test]
+fn unassigned_acl_groups_are_refused() {
+    let dir = temp_dir("unassigned-groups");
+    let kong = MockKong::start(VERSION);
+    let path = write(
+        &dir,
+        "acl.yaml",
+        "services: []\nroutes: []\nplugins:\n  - name: acl\n    enabled: true\n    target: global\n    config:\n      whitelist: [admins, nobody]\nconsumers:\n  - username: alice\n    groups: [admins]\n",
+    );
+    let output = kong.kong_init().args(["--path", &path]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("nobody"));
+    assert!(kong.store().all("consumers").is_empty());
+}
+
+#[test]
+fn consumers_from_csv() {
+    let dir = temp_dir("consumers-from");
+    let kong = MockKong::start(VERSION);
+    let mut csv = String::from("username,custom_id,tags,key-auth.key,groups\n");
+    for idx in 0..20 {
+        csv.push_str(&format!("user-{0},id-{0},partner;batch,key-{0},partners;group-{1}\n", idx, idx % 3));
+    }
+    let csv = write(&dir, "partners.csv", &csv);
+    let path = write(
+        &dir,
+        "bulk.yaml",
+        &format!(
+            "services: []\nroutes: []\nconsumers:\n  - username: admin\nconsumers_from: {}\n",
+            csv
+        ),
+    );
+    success(kong.kong_init().args(["--path", &path, "--concurrency", "8"]));
+
+    {
+        let store = kong.store();
+        assert_eq!(store.all("consumers").len(), 21);
+        assert_eq!(store.credentials("user-7", "key-auth")[0]["key"], "key-7");
+        assert_eq!(store.find("consumers", "username", "user-7").unwrap()["custom_id"], "id-7");
+    }
+    assert_eq!(groups(&kong, "user-7"), ["group-1", "partners"]);
+
+    let writes = |kong: &MockKong| {
+        let store = kong.store();
+        ["POST", "PATCH", "DELETE"]
+            .iter()
+            .map(|m| store.requests.get(*m).cloned().unwrap_or(0) - store.own_requests.get(*m).cloned().unwrap_or(0))
+            .sum::<usize>()
+    };
+    let before = writes(&kong);
+    success(kong.kong_init().args(["--path", &path, "--concurrency", "8", "--force"]));
+    assert_eq!(writes(&kong), before);
+}
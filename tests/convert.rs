@@ -0,0 +1,67 @@
+//! `convert`: API style definitions rewritten into the Service/Route style, against golden files
+//! of `tests/golden/convert`.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs;
+use support::{assert_golden, kong_init, success, temp_dir, MockKong};
+
+const ADMIN_JWT_SECRET: &str = "s3cr3t-from-env";
+
+fn convert(path: &str) -> String {
+    let output = success(kong_init().args(["convert", "--path", path]));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn converted_definitions() {
+    assert_golden("convert/kong11.yaml", &convert("example/kong11.yaml"));
+    assert_golden("convert/kong11-semantics.yaml", &convert("tests/fixtures/kong11-semantics.yaml"));
+}
+
+#[test]
+fn plugin_config_strings_stay_strings() {
+    let converted: serde_yaml::Value =
+        serde_yaml::from_str(&convert("tests/fixtures/kong11-semantics.yaml")).unwrap();
+    let termination = converted["plugins"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .find(|p| p["name"] == "request-termination")
+        .unwrap();
+    assert_eq!(termination["config"]["message"], serde_yaml::Value::from("007"));
+    assert_eq!(termination["config"]["status_code"], serde_yaml::Value::from("503"));
+}
+
+#[test]
+fn converted_definitions_apply_what_the_legacy_ones_did() {
+    let dir = temp_dir("convert-apply");
+    let converted = dir.join("kong14.yaml");
+    fs::write(&converted, convert("example/kong11.yaml")).unwrap();
+
+    let kong = MockKong::start("0.14.1");
+    success(
+        kong.kong_init()
+            .args(["--path", converted.to_str().unwrap()])
+            .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET),
+    );
+    let store = kong.store();
+    assert_eq!(store.all("services").len(), 2);
+    assert_eq!(store.all("routes").len(), 2);
+    // the jwt of both services and the global rate limiting, not the `target_api: none` jwt
+    let mut plugins: Vec<(String, bool)> = store
+        .all("plugins")
+        .iter()
+        .map(|p| (p["name"].as_str().unwrap().to_string(), p.get("service_id").is_some() || p["service"].is_object()))
+        .collect();
+    plugins.sort();
+    assert_eq!(
+        plugins,
+        [("jwt".to_string(), true), ("jwt".to_string(), true), ("rate-limiting".to_string(), false)]
+    );
+    assert!(store.all("plugins").iter().all(|p| p["enabled"] == json!(true)));
+}
@@ -0,0 +1,191 @@
+//! `--daemon`: drift between kong and the definitions reported, exposed and healed.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use support::{eventually, logged, success, temp_dir, MockKong};
+
+const CONF: &str = "services:
+  - name: orders
+    url: http://orders:8080/
+routes:
+  - name: r-orders
+    apply_to: orders
+    config:
+      paths: [/orders]
+plugins:
+  - name: rate-limiting
+    target: r[r-orders]
+    config:
+      minute: 20
+    enabled: true
+";
+
+/// kills the daemon when the test ends, whatever the outcome
+struct Daemon(Child);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+impl Daemon {
+    /// SIGTERM, returns whether it exited successfully
+    fn terminate(&mut self) -> bool {
+        let pid = self.0.id().to_string();
+        assert!(Command::new("kill").args(["-TERM", &pid]).status().unwrap().success());
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.0.try_wait().unwrap() {
+                return status.success();
+            }
+            assert!(started.elapsed() < Duration::from_secs(10), "still running after SIGTERM");
+            sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+/// apply the definitions, then start the daemon on them
+fn start(kong: &MockKong, dir: &Path, definitions: &str, extra: &[&str]) -> (Daemon, PathBuf) {
+    let conf = dir.join("kong.yaml");
+    let logs = dir.join("kong-init.log");
+    fs::write(&conf, definitions).unwrap();
+    success(kong.kong_init().args(["--path", conf.to_str().unwrap()]));
+
+    let child = kong
+        .kong_init()
+        .args(["--path", conf.to_str().unwrap(), "--daemon", "--interval", "1"])
+        .args(extra)
+        .stdout(Stdio::null())
+        .stderr(File::create(&logs).unwrap())
+        .spawn()
+        .unwrap();
+    (Daemon(child), logs)
+}
+
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// the body of a GET
+fn get(addr: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response.split_once("\r\n\r\n").unwrap().1.to_string()
+}
+
+#[test]
+fn drift_is_reported_and_exposed() {
+    let kong = MockKong::start("0.14.1");
+    let addr = free_addr();
+    let (mut daemon, logs) = start(&kong, &temp_dir("daemon-report"), CONF, &["--status-addr", &addr]);
+    eventually(&logs, "a check in sync", || logged(&logs, "no drift, 3 entities as declared"));
+    let status: Value = serde_json::from_str(&get(&addr, "/status")).unwrap();
+    assert_eq!(status["in_sync"], json!(true));
+
+    // fixed in production with curl
+    kong.store().patch("services", "orders", "url", json!("http://orders-hotfix:8080/"));
+    eventually(&logs, "the drift", || logged(&logs, "drift detected: 0 to create, 1 to update, 0 to delete, 2 unchanged"));
+    assert!(logged(
+        &logs,
+        "~ service orders: url: \"http://orders-hotfix:8080/\" -> \"http://orders:8080/\""
+    ));
+    let status: Value = serde_json::from_str(&get(&addr, "/status")).unwrap();
+    assert_eq!(status["in_sync"], json!(false));
+    assert_eq!(
+        status["drift"],
+        json!(["~ service orders: url: \"http://orders-hotfix:8080/\" -> \"http://orders:8080/\""])
+    );
+    assert!(get(&addr, "/metrics").contains("\nkong_init_drift_changes 1\n"));
+
+    // only reported
+    let url = kong.store().find("services", "name", "orders").unwrap()["url"].clone();
+    assert_eq!(url, "http://orders-hotfix:8080/");
+
+    assert!(daemon.terminate());
+    assert!(logged(&logs, "stopping on signal"));
+}
+
+#[test]
+fn drift_is_healed() {
+    let kong = MockKong::start("0.14.1");
+    let (mut daemon, logs) = start(&kong, &temp_dir("daemon-heal"), CONF, &["--heal"]);
+    eventually(&logs, "a check in sync", || logged(&logs, "no drift, 3 entities as declared"));
+
+    let plugin = kong.store().all("plugins")[0]["id"].as_str().unwrap().to_string();
+    kong.store().remove("plugins", &plugin);
+    kong.store().patch("services", "orders", "url", json!("http://orders-hotfix:8080/"));
+    eventually(&logs, "the healing", || logged(&logs, "drift healed"));
+    eventually(&logs, "kong back to the definitions", || {
+        let store = kong.store();
+        store.all("plugins").len() == 1
+            && store.find("services", "name", "orders").unwrap()["url"] == "http://orders:8080/"
+    });
+    assert!(logged(&logs, "+ plugin rate-limiting on route r-orders"));
+    assert_eq!(kong.store().all("routes").len(), 1);
+
+    assert!(daemon.terminate());
+    assert!(kong.store().find("consumers", "username", "kong-init-lock").is_none());
+}
+
+#[test]
+fn idle_clients_hold_up_no_request() {
+    let kong = MockKong::start("0.14.1");
+    let addr = free_addr();
+    let (mut daemon, logs) = start(&kong, &temp_dir("daemon-idle"), CONF, &["--status-addr", &addr]);
+    eventually(&logs, "a check in sync", || logged(&logs, "no drift, 3 entities as declared"));
+
+    // connected, never sending its request
+    let _idle = TcpStream::connect(&addr).unwrap();
+    let started = Instant::now();
+    let status: Value = serde_json::from_str(&get(&addr, "/status")).unwrap();
+    assert_eq!(status["in_sync"], json!(true));
+    assert!(started.elapsed() < Duration::from_secs(2), "answered after {:?}", started.elapsed());
+    assert!(get(&addr, "/metrics").contains("\nkong_init_in_sync 1\n"));
+
+    assert!(daemon.terminate());
+}
+
+#[test]
+fn failed_lookups_are_failed_checks() {
+    let kong = MockKong::start("0.14.1");
+    let addr = free_addr();
+    let definitions = format!(
+        "{}  - name: jwt\n    target: r[r-orders]\n    config:\n      anonymous: {{{{k-upsert-consumer:guest_user}}}}\n    enabled: true\n",
+        CONF
+    );
+    let dir = temp_dir("daemon-failed-lookup");
+    let (mut daemon, logs) = start(&kong, &dir, &definitions, &["--heal", "--status-addr", &addr]);
+    eventually(&logs, "a check in sync", || logged(&logs, "no drift, 4 entities as declared"));
+    let guest = kong.store().find("consumers", "username", "guest_user").unwrap()["id"].clone();
+
+    // kong failing to tell about the consumer is no drift to heal
+    kong.store().failing.push("POST /consumers".to_string());
+    kong.store().failing.push("GET /consumers/guest_user".to_string());
+    eventually(&logs, "the failed check", || logged(&logs, "unable to check for drift: upsert consumer guest_user"));
+    let status: Value = serde_json::from_str(&get(&addr, "/status")).unwrap();
+    assert!(status["failed_checks"].as_u64().unwrap() >= 1);
+    assert!(status["error"].as_str().unwrap().contains("status=500"), "{}", status);
+    assert_eq!(status["heals"], json!(0));
+    let jwt = kong.store().all("plugins").into_iter().find(|p| p["name"] == "jwt").unwrap();
+    assert_eq!(jwt["config"]["anonymous"], guest);
+
+    assert!(daemon.terminate());
+}
@@ -0,0 +1,51 @@
+//! DB-less: the `kong.yml` rendered from the definitions, and the same document pushed to
+//! `/config`, against golden files of `tests/golden/dbless`.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use support::{assert_golden, kong_init, success, MockKong};
+
+const DBLESS_VERSIONS: &[&str] = &["1.1.0", "1.4.3", "2.8.1", "3.4.2"];
+
+const ADMIN_JWT_SECRET: &str = "s3cr3t-from-env";
+
+/// the examples and what they render into
+const RENDERED: &[(&str, &str)] = &[
+    ("example/kong14.v2.yaml", "dbless/kong14.v2.yml"),
+    ("tests/fixtures/kong1x.yaml", "dbless/kong1x.yml"),
+];
+
+#[test]
+fn rendered_kong_yml() {
+    for (path, golden) in RENDERED {
+        let output = success(
+            kong_init()
+                .args(["render", "--target", "dbless", "--path", path])
+                .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET),
+        );
+        assert_golden(golden, &String::from_utf8(output.stdout).unwrap());
+    }
+}
+
+#[test]
+fn pushed_config_is_the_rendered_one() {
+    for version in DBLESS_VERSIONS {
+        for (path, golden) in RENDERED {
+            let kong = MockKong::start(version);
+            success(
+                kong.kong_init()
+                    .args(["--path", path, "--dbless"])
+                    .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET),
+            );
+            let store = kong.store();
+            assert_eq!(store.configs.len(), 1, "{} on {}", path, version);
+            // printed with a final newline by `render`
+            assert_golden(golden, &format!("{}\n", store.configs[0]));
+            assert_eq!(store.requests.get("POST"), Some(&1), "{} on {}", path, version);
+        }
+    }
+}
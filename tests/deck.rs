@@ -0,0 +1,104 @@
+//! decK state files: kong-init definitions rendered into decK and decK dumps imported, both
+//! ways round trip, against golden files of `tests/golden/deck`.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs;
+use std::path::Path;
+use support::{assert_golden, kong_init, success, temp_dir};
+
+const ADMIN_JWT_SECRET: &str = "s3cr3t-from-env";
+
+fn render_deck(path: &Path) -> String {
+    let output = success(
+        kong_init()
+            .args(["render", "--target", "deck", "--path", path.to_str().unwrap()])
+            .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET),
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn import_deck(path: &Path) -> String {
+    let output = success(kong_init().args(["import-deck", "--input", path.to_str().unwrap()]));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// decK -> kong-init -> decK gives back the decK state
+fn round_trip(deck: &str, name: &str) -> String {
+    let dir = temp_dir(&format!("deck-{}", name));
+    fs::write(dir.join("deck.yaml"), deck).unwrap();
+    let imported = import_deck(&dir.join("deck.yaml"));
+    fs::write(dir.join("kong.yaml"), &imported).unwrap();
+    assert_eq!(render_deck(&dir.join("kong.yaml")), deck, "{} round trip", name);
+    imported
+}
+
+#[test]
+fn examples_round_trip() {
+    for (path, name) in [("example/kong14.v2.yaml", "kong14.v2"), ("tests/fixtures/kong1x.yaml", "kong1x")] {
+        let deck = render_deck(Path::new(path));
+        assert_golden(&format!("deck/{}.yaml", name), &deck);
+        let imported = round_trip(&deck, name);
+        assert_golden(&format!("deck/{}.imported.yaml", name), &imported);
+    }
+}
+
+#[test]
+fn dumps_are_imported_without_generated_fields() {
+    let imported = import_deck(Path::new("tests/fixtures/deck-dump.yaml"));
+    assert_golden("deck/deck-dump.imported.yaml", &imported);
+
+    // what can be imported round trips, plugins nested under their route or service
+    let dir = temp_dir("deck-dump");
+    fs::write(dir.join("kong.yaml"), &imported).unwrap();
+    let deck = render_deck(&dir.join("kong.yaml"));
+    assert_golden("deck/deck-dump.yaml", &deck);
+    round_trip(&deck, "dump");
+}
+
+const UNNAMED_ROUTES: &str = "services:
+  - name: orders
+    url: http://orders:8080
+    routes:
+      - paths: [/orders]
+      - paths: [/orders/v2]
+routes:
+  - service: orders
+    paths: [/orders/v3]
+  - name: orders-route-9
+    service: orders
+    paths: [/orders/v9]
+";
+
+#[test]
+fn unnamed_routes_are_numbered_per_service() {
+    let dir = temp_dir("deck-unnamed");
+    fs::write(dir.join("deck.yaml"), UNNAMED_ROUTES).unwrap();
+    let imported: serde_yaml::Value = serde_yaml::from_str(&import_deck(&dir.join("deck.yaml"))).unwrap();
+    let routes: Vec<(&str, &str)> = imported["routes"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .map(|r| (r["name"].as_str().unwrap(), r["config"]["paths"][0].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        routes,
+        [
+            ("orders-route-1", "/orders"),
+            ("orders-route-2", "/orders/v2"),
+            ("orders-route-3", "/orders/v3"),
+            ("orders-route-9", "/orders/v9"),
+        ]
+    );
+
+    // a name given to another route is refused, not overwritten
+    let taken = UNNAMED_ROUTES.replace("orders-route-9", "orders-route-2");
+    fs::write(dir.join("taken.yaml"), taken).unwrap();
+    let output = kong_init().args(["import-deck", "--input", dir.join("taken.yaml").to_str().unwrap()]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("route orders-route-2 is listed twice"));
+}
@@ -0,0 +1,208 @@
+//! Every example of `example/` applied against the fake Admin API of each kong version it
+//! supports, asserting the entities kong ends up with.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use serde_json::Value;
+use support::{success, MockKong, Store};
+
+const LEGACY_VERSIONS: &[&str] = &["0.11.2", "0.12.3", "0.13.1", "0.14.1", "0.33-enterprise-edition"];
+const SERVICE_VERSIONS: &[&str] = &["0.13.1", "0.14.1"];
+const DBLESS_VERSIONS: &[&str] = &["1.1.0", "1.4.3", "2.8.1", "3.4.2"];
+
+const ADMIN_JWT_SECRET: &str = "s3cr3t-from-env";
+
+fn names(entities: &[Value], field: &str) -> Vec<String> {
+    let mut names: Vec<String> = entities
+        .iter()
+        .filter_map(|e| e[field].as_str())
+        .map(String::from)
+        .collect();
+    names.sort();
+    names
+}
+
+fn plugins_named(store: &Store, name: &str) -> Vec<Value> {
+    store
+        .all("plugins")
+        .into_iter()
+        .filter(|p| p["name"] == name)
+        .collect()
+}
+
+fn assert_kong11(kong: &MockKong) {
+    let store = kong.store();
+    assert_eq!(names(&store.all("apis"), "name"), ["cookie-api", "jar-api"]);
+    let jar = store.find("apis", "name", "jar-api").unwrap();
+    assert_eq!(jar["strip_uri"], json!(false));
+
+    let jwt = plugins_named(&store, "jwt");
+    let mut api_ids: Vec<String> = jwt.iter().map(|p| p["api_id"].to_string()).collect();
+    api_ids.sort();
+    let mut expected: Vec<String> = store.all("apis").iter().map(|a| a["id"].to_string()).collect();
+    expected.sort();
+    assert_eq!(api_ids, expected);
+    assert_eq!(jwt[0]["config"]["uri_param_names"], "jwt");
+
+    let rate_limiting = plugins_named(&store, "rate-limiting");
+    assert_eq!(rate_limiting.len(), 1);
+    assert!(rate_limiting[0].get("api_id").is_none());
+    assert_eq!(store.all("plugins").len(), 3);
+
+    assert_eq!(names(&store.all("consumers"), "username"), ["admin", "guest-user"]);
+    let credentials = store.credentials("admin", "jwt");
+    assert_eq!(credentials.len(), 1);
+    assert_eq!(credentials[0]["key"], "a36c3049b36249a3c9f8891cb127243c");
+    assert_eq!(credentials[0]["secret"], "replace_this");
+}
+
+#[test]
+fn kong11_example_on_legacy_versions() {
+    for version in LEGACY_VERSIONS {
+        let kong = MockKong::start(version);
+        success(kong.kong_init().args(["--path", "example/kong11.yaml"]));
+        assert_kong11(&kong);
+
+        success(kong.kong_init().args(["--path", "example/kong11.yaml"]));
+        assert_kong11(&kong);
+    }
+}
+
+fn assert_kong14(kong: &MockKong) {
+    let store = kong.store();
+    let services = store.all("services");
+    assert_eq!(names(&services, "name"), ["dummy", "netdisk"]);
+    // routes have no names before kong 1.0, they are told apart by path
+    let routes = store.all("routes");
+    let route = |path: &str| {
+        routes
+            .iter()
+            .find(|r| r["paths"] == json!([path]))
+            .unwrap_or_else(|| panic!("no route of {}", path))
+    };
+    let service_of = |path: &str| {
+        let service_id = &route(path)["service"]["id"];
+        let service = services.iter().find(|s| &s["id"] == service_id).unwrap();
+        service["name"].as_str().unwrap().to_string()
+    };
+    assert_eq!(routes.len(), 3);
+    assert_eq!(service_of("/api/v1/netdisk"), "netdisk");
+    assert_eq!(service_of("/dummy"), "dummy");
+    assert_eq!(service_of("/dummy/login"), "dummy");
+
+    let prometheus = plugins_named(&store, "prometheus");
+    assert_eq!(prometheus.len(), 1);
+    assert!(prometheus[0].get("service_id").is_none() && prometheus[0].get("route_id").is_none());
+
+    let jwt = plugins_named(&store, "jwt");
+    assert_eq!(jwt.len(), 1);
+    assert_eq!(jwt[0]["route_id"], route("/dummy")["id"]);
+    assert_eq!(jwt[0]["enabled"], json!(false));
+    let guest = store.find("consumers", "custom_id", "guest_user").unwrap();
+    assert_eq!(jwt[0]["config"]["anonymous"], guest["id"]);
+
+    let rate_limiting = plugins_named(&store, "rate-limiting");
+    let mut service_ids: Vec<String> = rate_limiting.iter().map(|p| p["service_id"].to_string()).collect();
+    service_ids.sort();
+    let mut expected: Vec<String> = services.iter().map(|s| s["id"].to_string()).collect();
+    expected.sort();
+    assert_eq!(service_ids, expected);
+    assert_eq!(store.all("plugins").len(), 4);
+
+    assert_eq!(
+        names(&store.all("consumers"), "username"),
+        ["admin", "guest-user", "guest_user"]
+    );
+    let credentials = store.credentials("admin", "jwt");
+    assert_eq!(credentials.len(), 1);
+    assert_eq!(credentials[0]["secret"], ADMIN_JWT_SECRET);
+}
+
+#[test]
+fn kong14_example_on_service_versions() {
+    for version in SERVICE_VERSIONS {
+        let kong = MockKong::start(version);
+        let run = || {
+            success(
+                kong.kong_init()
+                    .args(["--path", "example/kong14.v2.yaml"])
+                    .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET),
+            )
+        };
+        run();
+        assert_kong14(&kong);
+
+        run();
+        assert_kong14(&kong);
+    }
+}
+
+#[test]
+fn kong14_example_pushed_to_dbless_versions() {
+    for version in DBLESS_VERSIONS {
+        let kong = MockKong::start(version);
+        success(
+            kong.kong_init()
+                .args(["--path", "example/kong14.v2.yaml", "--dbless"])
+                .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET),
+        );
+
+        let store = kong.store();
+        assert_eq!(store.configs.len(), 1, "{}", version);
+        let config: Value = serde_yaml::from_str(&store.configs[0]).unwrap();
+        let services = config["services"].as_array().unwrap();
+        assert_eq!(names(services, "name"), ["dummy", "netdisk"]);
+        let routes: Vec<Value> = services
+            .iter()
+            .flat_map(|s| s["routes"].as_array().cloned().unwrap_or_default())
+            .collect();
+        assert_eq!(names(&routes, "name"), ["r-dummy", "r-dummy-no-auth", "r-netdisk"]);
+
+        let consumers = config["consumers"].as_array().unwrap();
+        assert_eq!(
+            names(consumers, "username"),
+            ["admin", "guest-user", "guest_user"]
+        );
+        let guest = consumers.iter().find(|c| c["username"] == "guest_user").unwrap();
+        let jwt = routes
+            .iter()
+            .find(|r| r["name"] == "r-dummy")
+            .and_then(|r| r["plugins"].as_array())
+            .and_then(|plugins| plugins.iter().find(|p| p["name"] == "jwt"))
+            .unwrap();
+        assert_eq!(jwt["config"]["anonymous"], guest["id"]);
+
+        // nothing but the declarative config
+        assert!(store.all("services").is_empty());
+        assert!(store.all("consumers").is_empty());
+    }
+}
+
+#[test]
+fn unsupported_modes_fail() {
+    for version in &["1.3.0", "3.4.2"] {
+        let kong = MockKong::start(version);
+        let output = kong
+            .kong_init()
+            .args(["--path", "example/kong14.v2.yaml"])
+            .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "classic mode on {}", version);
+        assert!(kong.store().all("services").is_empty());
+    }
+
+    let kong = MockKong::start("0.14.1");
+    let output = kong
+        .kong_init()
+        .args(["--path", "example/kong14.v2.yaml", "--dbless"])
+        .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(kong.store().configs.is_empty());
+}
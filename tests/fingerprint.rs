@@ -0,0 +1,157 @@
+//! Runs skipped when neither the config nor kong changed since the last one.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs;
+use std::process::Output;
+use support::{success, temp_dir, MockKong, PAGE_SIZE};
+
+/// requests changing kong, but creating: the guest consumers of `k-upsert-consumer` are
+/// created(or found existing) whenever the config is read. Nor taking the lock and keeping the
+/// fingerprint, kong-init's own consumers.
+fn writes(kong: &MockKong) -> usize {
+    let store = kong.store();
+    ["PUT", "PATCH", "DELETE"]
+        .iter()
+        .map(|m| store.requests.get(*m).cloned().unwrap_or(0) - store.own_requests.get(*m).cloned().unwrap_or(0))
+        .sum()
+}
+
+fn skipped(output: &Output) -> bool {
+    String::from_utf8_lossy(&output.stderr).contains("nothing to apply")
+}
+
+#[test]
+fn unchanged_runs_are_skipped() {
+    let dir = temp_dir("fingerprint");
+    let conf = dir.join("kong.yaml");
+    let conf = conf.to_str().unwrap();
+    fs::copy("example/kong14.v2.yaml", conf).unwrap();
+    let kong = MockKong::start("0.14.1");
+    let run = |args: &[&str]| {
+        let mut command = kong.kong_init();
+        command.args(["--path", conf]).args(args).env("ADMIN_JWT_SECRET", "fingerprinted");
+        success(&mut command)
+    };
+
+    assert!(!skipped(&run(&[])));
+    let marker = kong.store().find("consumers", "username", "kong-init-fingerprint").unwrap();
+    assert!(marker["custom_id"].as_str().unwrap().contains('.'));
+
+    let before = writes(&kong);
+    let services = kong.store().all("services");
+    assert!(skipped(&run(&[])));
+    assert_eq!(writes(&kong), before);
+    assert_eq!(kong.store().all("services"), services);
+
+    // --force applies all the same
+    assert!(!skipped(&run(&["--force"])));
+    assert!(writes(&kong) > before);
+    assert_ne!(kong.store().all("services"), services);
+
+    // a change in kong is applied over
+    kong.store().patch("services", "dummy", "url", json!("http://elsewhere"));
+    assert!(!skipped(&run(&[])));
+    let dummy = kong.store().find("services", "name", "dummy").unwrap();
+    assert_eq!(dummy["url"], json!("http://host.docker.internal:7090/dummy"));
+    assert!(skipped(&run(&[])));
+
+    // and so is a change of the config
+    let changed = fs::read_to_string(conf).unwrap().replace(":7090/dummy", ":7091/dummy");
+    fs::write(conf, changed).unwrap();
+    assert!(!skipped(&run(&[])));
+    let dummy = kong.store().find("services", "name", "dummy").unwrap();
+    assert_eq!(dummy["url"], json!("http://host.docker.internal:7091/dummy"));
+    assert!(skipped(&run(&[])));
+}
+
+const WITH_CREDENTIALS: &str = "upstreams:
+  - name: orders-upstream
+    targets:
+      - target: 10.0.0.1:8080
+services:
+  - name: orders
+    url: http://orders-upstream/orders
+routes: []
+consumers:
+  - username: billing
+credentials:
+  - name: key-auth
+    target: billing
+    config:
+      key: billing-key
+";
+
+#[test]
+fn credentials_and_targets_are_fingerprinted() {
+    let dir = temp_dir("fingerprint-credentials");
+    let conf = dir.join("kong.yaml");
+    fs::write(&conf, WITH_CREDENTIALS).unwrap();
+    let kong = MockKong::start("0.14.1");
+    let run = || success(kong.kong_init().args(["--path", conf.to_str().unwrap()]));
+
+    assert!(!skipped(&run()));
+    assert!(skipped(&run()));
+
+    let key = kong.store().credentials("billing", "key-auth")[0]["id"].as_str().unwrap().to_string();
+    kong.store().patch("key-auth", &key, "key", json!("leaked"));
+    assert!(!skipped(&run()));
+    let keys = kong.store().credentials("billing", "key-auth");
+    assert!(keys.iter().any(|c| c["key"] == "billing-key"), "{:?}", keys);
+    assert!(skipped(&run()));
+
+    let target = kong.store().all("targets")[0]["id"].as_str().unwrap().to_string();
+    kong.store().remove("targets", &target);
+    assert!(!skipped(&run()));
+    assert_eq!(kong.store().all("targets").len(), 1);
+    assert!(skipped(&run()));
+}
+
+#[test]
+fn failed_runs_are_not_fingerprinted() {
+    let dir = temp_dir("fingerprint-failed");
+    let conf = dir.join("kong.yaml");
+    fs::write(&conf, WITH_CREDENTIALS).unwrap();
+    let kong = MockKong::start("0.14.1");
+    // another consumer already holds the declared key, creating it is refused
+    let intruder = kong.store().insert("consumers", json!({ "username": "intruder" }));
+    kong.store()
+        .insert("key-auth", json!({ "key": "billing-key", "consumer": { "id": intruder["id"] } }));
+
+    for _ in 0..2 {
+        let output = kong.kong_init().args(["--path", conf.to_str().unwrap()]).output().unwrap();
+        assert!(!output.status.success());
+        assert!(!skipped(&output));
+        assert!(kong.store().find("consumers", "username", "kong-init-fingerprint").is_none());
+    }
+}
+
+/// GET requests of a run skipped over kong holding `consumers` undeclared consumers with a key each
+fn skipped_reads(consumers: usize) -> usize {
+    let dir = temp_dir(&format!("fingerprint-reads-{}", consumers));
+    let conf = dir.join("kong.yaml");
+    fs::write(&conf, WITH_CREDENTIALS).unwrap();
+    let kong = MockKong::start("0.14.1");
+    for n in 0..consumers {
+        let consumer = kong.store().insert("consumers", json!({ "username": format!("partner-{}", n) }));
+        kong.store().insert("key-auth", json!({ "key": format!("partner-key-{}", n), "consumer": { "id": consumer["id"] } }));
+    }
+    let run = || success(kong.kong_init().args(["--path", conf.to_str().unwrap()]));
+
+    assert!(!skipped(&run()));
+    let before = kong.store().requests.get("GET").cloned().unwrap_or(0);
+    assert!(skipped(&run()));
+    let after = kong.store().requests.get("GET").cloned().unwrap_or(0);
+    after - before
+}
+
+#[test]
+fn skipping_reads_the_credentials_by_type() {
+    // the more consumers, the more pages of consumers and of keys, never a listing per consumer
+    let pages = 2 * (200 / PAGE_SIZE + 1);
+    assert!(skipped_reads(200) <= skipped_reads(0) + pages);
+}
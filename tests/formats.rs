@@ -0,0 +1,119 @@
+//! Definition files in yaml, json and toml, from files or stdin, read into the same definitions.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+use support::{kong_init, success, temp_dir};
+
+const YAML: &str = "# apis: were used before services and routes
+services:
+  - name: orders
+    url: http://orders:8080
+routes:
+  - name: r-orders
+    apply_to: orders
+    config:
+      paths: [/orders]
+";
+
+const JSON: &str = r#"{
+  "services": [{ "name": "orders", "url": "http://orders:8080" }],
+  "routes": [{ "name": "r-orders", "apply_to": "orders", "config": { "paths": ["/orders"] } }]
+}
+"#;
+
+const TOML: &str = r#"[[services]]
+name = "orders"
+url = "http://orders:8080"
+
+[[routes]]
+name = "r-orders"
+apply_to = "orders"
+config = { paths = ["/orders"] }
+"#;
+
+/// the decK state the definitions render into
+const RENDERED: &str = "---
+_format_version: \"1.1\"
+services:
+  - name: orders
+    routes:
+      - name: \"r-orders\"
+        paths:
+          - /orders
+    url: \"http://orders:8080\"";
+
+fn render(path: &Path, extra: &[&str]) -> String {
+    let output = success(
+        kong_init()
+            .args(["render", "--target", "deck", "--path", path.to_str().unwrap()])
+            .args(extra),
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn formats_are_detected_by_extension() {
+    let dir = temp_dir("formats-extension");
+    for (file, contents) in [("kong.yaml", YAML), ("kong.yml", YAML), ("kong.json", JSON), ("kong.toml", TOML)] {
+        fs::write(dir.join(file), contents).unwrap();
+        assert_eq!(render(&dir.join(file), &[]).trim_end(), RENDERED, "{}", file);
+    }
+}
+
+#[test]
+fn format_overrides_the_extension() {
+    let dir = temp_dir("formats-override");
+    fs::write(dir.join("kong.conf"), JSON).unwrap();
+    assert_eq!(render(&dir.join("kong.conf"), &["--format", "json"]).trim_end(), RENDERED);
+    // a toml file misnamed
+    fs::write(dir.join("kong.yaml"), TOML).unwrap();
+    assert_eq!(render(&dir.join("kong.yaml"), &["--format", "toml"]).trim_end(), RENDERED);
+}
+
+#[test]
+fn stdin_is_read_from_dash() {
+    for (format, contents) in [("yaml", YAML), ("json", JSON), ("toml", TOML)] {
+        let mut child = kong_init()
+            .args(["render", "--target", "deck", "--path", "-", "--format", format])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(contents.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), RENDERED, "{}", format);
+    }
+}
+
+#[test]
+fn crlf_line_endings_are_read() {
+    let dir = temp_dir("formats-crlf");
+    for (file, contents) in [("kong.yaml", YAML), ("kong.json", JSON), ("kong.toml", TOML)] {
+        fs::write(dir.join(file), contents.replace('\n', "\r\n")).unwrap();
+        assert_eq!(render(&dir.join(file), &[]).trim_end(), RENDERED, "{}", file);
+    }
+}
+
+#[test]
+fn apis_in_a_comment_is_not_api_style() {
+    let dir = temp_dir("formats-comment");
+    let path = dir.join("kong.yaml");
+    fs::write(&path, YAML).unwrap();
+    // `apis:` at the start of the file, but commented out
+    assert!(YAML.starts_with("# apis:"));
+    assert_eq!(render(&path, &[]).trim_end(), RENDERED);
+
+    // and not only at the start of a line
+    fs::write(&path, format!("{}plugins:\n  - name: prometheus # apis: too\n    target: global\n    enabled: true\n", YAML)).unwrap();
+    assert!(render(&path, &[]).contains("prometheus"));
+}
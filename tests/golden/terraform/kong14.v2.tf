@@ -0,0 +1,102 @@
+resource "kong_service" "netdisk" {
+  name     = "netdisk"
+  protocol = "http"
+  host     = "host.docker.internal"
+  port     = 8090
+}
+
+resource "kong_service" "dummy" {
+  name     = "dummy"
+  protocol = "http"
+  host     = "host.docker.internal"
+  port     = 7090
+  path     = "/dummy"
+}
+
+resource "kong_route" "r-netdisk" {
+  name       = "r-netdisk"
+  service_id = kong_service.netdisk.id
+  paths      = ["/api/v1/netdisk"]
+  strip_path = false
+}
+
+resource "kong_route" "r-dummy" {
+  name       = "r-dummy"
+  service_id = kong_service.dummy.id
+  paths      = ["/dummy"]
+  strip_path = true
+}
+
+resource "kong_route" "r-dummy-no-auth" {
+  name       = "r-dummy-no-auth"
+  service_id = kong_service.dummy.id
+  paths      = ["/dummy/login"]
+  strip_path = true
+}
+
+resource "kong_plugin" "prometheus_global" {
+  name    = "prometheus"
+  enabled = true
+}
+
+resource "kong_plugin" "jwt_r_r-dummy" {
+  name        = "jwt"
+  route_id    = kong_route.r-dummy.id
+  enabled     = false
+  config_json = <<EOT
+{
+  "anonymous": "${kong_consumer.guest_user.id}",
+  "secret_is_base64": false,
+  "uri_param_names": "jwt"
+}
+EOT
+}
+
+resource "kong_plugin" "rate-limiting_s_netdisk" {
+  name        = "rate-limiting"
+  service_id  = kong_service.netdisk.id
+  enabled     = true
+  config_json = <<EOT
+{
+  "hour": 1800,
+  "limit_by": "consumer",
+  "minute": 100,
+  "second": 20
+}
+EOT
+}
+
+resource "kong_plugin" "rate-limiting_s_dummy" {
+  name        = "rate-limiting"
+  service_id  = kong_service.dummy.id
+  enabled     = true
+  config_json = <<EOT
+{
+  "hour": 1800,
+  "limit_by": "consumer",
+  "minute": 100,
+  "second": 20
+}
+EOT
+}
+
+resource "kong_consumer" "guest-user" {
+  username  = "guest-user"
+  custom_id = "anonymous-user"
+}
+
+resource "kong_consumer" "admin" {
+  username  = "admin"
+  custom_id = "admin"
+}
+
+resource "kong_consumer" "guest_user" {
+  username  = "guest_user"
+  custom_id = "guest_user"
+}
+
+resource "kong_consumer_jwt_auth" "admin_jwt_1" {
+  consumer_id = kong_consumer.admin.id
+  key         = "a36c3049b36249a3c9f8891cb127243c"
+  secret      = "s3cr3t-from-env"
+}
@@ -0,0 +1,56 @@
+resource "kong_service" "orders" {
+  name     = "orders"
+  protocol = "https"
+  host     = "orders.internal"
+  port     = 8443
+  path     = "/api"
+}
+
+resource "kong_route" "r-orders" {
+  name       = "r-orders"
+  service_id = kong_service.orders.id
+  hosts      = ["orders.example.com"]
+  paths      = ["/orders"]
+}
+
+resource "kong_plugin" "request-transformer_r_r-orders" {
+  name        = "request-transformer"
+  route_id    = kong_route.r-orders.id
+  enabled     = true
+  config_json = <<EOT
+{
+  "add": {
+    "headers": [
+      "X-Literal:$${KONG_INIT_UNSET_VARIABLE}",
+      "X-Template:%%{if true}yes%%{endif}",
+      "X-Quote:\"q\""
+    ]
+  }
+}
+EOT
+}
+
+resource "kong_plugin" "key-auth_s_orders" {
+  name        = "key-auth"
+  service_id  = kong_service.orders.id
+  enabled     = true
+  config_json = <<EOT
+{
+  "anonymous": "${kong_consumer.anonymous_user.id}"
+}
+EOT
+}
+
+resource "kong_consumer" "billing" {
+  username = "billing"
+}
+
+resource "kong_consumer" "anonymous_user" {
+  username  = "anonymous.user"
+  custom_id = "anonymous.user"
+}
+
+resource "kong_consumer_key_auth" "billing_key-auth_1" {
+  consumer_id = kong_consumer.billing.id
+  key         = "billing-api-key"
+}
@@ -0,0 +1,40 @@
+//! The admin api client: pages, pooled connections and the rate limit.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::time::{Duration, Instant};
+use support::{kong_init, success, MockKong};
+
+#[test]
+fn rate_limit_spaces_requests() {
+    let kong = MockKong::start("0.14.1");
+    let started = Instant::now();
+    success(
+        kong.kong_init()
+            .args(["--rate-limit", "25", "--concurrency", "8", "--path", "example/kong14.v2.yaml"])
+            .env("ADMIN_JWT_SECRET", "limited"),
+    );
+    let elapsed = started.elapsed();
+    let requests: usize = kong.store().requests.values().sum();
+    assert!(requests > 10, "{} requests", requests);
+    // the first request goes at once, each other one 40ms after the one before
+    let spaced = Duration::from_millis(40 * (requests as u64 - 1));
+    assert!(elapsed >= spaced, "{} requests in {:?}", requests, elapsed);
+    assert_eq!(kong.store().credentials("admin", "jwt")[0]["secret"], json!("limited"));
+}
+
+#[test]
+fn rate_limit_must_be_positive() {
+    for rate in &["0", "fast", "inf"] {
+        let output = kong_init()
+            .args(["--url", "http://127.0.0.1:9", "--rate-limit", rate, "--path", "example/kong14.v2.yaml"])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("--rate-limit must be a positive number"));
+    }
+}
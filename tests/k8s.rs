@@ -0,0 +1,48 @@
+//! `render --target k8s`: manifests for the Kong Ingress Controller, against golden files of
+//! `tests/golden/k8s`.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use support::{assert_golden, kong_init, success};
+
+const ADMIN_JWT_SECRET: &str = "s3cr3t-from-env";
+
+fn render(path: &str, extra: &[&str]) -> String {
+    let output = success(
+        kong_init()
+            .args(["render", "--target", "k8s", "--path", path])
+            .args(extra)
+            .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET),
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn rendered_manifests() {
+    assert_golden("k8s/kong14.v2.yaml", &render("example/kong14.v2.yaml", &[]));
+    // upstreams are skipped, certificates become tls secrets of the ingresses matching their snis
+    assert_golden(
+        "k8s/kong1x.yaml",
+        &render("tests/fixtures/kong1x.yaml", &["--namespace", "gateway", "--ingress-class", "kong-internal"]),
+    );
+}
+
+#[test]
+fn manifests_are_valid_yaml_documents() {
+    let rendered = render("tests/fixtures/kong1x.yaml", &["--namespace", "gateway"]);
+    let documents: Vec<serde_json::Value> = rendered
+        .split("\n---")
+        .filter(|doc| !doc.trim().is_empty() && doc.trim() != "---")
+        .map(|doc| serde_yaml::from_str(doc).unwrap())
+        .collect();
+    assert!(!documents.is_empty());
+    for document in &documents {
+        assert!(document["apiVersion"].is_string(), "{}", document);
+        assert!(document["kind"].is_string(), "{}", document);
+        assert_eq!(document["metadata"]["namespace"], json!("gateway"), "{}", document);
+    }
+}
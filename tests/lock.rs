@@ -0,0 +1,121 @@
+//! The lock keeping kong-init runs against the same kong from applying at the same time.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use support::{success, temp_dir, MockKong};
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// another run holding the lock until `expires`
+fn hold_lock(kong: &MockKong, expires: u64) {
+    kong.store().insert(
+        "consumers",
+        json!({ "username": "kong-init-lock", "custom_id": format!("other-host:1@{}", expires) }),
+    );
+}
+
+#[test]
+fn held_lock_is_waited_for() {
+    let kong = MockKong::start("0.14.1");
+    hold_lock(&kong, unix_now() + 2);
+
+    let output = kong
+        .kong_init()
+        .args(["--path", "example/kong14.v2.yaml", "--lock-wait", "0"])
+        .env("ADMIN_JWT_SECRET", "locked")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let logs = String::from_utf8_lossy(&output.stderr);
+    assert!(logs.contains("unable to acquire the lock: held by other-host:1"), "{}", logs);
+    assert!(kong.store().all("services").is_empty());
+
+    // the lease runs out while waiting
+    let started = Instant::now();
+    let output = success(
+        kong.kong_init()
+            .args(["--path", "example/kong14.v2.yaml", "--lock-wait", "30"])
+            .env("ADMIN_JWT_SECRET", "locked"),
+    );
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    let logs = String::from_utf8_lossy(&output.stderr);
+    assert!(logs.contains("waiting for the lock held by other-host:1"), "{}", logs);
+    assert!(logs.contains("the lock of other-host:1 expired, taking it over"), "{}", logs);
+    assert_eq!(kong.store().all("services").len(), 2);
+    assert!(kong.store().find("consumers", "username", "kong-init-lock").is_none());
+}
+
+#[test]
+fn concurrent_runs_take_turns() {
+    let kong = MockKong::start("0.14.1");
+    let runs: Vec<_> = (0..3)
+        .map(|_| {
+            let mut command = kong.kong_init();
+            command.args(["--path", "example/kong14.v2.yaml"]).env("ADMIN_JWT_SECRET", "locked");
+            thread::spawn(move || success(&mut command))
+        }).collect();
+    for run in runs {
+        run.join().unwrap();
+    }
+
+    // each run cleared what the one before applied, none interleaved
+    let store = kong.store();
+    assert_eq!(store.all("services").len(), 2);
+    assert_eq!(store.all("routes").len(), 3);
+    assert_eq!(store.all("plugins").len(), 4);
+    assert!(store.find("consumers", "username", "kong-init-lock").is_none());
+}
+
+#[test]
+fn failed_runs_release_the_lock() {
+    let conf = temp_dir("lock-failed").join("unknown.yaml");
+    fs::write(&conf, "services: []\nroutes:\n  - name: r\n    apply_to: nowhere\n    config:\n      paths: [/r]\n").unwrap();
+    let kong = MockKong::start("0.14.1");
+    let output = kong
+        .kong_init()
+        .args(["--path", conf.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("released the lock"));
+    assert!(kong.store().find("consumers", "username", "kong-init-lock").is_none());
+}
+
+#[test]
+fn runs_longer_than_the_lease_keep_the_lock() {
+    let kong = MockKong::start("0.14.1");
+    // about 50 requests at 8 a second, well beyond the lease
+    let mut slow = kong.kong_init();
+    slow.args(["--path", "example/kong14.v2.yaml", "--lock-ttl", "2", "--rate-limit", "8"])
+        .env("ADMIN_JWT_SECRET", "locked");
+    let started = Instant::now();
+    let slow = thread::spawn(move || success(&mut slow));
+
+    thread::sleep(Duration::from_secs(4));
+    let lock = kong.store().find("consumers", "username", "kong-init-lock").expect("the lock was released early");
+    let expires: u64 = lock["custom_id"].as_str().unwrap().rsplit('@').next().unwrap().parse().unwrap();
+    assert!(expires > unix_now(), "the lease ran out at {}", expires);
+
+    let output = kong
+        .kong_init()
+        .args(["--path", "example/kong14.v2.yaml", "--lock-wait", "0"])
+        .env("ADMIN_JWT_SECRET", "locked")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unable to acquire the lock: held by"));
+
+    slow.join().unwrap();
+    assert!(started.elapsed() > Duration::from_secs(4));
+    assert_eq!(kong.store().all("services").len(), 2);
+    assert!(kong.store().find("consumers", "username", "kong-init-lock").is_none());
+}
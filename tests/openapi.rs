@@ -0,0 +1,52 @@
+//! `import-openapi`: services, routes and plugins generated from an OpenAPI 3 spec, against
+//! golden files of `tests/golden/openapi`.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs;
+use support::{assert_golden, kong_init, success, temp_dir};
+
+fn import(args: &[&str]) -> String {
+    let output = success(kong_init().arg("import-openapi").args(args));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn spec_is_imported() {
+    // path parameters as regex paths, the first server as url, a route per methods sharing auth
+    assert_golden("openapi/petstore.yaml", &import(&["--spec", "tests/fixtures/petstore.yaml"]));
+    assert_golden(
+        "openapi/petstore-named.yaml",
+        &import(&["--spec", "tests/fixtures/petstore.yaml", "--service-name", "pets"]),
+    );
+}
+
+#[test]
+fn spec_is_merged_into_definitions() {
+    let dir = temp_dir("openapi-merge");
+    let existing = dir.join("kong.yaml");
+    fs::write(
+        &existing,
+        "services:
+  - name: swagger-petstore
+    url: http://stale:8080
+  - name: billing
+    url: http://billing:8080
+routes:
+  - name: r-billing
+    apply_to: billing
+    config:
+      paths: [/billing]
+plugins:
+  - name: prometheus
+    target: global
+    enabled: true
+",
+    ).unwrap();
+    let merged = import(&["--spec", "tests/fixtures/petstore.yaml", "--merge", existing.to_str().unwrap()]);
+    assert_golden("openapi/petstore-merged.yaml", &merged);
+}
@@ -0,0 +1,160 @@
+//! Secrets generated for credentials declaring none, kept in the `--state` file across runs.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use support::{success, temp_dir, MockKong};
+
+const VERSION: &str = "0.14.1";
+
+fn apply(kong: &MockKong, dir: &Path, definitions: &str) {
+    let path = dir.join("kong.yaml");
+    fs::write(&path, format!("services: []\nroutes: []\n{}", definitions)).unwrap();
+    let state = dir.join("state.json");
+    success(kong.kong_init().args([
+        "--path",
+        path.to_str().unwrap(),
+        "--state",
+        state.to_str().unwrap(),
+    ]));
+}
+
+/// `field` of the credentials of billing, sorted by `by`
+fn values(kong: &MockKong, endpoint: &str, by: &str, field: &str) -> Vec<(String, String)> {
+    let mut values: Vec<(String, String)> = kong
+        .store()
+        .credentials("billing", endpoint)
+        .iter()
+        .map(|c| (c[by].as_str().unwrap_or_default().to_string(), c[field].as_str().unwrap().to_string()))
+        .collect();
+    values.sort();
+    values
+}
+
+const FIRST: &str = "consumers:
+  - username: billing
+credentials:
+  - name: key-auth
+    target: billing
+  - name: oauth2
+    target: billing
+    config:
+      name: billing-app
+      redirect_uris: ['https://billing.example.com/callback']
+  - name: jwt
+    target: billing
+    config:
+      key: billing-jwt
+";
+
+/// the same credentials reordered, with new ones declared before them
+const REORDERED: &str = "consumers:
+  - username: billing
+credentials:
+  - name: jwt
+    target: billing
+    config:
+      key: billing-jwt-v2
+  - name: oauth2
+    target: billing
+    config:
+      name: reporting-app
+      redirect_uris: ['https://reporting.example.com/callback']
+  - name: jwt
+    target: billing
+    config:
+      key: billing-jwt
+  - name: key-auth
+    target: billing
+    config:
+      tags: [ci]
+  - name: oauth2
+    target: billing
+    config:
+      name: billing-app
+      redirect_uris: ['https://billing.example.com/callback']
+  - name: key-auth
+    target: billing
+";
+
+#[test]
+fn secrets_stay_with_their_credential() {
+    let dir = temp_dir("secrets-reordered");
+    let kong = MockKong::start(VERSION);
+    apply(&kong, &dir, FIRST);
+    let jwt = values(&kong, "jwt", "key", "secret");
+    let oauth2 = values(&kong, "oauth2", "name", "client_secret");
+    let key_auth = values(&kong, "key-auth", "id", "key");
+    assert_eq!(jwt.len(), 1);
+    assert_eq!(oauth2.len(), 1);
+    assert_eq!(key_auth.len(), 1);
+
+    apply(&kong, &dir, REORDERED);
+    let after = values(&kong, "jwt", "key", "secret");
+    assert_eq!(after.len(), 2);
+    assert!(after.contains(&jwt[0]), "{:?} rotated: {:?}", jwt, after);
+    let after = values(&kong, "oauth2", "name", "client_secret");
+    assert_eq!(after.len(), 2);
+    assert!(after.contains(&oauth2[0]), "{:?} rotated: {:?}", oauth2, after);
+    let after = values(&kong, "key-auth", "id", "key");
+    assert_eq!(after.len(), 2);
+    assert!(after.contains(&key_auth[0]), "{:?} rotated: {:?}", key_auth, after);
+
+    let state: Value = serde_json::from_str(&fs::read_to_string(dir.join("state.json")).unwrap()).unwrap();
+    let mut slots: Vec<&String> = state["secrets"].as_object().unwrap().keys().collect();
+    slots.sort();
+    assert_eq!(
+        slots,
+        [
+            "billing:jwt:{\"key\":\"billing-jwt\"}",
+            "billing:jwt:{\"key\":\"billing-jwt-v2\"}",
+            "billing:key-auth:{\"tags\":[\"ci\"]}",
+            "billing:key-auth:{}",
+            "billing:oauth2:{\"name\":\"billing-app\",\"redirect_uris\":[\"https://billing.example.com/callback\"]}",
+            "billing:oauth2:{\"name\":\"reporting-app\",\"redirect_uris\":[\"https://reporting.example.com/callback\"]}",
+        ]
+    );
+}
+
+#[test]
+fn identical_credentials_are_told_apart_by_rank() {
+    let dir = temp_dir("secrets-identical");
+    let kong = MockKong::start(VERSION);
+    let twice = "consumers:
+  - username: billing
+credentials:
+  - name: key-auth
+    target: billing
+  - name: key-auth
+    target: billing
+";
+    apply(&kong, &dir, twice);
+    let keys = values(&kong, "key-auth", "id", "key");
+    assert_eq!(keys.len(), 2);
+    assert_ne!(keys[0].1, keys[1].1);
+    apply(&kong, &dir, twice);
+    assert_eq!(values(&kong, "key-auth", "id", "key"), keys);
+}
+
+#[test]
+fn index_slots_of_earlier_versions_are_taken_over() {
+    let dir = temp_dir("secrets-legacy");
+    fs::write(
+        dir.join("state.json"),
+        json!({ "secrets": { "billing:key-auth:1": { "key": "kept-from-an-earlier-version" } } }).to_string(),
+    ).unwrap();
+    let kong = MockKong::start(VERSION);
+    apply(&kong, &dir, FIRST);
+    let keys: Vec<String> = values(&kong, "key-auth", "id", "key").into_iter().map(|(_, key)| key).collect();
+    assert_eq!(keys, ["kept-from-an-earlier-version"]);
+
+    let state: Value = serde_json::from_str(&fs::read_to_string(dir.join("state.json")).unwrap()).unwrap();
+    assert!(state["secrets"].get("billing:key-auth:1").is_none());
+    assert_eq!(state["secrets"]["billing:key-auth:{}"]["key"], "kept-from-an-earlier-version");
+}
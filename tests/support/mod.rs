@@ -0,0 +1,722 @@
+//! In-process fake of the Kong Admin API, enough of it to run kong-init against.
+//!
+//! Entities live in memory, listings are paged through `offset` a few entities at a time and
+//! the endpoints available follow the reported version: `/apis` before 1.0, services/routes
+//! since 0.13 and the declarative `/config` since 1.1.
+#![allow(dead_code)]
+
+use serde_json::{Map, Value};
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+/// entities per page, small so every listing goes through `offset`
+pub const PAGE_SIZE: usize = 2;
+
+const CREDENTIALS: &[&str] = &["key-auth", "basic-auth", "hmac-auth", "jwt", "oauth2", "acls"];
+
+/// top-level listings of the credentials of every consumer, by endpoint
+const CREDENTIAL_LISTINGS: &[(&str, &str)] = &[
+    ("key-auths", "key-auth"),
+    ("basic-auths", "basic-auth"),
+    ("hmac-auths", "hmac-auth"),
+    ("jwts", "jwt"),
+    ("oauth2", "oauth2"),
+    ("acls", "acls"),
+];
+
+/// what kong 0.14 gives the fields left out of a created entity
+fn defaults(collection: &str) -> Value {
+    match collection {
+        "services" => json!({ "retries": 5, "connect_timeout": 60000, "write_timeout": 60000, "read_timeout": 60000 }),
+        "routes" => json!({ "protocols": ["http", "https"], "regex_priority": 0, "strip_path": true, "preserve_host": false }),
+        "upstreams" => json!({ "slots": 1000, "hash_on": "none", "hash_fallback": "none", "hash_on_cookie_path": "/" }),
+        "targets" => json!({ "weight": 100 }),
+        _ => json!({}),
+    }
+}
+
+/// `/plugins/schema/<name>` in kong 0.14's shape, the defaults filled into created plugins
+fn plugin_schema(name: &str) -> Option<Value> {
+    match name {
+        "rate-limiting" => Some(json!({ "fields": {
+            "second": { "type": "number" },
+            "minute": { "type": "number" },
+            "hour": { "type": "number" },
+            "limit_by": { "type": "string", "default": "consumer" },
+            "policy": { "type": "string", "default": "cluster" },
+            "fault_tolerant": { "type": "boolean", "default": true },
+            "hide_client_headers": { "type": "boolean", "default": false },
+        } })),
+        _ => None,
+    }
+}
+
+/// usernames of the consumers kong-init keeps for itself, its lock and fingerprint
+const RESERVED_PREFIX: &str = "kong-init-";
+
+/// fields identifying an entity besides its id, by collection
+const UNIQUE_FIELDS: &[(&str, &[&str])] = &[
+    ("apis", &["name"]),
+    ("services", &["name"]),
+    ("upstreams", &["name"]),
+    ("consumers", &["username", "custom_id"]),
+    ("key-auth", &["key"]),
+    ("basic-auth", &["username"]),
+    ("hmac-auth", &["username"]),
+    ("jwt", &["key"]),
+    ("oauth2", &["client_id"]),
+];
+
+#[derive(Default)]
+pub struct Store {
+    /// entities by collection, credentials by endpoint(`key-auth`...) and targets by `targets`
+    entities: BTreeMap<String, Vec<Value>>,
+    /// declarative configs posted to `/config`
+    pub configs: Vec<String>,
+    /// number of requests by method
+    pub requests: BTreeMap<String, usize>,
+    /// number of requests by method on kong-init's own consumers, its lock and fingerprint
+    pub own_requests: BTreeMap<String, usize>,
+    /// requests(`GET /consumers/guest_user`) answered with a 500, as a kong in trouble would
+    pub failing: Vec<String>,
+    next_id: u64,
+}
+
+impl Store {
+    /// the entities of a collection, without kong-init's own consumers(see `find`)
+    pub fn all(&self, collection: &str) -> Vec<Value> {
+        self.stored(collection)
+            .into_iter()
+            .filter(|e| collection != "consumers" || !is_reserved(e))
+            .collect()
+    }
+
+    /// the entity whose `field` is `value`
+    pub fn find(&self, collection: &str, field: &str, value: &str) -> Option<Value> {
+        self.stored(collection)
+            .into_iter()
+            .find(|e| e.get(field).and_then(|v| v.as_str()) == Some(value))
+    }
+
+    /// credentials of an endpoint belonging to the consumer with `username`
+    pub fn credentials(&self, username: &str, endpoint: &str) -> Vec<Value> {
+        let consumer_id = self.find("consumers", "username", username).map(|c| c["id"].clone());
+        self.stored(endpoint)
+            .into_iter()
+            .filter(|c| Some(&c["consumer"]["id"]) == consumer_id.as_ref())
+            .collect()
+    }
+
+    /// create an entity as another admin api client would
+    pub fn insert(&mut self, collection: &str, entity: Value) -> Value {
+        let (status, created) = create(self, collection, entity);
+        assert_eq!(status, 201, "{}", created);
+        created
+    }
+
+    /// change a field of an entity behind kong-init's back, as a curl to the admin api would
+    pub fn patch(&mut self, collection: &str, key: &str, field: &str, value: Value) {
+        let idx = self.lookup(collection, key).unwrap_or_else(|| panic!("no {} {}", collection, key));
+        self.entities.get_mut(collection).unwrap()[idx][field] = value;
+    }
+
+    /// delete an entity behind kong-init's back
+    pub fn remove(&mut self, collection: &str, key: &str) {
+        let idx = self.lookup(collection, key).unwrap_or_else(|| panic!("no {} {}", collection, key));
+        self.entities.get_mut(collection).unwrap().remove(idx);
+    }
+
+    fn stored(&self, collection: &str) -> Vec<Value> {
+        self.entities.get(collection).cloned().unwrap_or_default()
+    }
+
+    /// whether a request reads or changes one of kong-init's own consumers
+    fn on_reserved(&self, segments: &[&str], body: &Value) -> bool {
+        match segments {
+            ["consumers"] => is_reserved(body),
+            ["consumers", key, ..] => {
+                key.starts_with(RESERVED_PREFIX)
+                    || self.lookup("consumers", key).is_some_and(|idx| is_reserved(&self.entities["consumers"][idx]))
+            }
+            _ => false,
+        }
+    }
+
+    /// ids are uuids, counting up
+    fn new_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("00000000-0000-4000-8000-{:012}", self.next_id)
+    }
+
+    fn lookup(&self, collection: &str, key: &str) -> Option<usize> {
+        let by_name: &[&str] = match collection {
+            "consumers" => &["username"],
+            "apis" | "services" | "upstreams" | "routes" => &["name"],
+            _ => &[],
+        };
+        self.entities.get(collection)?.iter().position(|e| {
+            e["id"].as_str() == Some(key)
+                || by_name.iter().any(|f| e[*f].as_str() == Some(key))
+                || (collection == "certificates"
+                    && e["snis"].as_array().is_some_and(|snis| snis.iter().any(|s| s == key)))
+        })
+    }
+}
+
+pub struct MockKong {
+    pub url: String,
+    store: Arc<Mutex<Store>>,
+}
+
+impl MockKong {
+    /// listen on a free local port, reporting `version`
+    pub fn start(version: &str) -> MockKong {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let store = Arc::new(Mutex::new(Store::default()));
+        let shared = Arc::clone(&store);
+        let version = Version::parse(version);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let store = Arc::clone(&shared);
+                let version = version.clone();
+                thread::spawn(move || serve(stream, &version, &store));
+            }
+        });
+        MockKong { url, store }
+    }
+
+    pub fn store(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap()
+    }
+
+    /// kong-init pointed at this admin api
+    pub fn kong_init(&self) -> Command {
+        let mut command = kong_init();
+        command.arg("--url").arg(&self.url);
+        command
+    }
+}
+
+/// the kong-init binary, run from the crate root so `example/` paths resolve
+pub fn kong_init() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_kong-init"));
+    command.current_dir(env!("CARGO_MANIFEST_DIR"));
+    command
+}
+
+/// run to completion, failing the test with the output unless it succeeded
+pub fn success(command: &mut Command) -> Output {
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "kong-init failed({}):\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// an empty directory for the files of one test
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("kong-init-tests-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// wait for `done` for a while, failing with the logs
+pub fn eventually<F: Fn() -> bool>(logs: &Path, what: &str, done: F) {
+    let started = Instant::now();
+    while !done() {
+        if started.elapsed() > Duration::from_secs(20) {
+            panic!("{} never happened:\n{}", what, fs::read_to_string(logs).unwrap_or_default());
+        }
+        sleep(Duration::from_millis(100));
+    }
+}
+
+pub fn logged(logs: &Path, text: &str) -> bool {
+    fs::read_to_string(logs).unwrap_or_default().contains(text)
+}
+
+/// Compare `actual` with the golden file `tests/golden/<name>`, failing with both unless they
+/// are the same. `UPDATE_GOLDEN=1 cargo test` rewrites the golden files instead.
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|why| panic!("{}: {}, UPDATE_GOLDEN=1 writes it", path.display(), why));
+    assert!(
+        expected == actual,
+        "{} differs, UPDATE_GOLDEN=1 rewrites it\n--- expected\n{}\n--- actual\n{}",
+        path.display(),
+        expected,
+        actual
+    );
+}
+
+#[derive(Clone)]
+struct Version {
+    text: String,
+    major_minor: (u64, u64),
+}
+
+impl Version {
+    fn parse(text: &str) -> Version {
+        let mut numbers = text
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|n| n.parse().unwrap_or(0));
+        let major_minor = (numbers.next().unwrap_or(0), numbers.next().unwrap_or(0));
+        Version {
+            text: text.to_string(),
+            major_minor,
+        }
+    }
+
+    fn at_least(&self, major: u64, minor: u64) -> bool {
+        self.major_minor >= (major, minor)
+    }
+
+    fn serves(&self, collection: &str) -> bool {
+        match collection {
+            "apis" => !self.at_least(1, 0),
+            "services" | "routes" => self.at_least(0, 13),
+            "config" => self.at_least(1, 1),
+            _ => true,
+        }
+    }
+}
+
+fn serve(stream: TcpStream, version: &Version, store: &Mutex<Store>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+    let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let (status, response) = {
+        let mut store = store.lock().unwrap();
+        let path = target.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        if store.on_reserved(&segments, &body) {
+            *store.own_requests.entry(method.clone()).or_default() += 1;
+        }
+        *store.requests.entry(method.clone()).or_default() += 1;
+        if store.failing.contains(&format!("{} {}", method, path)) {
+            (500, json!({ "message": "An unexpected error occurred" }))
+        } else {
+            handle(&mut store, version, &method, &target, body)
+        }
+    };
+    let text = if status == 204 { String::new() } else { response.to_string() };
+    let mut stream = stream;
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        text.len(),
+        text
+    );
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        500 => "Internal Server Error",
+        _ => "Unknown",
+    }
+}
+
+fn is_reserved(entity: &Value) -> bool {
+    entity["username"].as_str().is_some_and(|u| u.starts_with(RESERVED_PREFIX))
+}
+
+/// decode a query value the way kong does, a `+` is a space
+fn decode(value: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = value.bytes();
+    while let Some(b) = rest.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: String = rest.by_ref().take(2).map(char::from).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).unwrap_or(b'?'));
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// the offset of a page ending with the entity of `id`, opaque like kong's base64 ones
+fn cursor(id: &str) -> String {
+    format!("{}/+=", id)
+}
+
+fn handle(store: &mut Store, version: &Version, method: &str, target: &str, body: Value) -> (u16, Value) {
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (target, ""),
+    };
+    let query: BTreeMap<String, String> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.to_string(), decode(v)))
+        .collect();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if let Some(collection) = segments.first() {
+        if !version.serves(collection) {
+            return not_found();
+        }
+    }
+
+    match (method, segments.as_slice()) {
+        ("GET", []) => (200, json!({ "version": version.text, "tagline": "Welcome to kong" })),
+        ("POST", ["config"]) => {
+            store.configs.push(body["config"].as_str().unwrap_or_default().to_string());
+            (201, json!({}))
+        }
+        ("GET", [listing]) if CREDENTIAL_LISTINGS.iter().any(|(l, _)| l == listing) => {
+            let endpoint = CREDENTIAL_LISTINGS.iter().find(|(l, _)| l == listing).unwrap().1;
+            list(store, endpoint, &query, |_| true)
+        }
+        ("GET", [collection]) => list(store, collection, &query, |_| true),
+        ("POST", [collection]) => create(store, collection, body),
+        ("PUT", ["apis"]) => {
+            let name = body["name"].as_str().unwrap_or_default().to_string();
+            if let Some(idx) = store.lookup("apis", &name) {
+                store.entities.get_mut("apis").unwrap().remove(idx);
+            }
+            create(store, "apis", body)
+        }
+        ("GET", ["plugins", "schema", name]) => match plugin_schema(name) {
+            Some(schema) => (200, schema),
+            None => not_found(),
+        },
+        ("GET", [collection, key]) => match store.lookup(collection, key) {
+            Some(idx) => (200, store.entities[*collection][idx].clone()),
+            None => not_found(),
+        },
+        ("PATCH", [collection, key]) => patch(store, collection, key, body),
+        ("DELETE", [collection, key]) => delete(store, collection, key),
+        ("POST", ["apis", api, "plugins"]) => match store.lookup("apis", api) {
+            Some(idx) => {
+                let api_id = store.entities["apis"][idx]["id"].clone();
+                create(store, "plugins", with(body, "api_id", api_id))
+            }
+            None => not_found(),
+        },
+        ("GET", ["upstreams", upstream, "targets"]) => match store.lookup("upstreams", upstream) {
+            Some(idx) => {
+                let upstream_id = store.entities["upstreams"][idx]["id"].clone();
+                list(store, "targets", &query, |t| t["upstream_id"] == upstream_id)
+            }
+            None => not_found(),
+        },
+        ("POST", ["upstreams", upstream, "targets"]) => match store.lookup("upstreams", upstream) {
+            Some(idx) => {
+                let upstream_id = store.entities["upstreams"][idx]["id"].clone();
+                create(store, "targets", with(body, "upstream_id", upstream_id))
+            }
+            None => not_found(),
+        },
+        (_, ["consumers", consumer, endpoint, rest @ ..]) if CREDENTIALS.contains(endpoint) => {
+            let consumer_id = match store.lookup("consumers", consumer) {
+                Some(idx) => store.entities["consumers"][idx]["id"].clone(),
+                None => return not_found(),
+            };
+            let owned = |c: &Value| c["consumer"]["id"] == consumer_id;
+            match (method, rest) {
+                ("GET", []) => list(store, endpoint, &query, owned),
+                ("POST", []) => {
+                    let credential = with(body, "consumer", json!({ "id": consumer_id }));
+                    create(store, endpoint, credential)
+                }
+                ("PATCH", [id]) => patch(store, endpoint, id, body),
+                ("DELETE", [id]) => delete(store, endpoint, id),
+                _ => not_found(),
+            }
+        }
+        _ => not_found(),
+    }
+}
+
+fn not_found() -> (u16, Value) {
+    (404, json!({ "message": "Not found" }))
+}
+
+fn with(body: Value, field: &str, value: Value) -> Value {
+    let mut body = match body {
+        Value::Object(fields) => fields,
+        _ => Map::new(),
+    };
+    body.insert(field.to_string(), value);
+    Value::Object(body)
+}
+
+fn list<F>(store: &Store, collection: &str, query: &BTreeMap<String, String>, keep: F) -> (u16, Value)
+where
+    F: Fn(&Value) -> bool,
+{
+    // kong pages `size` entities(100 by default) up to 1000, this admin api fewer still
+    let size = match query.get("size").map(|size| size.parse::<usize>()) {
+        None => PAGE_SIZE,
+        Some(Ok(size)) if size > 0 && size <= 1000 => size.min(PAGE_SIZE),
+        Some(_) => return (400, json!({ "size": "must be a number between 1 and 1000" })),
+    };
+    let matching: Vec<Value> = store
+        .stored(collection)
+        .into_iter()
+        .filter(|e| keep(e))
+        .filter(|e| {
+            query
+                .iter()
+                .filter(|(k, _)| *k != "offset" && *k != "size")
+                .all(|(k, v)| e[k.as_str()].as_str() == Some(v.as_str()))
+        }).collect();
+    // like kong's, the offset is a cursor(the last id listed) and not a position, deleting the
+    // entities of a page does not make the next one skip any
+    let total = matching.len();
+    let mut matching = matching;
+    matching.sort_by(|a, b| a["id"].as_str().cmp(&b["id"].as_str()));
+    let after = match query.get("offset").map(|offset| offset.strip_suffix("/+=")) {
+        None => None,
+        Some(Some(after)) => Some(after),
+        Some(None) => return (400, json!({ "offset": "invalid offset" })),
+    };
+    let rest: Vec<Value> = matching
+        .into_iter()
+        .filter(|e| after.is_none_or(|after| e["id"].as_str().unwrap_or_default() > after))
+        .collect();
+    let page: Vec<Value> = rest.iter().take(size).cloned().collect();
+    let (next_url, next_offset) = match page.last() {
+        Some(last) if rest.len() > size => (
+            json!(format!(
+                "/{}?offset={}",
+                collection,
+                cursor(last["id"].as_str().unwrap_or_default()).replace('/', "%2F").replace('+', "%2B").replace('=', "%3D")
+            )),
+            json!(cursor(last["id"].as_str().unwrap_or_default())),
+        ),
+        _ => (Value::Null, Value::Null),
+    };
+    (
+        200,
+        json!({ "data": page, "total": total, "next": next_url, "offset": next_offset }),
+    )
+}
+
+fn create(store: &mut Store, collection: &str, body: Value) -> (u16, Value) {
+    let mut entity = match nested(body) {
+        Value::Object(fields) => fields,
+        _ => return (400, json!({ "message": "body must be an object" })),
+    };
+    if let Some(conflict) = conflict(store, collection, &entity) {
+        return (409, json!({ "message": conflict }));
+    }
+
+    let id = match entity.get("id").and_then(|id| id.as_str()) {
+        Some(id) => id.to_string(),
+        None => store.new_id(),
+    };
+    entity.insert("id".to_string(), Value::String(id));
+    entity.insert("created_at".to_string(), json!(1_500_000_000));
+    match collection {
+        "key-auth" | "jwt" if !entity.contains_key("key") => {
+            let key = store.new_id();
+            entity.insert("key".to_string(), Value::String(key));
+        }
+        "basic-auth" => {
+            let password = format!(
+                "{}{}",
+                entity.get("password").and_then(|p| p.as_str()).unwrap_or_default(),
+                entity["consumer"]["id"].as_str().unwrap_or_default()
+            );
+            entity.insert("password".to_string(), Value::String(sha1_hex(&password)));
+        }
+        _ => {}
+    }
+    if !entity.contains_key("enabled") && collection == "plugins" {
+        entity.insert("enabled".to_string(), Value::Bool(true));
+    }
+    for (field, default) in defaults(collection).as_object().unwrap() {
+        entity.entry(field.clone()).or_insert_with(|| default.clone());
+    }
+    let schema = entity.get("name").and_then(|name| name.as_str()).and_then(plugin_schema);
+    if let (Some(schema), "plugins") = (schema, collection) {
+        let config = entity.entry("config").or_insert_with(|| json!({}));
+        for (field, definition) in schema["fields"].as_object().unwrap() {
+            if let Some(default) = definition.get("default") {
+                config[field.as_str()] = config.get(field.as_str()).cloned().unwrap_or_else(|| default.clone());
+            }
+        }
+    }
+
+    let entity = Value::Object(entity);
+    store
+        .entities
+        .entry(collection.to_string())
+        .or_default()
+        .push(entity.clone());
+    (201, entity)
+}
+
+/// message of a unique constraint the entity would violate
+fn conflict(store: &Store, collection: &str, entity: &Map<String, Value>) -> Option<String> {
+    let existing = store.stored(collection);
+    let fields = UNIQUE_FIELDS
+        .iter()
+        .find(|(c, _)| *c == collection)
+        .map_or(&[][..], |(_, fields)| *fields);
+    for field in fields {
+        if let Some(value) = entity.get(*field).filter(|v| !v.is_null()) {
+            if existing.iter().any(|e| &e[*field] == value) {
+                return Some(format!("UNIQUE violation detected on '{{{}={}}}'", field, value));
+            }
+        }
+    }
+    let same_target = |e: &Value, fields: &[&str]| {
+        fields.iter().all(|f| e[*f] == *entity.get(*f).unwrap_or(&Value::Null))
+    };
+    match collection {
+        "plugins" if existing.iter().any(|e| {
+            same_target(e, &["name", "api_id", "service_id", "route_id", "consumer_id"])
+        }) => Some("plugin already exists".to_string()),
+        "acls" if existing.iter().any(|e| e["consumer"] == entity["consumer"] && same_target(e, &["group"])) => {
+            Some("group already assigned".to_string())
+        }
+        _ => None,
+    }
+}
+
+fn patch(store: &mut Store, collection: &str, key: &str, body: Value) -> (u16, Value) {
+    let idx = match store.lookup(collection, key) {
+        Some(idx) => idx,
+        None => return not_found(),
+    };
+    let mut fields = match nested(body) {
+        Value::Object(fields) => fields,
+        _ => return (400, json!({ "message": "body must be an object" })),
+    };
+    let entity = &mut store.entities.get_mut(collection).unwrap()[idx];
+    if collection == "basic-auth" {
+        if let Some(password) = fields.get("password").and_then(|p| p.as_str()) {
+            let salted = format!("{}{}", password, entity["consumer"]["id"].as_str().unwrap_or_default());
+            fields.insert("password".to_string(), Value::String(sha1_hex(&salted)));
+        }
+    }
+    if let Value::Object(current) = entity {
+        current.extend(fields);
+    }
+    (200, entity.clone())
+}
+
+fn delete(store: &mut Store, collection: &str, key: &str) -> (u16, Value) {
+    let idx = match store.lookup(collection, key) {
+        Some(idx) => idx,
+        None => return not_found(),
+    };
+    let id = store.entities[collection][idx]["id"].clone();
+    let referenced = collection == "services"
+        && store.all("routes").iter().any(|r| r["service"]["id"] == id);
+    if referenced {
+        return (400, json!({ "message": "an existing 'routes' entity references this 'services' entity" }));
+    }
+    store.entities.get_mut(collection).unwrap().remove(idx);
+
+    // what kong deletes along
+    let dependents: &[&str] = match collection {
+        "apis" => &["plugins"],
+        "upstreams" => &["targets"],
+        "consumers" => CREDENTIALS,
+        _ => &[],
+    };
+    for dependent in dependents {
+        if let Some(entities) = store.entities.get_mut(*dependent) {
+            entities.retain(|e| e["api_id"] != id && e["upstream_id"] != id && e["consumer"]["id"] != id);
+        }
+    }
+    (204, Value::Null)
+}
+
+/// `config.x` fields as nested objects and `"true"`/`"false"` as booleans, the way kong stores them
+fn nested(body: Value) -> Value {
+    let fields = match body {
+        Value::Object(fields) => fields,
+        other => return other,
+    };
+    let mut result = Map::new();
+    for (key, value) in fields {
+        let mut path: Vec<&str> = key.split('.').collect();
+        let last = path.pop().unwrap();
+        let mut current = &mut result;
+        for part in path {
+            current = match current
+                .entry(part.to_string())
+                .or_insert_with(|| Value::Object(Map::new()))
+            {
+                Value::Object(inner) => inner,
+                _ => panic!("`{}` is both a value and an object", key),
+            };
+        }
+        let value = match value.as_str() {
+            Some("true") => Value::Bool(true),
+            Some("false") => Value::Bool(false),
+            _ => value,
+        };
+        current.insert(last.to_string(), value);
+    }
+    Value::Object(result)
+}
+
+fn sha1_hex(text: &str) -> String {
+    Sha1::digest(text.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -0,0 +1,41 @@
+//! `render --target terraform`: HCL for the kong terraform provider, against golden files of
+//! `tests/golden/terraform`.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use support::{assert_golden, kong_init, success};
+
+const ADMIN_JWT_SECRET: &str = "s3cr3t-from-env";
+
+fn render(path: &str) -> String {
+    let output = success(
+        kong_init()
+            .args(["render", "--target", "terraform", "--path", path])
+            .env("ADMIN_JWT_SECRET", ADMIN_JWT_SECRET)
+            .env_remove("KONG_INIT_UNSET_VARIABLE"),
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn rendered_resources() {
+    assert_golden("terraform/kong14.v2.tf", &render("example/kong14.v2.yaml"));
+    assert_golden("terraform/kong1x.tf", &render("tests/fixtures/kong1x.yaml"));
+}
+
+#[test]
+fn consumer_references_become_interpolations() {
+    let rendered = render("tests/fixtures/terraform-refs.yaml");
+    assert_golden("terraform/terraform-refs.tf", &rendered);
+    // `{{k-upsert-consumer:..}}` refers to the consumer resource, declared along
+    assert!(rendered.contains("\"anonymous\": \"${kong_consumer.anonymous_user.id}\""));
+    assert!(rendered.contains("resource \"kong_consumer\" \"anonymous_user\" {"));
+    // the other `${..}` and `%{..}` are escaped, terraform takes them literally
+    assert!(rendered.contains("X-Literal:$${KONG_INIT_UNSET_VARIABLE}"));
+    assert!(rendered.contains("X-Template:%%{if true}yes%%{endif}"));
+    assert_eq!(rendered.matches("${kong_consumer.").count(), 1);
+}
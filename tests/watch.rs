@@ -0,0 +1,155 @@
+//! `--watch`: applying only what changed whenever the definition files change.
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+
+mod support;
+
+use std::fs::{self, File};
+use std::process::{Child, Stdio};
+use support::{eventually, logged, success, temp_dir, MockKong};
+
+const MAIN: &str = "include:
+  - services.yaml
+routes:
+  - name: r-orders
+    apply_to: orders
+    config:
+      paths: [/orders]
+plugins:
+  - name: rate-limiting
+    target: r[r-orders]
+    config:
+      minute: 20
+    enabled: true
+";
+
+fn services(host: &str) -> String {
+    format!("services:\n  - name: orders\n    url: http://{}:8080/\n", host)
+}
+
+/// kills the watching kong-init when the test ends, whatever the outcome
+struct Watching(Child);
+
+impl Drop for Watching {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn edits_apply_only_the_difference() {
+    let dir = temp_dir("watch");
+    let main = dir.join("kong.yaml");
+    let included = dir.join("services.yaml");
+    let logs = dir.join("kong-init.log");
+    fs::write(&main, MAIN).unwrap();
+    fs::write(&included, services("orders-v1")).unwrap();
+
+    let kong = MockKong::start("0.14.1");
+    let child = kong
+        .kong_init()
+        .args(["--path", main.to_str().unwrap(), "--watch"])
+        .stdout(Stdio::null())
+        .stderr(File::create(&logs).unwrap())
+        .spawn()
+        .unwrap();
+    let _watching = Watching(child);
+
+    eventually(&logs, "the first apply", || logged(&logs, "watching 2 files for changes"));
+    assert!(logged(&logs, "3 to create, 0 to update, 0 to delete, 0 unchanged"));
+    let route = kong.store().all("routes")[0]["id"].clone();
+    assert_eq!(kong.store().all("plugins").len(), 1);
+
+    // an edit of the included file updates the service, the rest is left alone
+    fs::write(&included, services("orders-v2")).unwrap();
+    eventually(&logs, "the service update", || {
+        kong.store().find("services", "name", "orders").unwrap()["url"] == "http://orders-v2:8080/"
+    });
+    eventually(&logs, "the second summary", || logged(&logs, "0 to create, 1 to update, 0 to delete, 2 unchanged"));
+    assert!(logged(&logs, "services.yaml changed"));
+    assert!(logged(
+        &logs,
+        "~ service orders: url: \"http://orders-v1:8080/\" -> \"http://orders-v2:8080/\""
+    ));
+    assert_eq!(kong.store().all("routes")[0]["id"], route);
+
+    // invalid definitions are reported and the next edit awaited
+    fs::write(&main, "routes: [").unwrap();
+    eventually(&logs, "the invalid config", || logged(&logs, "invalid config"));
+    fs::write(&main, MAIN.replace("minute: 20", "minute: 30")).unwrap();
+    eventually(&logs, "the plugin update", || {
+        kong.store().all("plugins")[0]["config"]["minute"] == json!(30)
+    });
+    assert!(logged(&logs, "~ plugin rate-limiting on route r-orders: config.minute: 20 -> 30"));
+    assert_eq!(kong.store().all("plugins").len(), 1);
+}
+
+const DECLARED: &str = "services:
+  - name: api.v1
+    url: http://orders:8080/
+    retries: 2
+routes:
+  - name: r_orders.v1
+    apply_to: api.v1
+    config:
+      paths: [/orders]
+      hosts: [orders.example.com]
+plugins:
+  - name: rate-limiting
+    target: r[r_orders.v1]
+    config:
+      minute: 20
+      hour: 500
+      policy: local
+    enabled: true
+";
+
+#[test]
+fn left_out_fields_are_reset() {
+    let dir = temp_dir("watch-left-out");
+    let main = dir.join("kong.yaml");
+    let logs = dir.join("kong-init.log");
+    fs::write(&main, DECLARED).unwrap();
+
+    // names are taken as written by the full apply and the watching one alike
+    let kong = MockKong::start("0.14.1");
+    success(kong.kong_init().args(["--path", main.to_str().unwrap()]));
+    let route = kong.store().all("routes")[0]["id"].clone();
+    assert_eq!(kong.store().all("plugins")[0]["route_id"], route);
+
+    let child = kong
+        .kong_init()
+        .args(["--path", main.to_str().unwrap(), "--watch"])
+        .stdout(Stdio::null())
+        .stderr(File::create(&logs).unwrap())
+        .spawn()
+        .unwrap();
+    let _watching = Watching(child);
+    eventually(&logs, "the first apply", || logged(&logs, "watching 1 files for changes"));
+    assert!(logged(&logs, "0 to create, 0 to update, 0 to delete, 3 unchanged"));
+
+    let edited = DECLARED
+        .replace("    retries: 2\n", "")
+        .replace("      hosts: [orders.example.com]\n", "")
+        .replace("      minute: 20\n", "")
+        .replace("      policy: local\n", "");
+    fs::write(&main, edited).unwrap();
+    eventually(&logs, "the resets", || logged(&logs, "1 to create, 2 to update, 1 to delete, 0 unchanged"));
+    assert!(logged(&logs, "~ service api.v1: retries: 2 -> 5"));
+    assert!(logged(&logs, "~ route r_orders.v1: hosts: [\"orders.example.com\"] -> null"));
+    assert!(logged(&logs, "+ plugin rate-limiting on route r_orders.v1: config.minute: 20 -> null, config.policy: \"local\" -> \"cluster\""));
+
+    let store = kong.store();
+    assert_eq!(store.find("services", "name", "api.v1").unwrap()["retries"], json!(5));
+    assert_eq!(store.all("routes")[0]["id"], route);
+    assert!(store.all("routes")[0]["hosts"].is_null());
+    let plugins = store.all("plugins");
+    assert_eq!(plugins.len(), 1);
+    assert_eq!(plugins[0]["route_id"], route);
+    assert!(plugins[0]["config"]["minute"].is_null());
+    assert_eq!(plugins[0]["config"]["policy"], "cluster");
+    assert_eq!(plugins[0]["config"]["hour"], json!(500));
+}